@@ -0,0 +1,445 @@
+//! Importing melodies written in ABC notation, so the folk tune collections
+//! out there can be turned into blip programs to mangle.
+//!
+//! This covers what melodies mostly use: the header fields for the meter,
+//! note length, tempo and key, then notes with accidentals, octave marks,
+//! lengths, broken rhythms, ties, triplets and rests. Repeats and first and
+//! second endings are played out in full, and each repeated part starts with
+//! a label so that jumps can be added to it afterwards. Chords play just their
+//! first note, and grace notes, decorations and chord symbols are left out.
+
+use std::collections::HashMap;
+use compiler::beats;
+use tuning::note_name;
+
+pub struct AbcError {
+    /// 1-based, like compile errors
+    pub line: usize,
+    pub message: String,
+}
+
+fn error(line: usize, message: &str) -> AbcError {
+    AbcError { line, message: message.to_string() }
+}
+
+enum Token {
+    /// a key number, or None for a rest, and a length in whole notes
+    Note(Option<i32>, f64),
+    StartRepeat,
+    EndRepeat,
+    /// the start of a numbered ending
+    Ending(u32),
+}
+
+/// The fifths above C of the major key on each letter, without accidentals.
+fn major_fifths(letter: char) -> Option<i32> {
+    match letter {
+        'C' => Some(0), 'G' => Some(1), 'D' => Some(2), 'A' => Some(3), 'E' => Some(4), 'B' => Some(5),
+        'F' => Some(-1),
+        _ => None,
+    }
+}
+
+/// The accidental each letter gets from a key such as `G`, `Bb`, `F#m` or
+/// `Ddor`.
+fn key_signature(field: &str) -> Option<HashMap<char, i32>> {
+    let field = field.split_whitespace().next().unwrap_or("C");
+    let mut chars = field.chars();
+    let letter = chars.next()?.to_ascii_uppercase();
+    let mut fifths = major_fifths(letter)?;
+    let mut rest = chars.as_str();
+    if let Some(after) = rest.strip_prefix('#') {
+        fifths += 7;
+        rest = after;
+    } else if let Some(after) = rest.strip_prefix('b') {
+        fifths -= 7;
+        rest = after;
+    }
+    let mode = rest.to_ascii_lowercase();
+    fifths += match &mode[..mode.len().min(3)] {
+        "" | "maj" | "ion" => 0,
+        "m" | "min" | "aeo" => -3,
+        "mix" => -1,
+        "dor" => -2,
+        "phr" => -4,
+        "lyd" => 1,
+        "loc" => -5,
+        // `Am` and friends are only one letter
+        _ if mode.starts_with('m') => -3,
+        _ => return None,
+    };
+    let mut signature = HashMap::new();
+    let (order, shift) = if fifths >= 0 { ("FCGDAEB", 1) } else { ("BEADGCF", -1) };
+    for letter in order.chars().take(fifths.unsigned_abs() as usize) {
+        signature.insert(letter, shift);
+    }
+    Some(signature)
+}
+
+/// a fraction such as `1/8`, or a whole number
+fn fraction(text: &str) -> Option<f64> {
+    let mut parts = text.trim().splitn(2, '/');
+    let num: f64 = parts.next()?.trim().parse().ok()?;
+    match parts.next() {
+        Some(den) => {
+            let den: f64 = den.trim().parse().ok()?;
+            if den > 0.0 { Some(num/den) } else { None }
+        },
+        None => Some(num),
+    }
+}
+
+/// Everything the header fields set.
+struct Header {
+    /// the length of a note with no number after it, in whole notes
+    unit: f64,
+    /// the length of a bar, in whole notes
+    bar: f64,
+    /// what a beat is, in whole notes, and how many there are a minute
+    beat: f64,
+    bpm: f64,
+    key: HashMap<char, i32>,
+    /// whether `L:` set the unit, rather than it coming from the meter
+    unit_set: bool,
+}
+
+impl Header {
+    fn new() -> Header {
+        Header { unit: 1.0/8.0, bar: 1.0, beat: 0.25, bpm: 120.0, key: HashMap::new(), unit_set: false }
+    }
+
+    /// Take in one field, with its letter and the text after the colon.
+    fn field(&mut self, letter: char, value: &str, line: usize) -> Result<(), AbcError> {
+        let value = value.trim();
+        match letter {
+            'M' => {
+                self.bar = match value {
+                    "C" | "C|" => 1.0,
+                    "none" | "" => return Ok(()),
+                    _ => fraction(value).ok_or_else(|| error(line, "bad meter"))?,
+                };
+                if !self.unit_set {
+                    self.unit = if self.bar < 0.75 { 1.0/16.0 } else { 1.0/8.0 };
+                }
+            },
+            'L' => {
+                self.unit = fraction(value).ok_or_else(|| error(line, "bad note length"))?;
+                self.unit_set = true;
+            },
+            // `1/4=120`, or an old-style `120` in note lengths a minute
+            'Q' => {
+                let tempo = value.split_whitespace().find(|part| part.contains('=')).unwrap_or(value);
+                let mut parts = tempo.splitn(2, '=');
+                let (beat, bpm) = match (parts.next(), parts.next()) {
+                    (Some(beat), Some(bpm)) => (fraction(beat), bpm.trim().parse().ok()),
+                    (Some(bpm), None) => (Some(self.unit), bpm.trim().parse().ok()),
+                    _ => (None, None),
+                };
+                match (beat, bpm) {
+                    (Some(beat), Some(bpm)) if bpm > 0.0 && beat > 0.0 => {
+                        self.beat = beat;
+                        self.bpm = bpm;
+                    },
+                    _ => return Err(error(line, "bad tempo")),
+                }
+            },
+            'K' => self.key = key_signature(value).ok_or_else(|| error(line, "unknown key"))?,
+            _ => {},
+        }
+        Ok(())
+    }
+}
+
+/// `(p` squeezes the next p notes into the time of this many
+fn tuplet_time(p: u32) -> u32 {
+    match p {
+        2 | 4 | 8 => 3,
+        _ => 2,
+    }
+}
+
+/// the length written after a note or rest, in units
+fn length(chars: &[char], i: &mut usize, line: usize) -> Result<f64, AbcError> {
+    let digits = |i: &mut usize| {
+        let start = *i;
+        while *i < chars.len() && chars[*i].is_ascii_digit() {
+            *i += 1;
+        }
+        chars[start..*i].iter().collect::<String>().parse::<f64>().ok()
+    };
+    let mut len = digits(i).unwrap_or(1.0);
+    while *i < chars.len() && chars[*i] == '/' {
+        *i += 1;
+        match digits(i).unwrap_or(2.0) {
+            den if den > 0.0 => len /= den,
+            _ => return Err(error(line, "bad note length")),
+        }
+    }
+    if len.is_finite() { Ok(len) } else { Err(error(line, "bad note length")) }
+}
+
+/// Read the tune's body into tokens, taking in any fields along the way.
+fn tokens(text: &str) -> Result<(Header, Vec<Token>), AbcError> {
+    let mut header = Header::new();
+    let mut in_body = false;
+    let mut tokens = vec![];
+    // accidentals last until the end of the bar, for the same note
+    let mut bar_accidentals: HashMap<(char, i32), i32> = HashMap::new();
+    let mut tuplet: Option<(f64, u32)> = None;
+    let mut broken = 1.0;
+    let mut tied = false;
+    for (n, line) in text.lines().enumerate() {
+        let line_no = n + 1;
+        let line = line.split('%').next().unwrap_or("");
+        let chars: Vec<char> = line.chars().collect();
+        if chars.len() >= 2 && chars[1] == ':' && chars[0].is_ascii_alphabetic() {
+            if chars[0] == 'X' && in_body {
+                // the next tune in the file
+                break;
+            }
+            header.field(chars[0], &line[2..], line_no)?;
+            if chars[0] == 'K' {
+                in_body = true;
+            }
+            continue;
+        }
+        if !in_body {
+            continue;
+        }
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            match c {
+                '|' | ':' => {
+                    let start = i;
+                    while i < chars.len() && (chars[i] == '|' || chars[i] == ':' || chars[i] == ']') {
+                        i += 1;
+                    }
+                    let bar: String = chars[start..i].iter().collect();
+                    if bar.starts_with(':') {
+                        tokens.push(Token::EndRepeat);
+                    }
+                    if bar.len() > 1 && bar.ends_with(':') {
+                        tokens.push(Token::StartRepeat);
+                    }
+                    if i < chars.len() && chars[i].is_ascii_digit() {
+                        tokens.push(Token::Ending(chars[i].to_digit(10).unwrap()));
+                        i += 1;
+                    }
+                    bar_accidentals.clear();
+                },
+                // the bar line [|
+                '[' if i + 1 < chars.len() && chars[i + 1] == '|' => i += 1,
+                '[' if i + 1 < chars.len() && chars[i + 1].is_ascii_digit() => {
+                    tokens.push(Token::Ending(chars[i + 1].to_digit(10).unwrap()));
+                    i += 2;
+                },
+                // an inline field such as [K:D]
+                '[' if i + 2 < chars.len() && chars[i + 1].is_ascii_alphabetic() && chars[i + 2] == ':' => {
+                    let end = chars[i..].iter().position(|&c| c == ']').map_or(chars.len(), |at| i + at);
+                    let value: String = chars[i + 3..end].iter().collect();
+                    header.field(chars[i + 1], &value, line_no)?;
+                    i = end + 1;
+                },
+                // a chord plays its first note, so skip the others
+                '[' => {
+                    let end = chars[i..].iter().position(|&c| c == ']').map_or(chars.len(), |at| i + at);
+                    let first = (i + 1..end).find(|&at| "ABCDEFGabcdefgz".contains(chars[at]));
+                    match first {
+                        Some(_) => {
+                            let mut at = i + 1;
+                            let note = note(&chars, &mut at, &header, &mut bar_accidentals, line_no)?;
+                            let mut after = end + 1;
+                            let len = length(&chars, &mut after, line_no)?;
+                            push_note(&mut tokens, note, len*header.unit, &mut tuplet, &mut broken, &mut tied);
+                            i = after;
+                        },
+                        None => i = end + 1,
+                    }
+                },
+                '"' | '!' | '+' => {
+                    i += 1 + chars[i + 1..].iter().position(|&end| end == c).map_or(chars.len(), |at| at + 1);
+                },
+                '{' => i += chars[i..].iter().position(|&c| c == '}').map_or(chars.len(), |at| at + 1),
+                '(' if i + 1 < chars.len() && chars[i + 1].is_ascii_digit() => {
+                    let p = chars[i + 1].to_digit(10).unwrap();
+                    tuplet = Some((tuplet_time(p) as f64/p as f64, p));
+                    i += 2;
+                    // the rarer (p:q:r form takes the defaults
+                    while i < chars.len() && (chars[i] == ':' || chars[i].is_ascii_digit()) {
+                        i += 1;
+                    }
+                },
+                '>' | '<' => {
+                    let mut dots = 0;
+                    while i < chars.len() && chars[i] == c {
+                        dots += 1;
+                        i += 1;
+                    }
+                    let short = 0.5_f64.powi(dots);
+                    let (this, next) = if c == '>' { (2.0 - short, short) } else { (short, 2.0 - short) };
+                    if let Some(Token::Note(_, len)) = tokens.last_mut() {
+                        *len *= this;
+                    }
+                    broken = next;
+                },
+                '-' => {
+                    tied = true;
+                    i += 1;
+                },
+                '^' | '_' | '=' | 'A'..='G' | 'a'..='g' | 'z' | 'x' => {
+                    let note = note(&chars, &mut i, &header, &mut bar_accidentals, line_no)?;
+                    let len = length(&chars, &mut i, line_no)?;
+                    push_note(&mut tokens, note, len*header.unit, &mut tuplet, &mut broken, &mut tied);
+                },
+                // a whole bar's rest, or several
+                'Z' => {
+                    i += 1;
+                    let bars = length(&chars, &mut i, line_no)?;
+                    push_note(&mut tokens, None, bars*header.bar, &mut tuplet, &mut broken, &mut tied);
+                },
+                // spaces, line continuations and decorations
+                _ => i += 1,
+            }
+        }
+    }
+    if !in_body {
+        return Err(error(1, "no K: field, so no tune"));
+    }
+    Ok((header, tokens))
+}
+
+/// Read a note's accidentals, letter and octave marks, or a rest.
+fn note(chars: &[char], i: &mut usize, header: &Header, bar_accidentals: &mut HashMap<(char, i32), i32>,
+        line: usize) -> Result<Option<i32>, AbcError> {
+    let mut accidental = None;
+    while *i < chars.len() && "^_=".contains(chars[*i]) {
+        let shift = match chars[*i] { '^' => 1, '_' => -1, _ => 0 };
+        accidental = Some(accidental.unwrap_or(0) + shift);
+        *i += 1;
+    }
+    let c = *chars.get(*i).ok_or_else(|| error(line, "accidental without a note"))?;
+    *i += 1;
+    if c == 'z' || c == 'x' {
+        return Ok(None);
+    }
+    let letter = c.to_ascii_uppercase();
+    let mut octave = if c.is_ascii_lowercase() { 5 } else { 4 };
+    while *i < chars.len() && (chars[*i] == '\'' || chars[*i] == ',') {
+        octave += if chars[*i] == '\'' { 1 } else { -1 };
+        *i += 1;
+    }
+    let semitone = match letter {
+        'C' => 0, 'D' => 2, 'E' => 4, 'F' => 5, 'G' => 7, 'A' => 9, 'B' => 11,
+        _ => return Err(error(line, "expected a note")),
+    };
+    let shift = match accidental {
+        Some(shift) => {
+            bar_accidentals.insert((letter, octave), shift);
+            shift
+        },
+        None => bar_accidentals.get(&(letter, octave)).cloned()
+            .unwrap_or_else(|| header.key.get(&letter).cloned().unwrap_or(0)),
+    };
+    Ok(Some((octave + 1)*12 + semitone + shift))
+}
+
+fn push_note(tokens: &mut Vec<Token>, note: Option<i32>, mut len: f64, tuplet: &mut Option<(f64, u32)>,
+             broken: &mut f64, tied: &mut bool) {
+    len *= *broken;
+    *broken = 1.0;
+    if let Some((ratio, left)) = *tuplet {
+        len *= ratio;
+        *tuplet = if left > 1 { Some((ratio, left - 1)) } else { None };
+    }
+    if ::std::mem::replace(tied, false) {
+        if let Some(Token::Note(last, last_len)) = tokens.last_mut() {
+            if *last == note {
+                *last_len += len;
+                return;
+            }
+        }
+    }
+    tokens.push(Token::Note(note, len));
+}
+
+/// start a new part, unless one just started
+fn part(out: &mut Vec<Option<(Option<i32>, f64)>>) {
+    if let Some(Some(_)) = out.last() {
+        out.push(None);
+    }
+}
+
+/// Play the repeats out into one long run of notes, marking where each part
+/// starts with `None`.
+fn unroll(tokens: &[Token]) -> Vec<Option<(Option<i32>, f64)>> {
+    let mut out = vec![None];
+    let (mut pos, mut start) = (0, 0);
+    let mut replaying = false;
+    while pos < tokens.len() {
+        match tokens[pos] {
+            Token::Note(key, len) => out.push(Some((key, len))),
+            Token::StartRepeat => {
+                start = pos + 1;
+                part(&mut out);
+            },
+            Token::EndRepeat => {
+                if replaying {
+                    replaying = false;
+                    start = pos + 1;
+                    // the second ending comes straight after
+                    if let Some(Token::Ending(_)) = tokens.get(pos + 1) {
+                        pos += 1;
+                    }
+                    part(&mut out);
+                } else {
+                    replaying = true;
+                    pos = start;
+                    continue;
+                }
+            },
+            // the first ending is skipped the second time through
+            Token::Ending(n) => if replaying && n == 1 {
+                match tokens[pos..].iter().position(|token| matches!(*token, Token::EndRepeat)) {
+                    Some(at) => {
+                        pos += at;
+                        continue;
+                    },
+                    None => break,
+                }
+            },
+        }
+        pos += 1;
+    }
+    if let Some(None) = out.last() {
+        out.pop();
+    }
+    out
+}
+
+/// A blip program that plays the first tune in `text`.
+pub fn import(text: &str) -> Result<String, AbcError> {
+    let (header, tokens) = tokens(text)?;
+    let mut out = String::new();
+    if header.bpm != 120.0 {
+        out.push_str(&format!("@bpm {}\n", header.bpm));
+    }
+    let meter = header.bar/header.beat;
+    if meter != 4.0 {
+        out.push_str(&format!("@meter {}\n", meter));
+    }
+    let mut part = 0;
+    for item in unroll(&tokens) {
+        match item {
+            Some((key, len)) => {
+                let pitch = key.map_or("0".to_string(), note_name);
+                out.push_str(&format!("sin {} {}\n", pitch, beats(len/header.beat)));
+            },
+            None => {
+                part += 1;
+                out.push_str(&format!("lbl part{}\n", part));
+            },
+        }
+    }
+    Ok(out)
+}
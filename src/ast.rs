@@ -0,0 +1,68 @@
+//! The first stage of compiling: a program's text parsed into what each
+//! line of it is, with where it is, before anything is looked up or
+//! checked. The compiler works out the labels from these, then lowers them
+//! to instructions. There's no stage between: labels become instruction
+//! indices as the lines are lowered, and the instruction list is what the
+//! renderer runs. Words are split at any run of spaces or tabs, the same
+//! as a block's rows are.
+//!
+//! Parsing never fails. A line the compiler won't understand is still a
+//! `Statement` or a `Directive`, and the compiler says what's wrong with
+//! it.
+
+use compiler::Span;
+
+pub enum Node<'a> {
+    /// a line with nothing on it but whitespace
+    Blank,
+    /// `lbl name`
+    Label(&'a str),
+    /// a line starting with `@`, split into words, the first being the
+    /// directive's name
+    Directive(Vec<&'a str>),
+    /// any other line, split into words: an instruction, if it's one the
+    /// compiler knows
+    Statement(Vec<&'a str>),
+    /// a `markov`, `lsystem` or `voice` line and the lines after it up to its `end`,
+    /// leaving out blank ones, or None for the rows if the program ends
+    /// first
+    Block { head: Vec<&'a str>, rows: Option<Vec<(Span, &'a str)>> },
+}
+
+pub struct Line<'a> {
+    /// where it is; for a block, its first line
+    pub at: Span,
+    pub node: Node<'a>,
+}
+
+/// Parse `text` into its lines, a block counting as one.
+pub fn parse<'a>(text: &'a str) -> Vec<Line<'a>> {
+    let mut parsed = vec![];
+    let mut lines = text.lines().enumerate();
+    while let Some((i, line)) = lines.next() {
+        let at = Span::of(i, line);
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let node = match &words[..] {
+            [] => Node::Blank,
+            ["lbl", name] => Node::Label(name),
+            ["markov", ..] | ["lsystem", ..] | ["voice", ..] => {
+                let mut rows = vec![];
+                let mut closed = false;
+                for (i, line) in lines.by_ref() {
+                    if line.trim() == "end" {
+                        closed = true;
+                        break;
+                    }
+                    if !line.trim().is_empty() {
+                        rows.push((Span::of(i, line), line));
+                    }
+                }
+                Node::Block { head: words, rows: if closed { Some(rows) } else { None } }
+            },
+            [directive, ..] if directive.starts_with('@') => Node::Directive(words),
+            _ => Node::Statement(words),
+        };
+        parsed.push(Line { at, node });
+    }
+    parsed
+}
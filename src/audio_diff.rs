@@ -0,0 +1,64 @@
+//! Comparing two renders sample by sample: where they part ways and by how
+//! much, for checking that a change to the renderer leaves its output alone.
+
+use renderer::SAMPLE_RATE;
+use stats::SILENCE;
+
+/// A stretch of whole seconds where two renders differ, in samples.
+pub struct Divergence {
+    pub from: usize,
+    pub to: usize,
+    /// the biggest difference in it
+    pub peak: f32,
+}
+
+pub struct AudioDiff {
+    pub len_a: usize,
+    pub len_b: usize,
+    /// every stretch where they differ by more than `SILENCE`, in order
+    pub divergences: Vec<Divergence>,
+    /// the biggest difference and the sample it's at
+    pub peak: f32,
+    pub peak_at: usize,
+    /// the difference's RMS over the longer of the two
+    pub rms: f32,
+}
+
+impl AudioDiff {
+    /// true if the two are the same length and never differ by more than
+    /// 16 bits can tell apart
+    pub fn same(&self) -> bool {
+        self.len_a == self.len_b && self.divergences.is_empty()
+    }
+}
+
+/// `a` minus `b`, the shorter taken to go on in silence
+pub fn difference(a: &[f32], b: &[f32]) -> Vec<f32> {
+    (0..a.len().max(b.len())).map(|i| a.get(i).unwrap_or(&0.0) - b.get(i).unwrap_or(&0.0)).collect()
+}
+
+/// Compare two renders.
+pub fn diff(a: &[f32], b: &[f32]) -> AudioDiff {
+    let difference = difference(a, b);
+    let second = SAMPLE_RATE as usize;
+    let mut divergences: Vec<Divergence> = vec![];
+    for (n, chunk) in difference.chunks(second).enumerate() {
+        let peak = chunk.iter().fold(0.0_f32, |peak, d| peak.max(d.abs()));
+        if peak <= SILENCE {
+            continue;
+        }
+        let (from, to) = (n*second, n*second + chunk.len());
+        match divergences.last_mut() {
+            Some(last) if last.to == from => {
+                last.to = to;
+                last.peak = last.peak.max(peak);
+            },
+            _ => divergences.push(Divergence { from, to, peak }),
+        }
+    }
+    let (peak_at, peak) = difference.iter().map(|d| d.abs()).enumerate()
+        .fold((0, 0.0), |best, (i, d)| if d > best.1 { (i, d) } else { best });
+    let squares: f64 = difference.iter().map(|&d| d as f64*d as f64).sum();
+    let rms = (squares/difference.len().max(1) as f64).sqrt() as f32;
+    AudioDiff { len_a: a.len(), len_b: b.len(), divergences, peak, peak_at, rms }
+}
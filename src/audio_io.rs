@@ -0,0 +1,281 @@
+//! Reading and writing audio files.
+//!
+//! Everything passes through here normalized to mono f32 at the engine's
+//! sample rate, so nothing else in the crate has to know about PCM widths,
+//! channel counts, or file sample rates.
+
+use std::convert::TryFrom;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::path::Path;
+use renderer::SAMPLE_RATE;
+use sink::{Sample, Sink};
+
+const FORMAT_PCM: u16 = 1;
+const FORMAT_FLOAT: u16 = 3;
+const FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+/// A sample format that can be stored in a WAV file.
+pub trait WavSample: Sample {
+    const FORMAT_TAG: u16;
+    const BITS: u16;
+}
+
+impl WavSample for u8 {
+    const FORMAT_TAG: u16 = FORMAT_PCM;
+    const BITS: u16 = 8;
+}
+
+impl WavSample for i16 {
+    const FORMAT_TAG: u16 = FORMAT_PCM;
+    const BITS: u16 = 16;
+}
+
+impl WavSample for i32 {
+    const FORMAT_TAG: u16 = FORMAT_PCM;
+    const BITS: u16 = 32;
+}
+
+impl WavSample for f32 {
+    const FORMAT_TAG: u16 = FORMAT_FLOAT;
+    const BITS: u16 = 32;
+}
+
+/// Writes a WAV file, mono unless `with_channels` says otherwise. The
+/// header's sizes are only known at the end, so they are filled in by
+/// `finish`; a file that never gets finished still plays in most tools but
+/// reports a length of zero.
+pub struct WavSink<W: Write + Seek, S: WavSample> {
+    out: W,
+    channels: u16,
+    data_len: u32,
+    buf: Vec<u8>,
+    /// the LIST chunk written after the samples, or nothing
+    info: Vec<u8>,
+    format: PhantomData<S>,
+}
+
+impl<S: WavSample> WavSink<BufWriter<File>, S> {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<WavSink<BufWriter<File>, S>> {
+        WavSink::new(BufWriter::new(File::create(path)?))
+    }
+
+    /// Carry on writing a mono file this sink was writing, left unfinished,
+    /// from just after its first `samples` samples. Anything written after
+    /// them is dropped.
+    pub fn resume<P: AsRef<Path>>(path: P, samples: u64) -> io::Result<WavSink<BufWriter<File>, S>> {
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+        let mut header = [0; 44];
+        file.read_exact(&mut header)?;
+        let mut expected = vec![];
+        write_header::<_, S>(&mut expected, 1, 0, 0)?;
+        // the sizes are only right if it was finished
+        if header[8..40] != expected[8..40] {
+            return Err(invalid("not a file this render was writing"));
+        }
+        let data_len = samples*(S::BITS/8) as u64;
+        if data_len > u32::MAX as u64 || file.metadata()?.len() < 44 + data_len {
+            return Err(invalid("shorter than the checkpoint"));
+        }
+        file.set_len(44 + data_len)?;
+        file.seek(SeekFrom::End(0))?;
+        Ok(WavSink { out: BufWriter::new(file), channels: 1, data_len: data_len as u32, buf: vec![], info: vec![],
+                     format: PhantomData })
+    }
+}
+
+impl<W: Write + Seek, S: WavSample> WavSink<W, S> {
+    pub fn new(mut out: W) -> io::Result<WavSink<W, S>> {
+        write_header::<W, S>(&mut out, 1, 0, 0)?;
+        Ok(WavSink { out, channels: 1, data_len: 0, buf: vec![], info: vec![], format: PhantomData })
+    }
+
+    /// Take blocks with a sample for each of `channels` in turn, like left
+    /// and right for stereo.
+    pub fn with_channels(mut self, channels: u16) -> WavSink<W, S> {
+        self.channels = channels;
+        self
+    }
+
+    /// the writer the file went to, once it's finished
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    /// Tag the file with a LIST/INFO chunk, each tag a four letter INFO id
+    /// like `ISFT` and its text, for players and tools that show them.
+    pub fn with_info(mut self, tags: &[([u8; 4], String)]) -> WavSink<W, S> {
+        self.info.clear();
+        if tags.is_empty() {
+            return self;
+        }
+        let mut list = b"INFO".to_vec();
+        for (id, text) in tags {
+            // the text is nul terminated, and padded to an even length
+            let len = text.len() + 1;
+            list.extend_from_slice(id);
+            list.extend_from_slice(&(len as u32).to_le_bytes());
+            list.extend_from_slice(text.as_bytes());
+            list.extend_from_slice(if len % 2 == 1 { &[0, 0] } else { &[0] });
+        }
+        self.info.extend_from_slice(b"LIST");
+        self.info.extend_from_slice(&(list.len() as u32).to_le_bytes());
+        self.info.extend_from_slice(&list);
+        self
+    }
+}
+
+/// `after` is how much comes after the samples: their padding, and the
+/// LIST chunk if there is one.
+fn write_header<W: Write, S: WavSample>(out: &mut W, channels: u16, data_len: u32, after: u32) -> io::Result<()> {
+    let rate = SAMPLE_RATE as u32;
+    let block_align = S::BITS / 8*channels;
+    let riff_len = data_len.checked_add(after).and_then(|len| len.checked_add(36)).ok_or_else(too_big)?;
+    let mut header = Vec::with_capacity(44);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&riff_len.to_le_bytes());
+    header.extend_from_slice(b"WAVEfmt ");
+    header.extend_from_slice(&16_u32.to_le_bytes());
+    header.extend_from_slice(&S::FORMAT_TAG.to_le_bytes());
+    header.extend_from_slice(&channels.to_le_bytes());
+    header.extend_from_slice(&rate.to_le_bytes());
+    header.extend_from_slice(&(rate*block_align as u32).to_le_bytes());
+    header.extend_from_slice(&block_align.to_le_bytes());
+    header.extend_from_slice(&S::BITS.to_le_bytes());
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&data_len.to_le_bytes());
+    out.write_all(&header)
+}
+
+impl<W: Write + Seek, S: WavSample> Sink for WavSink<W, S> {
+    fn write_block(&mut self, samples: &[f32]) -> io::Result<()> {
+        self.buf.clear();
+        for &sample in samples {
+            S::from_f32(sample).write_le(&mut self.buf);
+        }
+        // a WAV file's sizes are 32 bits, so stop before they'd wrap around
+        self.data_len = u32::try_from(self.buf.len()).ok().and_then(|len| self.data_len.checked_add(len))
+            .ok_or_else(too_big)?;
+        self.out.write_all(&self.buf)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        // data chunks have to be of even length
+        let pad = self.data_len % 2;
+        if pad == 1 {
+            self.out.write_all(&[0])?;
+        }
+        self.out.write_all(&self.info)?;
+        self.out.seek(SeekFrom::Start(0))?;
+        write_header::<W, S>(&mut self.out, self.channels, self.data_len, pad + self.info.len() as u32)?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+fn too_big() -> io::Error {
+    io::Error::other("WAV data over 4 GiB")
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+/// Decode a WAV file of any common PCM or float format, mixed down to mono.
+/// Returns the samples and the file's sample rate.
+pub fn decode_wav(bytes: &[u8]) -> io::Result<(Vec<f32>, u32)> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(invalid("not a WAV file"));
+    }
+
+    let mut fmt = None;
+    let mut data = None;
+    let mut at = 12;
+    while at + 8 <= bytes.len() {
+        let id = &bytes[at..at + 4];
+        let len = u32_at(bytes, at + 4) as usize;
+        let body = &bytes[at + 8..bytes.len().min(at + 8 + len)];
+        if id == b"fmt " {
+            if body.len() < 16 {
+                return Err(invalid("truncated fmt chunk"));
+            }
+            let mut tag = u16_at(body, 0);
+            if tag == FORMAT_EXTENSIBLE && body.len() >= 26 {
+                tag = u16_at(body, 24);
+            }
+            fmt = Some((tag, u16_at(body, 2), u32_at(body, 4), u16_at(body, 14)));
+        } else if id == b"data" {
+            data = Some(body);
+        }
+        at += 8 + len + len % 2;
+    }
+
+    let (tag, channels, rate, bits) = match fmt {
+        Some(fmt) => fmt,
+        None => return Err(invalid("missing fmt chunk")),
+    };
+    let data = match data {
+        Some(data) => data,
+        None => return Err(invalid("missing data chunk")),
+    };
+    if channels == 0 {
+        return Err(invalid("zero channels"));
+    }
+
+    let width = (bits as usize).div_ceil(8);
+    let decode: fn(&[u8]) -> f32 = match (tag, bits) {
+        (FORMAT_PCM, 8) => |b| b[0].to_f32(),
+        (FORMAT_PCM, 16) => |b| i16::from_le_bytes([b[0], b[1]]).to_f32(),
+        (FORMAT_PCM, 24) => |b| i32::from_le_bytes([0, b[0], b[1], b[2]]).to_f32(),
+        (FORMAT_PCM, 32) => |b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]).to_f32(),
+        (FORMAT_FLOAT, 32) => |b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+        (FORMAT_FLOAT, 64) => |b| f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]) as f32,
+        _ => return Err(invalid("unsupported sample format")),
+    };
+
+    let frame = width*channels as usize;
+    let samples = data.chunks(frame)
+        .filter(|f| f.len() == frame)
+        .map(|f| f.chunks(width).map(decode).sum::<f32>() / channels as f32)
+        .collect();
+    Ok((samples, rate))
+}
+
+/// Linear interpolation from `from_rate` to the engine's sample rate.
+pub fn resample(samples: &[f32], from_rate: u32) -> Vec<f32> {
+    let to_rate = SAMPLE_RATE as u32;
+    if from_rate == to_rate || samples.is_empty() {
+        return samples.to_vec();
+    }
+    let step = from_rate as f64 / to_rate as f64;
+    let len = (samples.len() as f64 / step) as usize;
+    (0..len).map(|i| {
+        let pos = i as f64*step;
+        let left = pos as usize;
+        let right = (left + 1).min(samples.len() - 1);
+        let frac = (pos - left as f64) as f32;
+        samples[left]*(1.0 - frac) + samples[right]*frac
+    }).collect()
+}
+
+/// Load a WAV file as mono f32 at the engine's sample rate.
+pub fn load_wav<P: AsRef<Path>>(path: P) -> io::Result<Vec<f32>> {
+    let mut bytes = vec![];
+    File::open(path)?.read_to_end(&mut bytes)?;
+    let (samples, rate) = decode_wav(&bytes)?;
+    Ok(resample(&samples, rate))
+}
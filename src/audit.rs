@@ -0,0 +1,69 @@
+//! Counting the heap allocations the render makes, for the `audit-alloc`
+//! feature. A realtime host like JACK or a plugin calls the renderer from
+//! its audio thread, where an allocation can take the allocator's lock at
+//! the wrong moment and glitch, so with the feature on `Renderer::render_into`
+//! asserts it didn't allocate, unless one of its buffers had to grow.
+//!
+//! The binary installs `CountingAllocator` as its global allocator when the
+//! feature's on. A program embedding blip-machine does the same to audit its
+//! own audio thread:
+//!
+//! ```text
+//! #[global_allocator]
+//! static ALLOCATOR: blip_machine::audit::CountingAllocator = blip_machine::audit::CountingAllocator;
+//! ```
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+thread_local! {
+    /// whether this thread's allocations are being counted, and how many
+    /// there have been since it started
+    static AUDITING: Cell<bool> = const { Cell::new(false) };
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// The system allocator, noting down each allocation made while `audited`
+/// is running on the same thread.
+pub struct CountingAllocator;
+
+fn count() {
+    // a thread being torn down has no thread locals left to count in
+    if AUDITING.try_with(Cell::get).unwrap_or(false) {
+        let _ = ALLOCATIONS.try_with(|allocations| allocations.set(allocations.get() + 1));
+    }
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count();
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count();
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count();
+        System.realloc(ptr, layout, new_size)
+    }
+
+    // freeing never waits on anything an allocation wouldn't, but it's only
+    // the allocations that the render path can avoid
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+/// Run `f`, and return what it did with how many allocations it made, or 0
+/// if `CountingAllocator` isn't the global allocator.
+pub fn audited<T, F: FnOnce() -> T>(f: F) -> (T, usize) {
+    let was = AUDITING.with(|auditing| auditing.replace(true));
+    let before = ALLOCATIONS.with(Cell::get);
+    let result = f();
+    let allocations = ALLOCATIONS.with(Cell::get) - before;
+    AUDITING.with(|auditing| auditing.set(was));
+    (result, allocations)
+}
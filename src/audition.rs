@@ -0,0 +1,81 @@
+//! Excerpts of several seeds of a program played one after the other, for
+//! `blip-machine audition`, to pick the realizations of a generative piece
+//! worth keeping by ear. Each is announced as it starts, and keys move
+//! between them and keep the good ones:
+//!
+//! ```text
+//! k       keep this seed, or stop keeping it
+//! n, →    the next seed
+//! p, ←    the one before
+//! r       this one again from the start
+//! q       stop
+//! ```
+
+use std::io;
+use std::sync::mpsc::Receiver;
+use renderer::SAMPLE_RATE;
+use sink::Sink;
+use term::{Key, Pacer};
+
+/// A seed's excerpt, rendered ahead of time.
+pub struct Take {
+    pub seed: usize,
+    pub samples: Vec<f32>,
+}
+
+/// how long the end of an excerpt fades out over, in seconds, so the cut
+/// doesn't click
+const FADE: f64 = 0.05;
+
+impl Take {
+    /// `samples` of `seed`, faded out at the end if they stop mid-note.
+    pub fn new(seed: usize, mut samples: Vec<f32>) -> Take {
+        let fade = ((FADE*SAMPLE_RATE) as usize).min(samples.len());
+        let start = samples.len() - fade;
+        for (i, sample) in samples[start..].iter_mut().enumerate() {
+            *sample *= (fade - i) as f32/fade as f32;
+        }
+        Take { seed, samples }
+    }
+}
+
+/// Play `takes` into `sink` in turn, at the pace of the audio, saying what's
+/// playing through `announce`, until the last has played or `q` is pressed.
+/// Returns the seeds kept, in the order of `takes`.
+pub fn audition(takes: &[Take], sink: &mut dyn Sink, block_size: usize, keys: &Receiver<Key>,
+                announce: &mut dyn FnMut(&str)) -> io::Result<Vec<usize>> {
+    let mut kept = vec![false; takes.len()];
+    let mut pacer = Pacer::new(block_size, SAMPLE_RATE);
+    let mut current = 0;
+    'takes: while current < takes.len() {
+        let take = &takes[current];
+        let mark = if kept[current] { ", kept" } else { "" };
+        announce(&format!("seed {} ({} of {}{})", take.seed, current + 1, takes.len(), mark));
+        for block in take.samples.chunks(block_size) {
+            pacer.wait();
+            for key in keys.try_iter() {
+                match key {
+                    Key::Char('k') => {
+                        kept[current] = !kept[current];
+                        let keeping = if kept[current] { "keeping" } else { "not keeping" };
+                        announce(&format!("{} seed {}", keeping, take.seed));
+                    },
+                    Key::Char('n') | Key::Right => {
+                        current += 1;
+                        continue 'takes;
+                    },
+                    Key::Char('p') | Key::Left => {
+                        current = current.saturating_sub(1);
+                        continue 'takes;
+                    },
+                    Key::Char('r') => continue 'takes,
+                    Key::Char('q') => break 'takes,
+                    _ => {},
+                }
+            }
+            sink.write_block(block)?;
+        }
+        current += 1;
+    }
+    Ok(takes.iter().zip(kept).filter(|&(_, kept)| kept).map(|(take, _)| take.seed).collect())
+}
@@ -0,0 +1,147 @@
+//! The PC speaker: one square wave at a time, on or off, at a pitch the PC's
+//! timer chip can divide down to.
+//!
+//! Each slice of time the loudest sounding thread gets the speaker and every
+//! other thread is left out. `Beep` renders that as audio, and `Speaker`
+//! sends its tones to the speaker itself, through the Linux `pcspkr` input
+//! device the way the `beep` program does, for machines with no sound card.
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::Write;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+use renderer::SAMPLE_RATE;
+use sink::Sink;
+use voices::{Voice, VoiceModel};
+
+/// the PIT's clock, which the speaker's pitch is a whole fraction of
+const PIT_CLOCK: f64 = 1_193_182.0;
+
+/// how often the speaker can change hands, in samples (10ms)
+const SLICE: usize = 80;
+
+/// where Linux puts the speaker when the `pcspkr` module is loaded
+pub static PC_SPEAKER: &str = "/dev/input/by-path/platform-pcspkr-event-spkr";
+
+/// a tone starting on a sample, or silence if it's None
+type Tone = (u64, Option<f64>);
+
+/// the pitch the timer gets closest to `freq` with, or None if it's outside
+/// what a 16-bit divider can do
+fn divided(freq: f64) -> Option<f64> {
+    let divider = (PIT_CLOCK/freq).round();
+    if freq <= 0.0 || !(1.0..=65535.0).contains(&divider) {
+        None
+    } else {
+        Some(PIT_CLOCK/divider)
+    }
+}
+
+pub struct Beep {
+    /// what's playing for the rest of this slice, and how far into it we are
+    tone: Option<f64>,
+    left: usize,
+    phase: f64,
+    now: u64,
+    /// where tone changes go, if a speaker is listening
+    tones: Option<Sender<Tone>>,
+}
+
+impl Beep {
+    pub fn new() -> Beep {
+        Beep { tone: None, left: 0, phase: 0.0, now: 0, tones: None }
+    }
+}
+
+impl Default for Beep {
+    fn default() -> Beep {
+        Beep::new()
+    }
+}
+
+impl VoiceModel for Beep {
+    fn next_sample(&mut self, voices: &[Voice]) -> f64 {
+        if self.left == 0 {
+            let loudest = voices.iter().filter(|voice| voice.gain > 0.0)
+                .max_by(|a, b| a.gain.partial_cmp(&b.gain).unwrap());
+            let tone = loudest.and_then(|voice| divided(voice.freq));
+            if tone != self.tone {
+                if let Some(ref tones) = self.tones {
+                    // a speaker that's gone away just isn't heard
+                    let _ = tones.send((self.now, tone));
+                }
+                self.tone = tone;
+            }
+            self.left = SLICE;
+        }
+        self.left -= 1;
+        self.now += 1;
+        match self.tone {
+            Some(freq) => {
+                self.phase = (self.phase + freq/SAMPLE_RATE).fract();
+                if self.phase < 0.5 { 1.0 } else { -1.0 }
+            },
+            None => 0.0,
+        }
+    }
+}
+
+/// Plays the tones a `Beep` picks on the PC speaker, in real time. The
+/// samples themselves only set the pace.
+pub struct Speaker {
+    out: File,
+    tones: Receiver<Tone>,
+    /// samples written so far, and when the first one was
+    written: u64,
+    start: Option<Instant>,
+}
+
+impl Speaker {
+    /// Open the speaker at `path`, and the `Beep` to render with so that it
+    /// hears what to play.
+    pub fn open(path: &str) -> io::Result<(Speaker, Beep)> {
+        let out = OpenOptions::new().write(true).open(path)?;
+        let (send, tones) = channel();
+        let beep = Beep { tones: Some(send), ..Beep::new() };
+        Ok((Speaker { out, tones, written: 0, start: None }, beep))
+    }
+
+    /// Start playing `freq`, or stop if it's None, as an `EV_SND`
+    /// `SND_TONE` input event.
+    fn play(&mut self, freq: Option<f64>) -> io::Result<()> {
+        let mut event = vec![0; 16];
+        event.extend_from_slice(&0x12u16.to_ne_bytes());
+        event.extend_from_slice(&0x02u16.to_ne_bytes());
+        event.extend_from_slice(&(freq.map_or(0, |freq| freq.round() as i32)).to_ne_bytes());
+        self.out.write_all(&event)
+    }
+
+    /// Wait until sample `at` is due.
+    fn wait_for(&self, start: Instant, at: u64) {
+        let due = start + Duration::from_secs_f64(at as f64/SAMPLE_RATE);
+        let now = Instant::now();
+        if due > now {
+            thread::sleep(due - now);
+        }
+    }
+}
+
+impl Sink for Speaker {
+    fn write_block(&mut self, samples: &[f32]) -> io::Result<()> {
+        let start = *self.start.get_or_insert_with(Instant::now);
+        let end = self.written + samples.len() as u64;
+        while let Ok((at, freq)) = self.tones.try_recv() {
+            self.wait_for(start, at);
+            self.play(freq)?;
+        }
+        self.wait_for(start, end);
+        self.written = end;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.play(None)
+    }
+}
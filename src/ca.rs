@@ -0,0 +1,23 @@
+//! One-dimensional cellular automata, for `ca` step sequences.
+
+/// Row `n` of an elementary cellular automaton `width` cells wide whose
+/// first row has just the middle cell alive, into `row`, working out each
+/// row after the first in `next`, so neither allocates once they're `width`
+/// long. The edges wrap around. Bit `b` of `rule` is the next state of a
+/// cell whose neighbourhood (left, self, right) spells `b` in binary, so rule
+/// 90 draws a Sierpinski triangle.
+pub fn generation(rule: u8, width: usize, n: usize, row: &mut Vec<bool>, next: &mut Vec<bool>) {
+    row.clear();
+    row.resize(width, false);
+    row[width/2] = true;
+    for _ in 0..n {
+        next.clear();
+        next.extend((0..width).map(|i| {
+            let left = row[(i + width - 1) % width] as u8;
+            let right = row[(i + 1) % width] as u8;
+            let neighbourhood = left << 2 | (row[i] as u8) << 1 | right;
+            rule >> neighbourhood & 1 == 1
+        }));
+        ::std::mem::swap(row, next);
+    }
+}
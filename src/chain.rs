@@ -0,0 +1,275 @@
+//! Programs played one after another, for `--chain`, to play a set list of
+//! generative pieces without stitching the audio together afterwards. Each
+//! hands over to the next with a transition:
+//!
+//! ```text
+//! cut                  the next starts the moment one ends
+//! crossfade:4s         the next fades in over the last 4 seconds of the one before
+//! wait-for-silence     one that's due to end plays on to a pause, and the next starts there
+//! ```
+//!
+//! A program ends when it finishes, or once it's played for `--each` if that
+//! comes first.
+
+use crossfade::Crossfade;
+use renderer::{Renderer, RuntimeError, SAMPLE_RATE};
+
+/// how quiet a sample has to be to count towards a pause, about -60dB
+const SILENT: f32 = 0.001;
+/// how long a pause has to last, in seconds, so a sine wave crossing zero
+/// isn't one
+const PAUSE: f64 = 0.1;
+/// the longest to wait for a pause, in seconds, before cutting anyway, for
+/// a drone that never stops
+const MAX_WAIT: f64 = 30.0;
+
+/// How one program hands over to the next.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Transition {
+    Cut,
+    /// over this many samples
+    Crossfade(usize),
+    WaitForSilence,
+}
+
+impl Transition {
+    /// `cut`, `crossfade:4s` or `wait-for-silence`
+    pub fn parse(text: &str) -> Option<Transition> {
+        match text {
+            "cut" => Some(Transition::Cut),
+            "wait-for-silence" => Some(Transition::WaitForSilence),
+            _ => {
+                let secs = text.strip_prefix("crossfade:")?;
+                let secs: f64 = secs.strip_suffix('s').unwrap_or(secs).parse().ok()?;
+                Some(secs).filter(|secs| secs.is_finite() && *secs > 0.0)
+                          .map(|secs| Transition::Crossfade((secs*SAMPLE_RATE) as usize))
+            }
+        }
+    }
+}
+
+/// A dry run of the program playing, a crossfade's length in front of it,
+/// to see its end coming in time to start fading.
+struct Lookahead {
+    renderer: Renderer,
+    played: u64,
+}
+
+impl Lookahead {
+    /// Go `len` samples further, stopping at `each`.
+    fn advance(&mut self, len: usize, each: Option<u64>) {
+        let len = each.map_or(len, |each| (each.saturating_sub(self.played) as usize).min(len));
+        self.played += self.renderer.render_block(len).len() as u64;
+    }
+
+    /// true once it's got to where the program ends
+    fn ended(&self, each: Option<u64>) -> bool {
+        self.renderer.is_finished() || each.is_some_and(|each| self.played >= each)
+    }
+}
+
+enum Playing {
+    One(Box<Renderer>),
+    Fading(Box<Crossfade>),
+    Done,
+}
+
+/// Plays programs in turn, see the module docs.
+pub struct Chain<'a> {
+    start: Box<dyn FnMut(usize) -> Renderer + 'a>,
+    programs: usize,
+    transition: Transition,
+    /// the most samples a program plays for
+    each: Option<u64>,
+    /// the program playing, or fading in, and how much of it has played
+    current: usize,
+    played: u64,
+    playing: Playing,
+    lookahead: Option<Lookahead>,
+    /// how long a program past its end has been quiet for, and has played
+    /// on for, waiting for a pause
+    quiet: usize,
+    waited: usize,
+    error: Option<(usize, RuntimeError)>,
+}
+
+impl<'a> Chain<'a> {
+    /// Play `programs` programs in turn, `start` setting up a renderer for
+    /// each by its index when it's due. It's called twice for each program
+    /// when crossfading, so both have to play the same way.
+    pub fn new(programs: usize, transition: Transition, each: Option<u64>,
+               start: Box<dyn FnMut(usize) -> Renderer + 'a>) -> Chain<'a> {
+        let mut chain = Chain {
+            start,
+            programs,
+            transition,
+            each,
+            current: 0,
+            played: 0,
+            playing: Playing::Done,
+            lookahead: None,
+            quiet: 0,
+            waited: 0,
+            error: None,
+        };
+        if programs > 0 {
+            let first = chain.begin(0);
+            chain.playing = Playing::One(Box::new(first));
+        }
+        chain
+    }
+
+    /// which program is playing, or fading in
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self.playing, Playing::Done)
+    }
+
+    /// the runtime error that stopped a program early, if one did, and
+    /// which program it was
+    pub fn take_error(&mut self) -> Option<(usize, RuntimeError)> {
+        self.error.take()
+    }
+
+    /// Render up to `len` samples, shorter only once the last program ends.
+    pub fn render_block(&mut self, len: usize) -> Vec<f32> {
+        let mut block = Vec::with_capacity(len);
+        while block.len() < len && !self.is_finished() {
+            let more = self.render_some(len - block.len());
+            block.extend(more);
+        }
+        block
+    }
+
+    /// the renderer for `program`, with its lookahead set going
+    fn begin(&mut self, program: usize) -> Renderer {
+        self.current = program;
+        self.played = 0;
+        self.quiet = 0;
+        self.waited = 0;
+        self.lookahead = match self.transition {
+            Transition::Crossfade(len) => {
+                let mut renderer = (self.start)(program);
+                renderer.set_dry_run(true);
+                let mut lookahead = Lookahead { renderer, played: 0 };
+                lookahead.advance(len, self.each);
+                Some(lookahead)
+            },
+            _ => None,
+        };
+        (self.start)(program)
+    }
+
+    /// Move the lookahead on as `len` more samples are about to be played.
+    fn look(&mut self, len: usize) {
+        let each = self.each;
+        if let Some(ref mut lookahead) = self.lookahead {
+            if !lookahead.ended(each) {
+                lookahead.advance(len, each);
+            }
+        }
+    }
+
+    /// how many samples `renderer` has left before it hands over, if it's
+    /// known yet
+    fn left(&self, renderer: &Renderer) -> Option<u64> {
+        if renderer.is_finished() {
+            return Some(0);
+        }
+        let by_each = self.each.map(|each| each.saturating_sub(self.played));
+        match (self.transition, self.lookahead.as_ref()) {
+            // the last program has nothing to fade into
+            (Transition::Crossfade(len), Some(lookahead)) if self.current + 1 < self.programs => {
+                if lookahead.ended(self.each) {
+                    Some(lookahead.played.saturating_sub(self.played).saturating_sub(len as u64))
+                } else {
+                    None
+                }
+            },
+            // a program past its end plays on to a pause, see `wait`
+            (Transition::WaitForSilence, _) if by_each == Some(0) => None,
+            _ => by_each,
+        }
+    }
+
+    /// Move on from `old` to the next program, if there is one.
+    fn hand_over(&mut self, mut old: Box<Renderer>) {
+        if let Some(error) = old.take_error() {
+            self.error = Some((self.current, error));
+        }
+        let next = self.current + 1;
+        if next >= self.programs {
+            self.playing = Playing::Done;
+            return;
+        }
+        // what's left of the old one is what the fade lasts
+        let fade = match self.lookahead {
+            Some(ref lookahead) if !old.is_finished() => lookahead.played.saturating_sub(self.played) as usize,
+            _ => 0,
+        };
+        let new = self.begin(next);
+        self.playing = if fade > 0 {
+            Playing::Fading(Box::new(Crossfade::new(*old, new, fade)))
+        } else {
+            Playing::One(Box::new(new))
+        };
+    }
+
+    /// Play on from past a program's end, cutting `block` short if a pause
+    /// comes up, or it's waited long enough. Returns whether it was cut.
+    fn wait(&mut self, block: &mut Vec<f32>) -> bool {
+        let (pause, max_wait) = ((PAUSE*SAMPLE_RATE) as usize, (MAX_WAIT*SAMPLE_RATE) as usize);
+        for (i, &sample) in block.iter().enumerate() {
+            self.quiet = if sample.abs() < SILENT { self.quiet + 1 } else { 0 };
+            self.waited += 1;
+            if self.quiet >= pause || self.waited >= max_wait {
+                block.truncate(i + 1);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Render up to `len` samples of whatever's playing, or none if it's
+    /// time to hand over.
+    fn render_some(&mut self, len: usize) -> Vec<f32> {
+        match ::std::mem::replace(&mut self.playing, Playing::Done) {
+            Playing::Done => vec![],
+            Playing::Fading(mut fade) => {
+                let len = len.min(fade.remaining());
+                self.look(len);
+                let block = fade.render_block(len);
+                self.played += block.len() as u64;
+                self.playing = if fade.is_done() {
+                    Playing::One(Box::new(fade.into_new()))
+                } else {
+                    Playing::Fading(fade)
+                };
+                block
+            },
+            Playing::One(mut renderer) => {
+                self.look(len);
+                let len = match self.left(&renderer) {
+                    Some(0) => {
+                        self.hand_over(renderer);
+                        return vec![];
+                    },
+                    Some(left) => (left as usize).min(len),
+                    None => len,
+                };
+                let mut block = renderer.render_block(len);
+                self.played += block.len() as u64;
+                let past_end = self.each.is_some_and(|each| self.played > each);
+                if self.transition == Transition::WaitForSilence && past_end && self.wait(&mut block) {
+                    self.hand_over(renderer);
+                } else {
+                    self.playing = Playing::One(renderer);
+                }
+                block
+            },
+        }
+    }
+}
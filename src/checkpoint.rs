@@ -0,0 +1,209 @@
+//! Checkpoints of a long render, for `--checkpoint`, so that one cut short
+//! by a crash or a reboot can be picked up again with `--resume` instead of
+//! started over, and come out exactly as it would have.
+//!
+//! A checkpoint holds the program, the settings it's rendered with, how far
+//! into the WAV file it got, and the renderer's state at that point: its
+//! threads, where the seeded generator is up to, the `@duck` followers and
+//! the clock. Everything is a little-endian number, floats by their bits, so
+//! nothing is rounded on the way through.
+
+use std::convert::TryFrom;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use renderer::Steal;
+
+/// the start of every checkpoint, in case the format ever changes
+static MAGIC: &[u8] = b"blip-machine checkpoint 1\n";
+
+fn invalid(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("bad checkpoint: {}", what))
+}
+
+/// Numbers and text one after the other, the way a checkpoint stores them.
+#[derive(Default)]
+pub struct Encoder {
+    bytes: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn u64(&mut self, x: u64) {
+        self.bytes.extend_from_slice(&x.to_le_bytes());
+    }
+
+    pub fn i64(&mut self, x: i64) {
+        self.bytes.extend_from_slice(&x.to_le_bytes());
+    }
+
+    pub fn f64(&mut self, x: f64) {
+        self.u64(x.to_bits());
+    }
+
+    pub fn option_u64(&mut self, x: Option<u64>) {
+        self.u64(x.is_some() as u64);
+        self.u64(x.unwrap_or(0));
+    }
+
+    pub fn option_f64(&mut self, x: Option<f64>) {
+        self.option_u64(x.map(f64::to_bits));
+    }
+
+    /// bytes of any length, with their length in front
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.u64(bytes.len() as u64);
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub fn text(&mut self, text: &str) {
+        self.bytes(text.as_bytes());
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// Reads back what an `Encoder` wrote, in the same order.
+pub struct Decoder<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(bytes: &'a [u8]) -> Decoder<'a> {
+        Decoder { bytes }
+    }
+
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.bytes.len() < len {
+            return Err(invalid("cut short"));
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    pub fn u64(&mut self) -> io::Result<u64> {
+        let mut x = [0; 8];
+        x.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(x))
+    }
+
+    pub fn i64(&mut self) -> io::Result<i64> {
+        self.u64().map(|x| x as i64)
+    }
+
+    pub fn f64(&mut self) -> io::Result<f64> {
+        self.u64().map(f64::from_bits)
+    }
+
+    /// a count or an index, which has to fit in a usize
+    pub fn usize(&mut self) -> io::Result<usize> {
+        usize::try_from(self.u64()?).map_err(|_| invalid("number out of range"))
+    }
+
+    pub fn option_u64(&mut self) -> io::Result<Option<u64>> {
+        let some = self.u64()?;
+        let x = self.u64()?;
+        match some {
+            0 => Ok(None),
+            1 => Ok(Some(x)),
+            _ => Err(invalid("expected an option")),
+        }
+    }
+
+    pub fn option_f64(&mut self) -> io::Result<Option<f64>> {
+        self.option_u64().map(|x| x.map(f64::from_bits))
+    }
+
+    pub fn bytes(&mut self) -> io::Result<&'a [u8]> {
+        let len = self.usize()?;
+        self.take(len)
+    }
+
+    pub fn text(&mut self) -> io::Result<String> {
+        String::from_utf8(self.bytes()?.to_vec()).map_err(|_| invalid("text isn't UTF-8"))
+    }
+
+    /// Check there's nothing left over, which would mean it was read wrong.
+    pub fn finish(&self) -> io::Result<()> {
+        if self.bytes.is_empty() { Ok(()) } else { Err(invalid("more than expected")) }
+    }
+}
+
+/// A render as far as it got, and what it takes to carry on with it.
+pub struct Checkpoint {
+    /// what rendered it, from `session::engine`
+    pub engine: String,
+    pub source: String,
+    /// what the program is called in messages, usually its file
+    pub name: String,
+    pub seed: usize,
+    pub dedup: bool,
+    pub max_threads: Option<(usize, Steal)>,
+    /// the WAV file being rendered into, and how many samples of it the
+    /// checkpoint was saved after
+    pub output: String,
+    pub samples: u64,
+    /// how many samples to save another checkpoint after
+    pub every: u64,
+    /// from `Renderer::save_state`
+    pub state: Vec<u8>,
+}
+
+impl Checkpoint {
+    /// Save to `path`, through a file next to it that's renamed over it once
+    /// it's safely on disk, so a crash while saving leaves the last one.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut out = Encoder::default();
+        out.text(&self.engine);
+        out.text(&self.source);
+        out.text(&self.name);
+        out.u64(self.seed as u64);
+        out.u64(self.dedup as u64);
+        out.option_u64(self.max_threads.map(|(max, _)| max as u64));
+        out.text(self.max_threads.map_or("", |(_, steal)| steal.name()));
+        out.text(&self.output);
+        out.u64(self.samples);
+        out.u64(self.every);
+        out.bytes(&self.state);
+        let mut saving = path.as_os_str().to_owned();
+        saving.push(".saving");
+        let mut file = fs::File::create(&saving)?;
+        file.write_all(MAGIC)?;
+        file.write_all(&out.into_bytes())?;
+        file.sync_all()?;
+        fs::rename(&saving, path)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Checkpoint> {
+        let bytes = fs::read(path)?;
+        let body = match bytes.strip_prefix(MAGIC) {
+            Some(body) => body,
+            None => return Err(invalid("not a checkpoint, or from a newer version")),
+        };
+        let mut from = Decoder::new(body);
+        let engine = from.text()?;
+        let source = from.text()?;
+        let name = from.text()?;
+        let seed = from.usize()?;
+        let dedup = from.u64()? != 0;
+        let max = from.option_u64()?;
+        let steal = from.text()?;
+        let max_threads = match max {
+            Some(max) => match Steal::parse(&steal) {
+                Some(steal) => Some((max as usize, steal)),
+                None => return Err(invalid("unknown --steal")),
+            },
+            None => None,
+        };
+        let output = from.text()?;
+        let samples = from.u64()?;
+        let every = from.u64()?;
+        let state = from.bytes()?.to_vec();
+        from.finish()?;
+        Ok(Checkpoint { engine, source, name, seed, dedup, max_threads, output, samples, every, state })
+    }
+}
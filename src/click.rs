@@ -0,0 +1,60 @@
+//! A metronome, for playing or recording along with a piece.
+
+use renderer::SAMPLE_RATE;
+
+/// pitch of the click
+const CLICK_FREQ: f64 = 1500.0;
+/// how quickly the click dies away, in seconds
+const CLICK_DECAY: f64 = 0.008;
+/// the click is cut off once it has decayed this long
+const CLICK_LENGTH: f64 = 0.05;
+
+/// Clicks on every beat, starting with one at the very first sample.
+pub struct Click {
+    /// position within the current beat, from 0 to 1
+    phase: f64,
+    /// samples since the last click started
+    since_click: usize,
+}
+
+impl Click {
+    pub fn new() -> Click {
+        Click { phase: 0.0, since_click: 0 }
+    }
+
+    /// Render `len` samples of clicks at `bpm`.
+    pub fn render_block(&mut self, len: usize, bpm: f64) -> Vec<f32> {
+        let step = bpm / 60.0 / SAMPLE_RATE;
+        (0..len).map(|_| {
+            let t = self.since_click as f64 / SAMPLE_RATE;
+            let sample = if t < CLICK_LENGTH {
+                (2.0*::std::f64::consts::PI*CLICK_FREQ*t).sin()*(-t / CLICK_DECAY).exp()
+            } else {
+                0.0
+            };
+            self.since_click += 1;
+            self.phase += step;
+            if self.phase >= 1.0 {
+                self.phase -= 1.0;
+                self.since_click = 0;
+            }
+            sample as f32
+        }).collect()
+    }
+}
+
+impl Default for Click {
+    fn default() -> Click {
+        Click::new()
+    }
+}
+
+/// how loud the click is against the program when they share an output
+pub static CLICK_MIX: f32 = 0.25;
+
+/// Mix a click into a block of program output, keeping the total in range.
+pub fn mix_click(block: &mut [f32], click: &[f32]) {
+    for (sample, click) in block.iter_mut().zip(click) {
+        *sample = *sample*(1.0 - CLICK_MIX) + *click*CLICK_MIX;
+    }
+}
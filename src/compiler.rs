@@ -0,0 +1,1690 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use ast::{self, Line, Node};
+use dist::Dist;
+use duck::Duck;
+use euclid::pattern;
+use markov::{Chain, TOLERANCE};
+use plugin::{self, Plugin};
+use renderer::SAMPLE_RATE;
+use tuning::{parse_note, pitches, Edo, Scale, Tuning, MIDDLE_C};
+
+/// Where in a program something is: the line, counting from 1, and the
+/// columns of it, also from 1, with `end` just past the last one.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Span {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// line `i` (counting from 0) of the text, from its first character that
+    /// isn't blank to its last
+    pub fn of(i: usize, text: &str) -> Span {
+        let leading = text.chars().take_while(|c| c.is_whitespace()).count();
+        let len = text.trim_end().chars().count();
+        Span { line: i + 1, start: leading + 1, end: len.max(leading) + 1 }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}-{}", self.line, self.start, self.end)
+    }
+}
+
+pub enum CompileError {
+    Syntax(Span),
+    /// a jump to a label that isn't there, and the one it's closest to
+    Lbl(Span, Option<String>),
+    Prob(Span),
+    Num(Span),
+    Positive(Span),
+    Swing(Span),
+    Humanize(Span),
+    /// `play` of a chain no `markov` block defines
+    Chain(Span),
+    /// a row of a markov block whose probabilities don't add up to 1
+    Markov(Span),
+    /// a markov transition to a state that has no row of its own
+    State(Span),
+    /// a euclidean rhythm with more hits than steps
+    Euclid(Span),
+    /// an L-system that grows past `MAX_LSYSTEM` symbols
+    LSystem(Span),
+    /// a cellular automaton rule outside 0 to 255
+    Rule(Span),
+    /// a scale file that couldn't be used, and why
+    Tuning(Span, String),
+    /// an `@duty` other than 12.5, 25, 50 or 75
+    Duty(Span),
+    /// an `@wave` that isn't 32 hex digits
+    Wave(Span),
+    /// an `@filter` resonance outside 0 to 1
+    Resonance(Span),
+    /// arguments a plugin's instruction turned down, and why
+    Plugin(Span, String),
+    /// an `@duck`, `@listen` or `modulate` naming a bus nothing plays into
+    Bus(Span),
+    /// an `@duck` amount outside 0 to 1
+    Duck(Span),
+    /// an `@anneal` or `@listen` of a label nothing jumps or forks to
+    Anneal(Span),
+    /// an `@fork-decay` outside 0 to 1
+    ForkDecay(Span),
+    /// a duration that isn't one
+    Dur(Span),
+    /// a `spawn` of a voice no `voice` block defines
+    Voice(Span),
+    /// a `spawn` with the wrong number of arguments for its voice, and the
+    /// number it takes
+    Arity(Span, usize),
+}
+
+/// Something that compiles but probably isn't what was meant.
+pub enum CompileWarning {
+    /// a pitch above half the sample rate, which aliases
+    Nyquist(Span),
+    /// a pitch below 0 Hz
+    Negative(Span),
+    /// a duration longer than `MAX_PLAUSIBLE_SECS`
+    Long(Span),
+}
+
+impl CompileError {
+    /// where the error is
+    pub fn span(&self) -> Span {
+        match *self {
+            CompileError::Syntax(at) | CompileError::Lbl(at, _) | CompileError::Prob(at) | CompileError::Num(at)
+            | CompileError::Positive(at) | CompileError::Swing(at) | CompileError::Humanize(at)
+            | CompileError::Chain(at) | CompileError::Markov(at) | CompileError::State(at)
+            | CompileError::Euclid(at) | CompileError::LSystem(at) | CompileError::Rule(at)
+            | CompileError::Tuning(at, _) | CompileError::Duty(at) | CompileError::Wave(at)
+            | CompileError::Resonance(at) | CompileError::Plugin(at, _) | CompileError::Bus(at)
+            | CompileError::Duck(at) | CompileError::Anneal(at) | CompileError::ForkDecay(at)
+            | CompileError::Dur(at) | CompileError::Voice(at) | CompileError::Arity(at, _) => at,
+        }
+    }
+
+    /// what's wrong, for a person to read
+    pub fn message(&self) -> String {
+        match *self {
+            CompileError::Syntax(_) => "bad syntax".to_string(),
+            CompileError::Lbl(_, Some(ref near)) => format!("unknown label (did you mean {}?)", near),
+            CompileError::Lbl(_, None) => "unknown label".to_string(),
+            CompileError::Prob(_) => "probabilities must be between 0 and 1".to_string(),
+            CompileError::Num(_) => "expected a number".to_string(),
+            CompileError::Positive(_) => "expected a positive number".to_string(),
+            CompileError::Swing(_) => "swing must be between 0 and 1".to_string(),
+            CompileError::Humanize(_) => "humanize amounts must be between 0 and 1".to_string(),
+            CompileError::Chain(_) => "unknown markov chain".to_string(),
+            CompileError::Markov(_) => "a state's probabilities must add up to 1".to_string(),
+            CompileError::State(_) => "unknown markov state".to_string(),
+            CompileError::Euclid(_) => "a rhythm can't have more hits than steps".to_string(),
+            CompileError::LSystem(_) => format!("L-system grows too long (more than {} symbols)", MAX_LSYSTEM),
+            CompileError::Rule(_) => "cellular automaton rules go from 0 to 255".to_string(),
+            CompileError::Tuning(_, ref why) => format!("can't use scale file: {}", why),
+            CompileError::Duty(_) => "duty must be 12.5, 25, 50 or 75".to_string(),
+            CompileError::Wave(_) => "a wave is 32 hex digits, one per sample".to_string(),
+            CompileError::Resonance(_) => "resonance must be between 0 and 1".to_string(),
+            CompileError::Plugin(_, ref why) => why.clone(),
+            CompileError::Bus(_) => "unknown bus".to_string(),
+            CompileError::Duck(_) => "duck amounts must be between 0 and 1, or at most 0dB".to_string(),
+            CompileError::Anneal(_) => "nothing jumps or forks to that label".to_string(),
+            CompileError::ForkDecay(_) => "fork decay must be between 0 and 1".to_string(),
+            CompileError::Dur(_) => "expected a duration, like 0.5, 1.5s, 500ms, 2b or 1/8".to_string(),
+            CompileError::Voice(_) => "unknown voice".to_string(),
+            CompileError::Arity(_, 1) => "that voice takes 1 argument".to_string(),
+            CompileError::Arity(_, n) => format!("that voice takes {} arguments", n),
+        }
+    }
+}
+
+impl CompileWarning {
+    /// where the warning is
+    pub fn span(&self) -> Span {
+        match *self {
+            CompileWarning::Nyquist(at) | CompileWarning::Negative(at) | CompileWarning::Long(at) => at,
+        }
+    }
+
+    /// what's probably wrong, for a person to read
+    pub fn message(&self) -> String {
+        match *self {
+            CompileWarning::Nyquist(_) => format!("pitch is above {} Hz and will alias", SAMPLE_RATE/2.0),
+            CompileWarning::Negative(_) => "pitch is negative".to_string(),
+            CompileWarning::Long(_) => format!("duration is longer than {} seconds", MAX_PLAUSIBLE_SECS),
+        }
+    }
+}
+
+/// durations longer than this (an hour) are most likely typos
+pub static MAX_PLAUSIBLE_SECS: f64 = 3600.0;
+
+fn check_pitch(freq: f64, at: Span, warnings: &mut Vec<CompileWarning>) {
+    if freq > SAMPLE_RATE/2.0 {
+        warnings.push(CompileWarning::Nyquist(at));
+    } else if freq < 0.0 {
+        warnings.push(CompileWarning::Negative(at));
+    }
+}
+
+fn check_dur(dur: Dur, bpm: f64, at: Span, warnings: &mut Vec<CompileWarning>) {
+    let secs = match dur {
+        Dur::Secs(secs) => secs,
+        Dur::Beats(beats) => beats*60.0/bpm,
+        Dur::RandomSecs(dist) => dist.mean(),
+        Dur::RandomBeats(dist) => dist.mean()*60.0/bpm,
+    };
+    if secs > MAX_PLAUSIBLE_SECS {
+        warnings.push(CompileWarning::Long(at));
+    }
+}
+
+/// warn about the pitches and durations of an instruction at `at`
+fn check_instruction(instruction: &Instruction, at: Span, bpm: f64, warnings: &mut Vec<CompileWarning>) {
+    let (pitches, dur) = match *instruction {
+        Sin(freq, dur) => (vec![freq], dur),
+        Bend(_, dur) => (vec![], dur),
+        Sweep(from, to, dur, _) | Zap(from, to, dur, _) => (vec![from, to], dur),
+        Walk(start, _, dur, _, _) => (vec![start], dur),
+        Play(ref chain, _) => (vec![], chain.dur),
+        Euclid(_, dur, _) | Ca(_, _, dur, _, _) | Custom(_, _, dur) => (vec![], dur),
+        _ => return,
+    };
+    for freq in pitches {
+        check_pitch(freq, at, warnings);
+    }
+    check_dur(dur, bpm, at, warnings);
+}
+
+/// How long a note lasts, either absolutely or relative to the tempo.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Dur {
+    Secs(f64),
+    Beats(f64),
+    /// drawn afresh every time the note plays
+    RandomSecs(Dist),
+    RandomBeats(Dist),
+}
+
+/// The boundary a quantized jump or fork waits for.
+#[derive(Clone, Copy)]
+pub enum Quantum {
+    Beat,
+    Bar,
+}
+
+/// How a sweep moves between its two pitches.
+#[derive(Clone, Copy)]
+pub enum Curve {
+    /// the same number of hertz every step
+    Lin,
+    /// the same interval every step
+    Exp,
+}
+
+pub use self::Instruction::*;
+#[derive(Clone)]
+pub enum Instruction {
+    Sin(f64, Dur),
+    /// bend the pitch the thread last played by some cents, without
+    /// restarting its wave
+    Bend(f64, Dur),
+    /// a chirp from one pitch to another
+    Sweep(f64, f64, Dur, Curve),
+    /// a drum hit: a pitch dropping quickly from one to the other while the
+    /// volume dies away, the last number being how fast (in seconds)
+    Zap(f64, f64, Dur, f64),
+    /// a random walk: from a starting pitch, some number of notes each a
+    /// step of so many cents up or down from the last, kept to the pitches of
+    /// the program's tuning if it set one
+    Walk(f64, f64, Dur, usize, Option<Vec<f64>>),
+    /// some number of notes from a markov chain
+    Play(Chain, usize),
+    /// a rhythm of steps of some length, starting a thread at the given pc
+    /// on each onset
+    Euclid(Vec<bool>, Dur, usize),
+    /// some number of generations of a cellular automaton with a rule and a
+    /// width, each lasting the step length; every live cell starts a thread
+    /// at its own note, the first of which is at the given pc, the next two
+    /// on, and so on
+    Ca(u8, usize, Dur, usize, usize),
+    PJump(f64, usize),
+    PFork(f64, usize),
+    /// like PJump and PFork but waiting, silently, for the next boundary
+    /// before deciding
+    QJump(f64, usize, Quantum),
+    QFork(f64, usize, Quantum),
+    /// give the thread random numbers of its own, starting from a seed, or
+    /// with None go back to the renderer's
+    Reseed(Option<u64>),
+    /// make the thread a modulator, its wave pushing the pitch of everything
+    /// playing into a bus up and down by as much as so many Hz instead of
+    /// being heard, or with None make it heard again
+    Modulate(Option<(usize, f64)>),
+    Terminate,
+    /// a registered plugin's instruction, with the numbers it compiled its
+    /// arguments to
+    Custom(Arc<dyn Plugin>, Vec<f64>, Dur),
+}
+
+impl Instruction {
+    /// the keyword that writes this instruction
+    pub fn op(&self) -> &'static str {
+        match *self {
+            Sin(..) => "sin",
+            Bend(..) => "bend",
+            Sweep(..) => "sweep",
+            Zap(..) => "zap",
+            Walk(..) => "walk",
+            Play(..) => "play",
+            Euclid(..) => "euclid",
+            Ca(..) => "ca",
+            PJump(..) => "pjump",
+            PFork(..) => "pfork",
+            QJump(..) => "qjump",
+            QFork(..) => "qfork",
+            Reseed(..) => "reseed",
+            Modulate(..) => "modulate",
+            Terminate => "terminate",
+            Custom(ref plugin, _, _) => plugin.mnemonic(),
+        }
+    }
+
+    /// Move the instruction this one goes to `by` further on, for moving it
+    /// along with the program it's in.
+    pub fn relocate(&mut self, by: usize) {
+        match *self {
+            Euclid(_, _, ref mut pc) | Ca(_, _, _, _, ref mut pc) | PJump(_, ref mut pc) | PFork(_, ref mut pc)
+            | QJump(_, ref mut pc, _) | QFork(_, ref mut pc, _) => *pc += by,
+            _ => {},
+        }
+    }
+}
+
+/// the tempo beat durations follow when a program doesn't set `@bpm`
+pub static DEFAULT_BPM: f64 = 120.0;
+
+/// How much to randomly vary each note so a program sounds less mechanical.
+#[derive(Clone, Copy, Default)]
+pub struct Humanize {
+    /// the most a note's volume can drop, as a share of full volume
+    pub vel: f64,
+    /// the most a note can start late by, in seconds
+    pub time: f64,
+}
+
+/// A branch probability that moves from one value to another over time,
+/// from `@anneal`.
+#[derive(Clone)]
+pub struct Anneal {
+    /// the jumps and forks it applies to
+    pub pcs: Vec<usize>,
+    pub from: f64,
+    pub to: f64,
+    /// how long it takes, in beats if `beats` says so and seconds if not
+    pub over: f64,
+    pub beats: bool,
+}
+
+impl Anneal {
+    /// the probability `secs` seconds and `beats` beats after the start
+    pub fn prob(&self, secs: f64, beats: f64) -> f64 {
+        let t = if self.beats { beats } else { secs }/self.over;
+        self.from + (self.to - self.from)*t.clamp(0.0, 1.0)
+    }
+}
+
+/// A branch probability that follows how loud the mix, or a bus, has been
+/// over the last block, from `@listen`.
+#[derive(Clone)]
+pub struct Listen {
+    /// the jumps and forks it applies to
+    pub pcs: Vec<usize>,
+    /// the probability in silence, and at full level
+    pub from: f64,
+    pub to: f64,
+    /// the bus it hears, or None for the whole mix
+    pub bus: Option<usize>,
+}
+
+impl Listen {
+    /// the probability at `level`, 0 being silence and 1 a sine wave at
+    /// full scale
+    pub fn prob(&self, level: f64) -> f64 {
+        self.from + (self.to - self.from)*level.clamp(0.0, 1.0)
+    }
+}
+
+/// `60s`, `60`, `500ms` or `32b`, for `@anneal`, and whether it's in beats
+fn parse_span_of_time(text: &str) -> Option<(f64, bool)> {
+    match text.strip_suffix('b') {
+        Some(beats) => beats.parse().ok().map(|beats| (beats, true)),
+        None => parse_secs(text.strip_suffix('s').filter(|secs| !secs.ends_with('m')).unwrap_or(text))
+            .map(|secs| (secs, false)),
+    }
+}
+
+/// How a chip's channels play a line, for voice models that can do more than
+/// one thing. The sine renderer ignores it.
+#[derive(Clone, Copy)]
+pub struct Patch {
+    /// the share of each cycle a pulse channel is high for
+    pub duty: f64,
+    /// one cycle of a wave channel's waveform, as 4-bit samples
+    pub wave: [u8; 32],
+    /// what a SID voice plays, and whether the voice before it ring-modulates
+    /// it or restarts its wave every cycle
+    pub waveform: Waveform,
+    pub ring: bool,
+    pub sync: bool,
+    /// how many times a second a SID pulse sweeps its width, if it does
+    pub pwm: Option<f64>,
+    /// whether a SID voice goes through the filter, and how it's set
+    pub filter: Option<Filter>,
+}
+
+impl Default for Patch {
+    fn default() -> Patch {
+        Patch { duty: 0.5, wave: DEFAULT_WAVE, waveform: Waveform::Pulse, ring: false, sync: false, pwm: None,
+                filter: None }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Waveform {
+    Triangle,
+    Saw,
+    Pulse,
+    Noise,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum FilterMode {
+    LowPass,
+    BandPass,
+    HighPass,
+}
+
+#[derive(Clone, Copy)]
+pub struct Filter {
+    pub mode: FilterMode,
+    /// in Hz
+    pub cutoff: f64,
+    /// from 0 to 1, where 1 is just short of ringing by itself
+    pub resonance: f64,
+    /// how hard the voices are pushed into the filter, 1 being clean
+    pub drive: f64,
+}
+
+/// `@filter lp 800 0.5` and the like, with how hard it's driven if the
+/// line says
+fn parse_filter(mode: &str, cutoff: &str, resonance: &str, drive: &str, at: Span) -> Result<Filter, CompileError> {
+    let mode = match mode {
+        "lp" => FilterMode::LowPass,
+        "bp" => FilterMode::BandPass,
+        "hp" => FilterMode::HighPass,
+        _ => return Err(CompileError::Syntax(at)),
+    };
+    let cutoff = match cutoff.parse() {
+        Ok(num) if num > 0.0 => num,
+        Ok(_) => return Err(CompileError::Positive(at)),
+        Err(_) => return Err(CompileError::Num(at)),
+    };
+    let resonance = match resonance.parse() {
+        Ok(num) if (0.0..=1.0).contains(&num) => num,
+        Ok(_) => return Err(CompileError::Resonance(at)),
+        Err(_) => return Err(CompileError::Num(at)),
+    };
+    let drive = match drive.parse() {
+        Ok(num) if num > 0.0 => num,
+        Ok(_) => return Err(CompileError::Positive(at)),
+        Err(_) => return Err(CompileError::Num(at)),
+    };
+    Ok(Filter { mode, cutoff, resonance, drive })
+}
+
+/// a triangle, until an `@wave` says otherwise
+const DEFAULT_WAVE: [u8; 32] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+                                15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0];
+
+/// `fedcba98...` as the 4-bit samples of a wave, if it's exactly 32 of them
+fn parse_wave(text: &str) -> Option<[u8; 32]> {
+    let mut wave = [0; 32];
+    if text.chars().count() != wave.len() {
+        return None;
+    }
+    for (sample, c) in wave.iter_mut().zip(text.chars()) {
+        *sample = c.to_digit(16)? as u8;
+    }
+    Some(wave)
+}
+
+/// A compiled program: its instructions plus whatever the directives set.
+pub struct Program {
+    pub instructions: Vec<Instruction>,
+    /// beats per minute, for durations given in beats
+    pub bpm: f64,
+    /// beats per bar
+    pub meter: f64,
+    /// the share of each beat its first half gets, for durations in beats
+    pub swing: Option<f64>,
+    pub humanize: Humanize,
+    /// how loud each thread a fork starts is next to the thread that forked
+    /// it, from `@fork-decay`
+    pub fork_decay: f64,
+    pub warnings: Vec<CompileWarning>,
+    /// where each instruction came from, for telling where a runtime error
+    /// happened; only the terminate added at the very end has no line of its
+    /// own
+    pub spans: Vec<Option<Span>>,
+    /// the names of the mixer's buses, starting with `main`
+    pub buses: Vec<String>,
+    /// the bus each instruction plays into
+    pub bus_of: Vec<usize>,
+    /// the `@duty` and `@wave` each instruction plays with
+    pub patches: Vec<Patch>,
+    /// which buses turn which others down, from `@duck`
+    pub ducks: Vec<Duck>,
+    /// branch probabilities that change as it plays, from `@anneal`
+    pub anneals: Vec<Anneal>,
+    /// branch probabilities that follow how loud it's playing, from
+    /// `@listen`
+    pub listens: Vec<Listen>,
+}
+
+/// the bus instructions play into until an `@bus` says otherwise
+pub static MAIN_BUS: &str = "main";
+
+/// `0.5`, `0.5s` and `500ms` are half a second, `0.5b` is half a beat, and
+/// so is `1/8`, an eighth note, taking a beat to be a quarter note;
+/// `U(0.2,0.8)`, `~N(500,100)ms` and `~N(0.5,0.1)b` are random
+fn parse_dur(text: &str) -> Option<Dur> {
+    if let Some(beats) = text.strip_suffix('b') {
+        return match Dist::parse(beats) {
+            Some(dist) => Some(Dur::RandomBeats(dist)),
+            None => parse_length(beats).map(Dur::Beats),
+        };
+    }
+    if let Some((num, den)) = text.split_once('/') {
+        let (num, den) = (parse_length(num)?, parse_length(den).filter(|&den| den > 0.0)?);
+        return Some(Dur::Beats(4.0*num/den));
+    }
+    let (text, unit) = match text.strip_suffix("ms") {
+        Some(ms) => (ms, 0.001),
+        None => (text.strip_suffix('s').unwrap_or(text), 1.0),
+    };
+    match Dist::parse(text) {
+        Some(dist) => Some(Dur::RandomSecs(dist.scaled(unit))),
+        None => parse_length(text).map(|secs| Dur::Secs(secs*unit)),
+    }
+}
+
+/// a number that can be how long something lasts, which isn't negative
+fn parse_length(text: &str) -> Option<f64> {
+    text.parse().ok().filter(|&length: &f64| length >= 0.0 && length.is_finite())
+}
+
+/// A length in beats written the way `parse_dur` reads it, for programs
+/// generated from other formats.
+pub fn beats(beats: f64) -> String {
+    let text = format!("{:.6}", beats);
+    format!("{}b", text.trim_end_matches('0').trim_end_matches('.'))
+}
+
+/// `440` is in hertz, `A4` is a note name in the program's tuning and `3:2`
+/// is a ratio above `root`
+fn parse_pitch(text: &str, tuning: &dyn Tuning, root: f64) -> Option<f64> {
+    if let Some(key) = parse_note(text) {
+        return Some(tuning.freq(key));
+    }
+    let mut ratio = text.splitn(2, ':');
+    match (ratio.next(), ratio.next()) {
+        (Some(num), Some(den)) => {
+            let num: f64 = num.parse().ok()?;
+            let den: f64 = den.parse().ok()?;
+            if num > 0.0 && den > 0.0 { Some(root*num/den) } else { None }
+        },
+        _ => text.parse().ok(),
+    }
+}
+
+/// The tuning note names follow: the last `@tuning` or `@edo` in the
+/// program, or None for plain 12-TET. Like labels it applies to the whole
+/// program, wherever it appears.
+fn find_tuning(program: &[Line]) -> Result<Option<Box<dyn Tuning>>, CompileError> {
+    let mut tuning: Option<Box<dyn Tuning>> = None;
+    for line in program {
+        let splt = match line.node {
+            Node::Directive(ref splt) => splt,
+            _ => continue,
+        };
+        match &splt[..] {
+            ["@tuning", path] => {
+                tuning = match Scale::load(path) {
+                    Ok(scale) => Some(Box::new(scale)),
+                    Err(e) => return Err(CompileError::Tuning(line.at, e)),
+                };
+            },
+            ["@edo", steps] => {
+                tuning = match steps.parse() {
+                    Ok(steps) if steps > 0 => Some(Box::new(Edo { steps })),
+                    Ok(_) => return Err(CompileError::Positive(line.at)),
+                    Err(_) => return Err(CompileError::Num(line.at)),
+                };
+            },
+            _ => {},
+        }
+    }
+    Ok(tuning)
+}
+
+/// `10ms` is ten milliseconds and `0.01` is the same in seconds
+fn parse_secs(text: &str) -> Option<f64> {
+    match text.strip_suffix("ms") {
+        Some(ms) => ms.parse::<f64>().ok().map(|ms| ms/1000.0),
+        None => text.parse().ok(),
+    }
+}
+
+/// `-6dB` as a gain, near enough 0.5
+pub fn parse_db(text: &str) -> Option<f64> {
+    let db: f64 = text.strip_suffix("dB").or_else(|| text.strip_suffix("db"))?.parse().ok()?;
+    Some(10.0_f64.powf(db/20.0))
+}
+
+/// `0.5` is half as loud as written, and so is `-6dB`
+pub fn parse_gain(text: &str) -> Option<f64> {
+    parse_db(text).or_else(|| text.parse().ok())
+}
+
+/// resolve the label and probability of a quantized jump or fork
+fn parse_branch(lbls: &HashMap<&str, usize>, lbl: &str, prob: &str, at: Span) -> Result<(f64, usize), CompileError> {
+    let pc = match lbls.get(lbl) {
+        Some(pc) => *pc,
+        None => return Err(unknown_label(lbls, lbl, at)),
+    };
+    match prob.parse() {
+        Ok(num) if (0.0..=1.0).contains(&num) => Ok((num, pc)),
+        Ok(_) => Err(CompileError::Prob(at)),
+        Err(_) => Err(CompileError::Num(at)),
+    }
+}
+
+/// a `markov` block and its rows
+struct ChainBlock<'a> {
+    name: &'a str,
+    dur: Dur,
+    line: Span,
+    /// each row and where it is
+    rows: Vec<(Span, &'a str)>,
+}
+
+/// an `lsystem` block and its rows
+struct LsystemBlock<'a> {
+    iterations: usize,
+    line: Span,
+    rows: Vec<(Span, &'a str)>,
+}
+
+/// a `voice` block: the names of its parameters, and its rows
+struct VoiceBlock<'a> {
+    params: Vec<&'a str>,
+    line: Span,
+    rows: Vec<(Span, &'a str)>,
+}
+
+/// The `voice` blocks of a program and the copies of them `spawn` has made,
+/// which go after the end of the program. A voice is a program of its own,
+/// with labels only it can jump to, whose `$name` words are replaced by the
+/// arguments it was spawned with:
+///
+/// ```text
+/// voice arp low high
+/// lbl again
+/// sin $low 1/8
+/// sin $high 1/8
+/// pjump again 0.75
+/// end
+///
+/// spawn arp C4 G4
+/// ```
+///
+/// Spawning a voice with the same arguments again, from the same `@root`,
+/// starts the same copy, so voices can spawn themselves.
+struct Voices<'a> {
+    blocks: HashMap<&'a str, VoiceBlock<'a>>,
+    /// where the copy for each voice, arguments and root starts
+    copies: HashMap<(String, Vec<String>, u64), usize>,
+    instructions: Vec<Instruction>,
+    spans: Vec<Option<Span>>,
+}
+
+impl<'a> Voices<'a> {
+    /// Where the copy of voice `name` with `args` starts, counting from the
+    /// first copy, making it if there isn't one yet. `at` is the `spawn`.
+    fn spawn(&mut self, name: &str, args: &[&str], at: Span, ctx: &Scope, bpm: f64,
+             warnings: &mut Vec<CompileWarning>) -> Result<usize, Vec<CompileError>> {
+        let key = (name.to_string(), args.iter().map(|arg| arg.to_string()).collect(), ctx.root.to_bits());
+        if let Some(&start) = self.copies.get(&key) {
+            return Ok(start);
+        }
+        let (params, line, rows) = match self.blocks.get(name) {
+            Some(block) if block.params.len() == args.len() => (block.params.clone(), block.line, block.rows.clone()),
+            Some(block) => return Err(vec![CompileError::Arity(at, block.params.len())]),
+            None => return Err(vec![CompileError::Voice(at)]),
+        };
+        let start = self.instructions.len();
+        let mut lbls = HashMap::new();
+        let mut body = vec![];
+        for &(row_at, row) in &rows {
+            let words: Vec<&str> = row.split_whitespace().map(|word| {
+                match word.strip_prefix('$').and_then(|param| params.iter().position(|p| *p == param)) {
+                    Some(i) => args[i],
+                    None => word,
+                }
+            }).collect();
+            match words[..] {
+                ["lbl", lbl] => { lbls.insert(lbl, start + body.len()); },
+                _ => body.push((row_at, words)),
+            }
+        }
+        // room for the whole copy and the end of it before anything it
+        // spawns goes after it
+        self.instructions.resize(start + body.len() + 1, Terminate);
+        self.spans.resize(start + body.len() + 1, Some(line));
+        self.copies.insert(key, start);
+        let scope = Scope { lbls: &lbls, tuning: ctx.tuning, root: ctx.root, scale: ctx.scale };
+        let mut errors = vec![];
+        for (i, (row_at, words)) in body.into_iter().enumerate() {
+            let compiled = match words[..] {
+                ["spawn", name, ref args @ ..] => self.spawn(name, args, row_at, ctx, bpm, warnings)
+                    .map(|to| PFork(1.0, to)),
+                _ => match parse_instruction(&words, row_at, &scope) {
+                    Some(Ok(instruction)) => {
+                        check_instruction(&instruction, row_at, bpm, warnings);
+                        Ok(instruction)
+                    },
+                    Some(Err(e)) => Err(vec![e]),
+                    None => Err(vec![CompileError::Syntax(row_at)]),
+                },
+            };
+            match compiled {
+                Ok(instruction) => {
+                    self.instructions[start + i] = instruction;
+                    self.spans[start + i] = Some(row_at);
+                },
+                Err(e) => errors.extend(e),
+            }
+        }
+        if errors.is_empty() { Ok(start) } else { Err(errors) }
+    }
+}
+
+/// the most symbols an L-system can grow to, so a typo in the number of
+/// iterations can't eat all the memory
+pub const MAX_LSYSTEM: usize = 100_000;
+
+/// An L-system block has an `axiom` of symbols, a `rule` for each symbol that
+/// gets rewritten, and `sym` lines giving the instructions each symbol plays
+/// (a symbol can have several, or none):
+///
+/// ```text
+/// lsystem 4
+/// axiom A
+/// rule A A B
+/// rule B A
+/// sym A sin C4 0.25b
+/// sym B sin G4 0.25b
+/// end
+/// ```
+///
+/// The axiom is rewritten `iterations` times, then each symbol in the result
+/// is replaced by its instructions.
+fn expand_lsystem(block: &LsystemBlock, ctx: &Scope, bpm: f64, warnings: &mut Vec<CompileWarning>) -> Result<Vec<(Instruction, Span)>, Vec<CompileError>> {
+    let mut errors = vec![];
+    let mut axiom = None;
+    let mut rules: HashMap<&str, Vec<&str>> = HashMap::new();
+    let mut syms: HashMap<&str, Vec<(Instruction, Span)>> = HashMap::new();
+    for &(at, row) in &block.rows {
+        let splt: Vec<&str> = row.split_whitespace().collect();
+        match &splt[..] {
+            ["axiom", symbols @ ..] => axiom = Some(symbols.to_vec()),
+            ["rule", symbol, symbols @ ..] => { rules.insert(symbol, symbols.to_vec()); },
+            ["sym", symbol, instruction @ ..] => match parse_instruction(instruction, at, ctx) {
+                Some(Ok(instruction)) => {
+                    check_instruction(&instruction, at, bpm, warnings);
+                    syms.entry(symbol).or_default().push((instruction, at));
+                },
+                Some(Err(e)) => errors.push(e),
+                None => errors.push(CompileError::Syntax(at)),
+            },
+            _ => errors.push(CompileError::Syntax(at)),
+        }
+    }
+    let mut symbols = match axiom {
+        Some(axiom) => axiom,
+        None => { errors.push(CompileError::Syntax(block.line)); vec![] },
+    };
+    for _ in 0..block.iterations {
+        symbols = symbols.iter()
+            .flat_map(|symbol| rules.get(symbol).cloned().unwrap_or_else(|| vec![*symbol]))
+            .collect();
+        if symbols.len() > MAX_LSYSTEM {
+            errors.push(CompileError::LSystem(block.line));
+            break;
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let mut instructions = vec![];
+    for symbol in symbols {
+        if let Some(played) = syms.get(symbol) {
+            instructions.extend(played.iter().cloned());
+        }
+    }
+    Ok(instructions)
+}
+
+/// Each row of a markov block is a state and then pairs of the state to go to
+/// and how likely it is: `A4 C5 0.5 E5 0.5`. The first row's state is where
+/// the chain starts.
+fn parse_chain(block: &ChainBlock, tuning: &dyn Tuning, root: f64, warnings: &mut Vec<CompileWarning>) -> Result<Chain, Vec<CompileError>> {
+    let mut errors = vec![];
+    let names: Vec<&str> = block.rows.iter().map(|&(_, row)| row.split_whitespace().next().unwrap_or("")).collect();
+    let mut pitches = vec![];
+    let mut transitions = vec![];
+    for &(at, row) in &block.rows {
+        let splt: Vec<&str> = row.split_whitespace().collect();
+        // a state then whole pairs
+        if splt.len() < 3 || splt.len() % 2 != 1 {
+            errors.push(CompileError::Syntax(at));
+            continue;
+        }
+        match parse_pitch(splt[0], tuning, root) {
+            Some(pitch) => {
+                check_pitch(pitch, at, warnings);
+                pitches.push(pitch);
+            },
+            None => { errors.push(CompileError::Num(at)); continue; },
+        }
+        let mut row = vec![];
+        for pair in splt[1..].chunks(2) {
+            let to = match names.iter().position(|name| *name == pair[0]) {
+                Some(to) => to,
+                None => { errors.push(CompileError::State(at)); break; },
+            };
+            match pair[1].parse() {
+                Ok(p) if (0.0..=1.0).contains(&p) => row.push((to, p)),
+                Ok(_) => { errors.push(CompileError::Prob(at)); break; },
+                Err(_) => { errors.push(CompileError::Num(at)); break; },
+            }
+        }
+        if row.len() == splt.len()/2 && (row.iter().map(|&(_, p)| p).sum::<f64>() - 1.0).abs() > TOLERANCE {
+            errors.push(CompileError::Markov(at));
+        }
+        transitions.push(row);
+    }
+    if block.rows.is_empty() {
+        errors.push(CompileError::Syntax(block.line));
+    }
+    if errors.is_empty() {
+        Ok(Chain { pitches, transitions, dur: block.dur })
+    } else {
+        Err(errors)
+    }
+}
+
+/// the onsets and step length of `euclid hits steps dur`
+fn parse_euclid(hits: &str, steps: &str, dur: &str, at: Span) -> Result<(Vec<bool>, Dur), CompileError> {
+    let (hits, steps): (usize, usize) = match (hits.parse(), steps.parse()) {
+        (Ok(hits), Ok(steps)) => (hits, steps),
+        _ => return Err(CompileError::Num(at)),
+    };
+    if steps == 0 {
+        return Err(CompileError::Positive(at));
+    }
+    if hits > steps {
+        return Err(CompileError::Euclid(at));
+    }
+    match parse_dur(dur) {
+        Some(dur) => Ok((pattern(hits, steps), dur)),
+        None => Err(CompileError::Dur(at)),
+    }
+}
+
+/// the rule, width, step length and generations of `ca rule width dur n`
+fn parse_ca(rule: &str, width: &str, dur: &str, n: &str, at: Span) -> Result<(u8, usize, Dur, usize), CompileError> {
+    let rule = match rule.parse::<u32>() {
+        Ok(rule) if rule <= 255 => rule as u8,
+        Ok(_) => return Err(CompileError::Rule(at)),
+        Err(_) => return Err(CompileError::Num(at)),
+    };
+    let (width, generations): (usize, usize) = match (width.parse(), n.parse()) {
+        (Ok(width), Ok(n)) => (width, n),
+        _ => return Err(CompileError::Num(at)),
+    };
+    if width == 0 || generations == 0 {
+        return Err(CompileError::Positive(at));
+    }
+    match parse_dur(dur) {
+        Some(step) => Ok((rule, width, step, generations)),
+        None => Err(CompileError::Dur(at)),
+    }
+}
+
+/// map each label to the index of the instruction that follows it
+fn find_labels<'a>(program: &[Line<'a>]) -> HashMap<&'a str, usize> {
+    let mut lbls = HashMap::new();
+    let mut ctr = 0;
+    for line in program {
+        match line.node {
+            Node::Label(ident) => { lbls.insert(ident, ctr); },
+            // markov and voice blocks don't produce instructions where they
+            // are, and an lsystem block
+            // only produces one for its first line
+            Node::Block { ref head, .. } if head[0] == "lsystem" => ctr += 1,
+            // nor do directives, or lines of one word, which can't be
+            // instructions
+            Node::Statement(ref splt) if splt.len() > 1 => ctr += 1,
+            _ => {},
+        }
+    }
+    lbls
+}
+
+/// the number of characters to insert, delete or change to get from `a` to
+/// `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let changed = diagonal + (ca != cb) as usize;
+            diagonal = row[j + 1];
+            row[j + 1] = changed.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// An unknown label, with the label it was most likely meant to be: the
+/// closest one, if it's a typo or two away and not as far as a whole other
+/// name.
+fn unknown_label(lbls: &HashMap<&str, usize>, lbl: &str, at: Span) -> CompileError {
+    let len = lbl.chars().count();
+    let near = lbls.keys().map(|name| (edit_distance(lbl, name), *name))
+        .filter(|&(distance, _)| distance <= (len/3).max(1) && distance < len)
+        .min()
+        .map(|(_, name)| name.to_string());
+    CompileError::Lbl(at, near)
+}
+
+/// what an instruction can refer to where it appears
+struct Scope<'a> {
+    lbls: &'a HashMap<&'a str, usize>,
+    tuning: &'a dyn Tuning,
+    root: f64,
+    scale: &'a Option<Vec<f64>>,
+}
+
+/// Compile one line that's an instruction, or None if it's something else.
+fn parse_instruction(splt: &[&str], at: Span, ctx: &Scope) -> Option<Result<Instruction, CompileError>> {
+    match splt {
+        ["sin", freq, dur] => {
+            let freqf = match parse_pitch(freq, ctx.tuning, ctx.root) {
+                Some(num) => num,
+                _ => return Some(Err(CompileError::Num(at))),
+            };
+            let durd = match parse_dur(dur) {
+                Some(dur) => dur,
+                None => return Some(Err(CompileError::Dur(at))),
+            };
+            Some(Ok(Sin(freqf, durd)))
+        },
+        ["bend", cents, dur] => {
+            let centsf: f64 = match cents.parse() {
+                Ok(num) => num,
+                _ => return Some(Err(CompileError::Num(at))),
+            };
+            let durd = match parse_dur(dur) {
+                Some(dur) => dur,
+                None => return Some(Err(CompileError::Dur(at))),
+            };
+            Some(Ok(Bend(centsf, durd)))
+        },
+        ["sweep", from, to, dur, curve @ ..] => {
+            let curve = match curve {
+                [] | ["lin"] => Curve::Lin,
+                ["exp"] => Curve::Exp,
+                _ => return Some(Err(CompileError::Syntax(at))),
+            };
+            let (fromf, tof) = match (parse_pitch(from, ctx.tuning, ctx.root), parse_pitch(to, ctx.tuning, ctx.root)) {
+                (Some(from), Some(to)) => (from, to),
+                _ => return Some(Err(CompileError::Num(at))),
+            };
+            // an exponential sweep can't pass through 0 Hz
+            if let Curve::Exp = curve {
+                if fromf <= 0.0 || tof <= 0.0 {
+                    return Some(Err(CompileError::Positive(at)));
+                }
+            }
+            let durd = match parse_dur(dur) {
+                Some(dur) => dur,
+                None => return Some(Err(CompileError::Dur(at))),
+            };
+            Some(Ok(Sweep(fromf, tof, durd, curve)))
+        },
+        ["zap", from, to, dur, decay] => {
+            let (fromf, tof) = match (parse_pitch(from, ctx.tuning, ctx.root), parse_pitch(to, ctx.tuning, ctx.root)) {
+                (Some(from), Some(to)) => (from, to),
+                _ => return Some(Err(CompileError::Num(at))),
+            };
+            let durd = match parse_dur(dur) {
+                Some(dur) => dur,
+                None => return Some(Err(CompileError::Dur(at))),
+            };
+            let decayf: f64 = match decay.parse() {
+                Ok(num) if num > 0.0 => num,
+                Ok(_) => return Some(Err(CompileError::Positive(at))),
+                Err(_) => return Some(Err(CompileError::Num(at))),
+            };
+            Some(Ok(Zap(fromf, tof, durd, decayf)))
+        },
+        ["walk", start, step, dur, n] => {
+            let startf = match parse_pitch(start, ctx.tuning, ctx.root) {
+                Some(num) if num > 0.0 => num,
+                Some(_) => return Some(Err(CompileError::Positive(at))),
+                None => return Some(Err(CompileError::Num(at))),
+            };
+            let stepf: f64 = match step.parse() {
+                Ok(num) => num,
+                _ => return Some(Err(CompileError::Num(at))),
+            };
+            let durd = match parse_dur(dur) {
+                Some(dur) => dur,
+                None => return Some(Err(CompileError::Dur(at))),
+            };
+            let notes: usize = match n.parse() {
+                Ok(num) if num > 0 => num,
+                Ok(_) => return Some(Err(CompileError::Positive(at))),
+                Err(_) => return Some(Err(CompileError::Num(at))),
+            };
+            Some(Ok(Walk(startf, stepf, durd, notes, ctx.scale.clone())))
+        },
+        ["pjump", lbl, prob] => {
+            let linenum = match ctx.lbls.get(lbl) {
+                Some(num) => *num,
+                None => return Some(Err(unknown_label(ctx.lbls, lbl, at))),
+            };
+            let probf: f64 = match prob.parse() {
+                Ok(num) if (0.0..=1.0).contains(&num) => num,
+                Ok(_) => return Some(Err(CompileError::Prob(at))),
+                Err(_) => return Some(Err(CompileError::Num(at))),
+            };
+            Some(Ok(PJump(probf, linenum)))
+        },
+        ["pfork", lbl, prob] => {
+            let linenum = match ctx.lbls.get(lbl) {
+                Some(num) => *num,
+                None => return Some(Err(unknown_label(ctx.lbls, lbl, at))),
+            };
+            let probf: f64 = match prob.parse() {
+                Ok(num) if (0.0..=1.0).contains(&num) => num,
+                Ok(_) => return Some(Err(CompileError::Prob(at))),
+                Err(_) => return Some(Err(CompileError::Num(at))),
+            };
+            Some(Ok(PFork(probf, linenum)))
+        },
+        [op @ "qjump", lbl, prob, quantum @ ..] | [op @ "qfork", lbl, prob, quantum @ ..] => {
+            let quantum = match quantum {
+                [] | ["beat"] => Quantum::Beat,
+                ["bar"] => Quantum::Bar,
+                _ => return Some(Err(CompileError::Syntax(at))),
+            };
+            let (p, pc) = match parse_branch(ctx.lbls, lbl, prob, at) {
+                Ok(branch) => branch,
+                Err(e) => return Some(Err(e)),
+            };
+            Some(Ok(if *op == "qjump" { QJump(p, pc, quantum) } else { QFork(p, pc, quantum) }))
+        },
+        ["reseed", "off"] => Some(Ok(Reseed(None))),
+        ["reseed", seed] => Some(match seed.parse() {
+            Ok(seed) => Ok(Reseed(Some(seed))),
+            Err(_) => Err(CompileError::Num(at)),
+        }),
+        // anything else might be a plugin's, the last word being how long
+        // it plays for
+        [op, args @ .., dur] => {
+            let plugin = plugin::find(op)?;
+            let durd = match parse_dur(dur) {
+                Some(dur) => dur,
+                None => return Some(Err(CompileError::Dur(at))),
+            };
+            Some(match plugin.compile(args) {
+                Ok(params) => Ok(Custom(plugin, params, durd)),
+                Err(why) => Err(CompileError::Plugin(at, why)),
+            })
+        },
+        _ => None,
+    }
+}
+
+/// The labels of a program and the instruction index each one points at, as
+/// compiled by `compile`.
+pub fn label_table(text: &str) -> HashMap<String, usize> {
+    find_labels(&ast::parse(text)).into_iter().map(|(name, pc)| (name.to_string(), pc)).collect()
+}
+
+/// Where each copy of a voice `spawn` made starts in the compiled program,
+/// by the voice's name and the arguments it was spawned with, like `arp C4
+/// G4`. Empty if the program doesn't compile.
+pub fn voice_table(text: &str) -> HashMap<String, usize> {
+    compile_known(text).map(|(_, known)| known.voices.into_iter().collect()).unwrap_or_default()
+}
+
+/// Takes in a textual program and converts it to a list of instructions.
+/// This can later be interpreted by the magic virtual sound machine.
+pub fn compile(text: &str) -> Result<Program, Vec<CompileError>> {
+    compile_known(text).map(|(program, _)| program)
+}
+
+/// What `compile` knew at a line, for compiling it again on its own.
+#[derive(Clone, Copy)]
+struct LineContext {
+    /// the instruction the line compiled to, if it's a plain one that
+    /// `parse_instruction` reads, outside of any block
+    pc: Option<usize>,
+    root: f64,
+    bpm: f64,
+    /// the bus and patch set by the latest `@bus` and `@duty`, `@wave` and
+    /// so on above it
+    bus: usize,
+    patch: Patch,
+}
+
+/// What `compile` knew about the whole of a program besides the program
+/// itself.
+struct Known {
+    lines: Vec<LineContext>,
+    tuning: Box<dyn Tuning>,
+    scale: Option<Vec<f64>>,
+    /// the labels `@anneal`s and `@listen`s are of, and the jumps and forks to them
+    anneal_targets: Vec<usize>,
+    annealed: Vec<usize>,
+    /// where each copy of a voice starts, called after the voice and its
+    /// arguments
+    voices: Vec<(String, usize)>,
+}
+
+fn compile_known(text: &str) -> Result<(Program, Known), Vec<CompileError>> {
+    let mut instructions = vec![];
+    let mut spans = vec![];
+    let mut buses = vec![MAIN_BUS.to_string()];
+    let mut bus = 0;
+    let mut patch = Patch::default();
+    let mut lines = vec![];
+    let mut errors = vec![];
+    let mut warnings = vec![];
+    let mut bpm = DEFAULT_BPM;
+    let mut meter = 4.0;
+    let mut swing = None;
+    let mut fork_decay = 1.0;
+    let mut humanize = Humanize::default();
+    // each @duck with its buses' names, which can be set up further down
+    let mut ducks = vec![];
+    // each @anneal and the label it's of, for finding the jumps to it once
+    // they've all been compiled
+    let mut anneals = vec![];
+    // each @listen, its label and the bus it hears, likewise
+    let mut listens = vec![];
+    // ratios are relative to the latest @root above them
+    let mut root = MIDDLE_C;
+    let mut chains = HashMap::new();
+    // each play and the chain it needs, filled in once every chain's known
+    let mut plays = vec![];
+    // each modulate and the bus it's aimed at, which can be set up further
+    // down
+    let mut modulates = vec![];
+    // code that only euclidean rhythms and L-systems reach, which goes after
+    // the end of the program, and the instructions that point into it
+    let mut hidden = vec![];
+    let mut hidden_spans = vec![];
+    let mut into_hidden = vec![];
+    // spawns, which go to copies of voices after that
+    let mut into_voices = vec![];
+
+    let program = ast::parse(text);
+    let lbls = find_labels(&program);
+    // voices can be spawned from above where they're defined
+    let mut voices = Voices { blocks: HashMap::new(), copies: HashMap::new(), instructions: vec![], spans: vec![] };
+    for line in &program {
+        if let Node::Block { ref head, rows: Some(ref rows) } = line.node {
+            if let ["voice", name, ref params @ ..] = head[..] {
+                let block = VoiceBlock { params: params.to_vec(), line: line.at, rows: rows.clone() };
+                voices.blocks.insert(name, block);
+            }
+        }
+    }
+    let (tuning, scale): (Box<dyn Tuning>, _) = match find_tuning(&program) {
+        Ok(Some(tuning)) => {
+            let scale = pitches(&*tuning);
+            (tuning, Some(scale))
+        },
+        Ok(None) => (Box::new(Edo { steps: 12 }), None),
+        Err(e) => return Err(vec![e]),
+    };
+
+    for line in program {
+        let at = line.at;
+        // every line gets the bus, patch, root and tempo set above it, the
+        // rows of a block included
+        lines.resize(at.line, LineContext { pc: None, root, bpm, bus, patch });
+        let splt = match line.node {
+            Node::Blank | Node::Label(_) => continue,
+            Node::Block { rows: None, .. } => {
+                // never got to its end
+                errors.push(CompileError::Syntax(at));
+                continue;
+            },
+            Node::Block { head, rows: Some(rows) } => {
+                match &head[..] {
+                    ["markov", name, dur] => {
+                        // read the chain even if the duration's bad
+                        let dur = match parse_dur(dur) {
+                            Some(dur) => dur,
+                            None => { errors.push(CompileError::Dur(at)); Dur::Secs(0.0) },
+                        };
+                        let b = ChainBlock { name, dur, line: at, rows };
+                        check_dur(b.dur, bpm, at, &mut warnings);
+                        match parse_chain(&b, &*tuning, root, &mut warnings) {
+                            Ok(chain) => { chains.insert(b.name, Some(chain)); },
+                            Err(e) => {
+                                // already reported, so plays of it aren't as well
+                                chains.insert(b.name, None);
+                                errors.extend(e);
+                            },
+                        }
+                    },
+                    ["lsystem", n] => {
+                        let iterations = match n.parse() {
+                            Ok(num) => num,
+                            Err(_) => { errors.push(CompileError::Num(at)); 0 },
+                        };
+                        let b = LsystemBlock { iterations, line: at, rows };
+                        let ctx = Scope { lbls: &lbls, tuning: &*tuning, root, scale: &scale };
+                        let pc = instructions.len();
+                        instructions.push(Terminate);
+                        spans.push(Some(at));
+                        match expand_lsystem(&b, &ctx, bpm, &mut warnings) {
+                            Ok(expansion) => {
+                                // jump to the expansion, which jumps back after
+                                instructions[pc] = PJump(1.0, hidden.len());
+                                into_hidden.push(pc);
+                                for (instruction, at) in expansion {
+                                    hidden.push(instruction);
+                                    hidden_spans.push(Some(at));
+                                }
+                                hidden.push(PJump(1.0, pc + 1));
+                                hidden_spans.push(Some(at));
+                            },
+                            Err(e) => errors.extend(e),
+                        }
+                    },
+                    ["voice", _, ..] => {},
+                    _ => errors.push(CompileError::Syntax(at)),
+                }
+                continue;
+            },
+            Node::Directive(splt) => {
+                match &splt[..] {
+                    ["@tuning", _] | ["@edo", _] => {},
+                    // for `lint`, which reads them itself
+                    ["@allow", ..] => {},
+                    ["@bpm", tempo] => {
+                        bpm = match tempo.parse::<f64>() {
+                            Ok(num) if !num.is_finite() => { errors.push(CompileError::Num(at)); continue; },
+                            Ok(num) if num > 0.0 => num,
+                            Ok(_) => { errors.push(CompileError::Positive(at)); continue; },
+                            Err(_) => { errors.push(CompileError::Num(at)); continue; },
+                        };
+                    },
+                    ["@meter", beats] => {
+                        meter = match beats.parse::<f64>() {
+                            Ok(num) if !num.is_finite() => { errors.push(CompileError::Num(at)); continue; },
+                            Ok(num) if num > 0.0 => num,
+                            Ok(_) => { errors.push(CompileError::Positive(at)); continue; },
+                            Err(_) => { errors.push(CompileError::Num(at)); continue; },
+                        };
+                    },
+                    ["@swing", amount] => {
+                        swing = match amount.parse() {
+                            Ok(num) if num > 0.0 && num < 1.0 => Some(num),
+                            Ok(_) => { errors.push(CompileError::Swing(at)); continue; },
+                            Err(_) => { errors.push(CompileError::Num(at)); continue; },
+                        };
+                    },
+                    ["@fork-decay", amount] => {
+                        fork_decay = match amount.parse() {
+                            Ok(num) if (0.0..=1.0).contains(&num) => num,
+                            Ok(_) => { errors.push(CompileError::ForkDecay(at)); continue; },
+                            Err(_) => { errors.push(CompileError::Num(at)); continue; },
+                        };
+                    },
+                    ["@bus", name] => {
+                        bus = match buses.iter().position(|b| b == name) {
+                            Some(bus) => bus,
+                            None => {
+                                buses.push(name.to_string());
+                                buses.len() - 1
+                            },
+                        };
+                    },
+                    ["@duck", target, source, amount, attack, release] => {
+                        // in decibels it's what the target goes down to
+                        let amount = match parse_db(amount).map(|gain| 1.0 - gain).or_else(|| amount.parse().ok()) {
+                            Some(num) => num,
+                            None => { errors.push(CompileError::Num(at)); continue; },
+                        };
+                        if !(0.0..=1.0).contains(&amount) {
+                            errors.push(CompileError::Duck(at));
+                            continue;
+                        }
+                        let (attack, release) = match (parse_secs(attack), parse_secs(release)) {
+                            (Some(attack), Some(release)) if attack >= 0.0 && release >= 0.0 => (attack, release),
+                            (Some(_), Some(_)) => { errors.push(CompileError::Positive(at)); continue; },
+                            _ => { errors.push(CompileError::Num(at)); continue; },
+                        };
+                        ducks.push((*target, *source, amount, attack, release, at));
+                    },
+                    ["@anneal", label, from, to, "over", over] => {
+                        let pc = match lbls.get(label) {
+                            Some(&pc) => pc,
+                            None => { errors.push(unknown_label(&lbls, label, at)); continue; },
+                        };
+                        let prob = |p: &f64| (0.0..=1.0).contains(p);
+                        let (from, to) = match (from.parse::<f64>(), to.parse::<f64>()) {
+                            (Ok(from), Ok(to)) if prob(&from) && prob(&to) => (from, to),
+                            (Ok(_), Ok(_)) => { errors.push(CompileError::Prob(at)); continue; },
+                            _ => { errors.push(CompileError::Num(at)); continue; },
+                        };
+                        let (over, beats) = match parse_span_of_time(over) {
+                            Some((over, beats)) if over > 0.0 => (over, beats),
+                            Some(_) => { errors.push(CompileError::Positive(at)); continue; },
+                            None => { errors.push(CompileError::Num(at)); continue; },
+                        };
+                        anneals.push((pc, Anneal { pcs: vec![], from, to, over, beats }, at));
+                    },
+                    ["@listen", label, from, to, ref on @ ..] => {
+                        let pc = match lbls.get(label) {
+                            Some(&pc) => pc,
+                            None => { errors.push(unknown_label(&lbls, label, at)); continue; },
+                        };
+                        let bus = match on {
+                            [] => None,
+                            ["on", bus] => Some(*bus),
+                            _ => { errors.push(CompileError::Syntax(at)); continue; },
+                        };
+                        let prob = |p: &f64| (0.0..=1.0).contains(p);
+                        let (from, to) = match (from.parse::<f64>(), to.parse::<f64>()) {
+                            (Ok(from), Ok(to)) if prob(&from) && prob(&to) => (from, to),
+                            (Ok(_), Ok(_)) => { errors.push(CompileError::Prob(at)); continue; },
+                            _ => { errors.push(CompileError::Num(at)); continue; },
+                        };
+                        listens.push((pc, from, to, bus, at));
+                    },
+                    ["@duty", percent] => {
+                        patch.duty = match percent.parse::<f64>() {
+                            Ok(num) if [12.5, 25.0, 50.0, 75.0].contains(&num) => num/100.0,
+                            Ok(_) => { errors.push(CompileError::Duty(at)); continue; },
+                            Err(_) => { errors.push(CompileError::Num(at)); continue; },
+                        };
+                    },
+                    ["@wave", samples] => {
+                        patch.wave = match parse_wave(samples) {
+                            Some(wave) => wave,
+                            None => { errors.push(CompileError::Wave(at)); continue; },
+                        };
+                    },
+                    ["@waveform", name] => {
+                        patch.waveform = match *name {
+                            "tri" => Waveform::Triangle,
+                            "saw" => Waveform::Saw,
+                            "pulse" => Waveform::Pulse,
+                            "noise" => Waveform::Noise,
+                            _ => { errors.push(CompileError::Syntax(at)); continue; },
+                        };
+                    },
+                    ["@ring", "on"] => patch.ring = true,
+                    ["@ring", "off"] => patch.ring = false,
+                    ["@sync", "on"] => patch.sync = true,
+                    ["@sync", "off"] => patch.sync = false,
+                    ["@pwm", "off"] => patch.pwm = None,
+                    ["@pwm", rate] => {
+                        patch.pwm = match rate.parse() {
+                            Ok(num) if num > 0.0 => Some(num),
+                            Ok(_) => { errors.push(CompileError::Positive(at)); continue; },
+                            Err(_) => { errors.push(CompileError::Num(at)); continue; },
+                        };
+                    },
+                    ["@filter", "off"] => patch.filter = None,
+                    ["@filter", mode, cutoff, resonance] | ["@filter", mode, cutoff, resonance, _] => {
+                        let drive = splt.get(4).cloned().unwrap_or("1");
+                        patch.filter = match parse_filter(mode, cutoff, resonance, drive, at) {
+                            Ok(filter) => Some(filter),
+                            Err(e) => { errors.push(e); continue; },
+                        };
+                    },
+                    ["@root", pitch] => {
+                        root = match parse_pitch(pitch, &*tuning, root) {
+                            Some(num) if num > 0.0 => num,
+                            Some(_) => { errors.push(CompileError::Positive(at)); continue; },
+                            None => { errors.push(CompileError::Num(at)); continue; },
+                        };
+                    },
+                    ["@humanize", "vel", amount] => {
+                        humanize.vel = match amount.parse() {
+                            Ok(num) if (0.0..=1.0).contains(&num) => num,
+                            Ok(_) => { errors.push(CompileError::Humanize(at)); continue; },
+                            Err(_) => { errors.push(CompileError::Num(at)); continue; },
+                        };
+                    },
+                    ["@humanize", "time", amount] => {
+                        humanize.time = match parse_secs(amount) {
+                            Some(secs) if secs >= 0.0 => secs,
+                            Some(_) => { errors.push(CompileError::Positive(at)); continue; },
+                            None => { errors.push(CompileError::Num(at)); continue; },
+                        };
+                    },
+                    _ => errors.push(CompileError::Syntax(at)),
+                }
+                continue;
+            },
+            Node::Statement(splt) => splt,
+        };
+        let ctx = Scope { lbls: &lbls, tuning: &*tuning, root, scale: &scale };
+        // `spawn voice args...` starts a copy of the voice as a thread of
+        // its own
+        if let ["spawn", name, ref args @ ..] = splt[..] {
+            match voices.spawn(name, args, at, &ctx, bpm, &mut warnings) {
+                Ok(start) => {
+                    into_voices.push(instructions.len());
+                    instructions.push(PFork(1.0, start));
+                    spans.push(Some(at));
+                },
+                Err(e) => errors.extend(e),
+            }
+            continue;
+        }
+        // `euclid hits steps dur` in front of an instruction plays that
+        // instruction on the rhythm's onsets
+        if let ["euclid", hits, steps, dur, note @ ..] = &splt[..] {
+            match (parse_euclid(hits, steps, dur, at), parse_instruction(note, at, &ctx)) {
+                (Ok((pattern, step)), Some(Ok(note))) => {
+                    check_instruction(&note, at, bpm, &mut warnings);
+                    check_dur(step, bpm, at, &mut warnings);
+                    // the note goes after the end of the program, where only
+                    // the threads the rhythm starts reach it
+                    into_hidden.push(instructions.len());
+                    instructions.push(Euclid(pattern, step, hidden.len()));
+                    spans.push(Some(at));
+                    hidden.push(note);
+                    hidden.push(Terminate);
+                    hidden_spans.extend(&[Some(at), Some(at)]);
+                },
+                (Err(e), _) | (_, Some(Err(e))) => errors.push(e),
+                (_, None) => errors.push(CompileError::Syntax(at)),
+            }
+            continue;
+        }
+        // `ca rule width dur n pitch...` plays each generation's live cells,
+        // the nth cell playing the nth pitch (going round again if there
+        // are fewer pitches than cells)
+        if let ["ca", rule, width, dur, n, pitches @ ..] = &splt[..] {
+            match parse_ca(rule, width, dur, n, at) {
+                Ok(_) if pitches.is_empty() => errors.push(CompileError::Syntax(at)),
+                Ok((rule, width, step, generations)) => {
+                    let pitches: Option<Vec<f64>> = pitches.iter().map(|pitch| parse_pitch(pitch, &*tuning, root)).collect();
+                    match pitches {
+                        Some(pitches) => {
+                            for &pitch in &pitches {
+                                check_pitch(pitch, at, &mut warnings);
+                            }
+                            check_dur(step, bpm, at, &mut warnings);
+                            into_hidden.push(instructions.len());
+                            instructions.push(Ca(rule, width, step, generations, hidden.len()));
+                            spans.push(Some(at));
+                            for cell in 0..width {
+                                hidden.push(Sin(pitches[cell % pitches.len()], step));
+                                hidden.push(Terminate);
+                                hidden_spans.extend(&[Some(at), Some(at)]);
+                            }
+                        },
+                        None => errors.push(CompileError::Num(at)),
+                    }
+                },
+                Err(e) => errors.push(e),
+            }
+            continue;
+        }
+        if let Some(result) = parse_instruction(&splt, at, &ctx) {
+            match result {
+                Ok(instruction) => {
+                    check_instruction(&instruction, at, bpm, &mut warnings);
+                    lines[at.line - 1].pc = Some(instructions.len());
+                    instructions.push(instruction);
+                    spans.push(Some(at));
+                },
+                Err(e) => errors.push(e),
+            }
+            continue;
+        }
+        match &splt[..] {
+            ["play", name, n] => {
+                let notes: usize = match n.parse() {
+                    Ok(num) if num > 0 => num,
+                    Ok(_) => { errors.push(CompileError::Positive(at)); continue; },
+                    Err(_) => { errors.push(CompileError::Num(at)); continue; },
+                };
+                plays.push((instructions.len(), *name, notes, at));
+                instructions.push(Terminate);
+                spans.push(Some(at));
+            },
+            ["modulate", "off"] => {
+                instructions.push(Modulate(None));
+                spans.push(Some(at));
+            },
+            ["modulate", target, depth] => {
+                let depth = match depth.parse::<f64>() {
+                    Ok(num) if num.is_finite() => num,
+                    _ => { errors.push(CompileError::Num(at)); continue; },
+                };
+                modulates.push((instructions.len(), *target, depth, at));
+                instructions.push(Terminate);
+                spans.push(Some(at));
+            },
+            _ => errors.push(CompileError::Syntax(at)),
+        }
+    }
+    lines.resize(text.lines().count(), LineContext { pc: None, root, bpm, bus, patch });
+    instructions.push(Terminate);
+    spans.push(None);
+
+    let ducks = ducks.into_iter().filter_map(|(target, source, amount, attack, release, at)| {
+        let find = |name| buses.iter().position(|bus| bus == name);
+        match (find(target), find(source)) {
+            (Some(target), Some(source)) => Some(Duck { target, source, amount, attack, release }),
+            _ => {
+                errors.push(CompileError::Bus(at));
+                None
+            },
+        }
+    }).collect();
+    let anneal_targets = anneals.iter().map(|&(target, _, _)| target)
+        .chain(listens.iter().map(|&(target, _, _, _, _)| target)).collect();
+    let anneals: Vec<Anneal> = anneals.into_iter().filter_map(|(target, mut anneal, at)| {
+        anneal.pcs = (0..instructions.len()).filter(|&pc| match instructions[pc] {
+            PJump(_, to) | PFork(_, to) | QJump(_, to, _) | QFork(_, to, _) => to == target,
+            _ => false,
+        }).collect();
+        if anneal.pcs.is_empty() {
+            errors.push(CompileError::Anneal(at));
+            return None;
+        }
+        Some(anneal)
+    }).collect();
+    let listens: Vec<Listen> = listens.into_iter().filter_map(|(target, from, to, bus, at)| {
+        let pcs: Vec<usize> = (0..instructions.len()).filter(|&pc| match instructions[pc] {
+            PJump(_, to) | PFork(_, to) | QJump(_, to, _) | QFork(_, to, _) => to == target,
+            _ => false,
+        }).collect();
+        let bus = match bus.map(|name| buses.iter().position(|bus| bus == name)) {
+            Some(None) => { errors.push(CompileError::Bus(at)); return None; },
+            bus => bus.flatten(),
+        };
+        if pcs.is_empty() {
+            errors.push(CompileError::Anneal(at));
+            return None;
+        }
+        // it's heard nothing yet
+        for &pc in &pcs {
+            if let PJump(ref mut p, _) | PFork(ref mut p, _) | QJump(ref mut p, _, _) | QFork(ref mut p, _, _)
+                   = instructions[pc] {
+                *p = from;
+            }
+        }
+        Some(Listen { pcs, from, to, bus })
+    }).collect();
+    for (pc, name, notes, at) in plays {
+        match chains.get(name) {
+            Some(Some(chain)) => instructions[pc] = Play(chain.clone(), notes),
+            Some(None) => {},
+            None => errors.push(CompileError::Chain(at)),
+        }
+    }
+    for (pc, target, depth, at) in modulates {
+        match buses.iter().position(|bus| bus == target) {
+            Some(bus) => instructions[pc] = Modulate(Some((bus, depth))),
+            None => errors.push(CompileError::Bus(at)),
+        }
+    }
+    let end = instructions.len();
+    for at in into_hidden {
+        if let Euclid(_, _, ref mut pc) | PJump(_, ref mut pc) | Ca(_, _, _, _, ref mut pc) = instructions[at] {
+            *pc += end;
+        }
+    }
+    instructions.extend(hidden);
+    spans.extend(hidden_spans);
+    let end = instructions.len();
+    for at in into_voices {
+        instructions[at].relocate(end);
+    }
+    for mut instruction in voices.instructions {
+        instruction.relocate(end);
+        instructions.push(instruction);
+    }
+    spans.extend(voices.spans);
+    let copies = voices.copies.into_iter().map(|((name, args, _), start)| {
+        let name = if args.is_empty() { name } else { format!("{} {}", name, args.join(" ")) };
+        (name, start + end)
+    }).collect();
+
+    if errors.is_empty() {
+        let bus_of = spans.iter().map(|span| span.map_or(0, |at| lines[at.line - 1].bus)).collect();
+        let patches = spans.iter().map(|span| span.map_or(Patch::default(), |at| lines[at.line - 1].patch)).collect();
+        let program = Program { instructions, bpm, meter, swing, humanize, fork_decay, warnings, spans, buses, bus_of,
+                                patches, ducks, anneals, listens };
+        let annealed = program.anneals.iter().flat_map(|anneal| anneal.pcs.iter().cloned())
+            .chain(program.listens.iter().flat_map(|listen| listen.pcs.iter().cloned())).collect();
+        Ok((program, Known { lines, tuning, scale, anneal_targets, annealed, voices: copies }))
+    } else {
+        Err(errors)
+    }
+}
+
+/// A program compiled again as it's edited. When all an edit does is
+/// change instruction lines into other instruction lines, only those lines
+/// are compiled again; anything else compiles the whole program.
+/// A scale file is only read again along with the whole program.
+pub struct Incremental {
+    text: String,
+    known: Known,
+    labels: HashMap<String, usize>,
+}
+
+pub enum Recompiled {
+    /// the whole program, compiled from scratch
+    Full(Box<Program>),
+    /// the instructions that changed, where they are, and where they came
+    /// from, with the warnings they have; the rest of the program is the same
+    Patched { changes: Vec<(usize, Instruction, Span)>, warnings: Vec<CompileWarning> },
+}
+
+impl Incremental {
+    /// Compile the first version of a program.
+    pub fn new(text: &str) -> Result<(Incremental, Program), Vec<CompileError>> {
+        let (program, known) = compile_known(text)?;
+        Ok((Incremental { text: text.to_string(), known, labels: label_table(text) }, program))
+    }
+
+    /// Compile `text`, the next version of the program. If it doesn't
+    /// compile, the last version that did is still the one edits are
+    /// compared with.
+    pub fn recompile(&mut self, text: &str) -> Result<Recompiled, Vec<CompileError>> {
+        if let Some(patched) = self.patch(text) {
+            let patched = patched?;
+            self.text = text.to_string();
+            return Ok(patched);
+        }
+        let (program, known) = compile_known(text)?;
+        self.text = text.to_string();
+        self.known = known;
+        self.labels = label_table(text);
+        Ok(Recompiled::Full(Box::new(program)))
+    }
+
+    /// the changed lines compiled on their own, or None if the edit needs
+    /// the whole program compiled
+    fn patch(&self, text: &str) -> Option<Result<Recompiled, Vec<CompileError>>> {
+        if text.lines().count() != self.known.lines.len() {
+            return None;
+        }
+        let lbls: HashMap<&str, usize> = self.labels.iter().map(|(name, &pc)| (name.as_str(), pc)).collect();
+        let (mut changes, mut warnings, mut errors) = (vec![], vec![], vec![]);
+        for (i, (old, new)) in self.text.lines().zip(text.lines()).enumerate() {
+            if old == new {
+                continue;
+            }
+            let context = self.known.lines[i];
+            let pc = context.pc?;
+            let at = Span::of(i, new);
+            let splt: Vec<&str> = new.split_whitespace().collect();
+            let ctx = Scope { lbls: &lbls, tuning: &*self.known.tuning, root: context.root, scale: &self.known.scale };
+            match parse_instruction(&splt, at, &ctx)? {
+                Ok(instruction) => {
+                    // which jumps an anneal applies to is worked out from the
+                    // whole program
+                    let to = match instruction {
+                        PJump(_, to) | PFork(_, to) | QJump(_, to, _) | QFork(_, to, _) => Some(to),
+                        _ => None,
+                    };
+                    let known = &self.known;
+                    if known.annealed.contains(&pc) || to.is_some_and(|to| known.anneal_targets.contains(&to)) {
+                        return None;
+                    }
+                    check_instruction(&instruction, at, context.bpm, &mut warnings);
+                    changes.push((pc, instruction, at));
+                },
+                Err(e) => errors.push(e),
+            }
+        }
+        Some(if errors.is_empty() { Ok(Recompiled::Patched { changes, warnings }) } else { Err(errors) })
+    }
+}
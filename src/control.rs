@@ -0,0 +1,246 @@
+//! Controlling a program while it plays, for interactive pieces. Commands
+//! come in a line at a time on a FIFO (or stdin), or over HTTP (see
+//! `remote`), and are carried out between blocks:
+//!
+//! ```text
+//! trigger chorus      start a new thread at the label `chorus`
+//! set drums 0.2       turn the `drums` bus down to 0.2 of as loud as written
+//! set drums -14dB     the same in decibels
+//! set drums 0 4s      fade it out over 4 seconds instead of the usual ramp
+//! tempo 140           glide to 140 bpm, or over a ramp of its own like `tempo 90 8s`
+//! kill-group drums    stop every thread playing into the `drums` bus
+//! stop                play silence until a start
+//! start               carry on from where stop left off
+//! ```
+//!
+//! A gain or tempo glides to where it's set over `--ramp` (see `smooth`) unless
+//! the command gives a ramp of its own.
+
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use compiler::{compile, label_table, parse_gain};
+use hotswap::migrate_pc;
+use remote;
+use remote::Access;
+use renderer::Renderer;
+use smooth::parse_ramp;
+
+pub enum Command {
+    Trigger(String),
+    /// a bus, the gain to turn it to, and how many seconds to take about it
+    /// if not the usual ramp
+    Set(String, f64, Option<f64>),
+    /// the tempo to glide to, and over how many seconds if not the usual ramp
+    Tempo(f64, Option<f64>),
+    KillGroup(String),
+    Stop,
+    Start,
+    /// the text of a new version of the program, to carry the threads over
+    /// into the way `watch` does
+    Swap(String),
+}
+
+impl Command {
+    /// read one line of commands, or say what's wrong with it
+    pub fn parse(line: &str) -> Result<Command, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match &words[..] {
+            ["trigger", label] => Ok(Command::Trigger(label.to_string())),
+            ["set", bus, gain, ramp @ ..] if ramp.len() <= 1 => match parse_gain(gain) {
+                Some(level) if level >= 0.0 => Ok(Command::Set(bus.to_string(), level, command_ramp(ramp)?)),
+                _ => Err(format!("{} isn't a gain", gain)),
+            },
+            ["tempo", bpm, ramp @ ..] if ramp.len() <= 1 => match bpm.parse::<f64>() {
+                Ok(bpm) if bpm > 0.0 && bpm.is_finite() => Ok(Command::Tempo(bpm, command_ramp(ramp)?)),
+                _ => Err(format!("{} isn't a tempo", bpm)),
+            },
+            ["kill-group", bus] => Ok(Command::KillGroup(bus.to_string())),
+            ["stop"] => Ok(Command::Stop),
+            ["start"] => Ok(Command::Start),
+            _ => Err(format!("don't know what \"{}\" means", line.trim())),
+        }
+    }
+}
+
+/// the ramp a command ends with, if it has one
+fn command_ramp(words: &[&str]) -> Result<Option<f64>, String> {
+    match words.first() {
+        Some(ramp) => parse_ramp(ramp).map(Some).ok_or_else(|| format!("{} isn't a length of time", ramp)),
+        None => Ok(None),
+    }
+}
+
+/// The commands sent to a program as it plays, from background threads
+/// reading them, and the state of play for those threads to report back.
+pub struct Control {
+    /// the labels of the program as it is now
+    labels: HashMap<String, usize>,
+    commands: Receiver<Result<Command, String>>,
+    /// handed out to each source of commands
+    send: Sender<Result<Command, String>>,
+    stopped: bool,
+    /// the latest `status`, for whoever asks
+    status: Arc<Mutex<String>>,
+}
+
+impl Control {
+    /// Control the program `text`, once it's given somewhere to take
+    /// commands from.
+    pub fn new(text: &str) -> Control {
+        let (send, commands) = channel();
+        Control {
+            labels: label_table(text),
+            commands,
+            send,
+            stopped: false,
+            status: Arc::new(Mutex::new("{}".to_string())),
+        }
+    }
+
+    /// Take commands from `path` too, a FIFO or `-` for stdin. The FIFO is
+    /// opened again whenever whatever was writing to it closes it, so
+    /// commands can come from one `echo` after another.
+    pub fn listen(&mut self, path: &str) -> io::Result<()> {
+        if path != "-" {
+            // opening a FIFO waits for a writer, so only the background
+            // thread does that
+            fs::metadata(path)?;
+        }
+        let send = self.send.clone();
+        let path = path.to_string();
+        thread::spawn(move || loop {
+            let reader: Box<dyn BufRead> = if path == "-" {
+                Box::new(BufReader::new(io::stdin()))
+            } else {
+                match File::open(&path) {
+                    Ok(file) => Box::new(BufReader::new(file)),
+                    Err(_) => return,
+                }
+            };
+            for line in reader.lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => break,
+                };
+                if !line.trim().is_empty() && send.send(Command::parse(&line)).is_err() {
+                    return;
+                }
+            }
+            if path == "-" {
+                return;
+            }
+        });
+        Ok(())
+    }
+
+    /// Take commands over HTTP on `addr` too, from whoever `access` lets in, see `remote`.
+    pub fn serve(&mut self, addr: SocketAddr, access: Access) -> io::Result<()> {
+        remote::serve(addr, access, self.send.clone(), self.status.clone())
+    }
+
+    /// true between a stop and the next start
+    pub fn stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// Carry out every command that's come in since the last call, and say
+    /// what was wrong with any that couldn't be.
+    pub fn apply(&mut self, renderer: &mut Renderer) -> Vec<String> {
+        let mut problems = vec![];
+        while let Ok(command) = self.commands.try_recv() {
+            if let Err(why) = command.and_then(|command| self.run(command, renderer)) {
+                problems.push(why);
+            }
+        }
+        *self.status.lock().unwrap() = self.status(renderer);
+        problems
+    }
+
+    fn run(&mut self, command: Command, renderer: &mut Renderer) -> Result<(), String> {
+        match command {
+            Command::Trigger(label) => match self.labels.get(&label) {
+                Some(&pc) => {
+                    renderer.spawn(pc);
+                    Ok(())
+                },
+                None => Err(format!("there's no label {}", label)),
+            },
+            Command::Set(bus, gain, ramp) => {
+                let bus = find_bus(renderer, &bus)?;
+                let ramp = ramp.unwrap_or_else(|| renderer.ramp());
+                renderer.ramp_bus_gain(bus, gain, ramp);
+                Ok(())
+            },
+            Command::Tempo(bpm, ramp) => {
+                let ramp = ramp.unwrap_or_else(|| renderer.ramp());
+                renderer.ramp_bpm(bpm, ramp)
+            },
+            Command::KillGroup(bus) => {
+                let bus = find_bus(renderer, &bus)?;
+                renderer.kill_bus(bus);
+                Ok(())
+            },
+            Command::Stop => {
+                self.stopped = true;
+                Ok(())
+            },
+            Command::Start => {
+                self.stopped = false;
+                Ok(())
+            },
+            Command::Swap(text) => {
+                let program = compile(&text)
+                    .map_err(|errors| format!("the new score doesn't compile ({} errors)", errors.len()))?;
+                let labels = label_table(&text);
+                let len = program.instructions.len();
+                if renderer.swap_program(program, |pc| migrate_pc(pc, &self.labels, &labels, len)) == 0 {
+                    renderer.restart();
+                }
+                self.labels = labels;
+                Ok(())
+            },
+        }
+    }
+
+    /// the state of play as JSON: whether it's stopped, how far in it is,
+    /// the tempo, the buses, and what every thread is doing
+    fn status(&self, renderer: &Renderer) -> String {
+        let buses: Vec<String> = renderer.buses().iter().enumerate().map(|(bus, name)| {
+            format!("{{\"name\":{},\"gain\":{}}}", json_string(name), renderer.bus_gain(bus))
+        }).collect();
+        let threads: Vec<String> = renderer.threads().iter().map(|thread| {
+            let remaining = thread.remaining.map_or("null".to_string(), |secs| format!("{:.4}", secs));
+            format!("{{\"id\":{},\"pc\":{},\"op\":{},\"weight\":{},\"remaining\":{}}}", thread.id, thread.pc,
+                    json_string(thread.op), thread.weight, remaining)
+        }).collect();
+        format!("{{\"stopped\":{},\"elapsed\":{:.4},\"bpm\":{},\"thread_count\":{},\"buses\":[{}],\"threads\":[{}]}}",
+                self.stopped, renderer.elapsed(), renderer.bpm(), renderer.thread_count(), buses.join(","),
+                threads.join(","))
+    }
+}
+
+fn find_bus(renderer: &Renderer, name: &str) -> Result<usize, String> {
+    renderer.buses().iter().position(|bus| bus == name).ok_or_else(|| format!("there's no bus {}", name))
+}
+
+/// `text` as a JSON string, quotes and all
+pub fn json_string(text: &str) -> String {
+    let mut quoted = "\"".to_string();
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
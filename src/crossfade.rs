@@ -0,0 +1,54 @@
+//! Mixing one renderer out while another mixes in.
+
+use renderer::Renderer;
+
+/// An equal-power crossfade from an old renderer to a new one. A renderer that
+/// finishes early just contributes silence for the rest of the fade.
+pub struct Crossfade {
+    old: Renderer,
+    new: Renderer,
+    pos: usize,
+    len: usize,
+}
+
+impl Crossfade {
+    /// fade over `len` samples
+    pub fn new(old: Renderer, new: Renderer, len: usize) -> Crossfade {
+        Crossfade { old, new, pos: 0, len }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.pos >= self.len || self.old.is_finished()
+    }
+
+    /// how many samples of the fade are left
+    pub fn remaining(&self) -> usize {
+        self.len.saturating_sub(self.pos)
+    }
+
+    /// the renderer being faded in, to keep playing once the fade is done
+    pub fn into_new(self) -> Renderer {
+        self.new
+    }
+
+    pub fn incoming(&mut self) -> &mut Renderer {
+        &mut self.new
+    }
+
+    /// Render up to `len` samples, shorter only if both renderers finish.
+    pub fn render_block(&mut self, len: usize) -> Vec<f32> {
+        let mut block = Vec::with_capacity(len);
+        while block.len() < len {
+            let (old, new) = (self.old.next_sample(), self.new.next_sample());
+            if old.is_none() && new.is_none() {
+                break;
+            }
+            let t = (self.pos as f64 / self.len as f64).min(1.0);
+            let angle = t*::std::f64::consts::FRAC_PI_2;
+            let mixed = old.unwrap_or(0.0)*angle.cos() + new.unwrap_or(0.0)*angle.sin();
+            block.push(mixed as f32);
+            self.pos += 1;
+        }
+        block
+    }
+}
@@ -0,0 +1,159 @@
+//! A terminal debugger: plays a program while showing a live table of its
+//! threads, and can pause, step a block at a time, and kill threads. It
+//! pauses by itself at breakpoints, conditions like `threads > 50` or
+//! `label chorus` that the renderer checks as it dispatches the threads.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::io;
+use std::sync::mpsc::Receiver;
+use renderer::{Break, Breakpoint, Renderer, SAMPLE_RATE};
+use sink::Sink;
+use term::{Key, Pacer, Tty};
+
+/// the most threads the table shows at once
+const TABLE_ROWS: usize = 30;
+
+struct State {
+    paused: bool,
+    /// render one block then pause again
+    step: bool,
+    /// the row of the table that's picked out for killing
+    selected: usize,
+    quit: bool,
+    /// the breakpoint it's paused at, if it is
+    broke: Option<Break>,
+}
+
+/// `threads > 50`, `threads < 2` or `threads = 8`, going by the number of
+/// threads, or `label chorus` for any thread reaching that label, among
+/// `labels`.
+pub fn parse_breakpoint(text: &str, labels: &HashMap<String, usize>) -> Result<Breakpoint, String> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    match words[..] {
+        ["threads", compare, count] => {
+            let ordering = match compare {
+                ">" => Ordering::Greater,
+                "<" => Ordering::Less,
+                "=" | "==" => Ordering::Equal,
+                _ => return Err(format!("can't compare with {}: use >, < or =", compare)),
+            };
+            match count.parse() {
+                Ok(count) => Ok(Breakpoint::Threads(ordering, count)),
+                Err(_) => Err(format!("{} isn't a number of threads", count)),
+            }
+        },
+        ["label", name] => match labels.get(name) {
+            Some(&pc) => Ok(Breakpoint::Label(name.to_string(), pc)),
+            None => Err(format!("no label {}", name)),
+        },
+        _ => Err(format!("can't break on `{}`: try `threads > 50` or `label chorus`", text)),
+    }
+}
+
+/// what a break was, the way it would be written
+fn describe(hit: &Break) -> String {
+    match hit.breakpoint {
+        Breakpoint::Threads(ordering, count) => {
+            let compare = match ordering {
+                Ordering::Greater => ">",
+                Ordering::Less => "<",
+                Ordering::Equal => "=",
+            };
+            format!("threads {} {}", compare, count)
+        },
+        Breakpoint::Label(ref name, _) => match hit.thread {
+            Some(thread) => format!("label {}, reached by thread {}", name, thread),
+            None => format!("label {}", name),
+        },
+    }
+}
+
+fn handle(key: Key, state: &mut State, renderer: &mut Renderer) {
+    let threads = renderer.threads().len();
+    match key {
+        Key::Char(' ') => {
+            state.paused = !state.paused;
+            state.broke = None;
+        },
+        Key::Char('n') => {
+            state.paused = true;
+            state.step = true;
+            state.broke = None;
+        },
+        Key::Up | Key::Char('k') => state.selected = state.selected.saturating_sub(1),
+        Key::Down | Key::Char('j') => state.selected = (state.selected + 1).min(threads.saturating_sub(1)),
+        Key::Char('x') => {
+            if let Some(thread) = renderer.threads().get(state.selected) {
+                renderer.kill(thread.id);
+            }
+        },
+        Key::Char('q') => state.quit = true,
+        _ => {},
+    }
+}
+
+fn screen(renderer: &Renderer, state: &State) -> Vec<String> {
+    let threads = renderer.threads();
+    let status = if renderer.is_finished() {
+        "finished"
+    } else if state.paused {
+        "paused"
+    } else {
+        "playing"
+    };
+    let mut lines = vec![
+        format!("blip-machine debugger   {:.2}s   {}   {} threads", renderer.elapsed(), status, renderer.thread_count()),
+        state.broke.as_ref().map_or(String::new(), |hit| format!("break: {}", describe(hit))),
+        format!("  {:>6}  {:>6}  {:>5}  {:<11}  {:>8}", "thread", "line", "pc", "instruction", "left"),
+    ];
+    // keep the selected row in view
+    let first = state.selected.saturating_sub(TABLE_ROWS - 1);
+    for (row, thread) in threads.iter().enumerate().skip(first).take(TABLE_ROWS) {
+        let line = thread.span.map_or("-".to_string(), |at| at.line.to_string());
+        let op = if thread.weight > 1 { format!("{} x{}", thread.op, thread.weight) } else { thread.op.to_string() };
+        let left = thread.remaining.map_or("-".to_string(), |secs| format!("{:.3}s", secs));
+        let marker = if row == state.selected { '>' } else { ' ' };
+        lines.push(format!("{} {:>6}  {:>6}  {:>5}  {:<11}  {:>8}", marker, thread.id, line, thread.pc, op, left));
+    }
+    if threads.len() > first + TABLE_ROWS {
+        lines.push(format!("  ... and {} more", threads.len() - first - TABLE_ROWS));
+    }
+    lines.push(String::new());
+    lines.push("space pause/play   n step one block   up/down select   x kill   q quit".to_string());
+    lines
+}
+
+/// Play `renderer` into `sink` under the debugger until `q` is pressed. While
+/// it's paused, or once it's finished, the sink gets silence. Blocks go out
+/// in real time even when the sink would take them faster, like a file.
+pub fn debug(renderer: &mut Renderer, sink: &mut dyn Sink, block_size: usize, tty: &mut Tty) -> io::Result<()> {
+    let keys: Receiver<Key> = tty.keys()?;
+    let silence = vec![0.0; block_size];
+    let mut state = State { paused: false, step: false, selected: 0, quit: false, broke: None };
+    let mut pacer = Pacer::new(block_size, SAMPLE_RATE);
+    while !state.quit {
+        pacer.wait();
+        for key in keys.try_iter() {
+            handle(key, &mut state, renderer);
+        }
+        let rows = renderer.threads().len();
+        state.selected = state.selected.min(rows.saturating_sub(1));
+        tty.draw(&screen(renderer, &state))?;
+        if (!state.paused || state.step) && !renderer.is_finished() {
+            state.step = false;
+            let block = renderer.render_block(block_size);
+            if let Some(hit) = renderer.take_break() {
+                state.paused = true;
+                state.broke = Some(hit);
+            }
+            sink.write_block(&block)?;
+            if block.len() < block_size {
+                sink.write_block(&silence[block.len()..])?;
+            }
+        } else {
+            sink.write_block(&silence)?;
+        }
+    }
+    sink.finish()
+}
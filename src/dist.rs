@@ -0,0 +1,54 @@
+//! Distributions for operands that are drawn at random each time they're used.
+
+use rand::{Rng, RngExt};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Dist {
+    /// `N(mean,sd)`
+    Normal(f64, f64),
+    /// `U(low,high)`
+    Uniform(f64, f64),
+}
+
+impl Dist {
+    /// Parse `N(0.5,0.1)` or `U(0.2,0.8)`, optionally with a `~` in front.
+    pub fn parse(text: &str) -> Option<Dist> {
+        let text = text.strip_prefix('~').unwrap_or(text);
+        let (kind, args) = text.split_at(text.find('(')?);
+        let args = args.strip_prefix('(')?.strip_suffix(')')?;
+        let mut args = args.splitn(2, ',');
+        let a: f64 = args.next()?.parse().ok()?;
+        let b: f64 = args.next()?.parse().ok()?;
+        match kind {
+            "N" if b >= 0.0 => Some(Dist::Normal(a, b)),
+            "U" if a <= b => Some(Dist::Uniform(a, b)),
+            _ => None,
+        }
+    }
+
+    /// the same distribution in other units, `by` times as big
+    pub fn scaled(&self, by: f64) -> Dist {
+        match *self {
+            Dist::Normal(mean, sd) => Dist::Normal(mean*by, sd*by),
+            Dist::Uniform(low, high) => Dist::Uniform(low*by, high*by),
+        }
+    }
+
+    pub fn mean(&self) -> f64 {
+        match *self {
+            Dist::Normal(mean, _) => mean,
+            Dist::Uniform(low, high) => (low + high)/2.0,
+        }
+    }
+
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> f64 {
+        match *self {
+            Dist::Normal(mean, sd) => {
+                // Box-Muller; 1 - u keeps the log away from 0
+                let (u, v) = (1.0 - rng.random::<f64>(), rng.random::<f64>());
+                mean + sd*(-2.0*u.ln()).sqrt()*(2.0*::std::f64::consts::PI*v).cos()
+            },
+            Dist::Uniform(low, high) => low + (high - low)*rng.random::<f64>(),
+        }
+    }
+}
@@ -0,0 +1,60 @@
+//! Sidechain ducking between buses: `@duck pad drums 0.7 5ms 200ms` turns
+//! the `pad` bus down whenever the `drums` bus is playing, by up to 0.7 of
+//! its level, so a kick cuts through a wash of chords. How loud the drums
+//! are is tracked by an envelope follower that rises over the attack and
+//! falls back over the release.
+
+use renderer::SAMPLE_RATE;
+
+/// One `@duck`: the bus to turn down, the bus that turns it down, how far,
+/// and the follower's attack and release in seconds.
+#[derive(Clone, Copy)]
+pub struct Duck {
+    pub target: usize,
+    pub source: usize,
+    pub amount: f64,
+    pub attack: f64,
+    pub release: f64,
+}
+
+/// Follows the level of a `Duck`'s source bus.
+pub struct Follower {
+    duck: Duck,
+    /// the source's level, from 0 to 1
+    envelope: f64,
+    rise: f64,
+    fall: f64,
+}
+
+/// how much of the gap to the input a one-pole follower keeps each sample,
+/// to close most of it over `secs`
+fn coefficient(secs: f64) -> f64 {
+    if secs > 0.0 { (-1.0/(secs*SAMPLE_RATE)).exp() } else { 0.0 }
+}
+
+impl Follower {
+    pub fn new(duck: Duck) -> Follower {
+        Follower { duck, envelope: 0.0, rise: coefficient(duck.attack), fall: coefficient(duck.release) }
+    }
+
+    pub fn duck(&self) -> &Duck {
+        &self.duck
+    }
+
+    /// the source's level as it's been followed so far, for a checkpoint
+    pub fn envelope(&self) -> f64 {
+        self.envelope
+    }
+
+    pub fn set_envelope(&mut self, envelope: f64) {
+        self.envelope = envelope;
+    }
+
+    /// Take the source's level for one sample, from 0 to 1, and return what
+    /// to multiply the target's gain by.
+    pub fn follow(&mut self, level: f64) -> f64 {
+        let keep = if level > self.envelope { self.rise } else { self.fall };
+        self.envelope = level + keep*(self.envelope - level);
+        1.0 - self.duck.amount*self.envelope.min(1.0)
+    }
+}
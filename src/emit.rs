@@ -0,0 +1,71 @@
+//! Every note a render played as JSON, for `--emit`, so visualizers, MIDI
+//! exporters and analysis scripts can all start from the same events:
+//!
+//! ```text
+//! {"engine": "0.1.0", "seed": 0, "sample_rate": 8000, "events": [
+//! {"start": 0, "time": 0, "thread": 0, "op": "sin", "freq": 440, "to": 440, "dur": 0.5, "amplitude": 1,
+//!  "threads": 1, "span": {"line": 1, "start": 1, "end": 12}},
+//! ...
+//! ]}
+//! ```
+//!
+//! `engine` is the version that rendered it, see `session::engine`. `start`
+//! is the sample the note starts on and `time` the same in seconds.
+//! `amplitude` is how hard it's played, from 0 to 1, before the mix divides
+//! it between the threads sounding at once, and `threads` is how many
+//! identical threads it stands for under `--dedup`. A note that bends or
+//! sweeps starts on `freq` and ends up on `to`.
+
+use std::io;
+use std::io::Write;
+use compiler::Span;
+use control::json_string;
+use renderer::{Event, SAMPLE_RATE};
+use session::engine;
+
+pub struct EventFile {
+    out: Box<dyn Write>,
+    /// true until the first event has been written
+    first: bool,
+}
+
+/// a number as JSON, which has no NaN or infinity
+fn number(x: f64) -> String {
+    if x.is_finite() { format!("{}", x) } else { "null".to_string() }
+}
+
+fn span(at: Option<Span>) -> String {
+    match at {
+        Some(at) => format!("{{\"line\": {}, \"start\": {}, \"end\": {}}}", at.line, at.start, at.end),
+        None => "null".to_string(),
+    }
+}
+
+impl EventFile {
+    /// Start the events of a render with `seed` on `out`.
+    pub fn new(seed: usize, mut out: Box<dyn Write>) -> io::Result<EventFile> {
+        write!(out, "{{\"engine\": {}, \"seed\": {}, \"sample_rate\": {}, \"events\": [", json_string(&engine()),
+               seed, SAMPLE_RATE)?;
+        Ok(EventFile { out, first: true })
+    }
+
+    /// Write each of `events`, from `Renderer::take_events`.
+    pub fn write(&mut self, events: &[Event]) -> io::Result<()> {
+        for event in events {
+            let comma = if self.first { "" } else { "," };
+            self.first = false;
+            writeln!(self.out, "{}", comma)?;
+            write!(self.out, "{{\"start\": {}, \"time\": {}, \"thread\": {}, \"op\": {}, \"freq\": {}, \"to\": {}, \
+                              \"dur\": {}, \"amplitude\": {}, \"threads\": {}, \"span\": {}}}",
+                   event.start, number(event.time), event.thread, json_string(event.op), number(event.freq),
+                   number(event.to), number(event.dur), number(event.velocity), event.weight, span(event.span))?;
+        }
+        Ok(())
+    }
+
+    /// Close the list off once the render is over.
+    pub fn finish(&mut self) -> io::Result<()> {
+        writeln!(self.out, "\n]}}")?;
+        self.out.flush()
+    }
+}
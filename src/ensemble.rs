@@ -0,0 +1,278 @@
+//! Several scores played together, each with its own gain, pan and seed,
+//! from a session file in a small subset of TOML:
+//!
+//! ```text
+//! length = 60            # seconds, or until every score finishes
+//!
+//! [[score]]
+//! file = "drums.txt"
+//! bus = "rhythm"
+//! pan = -0.3
+//! seed = 7
+//!
+//! [[score]]
+//! file = "lead.txt"
+//! gain = "-2dB"
+//! pan = 0.5
+//! start = 8              # seconds of silence before it comes in
+//!
+//! [bus.rhythm]
+//! gain = 0.6
+//! ```
+//!
+//! A score's `bus` is a group of scores to turn up or down, or pan, together;
+//! scores without one go straight to the mix. Files are found relative to
+//! the session file.
+
+use std::f64::consts::PI;
+use compiler::{parse_db, Program};
+use renderer::{Renderer, RuntimeError, SAMPLE_RATE};
+
+/// One score in a session.
+pub struct Layer {
+    pub file: String,
+    pub gain: f64,
+    /// -1 for all the way left, 1 for all the way right
+    pub pan: f64,
+    pub bus: Option<String>,
+    pub seed: usize,
+    /// seconds in before it starts
+    pub start: f64,
+}
+
+/// A group of scores sharing a gain and a pan.
+pub struct Bus {
+    pub name: String,
+    pub gain: f64,
+    pub pan: f64,
+}
+
+pub struct Ensemble {
+    pub layers: Vec<Layer>,
+    pub buses: Vec<Bus>,
+    /// seconds to stop after, if not when every score has finished
+    pub length: Option<f64>,
+}
+
+enum Value {
+    Text(String),
+    Number(f64),
+}
+
+enum Table {
+    Top,
+    Score,
+    Bus,
+}
+
+impl Ensemble {
+    /// Read a session file, or say which line is wrong with it and why.
+    pub fn parse(text: &str) -> Result<Ensemble, String> {
+        let mut ensemble = Ensemble { layers: vec![], buses: vec![], length: None };
+        let mut table = Table::Top;
+        for (i, line) in text.lines().enumerate() {
+            let at = |why: String| format!("line {}: {}", i + 1, why);
+            let line = without_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line == "[[score]]" {
+                let layer = Layer { file: String::new(), gain: 1.0, pan: 0.0, bus: None, seed: 0, start: 0.0 };
+                ensemble.layers.push(layer);
+                table = Table::Score;
+                continue;
+            }
+            if let Some(name) = line.strip_prefix("[bus.").and_then(|rest| rest.strip_suffix(']')) {
+                let name = name.trim_matches('"').to_string();
+                if ensemble.buses.iter().any(|bus| bus.name == name) {
+                    return Err(at(format!("the bus {} is already set up", name)));
+                }
+                ensemble.buses.push(Bus { name, gain: 1.0, pan: 0.0 });
+                table = Table::Bus;
+                continue;
+            }
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), parse_value(value.trim()).map_err(&at)?),
+                None => return Err(at(format!("expected `key = value`, not \"{}\"", line))),
+            };
+            match table {
+                Table::Top => match key {
+                    "length" => ensemble.length = Some(seconds(key, value).map_err(&at)?),
+                    _ => return Err(at(format!("unknown setting {}", key))),
+                },
+                Table::Score => {
+                    let layer = ensemble.layers.last_mut().unwrap();
+                    match key {
+                        "file" => layer.file = text_value(key, value).map_err(&at)?,
+                        "bus" => layer.bus = Some(text_value(key, value).map_err(&at)?),
+                        "gain" => layer.gain = gain(key, value).map_err(&at)?,
+                        "pan" => layer.pan = pan(key, value).map_err(&at)?,
+                        "start" => layer.start = seconds(key, value).map_err(&at)?,
+                        "seed" => match value {
+                            Value::Number(seed) if seed >= 0.0 && seed.fract() == 0.0 => layer.seed = seed as usize,
+                            _ => return Err(at("seed should be a whole number".to_string())),
+                        },
+                        _ => return Err(at(format!("unknown score setting {}", key))),
+                    }
+                },
+                Table::Bus => {
+                    let bus = ensemble.buses.last_mut().unwrap();
+                    match key {
+                        "gain" => bus.gain = gain(key, value).map_err(&at)?,
+                        "pan" => bus.pan = pan(key, value).map_err(&at)?,
+                        _ => return Err(at(format!("unknown bus setting {}", key))),
+                    }
+                },
+            }
+        }
+        if ensemble.layers.is_empty() {
+            return Err("there are no [[score]]s".to_string());
+        }
+        for (i, layer) in ensemble.layers.iter().enumerate() {
+            if layer.file.is_empty() {
+                return Err(format!("score {} has no file", i + 1));
+            }
+            if let Some(ref name) = layer.bus {
+                if !ensemble.buses.iter().any(|bus| &bus.name == name) {
+                    return Err(format!("{} goes to the bus {}, but there's no [bus.{}]", layer.file, name, name));
+                }
+            }
+        }
+        Ok(ensemble)
+    }
+
+    fn bus(&self, layer: &Layer) -> Option<&Bus> {
+        layer.bus.as_ref().and_then(|name| self.buses.iter().find(|bus| &bus.name == name))
+    }
+}
+
+fn without_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => {},
+        }
+    }
+    line
+}
+
+fn parse_value(value: &str) -> Result<Value, String> {
+    if let Some(text) = value.strip_prefix('"').and_then(|rest| rest.strip_suffix('"')) {
+        return Ok(Value::Text(text.to_string()));
+    }
+    match value.parse::<f64>() {
+        Ok(number) if number.is_finite() => Ok(Value::Number(number)),
+        _ => Err(format!("{} isn't a number or a quoted string", value)),
+    }
+}
+
+fn text_value(key: &str, value: Value) -> Result<String, String> {
+    match value {
+        Value::Text(text) => Ok(text),
+        Value::Number(_) => Err(format!("{} should be a quoted string", key)),
+    }
+}
+
+fn number(key: &str, value: Value, lowest: f64, highest: f64) -> Result<f64, String> {
+    match value {
+        Value::Number(number) if (lowest..=highest).contains(&number) => Ok(number),
+        Value::Number(number) => Err(format!("{} should be from {} to {}, not {}", key, lowest, highest, number)),
+        Value::Text(_) => Err(format!("{} should be a number", key)),
+    }
+}
+
+/// a gain, or a string like `"-6dB"`
+fn gain(key: &str, value: Value) -> Result<f64, String> {
+    let value = match value {
+        Value::Text(text) => match parse_db(&text) {
+            Some(level) => Value::Number(level),
+            None => return Err(format!("{} should be a number, or decibels like \"-6dB\"", key)),
+        },
+        value => value,
+    };
+    number(key, value, 0.0, 16.0)
+}
+
+fn pan(key: &str, value: Value) -> Result<f64, String> {
+    number(key, value, -1.0, 1.0)
+}
+
+fn seconds(key: &str, value: Value) -> Result<f64, String> {
+    number(key, value, 0.0, 86_400.0)
+}
+
+struct Playing {
+    renderer: Renderer,
+    /// the sample it starts at
+    start: usize,
+    left: f32,
+    right: f32,
+}
+
+/// The scores of a session rendered together into interleaved stereo.
+pub struct Mixdown {
+    layers: Vec<Playing>,
+    /// samples rendered so far
+    now: usize,
+    end: Option<usize>,
+    block: Vec<f32>,
+}
+
+impl Mixdown {
+    /// Mix `programs`, compiled from the files of `ensemble`'s layers in
+    /// order. Pans are constant-power, so a score in the middle comes out of
+    /// each side at about 0.7 of its level.
+    pub fn new(ensemble: &Ensemble, programs: Vec<Program>) -> Mixdown {
+        let layers = ensemble.layers.iter().zip(programs).map(|(layer, program)| {
+            let (mut gain, mut pan) = (layer.gain, layer.pan);
+            if let Some(bus) = ensemble.bus(layer) {
+                gain *= bus.gain;
+                pan = (pan + bus.pan).clamp(-1.0, 1.0);
+            }
+            let angle = (pan + 1.0)*PI/4.0;
+            Playing {
+                renderer: Renderer::new(program, layer.seed),
+                start: (layer.start*SAMPLE_RATE) as usize,
+                left: (gain*angle.cos()) as f32,
+                right: (gain*angle.sin()) as f32,
+            }
+        }).collect();
+        Mixdown { layers, now: 0, end: ensemble.length.map(|secs| (secs*SAMPLE_RATE) as usize), block: vec![] }
+    }
+
+    /// true once the session's length is up, or every score has finished
+    pub fn is_finished(&self) -> bool {
+        self.end.is_some_and(|end| self.now >= end) || self.layers.iter().all(|layer| layer.renderer.is_finished())
+    }
+
+    /// The next `frames` frames of left and right samples, or fewer once
+    /// the session's length is up.
+    pub fn render_block(&mut self, frames: usize) -> Vec<f32> {
+        let frames = self.end.map_or(frames, |end| frames.min(end.saturating_sub(self.now)));
+        let mut out = vec![0.0; frames*2];
+        self.block.resize(frames, 0.0);
+        for layer in &mut self.layers {
+            // silent until it comes in
+            let skip = layer.start.saturating_sub(self.now).min(frames);
+            if skip == frames || layer.renderer.is_finished() {
+                continue;
+            }
+            let rendered = layer.renderer.render_into(&mut self.block[..frames - skip]);
+            for (i, sample) in self.block[..rendered].iter().enumerate() {
+                out[(skip + i)*2] += sample*layer.left;
+                out[(skip + i)*2 + 1] += sample*layer.right;
+            }
+        }
+        self.now += frames;
+        out
+    }
+
+    /// the first runtime error since the last call, and which layer it
+    /// stopped
+    pub fn take_error(&mut self) -> Option<(usize, RuntimeError)> {
+        self.layers.iter_mut().enumerate().filter_map(|(i, layer)| layer.renderer.take_error().map(|error| (i, error)))
+            .next()
+    }
+}
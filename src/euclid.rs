@@ -0,0 +1,20 @@
+//! Euclidean rhythms: some number of hits spread as evenly as they can be
+//! over a number of steps.
+
+/// Bjorklund's algorithm: `hits` onsets (true) among `steps` steps, starting
+/// with an onset unless there are none. `pattern(3, 8)` is `x..x..x.`.
+pub fn pattern(hits: usize, steps: usize) -> Vec<bool> {
+    let mut front: Vec<Vec<bool>> = vec![vec![true]; hits];
+    let mut back: Vec<Vec<bool>> = vec![vec![false]; steps - hits];
+    // pair a group from the back with each group at the front, over and over,
+    // until the back is down to one group
+    while back.len() > 1 && !front.is_empty() {
+        let pairs = front.len().min(back.len());
+        let rest = if front.len() > pairs { front.split_off(pairs) } else { back.split_off(pairs) };
+        for (group, tail) in front.iter_mut().zip(back) {
+            group.extend(tail);
+        }
+        back = rest;
+    }
+    front.into_iter().chain(back).flatten().collect()
+}
@@ -0,0 +1,89 @@
+//! A timeline of the choices a render made, for `--explain`, so what's heard
+//! can be traced back to the program:
+//!
+//! ```text
+//!     3.2000s  main#0 took a jump to chorus (p=0.3, line 12)
+//!     4.0000s  main#0 forked ornament#2 (line 6)
+//!     4.5000s  ornament#2 didn't take a jump to ornament (p=0.5, line 9)
+//! ```
+//!
+//! Threads are called after the label they were forked to and their number,
+//! and the thread the program starts with is `main#0`. Only jumps and forks
+//! that could have gone either way are listed, along with every fork that
+//! started a thread.
+
+use std::collections::HashMap;
+use std::io;
+use std::io::Write;
+use renderer::Branch;
+
+pub struct Timeline {
+    out: Box<dyn Write>,
+    /// the label at each place a jump can go to
+    labels: HashMap<usize, String>,
+    /// what each thread that's been seen is called
+    names: HashMap<usize, String>,
+}
+
+impl Timeline {
+    /// Explain the program whose labels are `labels`, as `label_table` gives
+    /// them, to `out`.
+    pub fn new(labels: &HashMap<String, usize>, out: Box<dyn Write>) -> Timeline {
+        let mut by_pc: HashMap<usize, String> = HashMap::new();
+        for (name, &pc) in labels {
+            // of several labels in one place, the first alphabetically
+            if by_pc.get(&pc).is_none_or(|other| name < other) {
+                by_pc.insert(pc, name.clone());
+            }
+        }
+        Timeline { out, labels: by_pc, names: HashMap::new() }
+    }
+
+    fn label(&self, pc: usize) -> String {
+        self.labels.get(&pc).cloned().unwrap_or_else(|| format!("instruction {}", pc))
+    }
+
+    fn name(&self, thread: usize) -> String {
+        self.names.get(&thread).cloned().unwrap_or_else(|| format!("main#{}", thread))
+    }
+
+    /// Write a line for each of `branches`, from `Renderer::take_branches`.
+    pub fn write(&mut self, branches: &[Branch]) -> io::Result<()> {
+        for branch in branches {
+            let line = self.line(branch);
+            writeln!(self.out, "{}", line)?;
+        }
+        Ok(())
+    }
+
+    fn line(&mut self, branch: &Branch) -> String {
+        let who = match branch.weight {
+            1 => self.name(branch.thread),
+            weight => format!("{} of the {} threads of {}", branch.taken, weight, self.name(branch.thread)),
+        };
+        // the threads of a merged one that didn't take it are in the count
+        let verb = if branch.taken > 0 || branch.weight > 1 { "took" } else { "didn't take" };
+        let what = match branch.started {
+            Some(started) => {
+                let name = format!("{}#{}", self.label(branch.to), started);
+                self.names.insert(started, name.clone());
+                format!("forked {}", name)
+            },
+            None => {
+                let op = if branch.fork { "a fork to" } else { "a jump to" };
+                format!("{} {} {}", verb, op, self.label(branch.to))
+            },
+        };
+        let mut why = vec![];
+        if branch.p < 1.0 {
+            why.push(format!("p={}", branch.p));
+        }
+        if let Some(at) = branch.span {
+            why.push(format!("line {}", at.line));
+        }
+        match why.len() {
+            0 => format!("{:10.4}s  {} {}", branch.time, who, what),
+            _ => format!("{:10.4}s  {} {} ({})", branch.time, who, what, why.join(", ")),
+        }
+    }
+}
@@ -0,0 +1,173 @@
+//! Writing out a realized run as code for other music systems, so a piece
+//! sketched here can be handed on to someone working in SuperCollider,
+//! Csound, or Sonic Pi.
+//!
+//! What gets exported is the list of notes one seeded run played, as a dry
+//! run found them, along with a single instrument that plays any of them.
+//! Random choices are already made by then, so the other system plays exactly
+//! the notes this one would have.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use compiler::{Curve, Instruction};
+use renderer::{Event, ZAP_PITCH_SPEED};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Target {
+    SuperCollider,
+    Csound,
+    SonicPi,
+}
+
+impl Target {
+    pub fn from_name(name: &str) -> Option<Target> {
+        match name {
+            "supercollider" | "sc" => Some(Target::SuperCollider),
+            "csound" => Some(Target::Csound),
+            "sonic-pi" | "sonicpi" => Some(Target::SonicPi),
+            _ => None,
+        }
+    }
+}
+
+/// how a note's pitch and volume move, numbered the same for SuperCollider and Csound
+fn shape(instruction: &Instruction) -> (u8, f64) {
+    match *instruction {
+        Instruction::Sweep(_, _, _, Curve::Exp) | Instruction::Bend(..) => (1, 1.0),
+        Instruction::Zap(_, _, _, decay) => (2, decay),
+        // and everything else holds its pitch, or glides in a straight line
+        _ => (0, 1.0),
+    }
+}
+
+/// The most threads sounding at once. The renderer divides by how many are
+/// sounding at each moment, which a score can't do, so every note is scaled
+/// down by the most there ever are instead.
+fn peak_voices(events: &[Event]) -> usize {
+    let mut changes: Vec<(f64, i64)> = vec![];
+    for event in events {
+        changes.push((event.time, event.weight as i64));
+        changes.push((event.time + event.dur, -(event.weight as i64)));
+    }
+    // a note ending frees its voice for one starting at the same moment
+    changes.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let (mut voices, mut peak) = (0, 0);
+    for (_, change) in changes {
+        voices += change;
+        peak = peak.max(voices);
+    }
+    peak.max(1) as usize
+}
+
+static SYNTHDEF: &str = "SynthDef(\\blip, { |out = 0, freq = 440, to = 440, dur = 1, amp = 0.1, shape = 0, decay = 1|
+    var t = Line.kr(0, dur, dur, doneAction: 2);
+    var f = Select.kr(shape, [
+        freq + ((to - freq) * (t / dur)),
+        freq * ((to / freq) ** (t / dur)),
+        to + ((freq - to) * exp(t.neg * SPEED / decay))
+    ]);
+    var g = Select.kr(shape, [1, 1, exp(t.neg / decay)]);
+    Out.ar(out, (SinOsc.ar(f) * g * amp).dup);
+}).add;
+";
+
+static ORCHESTRA: &str = "sr = RATE
+ksmps = 1
+nchnls = 1
+0dbfs = 1
+
+; p4 from, p5 to, p6 amp, p7 shape, p8 decay
+instr 1
+  kt line 0, p3, p3
+  if p7 == 0 then
+    kf = p4 + (p5 - p4)*kt/p3
+    kg = 1
+  elseif p7 == 1 then
+    kf = p4*(p5/p4)^(kt/p3)
+    kg = 1
+  else
+    kf = p5 + (p4 - p5)*exp(-kt*SPEED/p8)
+    kg = exp(-kt/p8)
+  endif
+  out poscil(p6*kg, kf)
+endin
+";
+
+/// a pitch in Hz as a MIDI note number, which is what Sonic Pi plays
+fn midi_note(freq: f64) -> f64 {
+    69.0 + 12.0*(freq/440.0).log2()
+}
+
+/// Each of the program's threads becomes a Sonic Pi thread of its own that
+/// plays its notes in turn, sleeping until each one starts.
+fn sonic_pi(instructions: &[Instruction], events: &[Event], peak: f64) -> String {
+    let mut threads: BTreeMap<usize, Vec<&Event>> = BTreeMap::new();
+    for event in events {
+        threads.entry(event.thread).or_default().push(event);
+    }
+    let mut out = String::from("# exported from blip-machine\nuse_bpm 60\nuse_synth :sine\n");
+    for (id, notes) in threads {
+        let _ = writeln!(out, "\n# thread {}\nin_thread do", id);
+        let mut now = 0.0;
+        for event in notes {
+            if event.time > now {
+                let _ = writeln!(out, "  sleep {:.6}", event.time - now);
+            }
+            let amp = event.velocity*event.weight as f64/peak;
+            let (from, to) = (midi_note(event.freq), midi_note(event.to));
+            // a zap dies away over the whole note, the rest hold steady
+            let envelope = match shape(&instructions[event.pc]) {
+                (2, _) => format!("attack: 0, sustain: 0, release: {:.6}", event.dur),
+                _ => format!("attack: 0, sustain: {:.6}, release: 0", event.dur),
+            };
+            if (to - from).abs() < 1e-6 {
+                let _ = writeln!(out, "  play {:.4}, {}, amp: {:.6}", from, envelope, amp);
+            } else {
+                // sliding the note number is a glide in pitch, which is
+                // what a bend or an exponential sweep is anyway
+                let _ = writeln!(out, "  s = play {:.4}, {}, amp: {:.6}, note_slide: {:.6}", from, envelope, amp,
+                                 event.dur);
+                let _ = writeln!(out, "  control s, note: {:.4}", to);
+            }
+            now = event.time;
+        }
+        out.push_str("end\n");
+    }
+    out
+}
+
+/// Code that plays `events`, which a dry run of `instructions` started.
+pub fn export(instructions: &[Instruction], events: &[Event], sample_rate: f64, target: Target) -> String {
+    let peak = peak_voices(events) as f64;
+    let mut out = String::new();
+    match target {
+        Target::SuperCollider => {
+            out.push_str("// exported from blip-machine\n(\ns.waitForBoot {\n");
+            out.push_str(&SYNTHDEF.replace("SPEED", &ZAP_PITCH_SPEED.to_string()));
+            out.push_str("s.sync;\nScore([\n");
+            for event in events {
+                let (shape, decay) = shape(&instructions[event.pc]);
+                let _ = writeln!(out, "    [{:.6}, [\\s_new, \\blip, -1, 0, 0, \\freq, {:.4}, \\to, {:.4}, \\dur, {:.6}, \
+                                       \\amp, {:.6}, \\shape, {}, \\decay, {}]],",
+                                 event.time, event.freq, event.to, event.dur,
+                                 event.velocity*event.weight as f64/peak, shape, decay);
+            }
+            out.push_str("]).play;\n};\n)\n");
+        },
+        Target::Csound => {
+            out.push_str("<CsoundSynthesizer>\n<CsOptions>\n-odac\n</CsOptions>\n<CsInstruments>\n");
+            out.push_str(&ORCHESTRA.replace("RATE", &sample_rate.to_string())
+                                   .replace("SPEED", &ZAP_PITCH_SPEED.to_string()));
+            out.push_str("</CsInstruments>\n<CsScore>\n; exported from blip-machine\n");
+            for event in events {
+                let (shape, decay) = shape(&instructions[event.pc]);
+                let _ = writeln!(out, "i 1 {:.6} {:.6} {:.4} {:.4} {:.6} {} {}",
+                                 event.time, event.dur, event.freq, event.to,
+                                 event.velocity*event.weight as f64/peak, shape, decay);
+            }
+            out.push_str("e\n</CsScore>\n</CsoundSynthesizer>\n");
+        },
+        Target::SonicPi => out = sonic_pi(instructions, events, peak),
+    }
+    out
+}
@@ -0,0 +1,52 @@
+//! A plain radix-2 FFT, for the few places that look at a render's
+//! spectrum, where an FFT crate would be a lot to take on for one loop.
+
+use std::f64::consts::PI;
+
+/// The FFT of `re` and `im` in place; they have to be a power of two long.
+pub fn fft(re: &mut [f64], im: &mut [f64]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0*PI/len as f64;
+        for start in (0..n).step_by(len) {
+            for k in 0..len/2 {
+                let (w_re, w_im) = ((angle*k as f64).cos(), (angle*k as f64).sin());
+                let (a, b) = (start + k, start + k + len/2);
+                let t_re = re[b]*w_re - im[b]*w_im;
+                let t_im = re[b]*w_im + im[b]*w_re;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+/// The inverse of `fft`, scaled so that one undoes the other.
+pub fn ifft(re: &mut [f64], im: &mut [f64]) {
+    for x in im.iter_mut() {
+        *x = -*x;
+    }
+    fft(re, im);
+    let n = re.len() as f64;
+    for (re, im) in re.iter_mut().zip(im.iter_mut()) {
+        *re /= n;
+        *im = -*im/n;
+    }
+}
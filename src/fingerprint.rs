@@ -0,0 +1,127 @@
+//! Coarse spectral fingerprints of renders, for snapshot tests that should
+//! survive a change in float rounding but not one you'd hear.
+//!
+//! Each second of audio is cut into windows, and the windows' spectra are
+//! averaged into `BANDS` equal bands from 0 Hz to half the sample rate, each
+//! kept as a level in dB. Two renders match if every band of every second is
+//! within a tolerance of the other's. Bands quieter than `FLOOR_DB` all
+//! count as silence, so noise far below anything audible can't fail a test.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::f64::consts::PI;
+use fft::fft;
+use renderer::SAMPLE_RATE;
+
+/// the bands each second is split into, 250 Hz wide at 8000 Hz
+pub const BANDS: usize = 16;
+
+/// the quietest a band is taken to be, in dB below full scale
+pub const FLOOR_DB: f64 = -90.0;
+
+/// the samples in each window, a power of two for the FFT
+const WINDOW: usize = 512;
+
+pub struct Fingerprint {
+    /// each band's level in dB, a row for each second
+    pub seconds: Vec<[f64; BANDS]>,
+}
+
+/// How a render differs from its snapshot.
+pub enum Mismatch {
+    /// it's a different length, in whole or part seconds
+    Length { expected: usize, got: usize },
+    /// a band of a second is too loud or too quiet, with the band's lowest
+    /// frequency in Hz
+    Level { second: usize, band: f64, expected: f64, got: f64 },
+}
+
+impl Fingerprint {
+    /// The fingerprint of a render. A last part second counts as a second.
+    pub fn of(samples: &[f32]) -> Fingerprint {
+        let per_second = SAMPLE_RATE as usize;
+        let bins = WINDOW/2/BANDS;
+        let hann: Vec<f64> = (0..WINDOW).map(|i| 0.5 - 0.5*(2.0*PI*i as f64/WINDOW as f64).cos()).collect();
+        let seconds = (0..samples.len()).step_by(per_second).map(|second| {
+            let mut power = [0.0; BANDS];
+            // windows start in the second but can run on into the next, so
+            // only the end of the render is cut off partway through one
+            let windows: Vec<usize> = (second..samples.len().min(second + per_second)).step_by(WINDOW).collect();
+            for &start in &windows {
+                let mut re: Vec<f64> = (0..WINDOW).map(|i| samples.get(start + i).map_or(0.0, |&s| s as f64)*hann[i])
+                    .collect();
+                let mut im = vec![0.0; WINDOW];
+                fft(&mut re, &mut im);
+                for (band, level) in power.iter_mut().enumerate() {
+                    for bin in band*bins..(band + 1)*bins {
+                        *level += (re[bin]*re[bin] + im[bin]*im[bin])/(WINDOW*WINDOW) as f64;
+                    }
+                }
+            }
+            let mut levels = [FLOOR_DB; BANDS];
+            for (level, power) in levels.iter_mut().zip(power.iter()) {
+                let mean = power/windows.len() as f64;
+                if mean > 0.0 {
+                    *level = (10.0*mean.log10()).max(FLOOR_DB);
+                }
+            }
+            levels
+        }).collect();
+        Fingerprint { seconds }
+    }
+
+    /// the fingerprint as text, a line of levels for each second
+    pub fn to_text(&self) -> String {
+        self.seconds.iter().map(|levels| {
+            let levels: Vec<String> = levels.iter().map(|level| format!("{:.1}", level)).collect();
+            levels.join(" ") + "\n"
+        }).collect()
+    }
+
+    /// read back what `to_text` wrote, or None if it isn't that
+    pub fn parse(text: &str) -> Option<Fingerprint> {
+        let seconds = text.lines().map(|line| {
+            let parsed: Vec<f64> = line.split_whitespace().map(|level| level.parse().ok()).collect::<Option<_>>()?;
+            if parsed.len() != BANDS {
+                return None;
+            }
+            let mut levels = [0.0; BANDS];
+            levels.copy_from_slice(&parsed);
+            Some(levels)
+        }).collect::<Option<_>>()?;
+        Some(Fingerprint { seconds })
+    }
+
+    /// Check this fingerprint against an `expected` one, every band within
+    /// `tolerance` dB, and say where it first differs if it doesn't match.
+    pub fn compare(&self, expected: &Fingerprint, tolerance: f64) -> Result<(), Mismatch> {
+        if self.seconds.len() != expected.seconds.len() {
+            return Err(Mismatch::Length { expected: expected.seconds.len(), got: self.seconds.len() });
+        }
+        for (second, (got, want)) in self.seconds.iter().zip(expected.seconds.iter()).enumerate() {
+            for band in 0..BANDS {
+                if (got[band] - want[band]).abs() > tolerance {
+                    let band_hz = band as f64*SAMPLE_RATE/2.0/BANDS as f64;
+                    return Err(Mismatch::Level { second, band: band_hz, expected: want[band], got: got[band] });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compare a render with the snapshot at `path`, every band within
+/// `tolerance` dB. If there's no snapshot yet, write this render's as the
+/// one to compare against from now on.
+pub fn check_snapshot(path: &Path, samples: &[f32], tolerance: f64) -> io::Result<Result<(), Mismatch>> {
+    let fingerprint = Fingerprint::of(samples);
+    if !path.exists() {
+        fs::write(path, fingerprint.to_text())?;
+        return Ok(Ok(()));
+    }
+    match Fingerprint::parse(&fs::read_to_string(path)?) {
+        Some(expected) => Ok(fingerprint.compare(&expected, tolerance)),
+        None => Err(io::Error::new(io::ErrorKind::InvalidData, "not a fingerprint")),
+    }
+}
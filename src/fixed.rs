@@ -0,0 +1,121 @@
+//! Fixed-point versions of the per-sample DSP, for chips with no FPU: the
+//! sine oscillator, the drum envelope and the bus mixer, in Q15 (16-bit
+//! numbers from -1 to 1) with the sums in 64 bits. The `fixed` feature puts
+//! them in place of the floating point ones.
+//!
+//! Only the work done for every sample of every thread is covered. Pitches,
+//! phases and timing stay floating point; they're cheap next to a `sin` and
+//! an `exp` per thread per sample, and keeping them lets a render take the
+//! same branches with and without the feature.
+
+/// 1 in Q15, which a Q15 sample can't quite reach
+pub const ONE: i32 = 1 << 15;
+
+/// the coefficients of the odd polynomial that gives sin(pi/2*z) for z from
+/// -1 to 1, in Q15
+const SINE_A: i32 = 51472;
+const SINE_B: i32 = 21024;
+const SINE_C: i32 = 2320;
+
+/// 2^(-k/16) for k from 0 to 16, in Q15
+const HALVINGS: [i32; 17] = [32768, 31379, 30048, 28774, 27554, 26386, 25268, 24196, 23170, 22188, 21247, 20347,
+                             19484, 18658, 17867, 17109, 16384];
+
+/// log2(e) in Q16
+const LOG2_E: i64 = 94548;
+
+/// `x` from -1 to 1 in Q15
+pub fn q15(x: f64) -> i32 {
+    (x*ONE as f64).round().clamp(-(ONE as f64), (ONE - 1) as f64) as i32
+}
+
+pub fn to_f32(x: i64) -> f32 {
+    x as f32/ONE as f32
+}
+
+/// two Q15 numbers multiplied
+pub fn mul(a: i32, b: i32) -> i32 {
+    ((a as i64*b as i64) >> 15) as i32
+}
+
+/// The sine of a phase from 0 to 1, in Q15.
+pub fn sine(phase: f64) -> i32 {
+    // a turn in 16 bits
+    let mut t = ((phase.fract()*65536.0) as i32) & 0xffff;
+    // fold onto the quarter turns either side of 0, where the polynomial works
+    if t >= 49152 {
+        t -= 65536;
+    } else if t >= 16384 {
+        t = 32768 - t;
+    }
+    let z = t << 1;
+    let z2 = mul(z, z);
+    let r = SINE_A - mul(z2, SINE_B - mul(z2, SINE_C));
+    mul(z, r).clamp(-ONE, ONE - 1)
+}
+
+/// e^-x for x of 0 or more, in Q15, as the halvings it is: whole ones are a
+/// shift and the rest come from a table. Past 16 it's too small for Q15
+/// anyway.
+pub fn decay(x: f64) -> i32 {
+    let halvings = ((x.clamp(0.0, 16.0)*65536.0) as i64*LOG2_E) >> 16;
+    let whole = halvings >> 16;
+    if whole >= 16 {
+        return 0;
+    }
+    // the part of a halving left over, in sixteenths and what's between them
+    let part = (halvings & 0xffff) as i32;
+    let (step, between) = ((part >> 12) as usize, part & 0xfff);
+    let from = HALVINGS[step];
+    let to = HALVINGS[step + 1];
+    let level = from + (((to - from)*between) >> 12);
+    level >> whole
+}
+
+/// A bus's share of the mix: its samples added up, turned up by `gain` and
+/// shared between the `voices` threads playing.
+pub fn mix_bus<I: Iterator<Item = f32>>(samples: I, gain: f64, voices: f64) -> f32 {
+    // each sample can stand for several threads, so it's only Q15 in that it
+    // counts in 2^-15ths
+    let sum: i64 = samples.map(|sample| (sample*ONE as f32).round() as i64).sum();
+    let gain = (gain*ONE as f64).round() as i64;
+    to_f32(((sum*gain) >> 15)/(voices.round() as i64).max(1))
+}
+
+#[cfg(all(test, feature = "fixed"))]
+mod tests {
+    use super::*;
+
+    /// -60 dBFS, about the level of the Q15 rounding and the polynomial's
+    /// error added up
+    const TOLERANCE: f64 = 0.001;
+
+    #[test]
+    fn sine_is_close_to_the_float_one() {
+        for i in 0..4096 {
+            let phase = i as f64/4096.0;
+            let expected = (phase*2.0*::std::f64::consts::PI).sin();
+            let error = (sine(phase) as f64/ONE as f64 - expected).abs();
+            assert!(error < TOLERANCE, "sine({}) is {} off", phase, error);
+        }
+    }
+
+    #[test]
+    fn decay_is_close_to_the_float_one() {
+        for i in 0..2000 {
+            let x = i as f64/100.0;
+            let error = (decay(x) as f64/ONE as f64 - (-x).exp()).abs();
+            assert!(error < TOLERANCE, "decay({}) is {} off", x, error);
+        }
+    }
+
+    #[test]
+    fn mix_bus_is_close_to_the_float_one() {
+        let samples: Vec<f32> = (0..8).map(|i| (i as f32*0.7).sin()*0.9).collect();
+        for &(gain, voices) in &[(1.0, 1.0), (0.5, 3.0), (0.8, 8.0)] {
+            let expected = samples.iter().map(|&sample| sample as f64).sum::<f64>()*gain/voices;
+            let error = (mix_bus(samples.iter().cloned(), gain, voices) as f64 - expected).abs();
+            assert!(error < TOLERANCE, "mixed at {} over {} voices it's {} off", gain, voices, error);
+        }
+    }
+}
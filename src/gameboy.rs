@@ -0,0 +1,142 @@
+//! The Game Boy's sound: two pulse channels, a wave channel that plays a
+//! 32-sample wavetable, and a noise channel.
+//!
+//! Pulses play the duty an `@duty` line asks for and the wave channel the
+//! samples of an `@wave` line. Pitches are rounded to what the 11-bit timers
+//! can play and volumes to 4 bits, except on the wave channel, which can only
+//! be full, half, a quarter or off. Every channel is a 4-bit number going into
+//! a DAC of its own, so a channel sits at one end of its range when it's
+//! silent but playing; the capacitor on the output takes that back out.
+//! Drums go to the noise channel and everything else to the pulses, then the
+//! wave channel.
+
+use std::f64::consts::PI;
+use renderer::SAMPLE_RATE;
+use voices::{Allocator, Voice, VoiceModel};
+
+/// what the pulse channels' timer counts, a 32nd of the CPU clock
+const PULSE_CLOCK: f64 = 131_072.0;
+/// the wave channel's timer runs half as fast for a whole wavetable
+const WAVE_CLOCK: f64 = 65_536.0;
+/// the noise channel's base clock, before its divider and shift
+const NOISE_CLOCK: f64 = 524_288.0;
+
+/// about where the output capacitor starts to cut
+const HIGH_PASS: f64 = 30.0;
+
+const PULSE1: usize = 0;
+const PULSE2: usize = 1;
+const WAVE: usize = 2;
+const NOISE: usize = 3;
+
+/// The pitch a timer driven by `clock` gets closest to `freq` with, or None
+/// if it's out of the timer's range.
+fn timer_freq(freq: f64, clock: f64) -> Option<f64> {
+    let timer = (2048.0 - clock/freq).round();
+    if !(0.0..2048.0).contains(&timer) {
+        None
+    } else {
+        Some(clock/(2048.0 - timer))
+    }
+}
+
+/// a gain from 0 to 1 as one of the 4-bit volumes
+fn volume(gain: f64) -> u8 {
+    (gain*15.0).round().clamp(0.0, 15.0) as u8
+}
+
+/// A channel's 4-bit output through its DAC, from -1 to 1.
+fn dac(level: u8) -> f64 {
+    1.0 - level as f64/7.5
+}
+
+pub struct GameBoy {
+    allocator: Allocator,
+    /// how far through its waveform each pulse channel and the wave channel
+    /// are
+    phases: [f64; 3],
+    /// the noise channel's shift register, and how far it is to the next
+    /// shift
+    lfsr: u16,
+    noise_phase: f64,
+    /// the filter's last input and output
+    last_in: f64,
+    last_out: f64,
+}
+
+impl GameBoy {
+    pub fn new() -> GameBoy {
+        GameBoy { allocator: Allocator::new(4), phases: [0.0; 3], lfsr: 0x7fff, noise_phase: 0.0, last_in: 0.0,
+                  last_out: 0.0 }
+    }
+
+    fn pulse(&mut self, channel: usize, voice: Voice) -> Option<u8> {
+        let freq = timer_freq(voice.freq, PULSE_CLOCK)?;
+        self.phases[channel] = (self.phases[channel] + freq/SAMPLE_RATE).fract();
+        // the duty is one of four, in eighths of the cycle
+        let high = ((self.phases[channel]*8.0) as u8) < (voice.patch.duty*8.0) as u8;
+        Some(if high { volume(voice.gain) } else { 0 })
+    }
+
+    fn wave(&mut self, voice: Voice) -> Option<u8> {
+        let freq = timer_freq(voice.freq, WAVE_CLOCK)?;
+        self.phases[WAVE] = (self.phases[WAVE] + freq/SAMPLE_RATE).fract();
+        let sample = voice.patch.wave[(self.phases[WAVE]*32.0) as usize % 32];
+        // the only volumes it has are shifts
+        let shift = match voice.gain {
+            g if g >= 0.75 => 0,
+            g if g >= 0.375 => 1,
+            g if g >= 0.125 => 2,
+            _ => 4,
+        };
+        Some(sample >> shift)
+    }
+
+    fn noise(&mut self, voice: Voice) -> Option<u8> {
+        // higher drums get a faster noise, by the same ratio as the NES's
+        let rate = voice.freq*100.0;
+        // the clock is divided by 1 to 7 (0 standing for a half) and then
+        // halved 1 to 14 more times
+        let speed = (0..7).flat_map(|r| (0..14).map(move |s| (r, s)))
+            .map(|(r, s)| NOISE_CLOCK/(if r == 0 { 0.5 } else { r as f64 })/2f64.powi(s + 1))
+            .min_by(|a, b| (a/rate).ln().abs().partial_cmp(&(b/rate).ln().abs()).unwrap())
+            .unwrap();
+        self.noise_phase += speed/SAMPLE_RATE;
+        while self.noise_phase >= 1.0 {
+            self.noise_phase -= 1.0;
+            let feedback = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+            self.lfsr = (self.lfsr >> 1) | (feedback << 14);
+        }
+        Some(if self.lfsr & 1 == 1 { 0 } else { volume(voice.gain) })
+    }
+}
+
+impl Default for GameBoy {
+    fn default() -> GameBoy {
+        GameBoy::new()
+    }
+}
+
+impl VoiceModel for GameBoy {
+    fn next_sample(&mut self, voices: &[Voice]) -> f64 {
+        let channels = self.allocator.assign(voices, |channel, voice| (channel == NOISE) == voice.drum);
+        let mut out = 0.0;
+        for (channel, voice) in channels.into_iter().enumerate() {
+            let level = voice.and_then(|voice| match channel {
+                PULSE1 | PULSE2 => self.pulse(channel, voice),
+                WAVE => self.wave(voice),
+                _ => self.noise(voice),
+            });
+            // a channel with nothing to play has its DAC off, which is 0
+            // rather than the top of its range
+            if let Some(level) = level {
+                out += dac(level)/4.0;
+            }
+        }
+        let rc = 1.0/(2.0*PI*HIGH_PASS);
+        let alpha = rc/(rc + 1.0/SAMPLE_RATE);
+        self.last_out = alpha*(self.last_out + out - self.last_in);
+        self.last_in = out;
+        self.last_out
+    }
+}
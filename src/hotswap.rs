@@ -0,0 +1,38 @@
+//! Carrying threads over from one version of a program to the next.
+//!
+//! A thread is identified by the label it's under (the closest one before its
+//! pc) and how far past that label it is. If the new version still has the
+//! label the thread continues at the same offset, or from the label itself if
+//! that block got shorter. Code before the first label counts as being under
+//! an unnamed label at the very start.
+
+use std::collections::HashMap;
+
+/// The block a pc falls in: the start of it and the labels naming it.
+fn enclosing_block(pc: usize, labels: &HashMap<String, usize>) -> (usize, Vec<&str>) {
+    let start = labels.values().cloned().filter(|&at| at <= pc).max().unwrap_or(0);
+    let mut names: Vec<&str> = labels.iter()
+        .filter(|&(_, &at)| at == start)
+        .map(|(name, _)| name.as_str())
+        .collect();
+    names.sort();
+    (start, names)
+}
+
+/// Where a thread at `pc` in the old program carries on in the new one, or
+/// None if its label is gone.
+pub fn migrate_pc(pc: usize, old_labels: &HashMap<String, usize>, new_labels: &HashMap<String, usize>, new_len: usize) -> Option<usize> {
+    let (old_start, names) = enclosing_block(pc, old_labels);
+    let new_start = if names.is_empty() {
+        // before any label
+        0
+    } else {
+        match names.iter().filter_map(|name| new_labels.get(*name)).next() {
+            Some(&at) => at,
+            None => return None,
+        }
+    };
+    let new_end = new_labels.values().cloned().filter(|&at| at > new_start).min().unwrap_or(new_len);
+    let new_pc = new_start + (pc - old_start);
+    Some(if new_pc < new_end { new_pc } else { new_start })
+}
@@ -0,0 +1,84 @@
+//! Ctrl-C, caught so a render stopped partway through still ends properly:
+//! the sound fades out over `FADE` instead of clicking off, and the output
+//! is finished, so a WAV file's header says how long it really is. Pressing
+//! Ctrl-C again stops it straight away, the usual way.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use renderer::SAMPLE_RATE;
+
+/// how long the fade-out takes, in seconds
+pub const FADE: f64 = 0.05;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+mod os {
+    use std::sync::atomic::Ordering;
+
+    const SIGINT: i32 = 2;
+    /// `SIG_DFL`, what a signal does when nothing catches it
+    const DEFAULT: usize = 0;
+
+    extern "C" {
+        fn signal(signum: i32, handler: usize) -> usize;
+    }
+
+    extern "C" fn on_interrupt(_: i32) {
+        super::INTERRUPTED.store(true, Ordering::SeqCst);
+        // so the next one isn't caught
+        unsafe { signal(SIGINT, DEFAULT) };
+    }
+
+    pub fn catch() {
+        unsafe { signal(SIGINT, on_interrupt as *const () as usize) };
+    }
+}
+
+#[cfg(not(unix))]
+mod os {
+    pub fn catch() {}
+}
+
+/// Catch the next Ctrl-C instead of dying of it, for `interrupted` to see.
+pub fn catch() {
+    os::catch();
+}
+
+/// whether Ctrl-C has been pressed since `catch`
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Turns the output down to nothing over `FADE` once Ctrl-C is pressed.
+pub struct FadeOut {
+    channels: usize,
+    /// the frames of the fade still to go
+    left: usize,
+}
+
+impl FadeOut {
+    /// A fade for blocks of `channels` interleaved channels.
+    pub fn new(channels: usize) -> FadeOut {
+        FadeOut { channels, left: FadeOut::frames() }
+    }
+
+    fn frames() -> usize {
+        (FADE*SAMPLE_RATE) as usize
+    }
+
+    /// Fade `block` if Ctrl-C has been pressed, and return whether it's
+    /// faded out all the way, so it's the last block to write.
+    pub fn apply(&mut self, block: &mut [f32]) -> bool {
+        if !interrupted() {
+            return false;
+        }
+        for frame in block.chunks_mut(self.channels) {
+            let gain = self.left as f32/FadeOut::frames() as f32;
+            for sample in frame {
+                *sample *= gain;
+            }
+            self.left = self.left.saturating_sub(1);
+        }
+        self.left == 0
+    }
+}
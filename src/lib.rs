@@ -0,0 +1,71 @@
+extern crate rand;
+
+pub mod abc;
+pub mod ast;
+pub mod audio_diff;
+pub mod audio_io;
+pub mod audition;
+pub mod audit;
+pub mod beep;
+pub mod ca;
+pub mod chain;
+pub mod checkpoint;
+pub mod click;
+pub mod compiler;
+pub mod control;
+pub mod crossfade;
+pub mod debugger;
+pub mod dist;
+pub mod duck;
+pub mod emit;
+pub mod ensemble;
+pub mod euclid;
+pub mod explain;
+pub mod export;
+pub mod fft;
+pub mod fingerprint;
+#[cfg(feature = "fixed")]
+pub mod fixed;
+pub mod gameboy;
+pub mod hotswap;
+pub mod interrupt;
+pub mod lint;
+pub mod markov;
+pub mod midi_clock;
+pub mod mixer;
+pub mod mml;
+pub mod nes;
+pub mod notebook;
+pub mod piano_roll;
+pub mod playback;
+pub mod playground;
+pub mod plugin;
+pub mod remote;
+pub mod renderer;
+pub mod route;
+pub mod rtttl;
+pub mod session;
+pub mod sid;
+pub mod signal;
+pub mod sink;
+pub mod smooth;
+pub mod source;
+pub mod stats;
+pub mod tempo;
+pub mod term;
+pub mod tracker;
+pub mod tuning;
+pub mod ugen;
+pub mod vocoder;
+pub mod voices;
+pub mod watch;
+#[cfg(feature = "async")]
+pub mod stream;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use audio_io::{load_wav, WavSink};
+pub use compiler::{compile, label_table, voice_table, CompileError, CompileWarning, Dur, Instruction, Program, Span};
+pub use renderer::{simulate, Branch, EventLog, Renderer, RuntimeError, Steal, ThreadContext, SAMPLE_RATE};
+pub use sink::{PcmSink, Sample, Sink};
+pub use source::{BlipSource, PcmReader};
@@ -0,0 +1,217 @@
+//! Things about a program that compile fine but are probably mistakes, for
+//! `blip-machine lint`. Each rule has a level, to leave it out, warn about it or
+//! fail on it, and some have a limit, all set by a lint table:
+//!
+//! ```text
+//! dead-branch deny 0.0001
+//! high-note warn 3000
+//! fork-growth allow
+//! ```
+//!
+//! Rules the table doesn't mention keep their defaults. A line `@allow
+//! dead-branch high-note` leaves those rules out for the line after it.
+
+use std::collections::HashMap;
+use compiler::{Instruction, Program, Span};
+use compiler::Instruction::*;
+use renderer::SAMPLE_RATE;
+
+/// what a rule does when it finds something
+#[derive(Clone, Copy, PartialEq)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+impl Level {
+    fn parse(text: &str) -> Option<Level> {
+        match text {
+            "allow" => Some(Level::Allow),
+            "warn" => Some(Level::Warn),
+            "deny" => Some(Level::Deny),
+            _ => None,
+        }
+    }
+}
+
+/// every rule, with its level and limit if the table doesn't say
+static RULES: &[(&str, Level, Option<f64>)] = &[
+    // a branch taken this rarely might as well not be there
+    ("dead-branch", Level::Warn, Some(0.001)),
+    // a fork that both threads come back to, so each time round there are
+    // more of them
+    ("fork-growth", Level::Warn, None),
+    // a note this high, which at 8 kHz folds back down to somewhere else
+    ("high-note", Level::Warn, Some(5000.0)),
+];
+
+/// Each rule's level and limit.
+pub struct Table {
+    rules: HashMap<&'static str, (Level, Option<f64>)>,
+}
+
+impl Default for Table {
+    fn default() -> Table {
+        Table { rules: RULES.iter().map(|&(name, level, limit)| (name, (level, limit))).collect() }
+    }
+}
+
+impl Table {
+    /// A table of `rule level [limit]` lines, or what's wrong with it.
+    pub fn parse(text: &str) -> Result<Table, String> {
+        let mut table = Table::default();
+        for (i, line) in text.lines().enumerate() {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.is_empty() {
+                continue;
+            }
+            let (name, default) = match RULES.iter().find(|&&(name, _, _)| name == words[0]) {
+                Some(&(name, _, limit)) => (name, limit),
+                None => return Err(format!("line {}: no rule called {}", i + 1, words[0])),
+            };
+            let level = match words.get(1).and_then(|level| Level::parse(level)) {
+                Some(level) => level,
+                None => return Err(format!("line {}: expected allow, warn or deny", i + 1)),
+            };
+            let limit = match (&words[2..], default) {
+                ([], _) => default,
+                ([limit], Some(_)) => match limit.parse::<f64>() {
+                    Ok(limit) if limit.is_finite() => Some(limit),
+                    _ => return Err(format!("line {}: expected a number", i + 1)),
+                },
+                (_, Some(_)) => return Err(format!("line {}: {} takes one limit", i + 1, name)),
+                (_, None) => return Err(format!("line {}: {} doesn't take a limit", i + 1, name)),
+            };
+            table.rules.insert(name, (level, limit));
+        }
+        Ok(table)
+    }
+
+    fn level(&self, rule: &str) -> Level {
+        self.rules[rule].0
+    }
+
+    fn limit(&self, rule: &str) -> f64 {
+        self.rules[rule].1.unwrap()
+    }
+}
+
+/// Something a rule found.
+pub struct Lint {
+    pub rule: &'static str,
+    pub level: Level,
+    pub at: Span,
+    pub message: String,
+}
+
+/// the instructions a thread at `pc` can go on to, and start threads at
+fn successors(instructions: &[Instruction], pc: usize) -> Vec<usize> {
+    let next = Some(pc + 1).filter(|&next| next < instructions.len());
+    let mut to: Vec<usize> = match instructions[pc] {
+        PJump(p, line) | QJump(p, line, _) if p >= 1.0 => vec![line],
+        PJump(p, line) | QJump(p, line, _) | PFork(p, line) | QFork(p, line, _) if p > 0.0 => {
+            next.into_iter().chain(Some(line)).collect()
+        },
+        Euclid(_, _, note) => next.into_iter().chain(Some(note)).collect(),
+        Ca(_, width, _, _, first) => next.into_iter().chain((0..width).map(|cell| first + 2*cell)).collect(),
+        Terminate => vec![],
+        _ => next.into_iter().collect(),
+    };
+    to.retain(|&pc| pc < instructions.len());
+    to
+}
+
+/// whether a thread at `from` can get to `to`
+fn reaches(instructions: &[Instruction], from: usize, to: usize) -> bool {
+    let mut seen = vec![false; instructions.len()];
+    let mut pending = vec![from];
+    while let Some(pc) = pending.pop() {
+        if pc == to {
+            return true;
+        }
+        if !seen[pc] {
+            seen[pc] = true;
+            pending.extend(successors(instructions, pc));
+        }
+    }
+    false
+}
+
+/// where a pitch ends up once it's sampled at `SAMPLE_RATE`
+fn aliased(freq: f64) -> f64 {
+    let folded = freq.abs() % SAMPLE_RATE;
+    folded.min(SAMPLE_RATE - folded)
+}
+
+/// the pitches an instruction starts out playing
+fn pitches(instruction: &Instruction) -> Vec<f64> {
+    match *instruction {
+        Sin(freq, _) | Walk(freq, _, _, _, _) => vec![freq],
+        Sweep(from, to, _, _) | Zap(from, to, _, _) => vec![from, to],
+        Play(ref chain, _) => chain.pitches.clone(),
+        _ => vec![],
+    }
+}
+
+/// the rules each line says to leave out of the line after it
+fn allowed(text: &str) -> HashMap<usize, Vec<&str>> {
+    let mut allowed = HashMap::new();
+    for (i, line) in text.lines().enumerate() {
+        let mut words = line.split_whitespace();
+        if words.next() == Some("@allow") {
+            // lines count from 1, so the next one is i + 2
+            allowed.insert(i + 2, words.collect());
+        }
+    }
+    allowed
+}
+
+/// Everything wrong with `program`, compiled from `text`, that `table`
+/// doesn't allow, in the order of the lines it's on.
+pub fn lint(text: &str, program: &Program, table: &Table) -> Vec<Lint> {
+    let instructions = &program.instructions;
+    let annealed: Vec<usize> = program.anneals.iter().flat_map(|anneal| anneal.pcs.iter().cloned())
+        .chain(program.listens.iter().flat_map(|listen| listen.pcs.iter().cloned())).collect();
+    let mut found = vec![];
+    for (pc, instruction) in instructions.iter().enumerate() {
+        let at = match program.spans.get(pc).cloned().unwrap_or(None) {
+            Some(at) => at,
+            None => continue,
+        };
+        match *instruction {
+            // an annealed or listening branch gets another probability as it plays
+            PJump(p, _) | PFork(p, _) | QJump(p, _, _) | QFork(p, _, _)
+                if p > 0.0 && p < table.limit("dead-branch") && !annealed.contains(&pc) => {
+                found.push(("dead-branch", at, format!("a probability of {} is so low the branch is all but never \
+                                                        taken", p)));
+            },
+            _ => {},
+        }
+        if let PFork(p, line) | QFork(p, line, _) = *instruction {
+            if p > 0.0 && pc + 1 < instructions.len() && reaches(instructions, line, pc)
+               && reaches(instructions, pc + 1, pc) {
+                found.push(("fork-growth", at, format!("both threads come back to this fork, so there are about {} \
+                                                        times as many each time round", 1.0 + p)));
+            }
+        }
+        let high = table.limit("high-note");
+        if let Some(freq) = pitches(instruction).into_iter().find(|&freq| freq.abs() > high) {
+            found.push(("high-note", at, format!("{} Hz is above {} Hz, and at {} Hz it plays as {} Hz", freq, high,
+                                                 SAMPLE_RATE, aliased(freq))));
+        }
+    }
+    let allowed = allowed(text);
+    let mut lints: Vec<Lint> = vec![];
+    for (rule, at, message) in found {
+        let level = table.level(rule);
+        let suppressed = allowed.get(&at.line).is_some_and(|rules| rules.contains(&rule));
+        // a line compiled to several instructions, like a spawn, says it once
+        let said = lints.iter().any(|lint| lint.rule == rule && lint.at.line == at.line && lint.message == message);
+        if level != Level::Allow && !suppressed && !said {
+            lints.push(Lint { rule, level, at, message });
+        }
+    }
+    lints.sort_by_key(|lint| (lint.at.line, lint.at.start));
+    lints
+}
@@ -1,11 +1,44 @@
-#![feature(slice_patterns)]
-#![feature(iter_arith)]
-extern crate rand;
-use std::io::Read;
+extern crate blip_machine;
+use std::io::{BufRead, Read};
 use std::io::Write;
-use std::collections::HashMap;
-use rand::{Rng, SeedableRng, StdRng};
-use rand::distributions::{IndependentSample, Range};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use blip_machine::{compile, label_table, voice_table, CompileError, CompileWarning, PcmSink, Renderer, RuntimeError,
+                   Sink, Span, ThreadContext, WavSink, load_wav, SAMPLE_RATE};
+use blip_machine::{abc, mml, rtttl};
+use blip_machine::audio_diff::{diff, difference};
+use blip_machine::audition::{audition, Take};
+use blip_machine::beep::{Speaker, PC_SPEAKER};
+use blip_machine::chain::{Chain, Transition};
+use blip_machine::checkpoint::Checkpoint;
+use blip_machine::click::{mix_click, Click};
+use blip_machine::control::Control;
+use blip_machine::remote::{self, Access};
+use blip_machine::debugger::{debug, parse_breakpoint};
+use blip_machine::ensemble::{Ensemble, Mixdown};
+use blip_machine::emit::EventFile;
+use blip_machine::explain::Timeline;
+use blip_machine::interrupt::{self, FadeOut};
+use blip_machine::compiler::parse_db;
+use blip_machine::export::{export, Target};
+use blip_machine::renderer::{Steal, MAX_TRANSITIONS};
+use blip_machine::lint::{lint, Level, Table};
+use blip_machine::midi_clock::MidiClock;
+use blip_machine::mixer::mix;
+use blip_machine::piano_roll;
+use blip_machine::plugin;
+use blip_machine::renderer::simulate;
+use blip_machine::tempo::SharedTempo;
+use blip_machine::playback::{list_devices, DeviceSink};
+use blip_machine::route::{parse_route, Router, LEAD};
+use blip_machine::session::{engine, Session};
+use blip_machine::smooth::parse_ramp;
+use blip_machine::stats::AudioStats;
+use blip_machine::term::{Pacer, Tty};
+use blip_machine::tracker::{write_mod, TooLong};
+use blip_machine::vocoder::Vocoded;
+use blip_machine::voices::voice_model;
+use blip_machine::watch::{watch, WatchConfig, WatchEvent};
 
 macro_rules! println_stderr(
     ($($arg:tt)*) => (
@@ -16,245 +49,2089 @@ macro_rules! println_stderr(
     )
 );
 
-static PROGRAM_NAME: &'static str = "blip-machine";
-
-enum CompileError {
-    Syntax(usize),
-    Lbl(usize),
-    Prob(usize),
-    Num(usize),
-}
-
-/// Takes in a textual program and converts it to a list of instructions.
-/// This can later be interpreted by the magic virtual sound machine.
-fn compile(text: &str) -> Result<Vec<Instruction>, Vec<CompileError>> {
-    let mut instructions = vec![];
-    let mut errors = vec![];
-
-    let mut lbls = HashMap::new();
-    let mut ctr = 0;
-    for line in text.lines() {
-        let splt: Vec<&str> = line.split(" ").collect();
-        if let ["lbl", ident] = splt.as_ref() {
-            lbls.insert(ident, ctr);
-        } else {
-            // line == 1: blank line, ignored
-            if splt.len() > 1 {
-                ctr += 1
-            }
-        }
-    }
+static PROGRAM_NAME: &str = "blip-machine";
 
-    for (i, line) in text.lines().enumerate() {
-        let splt: Vec<&str> = line.split(" ").collect();
-        match splt.as_ref() {
-            ["lbl", _] => {},
-            ["sin", freq, dur] => {
-                let freqf: f64 = match freq.parse() {
-                    Ok(num) => num,
-                    _ => { errors.push(CompileError::Num(i)); continue; },
-                };
-                let durf: f64 = match dur.parse() {
-                    Ok(num) => num,
-                    _ => { errors.push(CompileError::Num(i)); continue; },
-                };
-                instructions.push(Sin(freqf, durf));
+// counts allocations, so the renderer can assert it doesn't make any
+#[cfg(feature = "audit-alloc")]
+#[global_allocator]
+static ALLOCATOR: blip_machine::audit::CountingAllocator = blip_machine::audit::CountingAllocator;
+
+static BLOCK_SIZE: usize = 256;
+
+static USAGE: &str = "usage: blip-machine [-o out.wav | --device <name> | --pc-speaker] [--midi-clock <dev>]
+                    [--click | --click-out click.wav] [--strict-audio] [--strict-repro]
+                    [--dry-run] [--dedup] [--record-session <dir>] [--lang <blip | mml>]
+                    [--voice-model <model>] [--stretch <factor>] [--pitch <+n>st]
+                    [--control <fifo | ->] [--ramp <secs>] [--control-port <port> [--control-bind <addr>]
+                    [--control-token <token>] [--control-origin <origin>]]
+                    [--explain <file | ->] [--emit events.json]
+                    [--max-threads <n> [--steal <oldest | quietest | deepest>]]
+                    [--checkpoint <every>] [--route <bus>=<device>...] [program.txt]
+       blip-machine --resume out.wav.checkpoint
+       blip-machine --chain [--transition <cut | crossfade:<secs> | wait-for-silence>] [--each <every>]
+                    [-o out.wav | --device <name>] [--dedup] [--voice-model <model>] a.txt b.txt...
+       blip-machine batch --seeds <first..last> -o out/{seed}.wav [--dedup] [--voice-model <model>]
+                          [--click] [--strict-audio] [--strict-repro] [--stretch <factor>]
+                          [--pitch <+n>st] [--emit out/{seed}.json] [program.txt]
+       blip-machine search --seeds <first..last> [--min-duration <secs>] [--max-duration <secs>]
+                           [--max-peak <level>dB] [--dedup] [--voice-model <model>] [program.txt]
+       blip-machine audition --seeds <count | first..last> [--excerpt <secs>] [--device <name>] [--dedup]
+                             [--voice-model <model>] [program.txt]
+       blip-machine replay [-o out.wav | --device <name> | --pc-speaker] [--dry-run] [--strict-repro]
+                           [--control <fifo>] [--ramp <secs>] [--control-port <port> [--control-bind <addr>]
+                           [--control-token <token>] [--control-origin <origin>]]
+                           [--explain <file | ->] [--emit events.json] <dir>
+       blip-machine render --piano-roll out.svg [--seeds <first..last>] [program.txt]
+       blip-machine export --target <supercollider | csound | sonic-pi | mod> [-o out] [program.txt]
+       blip-machine import-abc [-o program.txt] [tune.abc]
+       blip-machine import-rtttl [-o program.txt] [ringtone.txt]
+       blip-machine lint [--table lints.txt] [program.txt]
+       blip-machine watch [--device <name>] [--dedup] [--midi-clock <dev>] [--crossfade <seconds>] program.txt
+       blip-machine debug [--device <name> | --pc-speaker] [--dedup] [--voice-model <model>]
+                          [--break <condition>]... [program.txt]
+       blip-machine mix [--device <name>] [program.txt]
+       blip-machine live [-o out.wav | --device <name>] [--dedup] [--midi-clock <dev>] [program.txt]
+       blip-machine session [-o out.wav | --device <name>] session.toml
+       blip-machine bench [--dedup] program.txt
+       blip-machine diff [-o difference.wav] a.wav b.wav
+       blip-machine devices
+any of these can start with --plugin <lib.so>, more than once, to load instructions from a library";
+
+struct Options {
+    /// read the program from here instead of stdin
+    input: Option<String>,
+    /// write a WAV file instead of raw PCM to stdout
+    output: Option<String>,
+    /// play on this sound card instead of writing to stdout
+    device: Option<String>,
+    /// seconds to crossfade over when a reload can't keep any threads
+    crossfade: f64,
+    /// raw MIDI device to take the tempo from
+    midi_clock: Option<String>,
+    /// mix a metronome into the output
+    click: bool,
+    /// write a metronome to its own WAV file
+    click_out: Option<String>,
+    /// fail if the output clipped or was silent
+    strict_audio: bool,
+    /// refuse anything that could keep a render from coming out the same
+    /// bit for bit every time
+    strict_repro: bool,
+    /// list the notes the program would play instead of playing them
+    dry_run: bool,
+    /// merge threads in identical states
+    dedup: bool,
+    /// save the render to this directory so it can be replayed exactly
+    record_session: Option<String>,
+    /// what to export to
+    target: Option<Target>,
+    /// export a tracker module instead
+    module: bool,
+    /// read the program as MML rather than blip
+    mml: bool,
+    /// play through this sound chip's channels
+    voice_model: Option<String>,
+    /// beep the tune on the PC speaker instead of the sound card
+    pc_speaker: bool,
+    /// the first and last seed for batch to render
+    seeds: Option<(usize, usize)>,
+    /// how many times longer to make the render, and how many semitones to
+    /// shift it by, after it's done
+    stretch: f64,
+    pitch: f64,
+    /// the FIFO, or `-` for stdin, to take commands from as the program
+    /// plays
+    control: Option<String>,
+    /// the port to take commands over HTTP on, the address to take them on
+    /// if it isn't only this machine's, the token requests have to carry,
+    /// and the origin whose web pages may send them
+    control_port: Option<u16>,
+    control_bind: Option<IpAddr>,
+    control_token: Option<String>,
+    control_origin: Option<String>,
+    /// how many seconds a gain or tempo set by a command glides in over
+    ramp: Option<f64>,
+    /// where to write a timeline of the branches the render took, `-` for
+    /// stderr
+    explain: Option<String>,
+    /// where to write every note the render played, as JSON
+    emit: Option<String>,
+    /// where to draw them
+    piano_roll: Option<String>,
+    /// the most threads to play at once, and which to stop to stay under it
+    max_threads: Option<usize>,
+    steal: Steal,
+    /// how many seconds of output to save a checkpoint after
+    checkpoint: Option<f64>,
+    /// a checkpoint to carry on a render from
+    resume: Option<String>,
+    /// conditions for the debugger to pause on
+    breaks: Vec<String>,
+    /// buses to play on devices of their own, each by name
+    route: Vec<(String, String)>,
+    /// play `input` and then each of `then`, see `chain`
+    chain: bool,
+    then: Vec<String>,
+    /// how each program in a chain hands over to the next, and the most
+    /// seconds each plays for
+    transition: Option<Transition>,
+    each: Option<f64>,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options { input: None, output: None, device: None, crossfade: 1.0, midi_clock: None,
+                               click: false, click_out: None, strict_audio: false, strict_repro: false, dry_run: false,
+                               dedup: false, record_session: None, target: None,
+                               module: false, mml: false, voice_model: None, pc_speaker: false,
+                               seeds: None, stretch: 1.0, pitch: 0.0,
+                               control: None, control_port: None, control_bind: None,
+                               control_token: None, control_origin: None, ramp: None, explain: None, emit: None,
+                               piano_roll: None, max_threads: None,
+                               steal: Steal::Oldest, checkpoint: None, resume: None, breaks: vec![],
+                               route: vec![], chain: false, then: vec![], transition: None, each: None };
+    // MML is picked by the file's extension unless --lang says otherwise
+    let mut lang = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => match args.next() {
+                Some(path) => options.output = Some(path.clone()),
+                None => return Err(format!("{} needs a file name", arg)),
             },
-            ["pjump", lbl, prob] => {
-                let linenum = match lbls.get(&lbl) {
-                    Some(num) => *num,
-                    None => { errors.push(CompileError::Lbl(i)); continue; },
-                };
-                let probf: f64 = match prob.parse() {
-                    Ok(num) => { 
-                        if 0.0 <= num && num <= 1.0 {
-                            num
-                        } else {
-                            errors.push(CompileError::Prob(i));
-                            continue;
-                        }
-                    }
-                    Err(_) => { errors.push(CompileError::Num(i)); continue; }
-                };
-                instructions.push(PJump(probf, linenum as usize));
+            "--device" => match args.next() {
+                Some(name) => options.device = Some(name.clone()),
+                None => return Err(format!("{} needs a device name", arg)),
             },
-            ["pfork", lbl, prob] => {
-                let linenum = match lbls.get(&lbl) {
-                    Some(num) => *num,
-                    None => { errors.push(CompileError::Lbl(i)); continue; },
-                };
-                let probf: f64 = match prob.parse() {
-                    Ok(num) => { 
-                        if 0.0 <= num && num <= 1.0 {
-                            num
-                        } else {
-                            errors.push(CompileError::Prob(i));
-                            continue;
-                        }
-                    }
-                    Err(_) => { errors.push(CompileError::Num(i)); continue; }
-                };
-                instructions.push(PFork(probf, linenum as usize));
+            "--midi-clock" => match args.next() {
+                Some(device) => options.midi_clock = Some(device.clone()),
+                None => return Err(format!("{} needs a MIDI device", arg)),
+            },
+            "--click" => options.click = true,
+            "--strict-audio" => options.strict_audio = true,
+            "--strict-repro" => options.strict_repro = true,
+            "--dry-run" => options.dry_run = true,
+            "--dedup" => options.dedup = true,
+            "--click-out" => match args.next() {
+                Some(path) => options.click_out = Some(path.clone()),
+                None => return Err(format!("{} needs a file name", arg)),
+            },
+            "--record-session" => match args.next() {
+                Some(dir) => options.record_session = Some(dir.clone()),
+                None => return Err(format!("{} needs a directory", arg)),
+            },
+            "--target" => match args.next().map(|name| name.as_str()) {
+                Some("mod") => options.module = true,
+                Some(name) if Target::from_name(name).is_some() => options.target = Target::from_name(name),
+                _ => return Err(format!("{} needs supercollider, csound, sonic-pi or mod", arg)),
+            },
+            "--lang" => match args.next().map(|lang| lang.as_str()) {
+                Some("blip") => lang = Some(false),
+                Some("mml") => lang = Some(true),
+                _ => return Err(format!("{} needs blip or mml", arg)),
+            },
+            "--voice-model" => match args.next() {
+                Some(name) if voice_model(name).is_some() => options.voice_model = Some(name.clone()),
+                _ => return Err(format!("{} needs nes, gameboy, sid or beep", arg)),
+            },
+            "--pc-speaker" => {
+                options.pc_speaker = true;
+                options.voice_model = Some("beep".to_string());
+            },
+            "--stretch" => match args.next().map(|factor| factor.parse::<f64>()) {
+                Some(Ok(factor)) if factor > 0.0 => options.stretch = factor,
+                _ => return Err(format!("{} needs a positive number", arg)),
+            },
+            "--pitch" => match args.next().map(|shift| shift.strip_suffix("st").unwrap_or(shift).parse::<f64>()) {
+                Some(Ok(semitones)) => options.pitch = semitones,
+                _ => return Err(format!("{} needs a number of semitones, like +3st", arg)),
+            },
+            "--control" => match args.next() {
+                Some(path) => options.control = Some(path.clone()),
+                None => return Err(format!("{} needs a FIFO, or - for stdin", arg)),
+            },
+            "--control-port" => match args.next().map(|port| port.parse::<u16>()) {
+                Some(Ok(port)) => options.control_port = Some(port),
+                _ => return Err(format!("{} needs a port number", arg)),
+            },
+            "--control-bind" => match args.next().map(|addr| addr.parse::<IpAddr>()) {
+                Some(Ok(addr)) => options.control_bind = Some(addr),
+                _ => return Err(format!("{} needs an IP address, like 0.0.0.0", arg)),
+            },
+            "--control-token" => match args.next() {
+                Some(token) if !token.is_empty() => options.control_token = Some(token.clone()),
+                _ => return Err(format!("{} needs a token", arg)),
+            },
+            "--control-origin" => match args.next() {
+                Some(origin) => options.control_origin = Some(origin.clone()),
+                None => return Err(format!("{} needs an origin, like https://panel.example", arg)),
+            },
+            "--ramp" => match args.next().and_then(|ramp| parse_ramp(ramp)) {
+                Some(secs) => options.ramp = Some(secs),
+                _ => return Err(format!("{} needs a length of time, like 2s or 50ms", arg)),
             },
-            _ => {
-                // blank line
-                if splt.len() == 1 && splt[0] == "" {
-                    continue;
+            "--explain" => match args.next() {
+                Some(path) => options.explain = Some(path.clone()),
+                None => return Err(format!("{} needs a file name, or - for stderr", arg)),
+            },
+            "--emit" => match args.next() {
+                Some(path) => options.emit = Some(path.clone()),
+                None => return Err(format!("{} needs a file name", arg)),
+            },
+            "--piano-roll" => match args.next() {
+                Some(path) => options.piano_roll = Some(path.clone()),
+                None => return Err(format!("{} needs a file name", arg)),
+            },
+            "--max-threads" => match args.next().map(|max| max.parse::<usize>()) {
+                Some(Ok(max)) if max > 0 => options.max_threads = Some(max),
+                _ => return Err(format!("{} needs a number of threads", arg)),
+            },
+            "--checkpoint" => match args.next().and_then(|every| parse_every(every)) {
+                Some(secs) => options.checkpoint = Some(secs),
+                None => return Err(format!("{} needs how often, like 60s or 10m", arg)),
+            },
+            "--resume" => match args.next() {
+                Some(path) => options.resume = Some(path.clone()),
+                None => return Err(format!("{} needs a checkpoint", arg)),
+            },
+            // any number of bus=device, one after the other
+            "--route" => {
+                match args.next().and_then(|route| parse_route(route)) {
+                    Some((bus, device)) => options.route.push((bus.to_string(), device.to_string())),
+                    None => return Err(format!("{} needs a bus and a device, like drums=hw:1", arg)),
                 }
-                // unknown line
-                errors.push(CompileError::Syntax(i));
+                while let Some((bus, device)) = args.as_slice().first()
+                        .filter(|next| !next.starts_with('-')).and_then(|next| parse_route(next)) {
+                    options.route.push((bus.to_string(), device.to_string()));
+                    args.next();
+                }
+            },
+            "--break" => match args.next() {
+                Some(condition) => options.breaks.push(condition.clone()),
+                None => return Err(format!("{} needs a condition, like \"threads > 50\" or \"label chorus\"", arg)),
+            },
+            "--steal" => match args.next().and_then(|name| Steal::parse(name)) {
+                Some(steal) => options.steal = steal,
+                None => return Err(format!("{} needs oldest, quietest or deepest", arg)),
+            },
+            "--seeds" => match args.next().and_then(|seeds| parse_seeds(seeds)) {
+                Some(seeds) => options.seeds = Some(seeds),
+                None => return Err(format!("{} needs a seed or a range like 1..100", arg)),
+            },
+            "--crossfade" => match args.next().map(|secs| secs.parse::<f64>()) {
+                Some(Ok(secs)) if secs >= 0.0 => options.crossfade = secs,
+                _ => return Err(format!("{} needs a number of seconds", arg)),
+            },
+            "--chain" => options.chain = true,
+            "--transition" => match args.next().and_then(|transition| Transition::parse(transition)) {
+                Some(transition) => options.transition = Some(transition),
+                None => return Err(format!("{} needs cut, crossfade:<secs> or wait-for-silence", arg)),
+            },
+            "--each" => match args.next().and_then(|each| parse_every(each)) {
+                Some(secs) => options.each = Some(secs),
+                None => return Err(format!("{} needs a length of time, like 90s or 5m", arg)),
+            },
+            _ if !arg.starts_with('-') && options.input.is_none() => {
+                options.input = Some(arg.clone());
+            },
+            _ if !arg.starts_with('-') => options.then.push(arg.clone()),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+    options.mml = lang.unwrap_or_else(|| options.input.as_ref().is_some_and(|path| path.ends_with(".mml")));
+    if options.output.is_some() && options.device.is_some() {
+        return Err("-o and --device can't be used together".to_string());
+    }
+    if options.pc_speaker && (options.output.is_some() || options.device.is_some()) {
+        return Err("--pc-speaker is where the sound goes, so it can't be used with -o or --device".to_string());
+    }
+    if options.dry_run && (options.output.is_some() || options.device.is_some() || options.click_out.is_some()
+                           || options.pc_speaker) {
+        return Err("--dry-run doesn't write any audio".to_string());
+    }
+    if (options.stretch != 1.0 || options.pitch != 0.0)
+       && (options.device.is_some() || options.pc_speaker || options.click_out.is_some() || options.dry_run) {
+        return Err("--stretch and --pitch work on the whole render once it's done, so they need -o or stdout"
+                   .to_string());
+    }
+    let controlled = options.control.is_some() || options.control_port.is_some();
+    if controlled && options.dry_run {
+        return Err("--dry-run doesn't play, so there's nothing for --control to control".to_string());
+    }
+    if options.ramp.is_some() && !controlled {
+        return Err("--ramp is how long changes from --control take, so it needs --control or --control-port"
+                   .to_string());
+    }
+    if options.control_port.is_none()
+       && (options.control_bind.is_some() || options.control_token.is_some() || options.control_origin.is_some()) {
+        return Err("--control-bind, --control-token and --control-origin are for --control-port".to_string());
+    }
+    if options.control.as_ref().is_some_and(|path| path == "-") && options.input.is_none() {
+        return Err("--control - reads commands from stdin, so the program has to come from a file".to_string());
+    }
+    if options.record_session.is_some() && controlled {
+        return Err("--record-session can't record control commands".to_string());
+    }
+    if options.record_session.is_some() && options.midi_clock.is_some() {
+        return Err("--record-session can't record an external clock".to_string());
+    }
+    if options.strict_repro && (controlled || options.midi_clock.is_some()) {
+        return Err("--strict-repro can't promise the same render with --midi-clock or --control playing along"
+                   .to_string());
+    }
+    if options.click && options.click_out.is_some() {
+        return Err("--click and --click-out can't be used together".to_string());
+    }
+    if options.checkpoint.is_some()
+       && (options.output.is_none() || options.device.is_some() || options.pc_speaker || options.click
+           || options.click_out.is_some() || options.dry_run || controlled || options.midi_clock.is_some()
+           || options.record_session.is_some() || options.voice_model.is_some() || options.stretch != 1.0
+           || options.pitch != 0.0 || options.explain.is_some() || options.emit.is_some()) {
+        return Err("--checkpoint only saves a plain render to -o, with no click, voice model, --stretch, --pitch, \
+                    --control, --midi-clock, --record-session, --explain or --emit".to_string());
+    }
+    if !options.route.is_empty()
+       && (options.output.is_some() || options.pc_speaker || options.click || options.click_out.is_some()
+           || options.dry_run || controlled || options.voice_model.is_some() || options.stretch != 1.0
+           || options.pitch != 0.0 || options.explain.is_some() || options.emit.is_some()) {
+        return Err("--route plays buses on sound cards, so it can't be used with -o, --pc-speaker, a click, a voice \
+                    model, --stretch, --pitch, --control, --explain or --emit".to_string());
+    }
+    if !options.then.is_empty() && !options.chain {
+        return Err(format!("unexpected argument '{}', only --chain plays more than one program", options.then[0]));
+    }
+    if (options.transition.is_some() || options.each.is_some()) && !options.chain {
+        return Err("--transition and --each are how --chain plays its programs".to_string());
+    }
+    if options.chain && options.input.is_none() {
+        return Err("--chain plays programs from files, one after another".to_string());
+    }
+    if options.chain
+       && (options.pc_speaker || options.click || options.click_out.is_some() || options.dry_run || controlled
+           || options.midi_clock.is_some() || options.record_session.is_some() || options.explain.is_some()
+           || options.emit.is_some() || options.checkpoint.is_some() || !options.route.is_empty()) {
+        return Err("--chain plays its programs to -o, --device or stdout, with no --pc-speaker, click, \
+                    --dry-run, --control, --midi-clock, --record-session, --explain, --emit, --checkpoint or \
+                    --route".to_string());
+    }
+    if options.resume.is_some()
+       && (options.input.is_some() || options.output.is_some() || options.checkpoint.is_some() || options.dedup
+           || options.max_threads.is_some() || options.voice_model.is_some() || options.click || options.dry_run
+           || !options.route.is_empty() || options.chain) {
+        return Err("--resume carries on with the program, settings and output the checkpoint was saved with"
+                   .to_string());
+    }
+    Ok(options)
+}
+
+fn report_errors(lst: &[CompileError], file: &str) {
+    for err in lst.iter() {
+        println_stderr!("{}: {}:{}: error: {}", PROGRAM_NAME, file, err.span(), err.message());
+    }
+}
+
+fn report_warnings(lst: &[CompileWarning], file: &str) {
+    for warning in lst.iter() {
+        println_stderr!("{}: {}:{}: warning: {}", PROGRAM_NAME, file, warning.span(), warning.message());
+    }
+}
+
+fn print_errors(lst: &[CompileError], file: &str) {
+    report_errors(lst, file);
+    println_stderr!("\nerror: aborting due to {} previous errors.", lst.len());
+}
+
+/// What to say about a runtime error in the program `text`, read from
+/// `file`.
+fn runtime_error_message(error: &RuntimeError, text: &str, file: &str) -> String {
+    match *error {
+        RuntimeError::Cycle { ref targets, ref jumps, ref thread } => {
+            // a copy of a voice goes by the voice's name
+            let mut labels = label_table(text);
+            labels.extend(voice_table(text));
+            let places: Vec<String> = targets.iter().map(|&pc| {
+                let mut names: Vec<&str> = labels.iter().filter(|&(_, &at)| at == pc).map(|(name, _)| name.as_str())
+                    .collect();
+                names.sort();
+                if names.is_empty() { format!("instruction {}", pc) } else { names.join("/") }
+            }).collect();
+            let mut message = format!("error: a thread got stuck going round {} ({} jumps without playing anything)",
+                                      places.join(", "), MAX_TRANSITIONS);
+            for at in jumps {
+                message.push_str(&format!("\n{}:{}: note: this jump is part of the loop", file, at));
+            }
+            message.push_str(&thread_context(thread, file));
+            message
+        },
+    }
+}
+
+/// Notes on which thread an error happened in and the forks that led to it.
+fn thread_context(thread: &ThreadContext, file: &str) -> String {
+    let place = |pc: usize, span: Option<Span>| match span {
+        Some(at) => format!("{}:{}", file, at),
+        None => format!("instruction {}", pc),
+    };
+    let mut notes = format!("\nnote: in thread {}, at {}", thread.id, place(thread.pc, thread.span));
+    let mut id = thread.id;
+    for fork in &thread.forks {
+        notes.push_str(&format!("\nnote: thread {} was forked by thread {} at {}", id, fork.by, place(fork.pc, fork.span)));
+        id = fork.by;
+    }
+    notes
+}
+
+/// Stop with the error that stopped `renderer`, if one did.
+fn check_runtime(renderer: &mut Renderer, text: &str, file: &str) {
+    if renderer.stolen() > 0 {
+        println_stderr!("{}: warning: {} threads were stopped to stay under --max-threads", PROGRAM_NAME,
+                        renderer.stolen());
+    }
+    if let Some(error) = renderer.take_error() {
+        fail(&runtime_error_message(&error, text, file));
+    }
+}
+
+/// where the metronome goes, if anywhere
+enum ClickTrack {
+    Off,
+    Mixed,
+    Separate(WavSink<std::io::BufWriter<std::fs::File>, i16>),
+}
+
+/// Interprets the list of instructions and produces sound. By default this "sound"
+/// is really an 8-bit 8000Hz PCM stream sent through stdout. It can be piped into
+/// something like aplay.
+/// Commands from `control` are carried out before each block, the branches
+/// each block took go to `explain`, and the notes it played to `emit`.
+fn interpret(renderer: &mut Renderer, sink: &mut dyn Sink, click: &mut ClickTrack, mut control: Option<&mut Control>,
+             mut explain: Option<&mut Timeline>, mut emit: Option<&mut EventFile>,
+             mut checkpoint: Option<&mut Checkpointer>) -> std::io::Result<AudioStats> {
+    let mut metronome = Click::new();
+    let mut stats = AudioStats::new();
+    let mut fade = FadeOut::new(1);
+    while !renderer.is_finished() {
+        if let Some(ref mut control) = control {
+            for problem in control.apply(renderer) {
+                println_stderr!("{}: control: {}", PROGRAM_NAME, problem);
+            }
+            // stopped is silent already, so there's nothing to fade
+            if control.stopped() && interrupt::interrupted() {
+                break;
+            }
+            if control.stopped() {
+                sink.write_block(&[0.0; BLOCK_SIZE])?;
                 continue;
+            }
+        }
+        let bpm = renderer.bpm();
+        let mut block = renderer.render_block(BLOCK_SIZE);
+        let faded_out = fade.apply(&mut block);
+        match *click {
+            ClickTrack::Off => {},
+            ClickTrack::Mixed => {
+                let clicks = metronome.render_block(block.len(), bpm);
+                mix_click(&mut block, &clicks);
             },
+            ClickTrack::Separate(ref mut out) => out.write_block(&metronome.render_block(block.len(), bpm))?,
+        }
+        stats.observe(&block);
+        sink.write_block(&block)?;
+        if let Some(ref mut explain) = explain {
+            explain.write(&renderer.take_branches())?;
+        }
+        if let Some(ref mut emit) = emit {
+            emit.write(&renderer.take_events())?;
+        }
+        if let Some(ref mut checkpoint) = checkpoint {
+            checkpoint.save_if_due(renderer, sink)?;
         }
+        if faded_out {
+            break;
+        }
+    }
+    if let Some(ref mut emit) = emit {
+        emit.finish()?;
+    }
+    if let ClickTrack::Separate(ref mut out) = *click {
+        out.finish()?;
+    }
+    sink.finish()?;
+    // a render cut short can still be picked up from the last one
+    if let Some(checkpoint) = checkpoint {
+        if renderer.is_finished() {
+            checkpoint.done()?;
+        }
+    }
+    Ok(stats)
+}
+
+/// Saves a checkpoint of a render every so often, for `--checkpoint`.
+struct Checkpointer {
+    /// how far it had got at the last one, and what it's of
+    checkpoint: Checkpoint,
+    path: String,
+    /// when the next one's due, in samples
+    next: u64,
+}
+
+impl Checkpointer {
+    /// Checkpoints of `text` with `seed`, if `--checkpoint` asks for them,
+    /// saved next to the output.
+    fn new(options: &Options, text: &str, seed: usize) -> Option<Checkpointer> {
+        let (every, output) = (options.checkpoint?, options.output.clone()?);
+        let checkpoint = Checkpoint {
+            engine: engine(), source: text.to_string(), name: source_name(options).to_string(), seed,
+            dedup: options.dedup, max_threads: options.max_threads.map(|max| (max, options.steal)),
+            output: output.clone(), samples: 0, every: ((every*SAMPLE_RATE) as u64).max(1), state: vec![],
+        };
+        Some(Checkpointer::resuming(checkpoint, format!("{}.checkpoint", output)))
     }
-    instructions.push(Terminate);
 
-    return if errors.len() == 0 {
-        Ok(instructions)
+    /// Carry on saving over the checkpoint at `path`.
+    fn resuming(checkpoint: Checkpoint, path: String) -> Checkpointer {
+        let next = checkpoint.samples + checkpoint.every;
+        Checkpointer { checkpoint, path, next }
+    }
+
+    /// Save a checkpoint if one is due, once all of `sink` so far is safely
+    /// on disk.
+    fn save_if_due(&mut self, renderer: &Renderer, sink: &mut dyn Sink) -> std::io::Result<()> {
+        if renderer.samples() < self.next {
+            return Ok(());
+        }
+        sink.flush()?;
+        // written through to the disk, in case what comes next is a reboot
+        std::fs::File::open(&self.checkpoint.output)?.sync_data()?;
+        self.checkpoint.samples = renderer.samples();
+        self.checkpoint.state = renderer.save_state().map_err(std::io::Error::other)?;
+        self.checkpoint.save(&self.path)?;
+        self.next = renderer.samples() + self.checkpoint.every;
+        Ok(())
+    }
+
+    /// Remove the last checkpoint, now the render's done.
+    fn done(&self) -> std::io::Result<()> {
+        match std::fs::remove_file(&self.path) {
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+}
+
+/// Go through the program without making any sound.
+fn dry_run_quietly(renderer: &mut Renderer) {
+    renderer.set_dry_run(true);
+    while !renderer.is_finished() {
+        renderer.render_block(BLOCK_SIZE);
+        renderer.take_events();
+    }
+}
+
+/// Go through the program without making any sound, listing each note on
+/// stdout as it starts and then how long the whole thing lasts.
+fn dry_run(renderer: &mut Renderer, mut explain: Option<Timeline>, mut emit: Option<EventFile>) {
+    renderer.set_dry_run(true);
+    while !renderer.is_finished() {
+        renderer.render_block(BLOCK_SIZE);
+        let events = renderer.take_events();
+        for event in &events {
+            println!("{:10.4}s  pc {:<4} {:<6} {:9.2} Hz  {:.4}s", event.time, event.pc, event.op, event.freq,
+                     event.dur);
+        }
+        if let Some(ref mut emit) = emit {
+            if let Err(e) = emit.write(&events) {
+                fail(&format!("can't write the events: {}", e));
+            }
+        }
+        if let Some(ref mut explain) = explain {
+            if let Err(e) = explain.write(&renderer.take_branches()) {
+                fail(&format!("can't write the timeline: {}", e));
+            }
+        }
+    }
+    if let Some(ref mut emit) = emit {
+        if let Err(e) = emit.finish() {
+            fail(&format!("can't write the events: {}", e));
+        }
+    }
+    println!("realized duration: {:.4}s", renderer.elapsed());
+}
+
+/// Point out output that clipped or made no sound. Returns true if there was
+/// anything to point out.
+fn report_audio(stats: &AudioStats) -> bool {
+    if stats.clipped > 0 {
+        println_stderr!("{}: warning: {} of {} samples clipped", PROGRAM_NAME, stats.clipped, stats.samples);
+    }
+    if stats.is_silent() {
+        println_stderr!("{}: warning: the output is entirely silent ({:.1} seconds of it)", PROGRAM_NAME,
+                        stats.samples as f64/SAMPLE_RATE);
+    }
+    stats.clipped > 0 || stats.is_silent()
+}
+
+/// 64-bit FNV-1a, a hash of a program that stays the same from build to
+/// build
+fn fnv1a(text: &str) -> u64 {
+    text.bytes().fold(0xcbf29ce484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+/// The INFO tags for a WAV file rendered from `text` with `seed`, so a file
+/// kept for months still says what made it. `text` is None when the program
+/// can change partway through, as under `watch`.
+fn render_info(options: &Options, text: Option<&str>, seed: usize) -> Vec<([u8; 4], String)> {
+    let comment = match text {
+        Some(text) => format!("seed {}, program fnv1a {:016x}", seed, fnv1a(text)),
+        None => format!("seed {}", seed),
+    };
+    vec![(*b"ISFT", format!("{} {}", PROGRAM_NAME, engine())),
+         (*b"ISRC", source_name(options).to_string()),
+         (*b"ICMT", comment)]
+}
+
+/// `info` is what to tag a WAV file with, from `render_info`.
+fn open_sink<'a>(options: &Options, stdout: &'a std::io::Stdout, info: &[([u8; 4], String)])
+                 -> Result<Box<dyn Sink + 'a>, String> {
+    if let Some(ref path) = options.output {
+        return match WavSink::<_, i16>::create(path) {
+            Ok(sink) => Ok(Box::new(sink.with_info(info))),
+            Err(e) => Err(format!("can't create {}: {}", path, e)),
+        };
+    }
+    if let Some(ref device) = options.device {
+        return match DeviceSink::open(device) {
+            Ok(sink) => Ok(Box::new(sink)),
+            Err(e) => Err(format!("can't start aplay: {}", e)),
+        };
+    }
+    Ok(Box::new(PcmSink::<_, u8>::new(stdout.lock())))
+}
+
+/// Like `open_sink`, but `--pc-speaker` also needs the renderer, to hear
+/// which tones to beep.
+fn open_output<'a>(options: &Options, stdout: &'a std::io::Stdout, info: &[([u8; 4], String)],
+                   renderer: &mut Renderer) -> Result<Box<dyn Sink + 'a>, String> {
+    if !options.pc_speaker {
+        return open_sink(options, stdout, info);
+    }
+    match Speaker::open(PC_SPEAKER) {
+        Ok((speaker, beep)) => {
+            renderer.set_voice_model(Box::new(beep));
+            Ok(Box::new(speaker))
+        },
+        Err(e) => Err(format!("can't open {}: {} (is the pcspkr module loaded?)", PC_SPEAKER, e)),
+    }
+}
+
+fn print_devices() {
+    match list_devices() {
+        Ok(devices) => {
+            for device in devices {
+                println!("{:<12} {}", device.name, device.description);
+            }
+        },
+        Err(e) => fail(&format!("can't list devices: {}", e)),
+    }
+}
+
+fn fail(msg: &str) -> ! {
+    println_stderr!("{}: {}", PROGRAM_NAME, msg);
+    std::process::exit(1);
+}
+
+/// `60`, `60s` or `10m`, in seconds
+fn parse_every(text: &str) -> Option<f64> {
+    let (number, unit) = match text.strip_suffix('m') {
+        Some(minutes) => (minutes, 60.0),
+        None => (text.strip_suffix('s').unwrap_or(text), 1.0),
+    };
+    number.parse::<f64>().ok().filter(|&every| every > 0.0).map(|every| every*unit)
+}
+
+/// `7` or `1..100`, both ends included
+fn parse_seeds(text: &str) -> Option<(usize, usize)> {
+    let (first, last) = match text.find("..") {
+        Some(at) => (text[..at].parse().ok()?, text[at + 2..].parse().ok()?),
+        None => (text.parse().ok()?, text.parse().ok()?),
+    };
+    // one past the last has to fit, see `take_seed`
+    if first <= last && last < usize::MAX { Some((first, last)) } else { None }
+}
+
+/// The next seed for a worker to render, counting `next` up to one past
+/// `last` and no further, or None once they've all been taken.
+fn take_seed(next: &AtomicUsize, last: usize) -> Option<usize> {
+    next.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |seed| if seed <= last { Some(seed + 1) } else { None })
+        .ok()
+}
+
+fn options_or_usage(args: &[String]) -> Options {
+    match parse_args(args) {
+        Ok(options) => options,
+        Err(msg) => fail(&format!("{}\n{}", msg, USAGE)),
+    }
+}
+
+/// what to call the program in diagnostics
+fn source_name(options: &Options) -> &str {
+    options.input.as_ref().map_or("<stdin>", |path| path.as_str())
+}
+
+fn read_program(options: &Options) -> String {
+    read_source(options.input.as_deref(), options.mml)
+}
+
+/// The program at `path`, or on stdin, translated if it's `from_mml`.
+fn read_source(path: Option<&str>, from_mml: bool) -> String {
+    let mut text = String::new();
+    match path {
+        Some(path) => match std::fs::File::open(path) {
+            Ok(mut file) => { file.read_to_string(&mut text).unwrap(); },
+            Err(e) => fail(&format!("can't read {}: {}", path, e)),
+        },
+        None => { std::io::stdin().read_to_string(&mut text).unwrap(); },
+    }
+    if from_mml {
+        return match mml::translate(&text) {
+            Ok(program) => program,
+            Err(e) => fail(&format!("{}:{}:{}: error: {}", path.unwrap_or("<stdin>"), e.line, e.column, e.message)),
+        };
+    }
+    text
+}
+
+/// Start following the MIDI clock if one was asked for. The clock starts out
+/// at `bpm` until it has heard a full beat.
+fn midi_tempo(options: &Options, bpm: f64) -> Option<SharedTempo> {
+    options.midi_clock.as_ref().map(|device| match MidiClock::open(device, bpm) {
+        Ok(clock) => clock.tempo(),
+        Err(e) => fail(&format!("can't open {}: {}", device, e)),
+    })
+}
+
+/// Play through the chip `--voice-model` picked, if it picked one.
+fn use_voice_model(name: &Option<String>, renderer: &mut Renderer) {
+    if let Some(model) = name.as_ref().and_then(|name| voice_model(name)) {
+        renderer.set_voice_model(model);
+    }
+}
+
+/// Write out what it takes to render this again exactly, if
+/// `--record-session` asked for it.
+fn save_session(options: &Options, text: &str, renderer: &mut Renderer) {
+    if let Some(ref dir) = options.record_session {
+        let session = Session { source: text.to_string(), seed: 0, dedup: options.dedup,
+                                voice_model: options.voice_model.clone(), decisions: renderer.take_decisions(),
+                                engine: Some(engine()) };
+        if let Err(e) = session.save(dir) {
+            fail(&format!("can't save the session to {}: {}", dir, e));
+        }
+    }
+}
+
+/// Cap the number of threads if `--max-threads` asks for it.
+fn limit_threads(options: &Options, renderer: &mut Renderer) {
+    if let Some(max) = options.max_threads {
+        renderer.set_max_threads(max, options.steal);
+    }
+}
+
+/// Start noting down the branches `renderer` takes if `--explain` asks for a
+/// timeline of them.
+fn explainer(options: &Options, text: &str, renderer: &mut Renderer) -> Option<Timeline> {
+    let path = options.explain.as_ref()?;
+    let out: Box<dyn Write> = if path == "-" {
+        Box::new(std::io::stderr())
     } else {
-        Err(errors)
+        match std::fs::File::create(path) {
+            Ok(file) => Box::new(std::io::BufWriter::new(file)),
+            Err(e) => fail(&format!("can't create {}: {}", path, e)),
+        }
     };
+    renderer.set_explain(true);
+    let mut labels = label_table(text);
+    labels.extend(voice_table(text));
+    Some(Timeline::new(&labels, out))
 }
 
-fn print_errors(lst: &Vec<CompileError>) {
-    for err in lst.iter() {
-        match *err {
-            CompileError::Syntax(line) => {
-                println_stderr!("{}:{} error: bad syntax", PROGRAM_NAME, line);
-            },
-            CompileError::Lbl(line) => {
-                println_stderr!("{}:{} error: unknown label", PROGRAM_NAME, line);
+/// Start recording the notes `renderer` plays into the file at `path`, if
+/// `--emit` gave one.
+fn emitter(path: Option<&str>, seed: usize, renderer: &mut Renderer) -> Result<Option<EventFile>, String> {
+    let path = match path {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    let file = std::fs::File::create(path).map_err(|e| format!("can't create {}: {}", path, e))?;
+    let events = EventFile::new(seed, Box::new(std::io::BufWriter::new(file)))
+        .map_err(|e| format!("can't write {}: {}", path, e))?;
+    renderer.set_emit(true);
+    Ok(Some(events))
+}
+
+/// `sink`, stretching and shifting the render on the way if the options
+/// ask for it
+fn post_process<'a>(options: &Options, sink: Box<dyn Sink + 'a>) -> Box<dyn Sink + 'a> {
+    if options.stretch == 1.0 && options.pitch == 0.0 {
+        sink
+    } else {
+        Box::new(Vocoded::new(sink, options.stretch, options.pitch))
+    }
+}
+
+/// Render to wherever the options say, with the click if one was asked for.
+/// Play each of `--route`'s buses on its device, and the rest on `--device`.
+/// Play the programs `--chain` lists one after another, see `chain`.
+fn run_chain(options: &Options) {
+    let paths: Vec<&str> = options.input.iter().chain(&options.then).map(|path| path.as_str()).collect();
+    let texts: Vec<String> = paths.iter().map(|&path| read_source(Some(path), options.mml)).collect();
+    // every program is compiled up front, so a mistake in the last doesn't wait for the rest to play
+    let mut failed = false;
+    for (text, &path) in texts.iter().zip(&paths) {
+        match compile(text) {
+            Ok(program) => report_warnings(&program.warnings, path),
+            Err(errors) => {
+                print_errors(&errors, path);
+                failed = true;
             },
-            CompileError::Prob(line) => {
-                println_stderr!("{}:{} error: probabilities must be between 0 and 1", PROGRAM_NAME, line);
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+    interrupt::catch();
+    let stdout = std::io::stdout();
+    let mut sink = match open_sink(options, &stdout, &render_info(options, None, 0)) {
+        Ok(sink) => post_process(options, sink),
+        Err(msg) => fail(&msg),
+    };
+    let transition = options.transition.unwrap_or(Transition::Cut);
+    let each = options.each.map(|secs| (secs*SAMPLE_RATE) as u64);
+    let mut chain = Chain::new(texts.len(), transition, each, Box::new(|program| {
+        let mut renderer = match compile(&texts[program]) {
+            Ok(program) => Renderer::new(program, 0),
+            Err(_) => unreachable!("it compiled above"),
+        };
+        renderer.set_dedup(options.dedup);
+        limit_threads(options, &mut renderer);
+        use_voice_model(&options.voice_model, &mut renderer);
+        renderer
+    }));
+    let played = (|| -> std::io::Result<AudioStats> {
+        let mut stats = AudioStats::new();
+        let mut fade = FadeOut::new(1);
+        let mut current = None;
+        while !chain.is_finished() {
+            if current != Some(chain.current()) {
+                current = Some(chain.current());
+                println_stderr!("{}: playing {}", PROGRAM_NAME, paths[chain.current()]);
+            }
+            let mut block = chain.render_block(BLOCK_SIZE);
+            let faded_out = fade.apply(&mut block);
+            stats.observe(&block);
+            sink.write_block(&block)?;
+            if let Some((program, error)) = chain.take_error() {
+                fail(&runtime_error_message(&error, &texts[program], paths[program]));
+            }
+            if faded_out {
+                break;
+            }
+        }
+        sink.finish()?;
+        Ok(stats)
+    })();
+    match played {
+        Ok(_) if interrupt::interrupted() => exit_interrupted(),
+        Ok(stats) => {
+            if report_audio(&stats) && options.strict_audio {
+                fail("the output failed --strict-audio");
+            }
+        },
+        Err(e) => fail(&format!("{}", e)),
+    }
+}
+
+fn play_routed(options: &Options, text: &str, renderer: &mut Renderer) {
+    interrupt::catch();
+    let buses = renderer.buses().to_vec();
+    let mut routes: Vec<(Vec<usize>, String)> = vec![];
+    let mut routed = vec![false; buses.len()];
+    for (name, device) in &options.route {
+        let bus = match buses.iter().position(|bus| bus == name) {
+            Some(bus) => bus,
+            None => fail(&format!("--route: no bus {}", name)),
+        };
+        routed[bus] = true;
+        match routes.iter_mut().find(|&&mut (_, ref to)| to == device) {
+            Some(&mut (ref mut on, _)) => on.push(bus),
+            None => routes.push((vec![bus], device.clone())),
+        }
+    }
+    let rest: Vec<usize> = (0..buses.len()).filter(|&bus| !routed[bus]).collect();
+    if !rest.is_empty() {
+        let device = options.device.clone().unwrap_or_else(|| "default".to_string());
+        match routes.iter_mut().find(|&&mut (_, ref to)| *to == device) {
+            Some(&mut (ref mut on, _)) => on.extend(rest),
+            None => routes.push((rest, device)),
+        }
+    }
+    renderer.split_buses();
+    let mut router = Router::open(routes).unwrap_or_else(|e| fail(&format!("can't play: {}", e)));
+    let played = (|| -> std::io::Result<()> {
+        // a head start, so each card has its queue to play from
+        for _ in 0..LEAD/BLOCK_SIZE {
+            renderer.render_block(BLOCK_SIZE);
+            router.write(&renderer.take_bus_blocks())?;
+        }
+        let mut pacer = Pacer::new(BLOCK_SIZE, SAMPLE_RATE);
+        while !renderer.is_finished() {
+            pacer.wait();
+            renderer.render_block(BLOCK_SIZE);
+            if router.write(&renderer.take_bus_blocks())? {
+                break;
+            }
+        }
+        Ok(())
+    })();
+    if let Err(e) = played.and_then(|_| router.finish()) {
+        fail(&format!("{}", e));
+    }
+    if interrupt::interrupted() {
+        exit_interrupted();
+    }
+    check_runtime(renderer, text, source_name(options));
+}
+
+fn play(options: &Options, text: &str, seed: usize, renderer: &mut Renderer) {
+    if !options.route.is_empty() {
+        return play_routed(options, text, renderer);
+    }
+    interrupt::catch();
+    let stdout = std::io::stdout();
+    let mut sink = match open_output(options, &stdout, &render_info(options, Some(text), seed), renderer) {
+        Ok(sink) => post_process(options, sink),
+        Err(msg) => fail(&msg),
+    };
+    let mut click = match options.click_out {
+        Some(ref path) => match WavSink::create(path) {
+            Ok(out) => ClickTrack::Separate(out),
+            Err(e) => fail(&format!("can't create {}: {}", path, e)),
+        },
+        None if options.click => ClickTrack::Mixed,
+        None => ClickTrack::Off,
+    };
+    let mut control = None;
+    if options.control.is_some() || options.control_port.is_some() {
+        let mut controls = Control::new(text);
+        if let Some(ref path) = options.control {
+            if let Err(e) = controls.listen(path) {
+                fail(&format!("can't open {}: {}", path, e));
+            }
+        }
+        if let Some(port) = options.control_port {
+            let addr = SocketAddr::new(options.control_bind.unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST)), port);
+            let token = options.control_token.clone().unwrap_or_else(|| {
+                let token = remote::new_token();
+                println_stderr!("control token: {}", token);
+                token
+            });
+            if let Err(e) = controls.serve(addr, Access { token, origin: options.control_origin.clone() }) {
+                fail(&format!("can't listen on {}: {}", addr, e));
+            }
+        }
+        if let Some(secs) = options.ramp {
+            renderer.set_ramp(secs);
+        }
+        control = Some(controls);
+    }
+    let mut explain = explainer(options, text, renderer);
+    let mut emit = emitter(options.emit.as_deref(), seed, renderer).unwrap_or_else(|msg| fail(&msg));
+    let mut checkpoint = Checkpointer::new(options, text, seed);
+    match interpret(renderer, &mut *sink, &mut click, control.as_mut(), explain.as_mut(), emit.as_mut(),
+                    checkpoint.as_mut()) {
+        Ok(_) if interrupt::interrupted() => exit_interrupted(),
+        Ok(stats) => {
+            check_runtime(renderer, text, source_name(options));
+            if report_audio(&stats) && options.strict_audio {
+                fail("the output failed --strict-audio");
+            }
+        },
+        Err(e) => fail(&format!("{}", e)),
+    }
+}
+
+fn run(args: &[String]) {
+    let options = options_or_usage(args);
+    if let Some(ref path) = options.resume {
+        return resume(path);
+    }
+    if options.chain {
+        return run_chain(&options);
+    }
+    let text = read_program(&options);
+    match compile(&text) {
+        Ok(program) => {
+            report_warnings(&program.warnings, source_name(&options));
+            let tempo = midi_tempo(&options, program.bpm);
+            let mut renderer = Renderer::new(program, 0);
+            renderer.set_dedup(options.dedup);
+            limit_threads(&options, &mut renderer);
+            if let Some(tempo) = tempo {
+                renderer.set_tempo_source(Box::new(tempo));
+            }
+            use_voice_model(&options.voice_model, &mut renderer);
+            if options.record_session.is_some() {
+                renderer.record_decisions();
+            }
+            if options.dry_run {
+                let explain = explainer(&options, &text, &mut renderer);
+                let emit = emitter(options.emit.as_deref(), 0, &mut renderer).unwrap_or_else(|msg| fail(&msg));
+                dry_run(&mut renderer, explain, emit);
+                check_runtime(&mut renderer, &text, source_name(&options));
+            } else {
+                play(&options, &text, 0, &mut renderer);
+            }
+            save_session(&options, &text, &mut renderer);
+        },
+        Err(errors) => {
+            print_errors(&errors, source_name(&options));
+        }
+    }
+}
+
+/// Render a session saved with `--record-session` again, drawing the same
+/// random numbers it did.
+fn run_replay(args: &[String]) {
+    let mut options = options_or_usage(args);
+    if options.midi_clock.is_some() || options.dedup || options.record_session.is_some() || options.checkpoint.is_some()
+        || options.resume.is_some() || options.chain {
+        fail(&format!("replay plays a session the way it was recorded\n{}", USAGE));
+    }
+    let dir = match options.input {
+        Some(ref dir) => dir.clone(),
+        None => fail(&format!("replay needs a session directory\n{}", USAGE)),
+    };
+    let session = match Session::load(&dir) {
+        Ok(session) => session,
+        Err(e) => fail(&format!("can't load the session in {}: {}", dir, e)),
+    };
+    if options.strict_repro && session.engine != Some(engine()) {
+        let recorded = session.engine.as_deref().unwrap_or("an engine too old to say which");
+        fail(&format!("--strict-repro: {} was recorded by {}, not {}", dir, recorded, engine()));
+    }
+    // so diagnostics point into the session
+    options.input = Some(std::path::Path::new(&dir).join("program.blip").display().to_string());
+    let program = match compile(&session.source) {
+        Ok(program) => program,
+        Err(errors) => {
+            print_errors(&errors, source_name(&options));
+            return;
+        },
+    };
+    let mut renderer = Renderer::new(program, session.seed);
+    renderer.set_dedup(session.dedup);
+    limit_threads(&options, &mut renderer);
+    use_voice_model(&session.voice_model, &mut renderer);
+    renderer.replay_decisions(session.decisions);
+    if options.dry_run {
+        let explain = explainer(&options, &session.source, &mut renderer);
+        let emit = emitter(options.emit.as_deref(), session.seed, &mut renderer).unwrap_or_else(|msg| fail(&msg));
+        dry_run(&mut renderer, explain, emit);
+        check_runtime(&mut renderer, &session.source, source_name(&options));
+    } else {
+        play(&options, &session.source, session.seed, &mut renderer);
+    }
+    if renderer.replay_diverged() && options.strict_repro {
+        fail("--strict-repro: the replay drew more random numbers than the session recorded, \
+              so it didn't come out the same");
+    } else if renderer.replay_diverged() {
+        println_stderr!("{}: warning: the replay drew more random numbers than the session recorded, \
+                         so it didn't come out the same", PROGRAM_NAME);
+    }
+}
+
+/// Carry on a render cut short from the checkpoint at `path`, into the same
+/// WAV file, so it comes out just as it would have if it hadn't stopped.
+fn resume(path: &str) {
+    interrupt::catch();
+    let checkpoint = Checkpoint::load(path).unwrap_or_else(|e| fail(&format!("can't resume {}: {}", path, e)));
+    if checkpoint.engine != engine() {
+        fail(&format!("{} was saved by blip-machine {}, not this one ({}), which wouldn't carry on the same way",
+                      path, checkpoint.engine, engine()));
+    }
+    let text = &checkpoint.source;
+    let program = match compile(text) {
+        Ok(program) => program,
+        Err(errors) => {
+            print_errors(&errors, &checkpoint.name);
+            return;
+        },
+    };
+    let mut renderer = Renderer::new(program, checkpoint.seed);
+    renderer.set_dedup(checkpoint.dedup);
+    if let Some((max, steal)) = checkpoint.max_threads {
+        renderer.set_max_threads(max, steal);
+    }
+    if let Err(e) = renderer.load_state(&checkpoint.state) {
+        fail(&format!("can't resume {}: {}", path, e));
+    }
+    // as far as the tags in the file go
+    let mut options = options_or_usage(&[]);
+    options.input = Some(checkpoint.name.clone());
+    let output = checkpoint.output.clone();
+    let mut sink = match WavSink::<_, i16>::resume(&output, checkpoint.samples) {
+        Ok(sink) => sink.with_info(&render_info(&options, Some(text), checkpoint.seed)),
+        Err(e) => fail(&format!("can't resume {}: {}", output, e)),
+    };
+    let mut checkpointer = Checkpointer::resuming(checkpoint, path.to_string());
+    match interpret(&mut renderer, &mut sink, &mut ClickTrack::Off, None, None, None, Some(&mut checkpointer)) {
+        Ok(_) if interrupt::interrupted() => exit_interrupted(),
+        Ok(stats) => {
+            check_runtime(&mut renderer, &checkpointer.checkpoint.source, &checkpointer.checkpoint.name);
+            report_audio(&stats);
+        },
+        Err(e) => fail(&format!("{}", e)),
+    }
+}
+
+/// Exit the way a program stopped by Ctrl-C does, once whatever it was
+/// writing has been finished.
+fn exit_interrupted() -> ! {
+    std::process::exit(130)
+}
+
+/// Seconds since `start`.
+fn secs_since(start: std::time::Instant) -> f64 {
+    let elapsed = start.elapsed();
+    elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64/1e9
+}
+
+/// Time a render with the output thrown away. Control flow is timed with a
+/// dry run, the DSP is whatever a full render takes on top of that, and
+/// encoding is the PCM conversion on its own.
+fn run_bench(args: &[String]) {
+    let options = options_or_usage(args);
+    let text = read_program(&options);
+    let (dry, full) = match (compile(&text), compile(&text)) {
+        (Ok(dry), Ok(full)) => (dry, full),
+        (Err(errors), _) | (_, Err(errors)) => {
+            print_errors(&errors, source_name(&options));
+            return;
+        },
+    };
+
+    let start = std::time::Instant::now();
+    let mut renderer = Renderer::new(dry, 0);
+    renderer.set_dedup(options.dedup);
+    dry_run_quietly(&mut renderer);
+    check_runtime(&mut renderer, &text, source_name(&options));
+    let control = secs_since(start);
+
+    let start = std::time::Instant::now();
+    let mut renderer = Renderer::new(full, 0);
+    renderer.set_dedup(options.dedup);
+    let mut blocks = vec![];
+    while !renderer.is_finished() {
+        blocks.push(renderer.render_block(BLOCK_SIZE));
+    }
+    check_runtime(&mut renderer, &text, source_name(&options));
+    let render = secs_since(start);
+
+    let start = std::time::Instant::now();
+    let mut sink = PcmSink::<_, u8>::new(std::io::sink());
+    for block in &blocks {
+        sink.write_block(block).unwrap();
+    }
+    sink.finish().unwrap();
+    let encoding = secs_since(start);
+
+    let samples: usize = blocks.iter().map(|block| block.len()).sum();
+    let audio = samples as f64/SAMPLE_RATE;
+    let total = render + encoding;
+    println!("{} samples ({:.2} seconds of audio) in {:.3} seconds", samples, audio, total);
+    println!("{:.0} samples/sec, {:.1}x realtime", samples as f64/total, audio/total);
+    println!("control flow {:8.3}s", control);
+    println!("dsp          {:8.3}s", (render - control).max(0.0));
+    println!("encoding     {:8.3}s", encoding);
+}
+
+type Screen = fn(&mut Renderer, &mut dyn Sink, usize, &mut Tty) -> std::io::Result<()>;
+
+/// Play each line typed on stdin as a thread of its own as soon as it's
+/// typed, over an output that keeps going in between, starting with the
+/// program file if there is one. Semicolons split a line into several, so
+/// `@bus drums; zap 200 50 0.1 0.05` works. Once stdin closes, whatever's
+/// still playing plays out.
+fn run_live(args: &[String]) {
+    let options = options_or_usage(args);
+    if options.pc_speaker || options.dry_run || options.click || options.click_out.is_some()
+        || options.record_session.is_some() || options.control.is_some() || options.control_port.is_some()
+        || options.stretch != 1.0 || options.pitch != 0.0 || options.mml || options.explain.is_some()
+        || options.emit.is_some() || options.strict_repro || options.checkpoint.is_some() || options.resume.is_some()
+        || !options.route.is_empty() || options.chain {
+        fail(&format!("live plays blip lines to -o, --device or stdout\n{}", USAGE));
+    }
+    let text = if options.input.is_some() { read_program(&options) } else { String::new() };
+    let program = match compile(&text) {
+        Ok(program) => program,
+        Err(errors) => {
+            print_errors(&errors, source_name(&options));
+            return;
+        },
+    };
+    report_warnings(&program.warnings, source_name(&options));
+    let tempo = midi_tempo(&options, program.bpm);
+    let mut renderer = Renderer::new(program, 0);
+    renderer.set_dedup(options.dedup);
+    limit_threads(&options, &mut renderer);
+    if let Some(tempo) = tempo {
+        renderer.set_tempo_source(Box::new(tempo));
+    }
+    use_voice_model(&options.voice_model, &mut renderer);
+    let stdout = std::io::stdout();
+    let mut sink = match open_sink(&options, &stdout, &render_info(&options, None, 0)) {
+        Ok(sink) => sink,
+        Err(msg) => fail(&msg),
+    };
+    let (send, lines) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let stdin = std::io::stdin();
+        for line in stdin.lock().lines() {
+            match line {
+                Ok(line) => if send.send(line).is_err() { return },
+                Err(_) => return,
+            }
+        }
+    });
+    let mut typed = 0;
+    let mut open = true;
+    let start = std::time::Instant::now();
+    let mut written = 0;
+    interrupt::catch();
+    let mut fade = FadeOut::new(1);
+    while open || !renderer.is_finished() {
+        loop {
+            match lines.try_recv() {
+                Ok(line) => {
+                    typed += 1;
+                    let name = format!("<line {}>", typed);
+                    let statements: Vec<&str> = line.split(';').map(|statement| statement.trim()).collect();
+                    match compile(&statements.join("\n")) {
+                        Ok(program) => {
+                            report_warnings(&program.warnings, &name);
+                            renderer.inject(program);
+                        },
+                        Err(errors) => report_errors(&errors, &name),
+                    }
+                },
+                Err(std::sync::mpsc::TryRecvError::Empty) => break,
+                Err(std::sync::mpsc::TryRecvError::Disconnected) => {
+                    open = false;
+                    break;
+                },
+            }
+        }
+        // silence until there's something to play
+        let mut block = renderer.render_block(BLOCK_SIZE);
+        block.resize(BLOCK_SIZE, 0.0);
+        let faded_out = fade.apply(&mut block);
+        if let Err(e) = sink.write_block(&block) {
+            fail(&format!("{}", e));
+        }
+        if faded_out {
+            break;
+        }
+        // keep to realtime, so that -o doesn't fill up with silence as fast
+        // as it can be written while nothing's been typed
+        written += block.len();
+        let ahead = written as f64/SAMPLE_RATE - secs_since(start);
+        if ahead > 0.1 {
+            std::thread::sleep(std::time::Duration::from_secs_f64(ahead - 0.05));
+        }
+        if let Some(error) = renderer.take_error() {
+            // the line that went wrong stops, along with everything else,
+            // but the next one typed starts things up again
+            println_stderr!("{}: {}", PROGRAM_NAME, runtime_error_message(&error, "", "<live>"));
+        }
+    }
+    if let Err(e) = sink.finish() {
+        fail(&format!("{}", e));
+    }
+    if interrupt::interrupted() {
+        exit_interrupted();
+    }
+}
+
+/// Render or play the scores of a session file together in stereo, see
+/// `ensemble`.
+fn run_session(args: &[String]) {
+    let options = options_or_usage(args);
+    if options.pc_speaker || options.dry_run || options.click || options.click_out.is_some() || options.dedup
+        || options.record_session.is_some() || options.control.is_some() || options.control_port.is_some()
+        || options.stretch != 1.0 || options.pitch != 0.0 || options.mml || options.voice_model.is_some()
+        || options.midi_clock.is_some() || options.explain.is_some() || options.emit.is_some() || options.strict_repro
+        || options.checkpoint.is_some() || options.resume.is_some() || !options.route.is_empty() || options.chain {
+        fail(&format!("session plays its scores to -o, --device or stdout\n{}", USAGE));
+    }
+    let path = match options.input {
+        Some(ref path) => path.clone(),
+        None => fail(&format!("session needs a session file\n{}", USAGE)),
+    };
+    let text = read_program(&options);
+    let ensemble = match Ensemble::parse(&text) {
+        Ok(ensemble) => ensemble,
+        Err(why) => fail(&format!("{}: {}", path, why)),
+    };
+    let dir = std::path::Path::new(&path).parent().unwrap_or_else(|| std::path::Path::new(""));
+    let mut files = vec![];
+    let mut texts = vec![];
+    let mut programs = vec![];
+    let mut failed = false;
+    for layer in &ensemble.layers {
+        let file = dir.join(&layer.file).to_string_lossy().into_owned();
+        let score = match std::fs::read_to_string(&file) {
+            Ok(score) => score,
+            Err(e) => fail(&format!("can't read {}: {}", file, e)),
+        };
+        match compile(&score) {
+            Ok(program) => {
+                report_warnings(&program.warnings, &file);
+                programs.push(program);
             },
-            CompileError::Num(line) => {
-                println_stderr!("{}:{} error: expected a number", PROGRAM_NAME, line);
+            Err(errors) => {
+                report_errors(&errors, &file);
+                failed = true;
             },
         }
+        files.push(file);
+        texts.push(score);
+    }
+    if failed {
+        std::process::exit(1);
+    }
+    let mut mixdown = Mixdown::new(&ensemble, programs);
+    let stdout = std::io::stdout();
+    let mut sink: Box<dyn Sink> = if let Some(ref out) = options.output {
+        let info = [(*b"ISFT", format!("{} {}", PROGRAM_NAME, engine())),
+                    (*b"ISRC", path.clone()),
+                    (*b"ICMT", format!("session fnv1a {:016x}", fnv1a(&text)))];
+        match WavSink::<_, i16>::create(out) {
+            Ok(sink) => Box::new(sink.with_channels(2).with_info(&info)),
+            Err(e) => fail(&format!("can't create {}: {}", out, e)),
+        }
+    } else if let Some(ref device) = options.device {
+        match DeviceSink::open_stereo(device) {
+            Ok(sink) => Box::new(sink),
+            Err(e) => fail(&format!("can't start aplay: {}", e)),
+        }
+    } else {
+        Box::new(PcmSink::<_, u8>::new(stdout.lock()))
+    };
+    interrupt::catch();
+    let mut fade = FadeOut::new(2);
+    while !mixdown.is_finished() {
+        let mut block = mixdown.render_block(BLOCK_SIZE);
+        let faded_out = fade.apply(&mut block);
+        if let Err(e) = sink.write_block(&block) {
+            fail(&format!("{}", e));
+        }
+        if let Some((i, error)) = mixdown.take_error() {
+            println_stderr!("{}: {}", PROGRAM_NAME, runtime_error_message(&error, &texts[i], &files[i]));
+        }
+        if faded_out {
+            break;
+        }
+    }
+    if let Err(e) = sink.finish() {
+        fail(&format!("{}", e));
+    }
+    if interrupt::interrupted() {
+        exit_interrupted();
     }
-    println_stderr!("\nerror: aborting due to {} previous errors.", lst.len());
 }
 
-pub use Instruction::*;
-enum Instruction {
-    Sin(f64, f64),
-    PJump(f64, usize),
-    PFork(f64, usize),
-    Terminate,
+/// Play a program under one of the full-screen modes, the debugger or the
+/// mixer, `breaks` being whether it pauses at `--break`s.
+fn run_screen(args: &[String], screen: Screen, breaks: bool) {
+    let options = options_or_usage(args);
+    if options.strict_repro {
+        fail("debug and mix play along with the keyboard, so they can't be --strict-repro");
+    }
+    if options.checkpoint.is_some() || options.resume.is_some() {
+        fail("debug and mix play to the speakers, which can't be checkpointed");
+    }
+    if !options.route.is_empty() {
+        fail("debug and mix play everything on one device, not --route");
+    }
+    if options.chain {
+        fail("debug and mix play one program, not a --chain");
+    }
+    if !breaks && !options.breaks.is_empty() {
+        fail("only debug stops at --break");
+    }
+    let text = read_program(&options);
+    let program = match compile(&text) {
+        Ok(program) => program,
+        Err(errors) => {
+            print_errors(&errors, source_name(&options));
+            return;
+        },
+    };
+    report_warnings(&program.warnings, source_name(&options));
+    let tempo = midi_tempo(&options, program.bpm);
+    let mut renderer = Renderer::new(program, 0);
+    renderer.set_dedup(options.dedup);
+    limit_threads(&options, &mut renderer);
+    if let Some(tempo) = tempo {
+        renderer.set_tempo_source(Box::new(tempo));
+    }
+    use_voice_model(&options.voice_model, &mut renderer);
+    let labels = label_table(&text);
+    let breakpoints = options.breaks.iter().map(|condition| parse_breakpoint(condition, &labels)).collect();
+    match breakpoints {
+        Ok(breakpoints) => renderer.set_breakpoints(breakpoints),
+        Err(msg) => fail(&format!("--break: {}", msg)),
+    }
+    let stdout = std::io::stdout();
+    let mut sink = match open_output(&options, &stdout, &render_info(&options, Some(&text), 0), &mut renderer) {
+        Ok(sink) => sink,
+        Err(msg) => fail(&msg),
+    };
+    let result = match Tty::open() {
+        // the terminal goes back to normal before anything's reported
+        Ok(mut tty) => screen(&mut renderer, &mut *sink, BLOCK_SIZE, &mut tty),
+        Err(e) => fail(&format!("can't use the terminal: {}", e)),
+    };
+    if let Err(e) = result {
+        fail(&format!("{}", e));
+    }
+    check_runtime(&mut renderer, &text, source_name(&options));
 }
 
-#[derive(Clone)]
-struct ThreadState {
-    sin_progress: i64,
-    pc: usize
+/// Render every seed `--seeds` asks for to a WAV file of its own, as many at
+/// once as there are cores.
+fn run_batch(args: &[String]) {
+    let options = options_or_usage(args);
+    let (first, last) = match options.seeds {
+        Some(seeds) => seeds,
+        None => fail(&format!("batch needs --seeds\n{}", USAGE)),
+    };
+    let pattern = match options.output {
+        Some(ref path) if path.contains("{seed}") => path.clone(),
+        _ => fail(&format!("batch needs -o with {{seed}} in the file name\n{}", USAGE)),
+    };
+    if options.emit.as_ref().is_some_and(|path| !path.contains("{seed}")) {
+        fail(&format!("batch needs --emit with {{seed}} in the file name\n{}", USAGE));
+    }
+    if options.device.is_some() || options.pc_speaker || options.midi_clock.is_some() || options.click_out.is_some()
+        || options.record_session.is_some() || options.dry_run || options.control.is_some()
+        || options.control_port.is_some() {
+        fail(&format!("batch only writes WAV files\n{}", USAGE));
+    }
+    if options.checkpoint.is_some() || options.resume.is_some() {
+        fail("batch renders every seed from the start, so it can't be checkpointed");
+    }
+    if !options.route.is_empty() {
+        fail("batch renders to files, so there's nothing to --route");
+    }
+    if options.chain {
+        fail("batch renders seeds of one program, not a --chain");
+    }
+    let text = read_program(&options);
+    // compiled here once so that warnings and errors are only reported once
+    match compile(&text) {
+        Ok(program) => report_warnings(&program.warnings, source_name(&options)),
+        Err(errors) => {
+            print_errors(&errors, source_name(&options));
+            return;
+        },
+    }
+    let next = AtomicUsize::new(first);
+    interrupt::catch();
+    let failed = AtomicBool::new(false);
+    let workers = std::thread::available_parallelism().map_or(1, |n| n.get()).min(last - first + 1);
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                // the renders already going fade out and are kept
+                let seed = match take_seed(&next, last) {
+                    Some(seed) if !interrupt::interrupted() => seed,
+                    _ => break,
+                };
+                let path = pattern.replace("{seed}", &seed.to_string());
+                let emit = options.emit.as_ref().map(|emit| emit.replace("{seed}", &seed.to_string()));
+                if let Err(msg) = render_seed(&options, &text, seed, &path, emit.as_deref()) {
+                    println_stderr!("{}: {}", PROGRAM_NAME, msg);
+                    failed.store(true, Ordering::SeqCst);
+                }
+            });
+        }
+    });
+    if failed.load(Ordering::SeqCst) {
+        std::process::exit(1);
+    }
+    if interrupt::interrupted() {
+        exit_interrupted();
+    }
 }
 
-static SAMPLE_RATE: f64 = 8000.0;
+/// Render `text` with `seed` into the WAV file at `path`, for `batch`.
+fn render_seed(options: &Options, text: &str, seed: usize, path: &str, emit: Option<&str>) -> Result<(), String> {
+    let program = match compile(text) {
+        Ok(program) => program,
+        // run_batch has already seen it compile
+        Err(_) => return Ok(()),
+    };
+    if let Some(dir) = std::path::Path::new(path).parent() {
+        std::fs::create_dir_all(dir).map_err(|e| format!("can't create {}: {}", dir.display(), e))?;
+    }
+    let sink = WavSink::<_, i16>::create(path).map_err(|e| format!("can't create {}: {}", path, e))?;
+    let mut sink = post_process(options, Box::new(sink.with_info(&render_info(options, Some(text), seed))));
+    let mut renderer = Renderer::new(program, seed);
+    renderer.set_dedup(options.dedup);
+    limit_threads(options, &mut renderer);
+    use_voice_model(&options.voice_model, &mut renderer);
+    let mut emit = emitter(emit, seed, &mut renderer)?;
+    let mut click = if options.click { ClickTrack::Mixed } else { ClickTrack::Off };
+    let stats = interpret(&mut renderer, &mut *sink, &mut click, None, None, emit.as_mut(), None)
+        .map_err(|e| format!("{}: {}", path, e))?;
+    if let Some(error) = renderer.take_error() {
+        return Err(format!("{}: {}", path, runtime_error_message(&error, text, source_name(options))));
+    }
+    if stats.clipped > 0 {
+        println_stderr!("{}: {}: warning: {} of {} samples clipped", PROGRAM_NAME, path, stats.clipped, stats.samples);
+    }
+    if stats.is_silent() {
+        println_stderr!("{}: {}: warning: the output is entirely silent", PROGRAM_NAME, path);
+    }
+    if (stats.clipped > 0 || stats.is_silent()) && options.strict_audio {
+        return Err(format!("{} failed --strict-audio", path));
+    }
+    Ok(())
+}
 
-/// sample a sine wave in range -1 to 1
-fn sine_wave(freq: f64, step: i64) -> f64 {
-    (2.0*std::f64::consts::PI*(step as f64)*freq/SAMPLE_RATE).sin()
+/// What `search` wants of a seed's render: how long it lasts, in samples,
+/// and the loudest it gets.
+#[derive(Clone, Copy)]
+struct Criteria {
+    min_samples: u64,
+    max_samples: Option<u64>,
+    max_peak: Option<f32>,
 }
 
-/// have all threads interpret until they're lined up at a sin instruction
-fn interpret_to_sin<R: Rng>(threads: &Vec<ThreadState>, instructions: &[Instruction], rng: &mut R) -> Vec<ThreadState> {
-    fn bernoulli_trial<R: Rng>(p: f64, rng: &mut R) -> bool {
-        let sample = Range::new(0_f64, 1_f64).ind_sample(rng);
-        p > sample
+/// Render `text` with `seed` as far as it takes to tell whether it meets
+/// `criteria`, and return how long it lasts and how loud it gets if it does.
+/// Without a peak to meet it's only a dry run, and the peak isn't known.
+fn measure_seed(options: &Options, text: &str, seed: usize, criteria: Criteria) -> Option<(u64, Option<f32>)> {
+    let mut renderer = Renderer::new(compile(text).ok()?, seed);
+    renderer.set_dedup(options.dedup);
+    limit_threads(options, &mut renderer);
+    use_voice_model(&options.voice_model, &mut renderer);
+    renderer.set_dry_run(criteria.max_peak.is_none());
+    let (mut samples, mut peak) = (0, 0.0_f32);
+    while !renderer.is_finished() {
+        let block = renderer.render_block(BLOCK_SIZE);
+        samples += block.len() as u64;
+        peak = block.iter().fold(peak, |peak, sample| peak.max(sample.abs()));
+        if criteria.max_samples.is_some_and(|max| samples > max) || criteria.max_peak.is_some_and(|max| peak > max) {
+            return None;
+        }
     }
+    // a thread that got stuck isn't a take worth keeping
+    if renderer.take_error().is_some() || samples < criteria.min_samples {
+        return None;
+    }
+    Some((samples, criteria.max_peak.map(|_| peak)))
+}
 
-    // Interpret, branching out like a tree, spawning nodes at forks, and killing
-    // nodes when the program counter reaches the terminate instruction.
-    fn recurse<R: Rng>(thread: ThreadState, instructions: &[Instruction], rng: &mut R) -> Vec<ThreadState> {
-        match instructions[thread.pc] {
-            Sin(_, _) => {
-                return vec![thread];
+/// Look through every seed `--seeds` asks for, as many at once as there are
+/// cores, and print the ones whose render meets `--min-duration`,
+/// `--max-duration` and `--max-peak`, with how long each lasts and how loud
+/// it gets.
+fn run_search(args: &[String]) {
+    let mut criteria = Criteria { min_samples: 0, max_samples: None, max_peak: None };
+    let mut rest = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--min-duration" | "--max-duration" => match args.next().and_then(|secs| parse_every(secs)) {
+                Some(secs) if arg == "--min-duration" => criteria.min_samples = (secs*SAMPLE_RATE) as u64,
+                Some(secs) => criteria.max_samples = Some((secs*SAMPLE_RATE) as u64),
+                None => fail(&format!("{} needs a duration, like 30s or 2m\n{}", arg, USAGE)),
             },
-            PJump(p, line) => {
-                return if bernoulli_trial(p, rng) {
-                    recurse(ThreadState { sin_progress: 0, pc: line }, instructions, rng)
-                } else {
-                    recurse(ThreadState { sin_progress: 0, pc: thread.pc + 1 }, instructions, rng)
-                }
+            "--max-peak" => match args.next().and_then(|db| parse_db(db)) {
+                Some(gain) => criteria.max_peak = Some(gain as f32),
+                None => fail(&format!("--max-peak needs a level, like -1dB\n{}", USAGE)),
             },
-            PFork(p, line) => {
-                return if bernoulli_trial(p, rng) { 
-                    [recurse(ThreadState { sin_progress: 0, pc: line }, instructions, rng),
-                    recurse(ThreadState { sin_progress: 0, pc: thread.pc + 1 }, instructions, rng)
-                    ].concat()
-                } else {
-                    recurse(ThreadState { sin_progress: 0, pc: thread.pc + 1 }, instructions, rng)
+            _ => rest.push(arg.clone()),
+        }
+    }
+    let options = options_or_usage(&rest);
+    let (first, last) = match options.seeds {
+        Some(seeds) => seeds,
+        None => fail(&format!("search needs --seeds\n{}", USAGE)),
+    };
+    if options.output.is_some() || options.device.is_some() || options.pc_speaker || options.click
+        || options.click_out.is_some() || options.record_session.is_some() || options.dry_run
+        || options.control.is_some() || options.control_port.is_some() || options.explain.is_some()
+        || options.emit.is_some() || options.checkpoint.is_some() || options.resume.is_some()
+        || !options.route.is_empty() || options.midi_clock.is_some() || options.chain {
+        fail(&format!("search only prints the seeds that match, for batch or -o to render\n{}", USAGE));
+    }
+    if criteria.max_samples.is_some_and(|max| max < criteria.min_samples) {
+        fail("--max-duration is shorter than --min-duration");
+    }
+    let text = read_program(&options);
+    match compile(&text) {
+        Ok(program) => report_warnings(&program.warnings, source_name(&options)),
+        Err(errors) => {
+            print_errors(&errors, source_name(&options));
+            std::process::exit(1);
+        },
+    }
+    let next = AtomicUsize::new(first);
+    let searched = AtomicUsize::new(0);
+    let found = std::sync::Mutex::new(vec![]);
+    interrupt::catch();
+    let workers = std::thread::available_parallelism().map_or(1, |n| n.get()).min(last - first + 1);
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                let seed = match take_seed(&next, last) {
+                    Some(seed) if !interrupt::interrupted() => seed,
+                    _ => break,
+                };
+                if let Some((samples, peak)) = measure_seed(&options, &text, seed, criteria) {
+                    found.lock().unwrap().push((seed, samples, peak));
                 }
-            },
-            Terminate => {
-                return vec![];
-            }
+                searched.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+    });
+    // whatever's been found is still worth printing after a Ctrl-C
+    let mut found = found.into_inner().unwrap();
+    found.sort_by_key(|&(seed, _, _)| seed);
+    for &(seed, samples, peak) in &found {
+        match peak {
+            Some(peak) => println!("{}: {:.1}s, peak {:.1} dB", seed, samples as f64/SAMPLE_RATE, db(peak)),
+            None => println!("{}: {:.1}s", seed, samples as f64/SAMPLE_RATE),
         }
     }
+    println_stderr!("{}: {} of {} seeds match", PROGRAM_NAME, found.len(), searched.load(Ordering::SeqCst));
+    if interrupt::interrupted() {
+        exit_interrupted();
+    }
+}
+
+/// how long each of `audition`'s excerpts is if `--excerpt` doesn't say
+const EXCERPT_SECS: f64 = 10.0;
 
-    let mut result = vec![];
-    for thread in threads.iter() {
-        result.extend(recurse(thread.clone(), &instructions, rng));
+/// Play the first `--excerpt` seconds of each seed `--seeds` asks for, one
+/// after the other, for picking the best by ear, then say which were kept.
+/// For audition, `--seeds 5` means five seeds, 0 to 4.
+fn run_audition(args: &[String]) {
+    let mut excerpt = EXCERPT_SECS;
+    let mut rest = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--excerpt" => match args.next().and_then(|secs| parse_every(secs)) {
+                Some(secs) => excerpt = secs,
+                None => fail(&format!("--excerpt needs a duration, like 10s\n{}", USAGE)),
+            },
+            "--seeds" => match args.next() {
+                Some(seeds) => {
+                    let count = seeds.parse::<usize>().ok().filter(|&count| count > 0);
+                    rest.push(arg.clone());
+                    rest.push(count.map_or(seeds.clone(), |count| format!("0..{}", count - 1)));
+                },
+                None => fail(&format!("--seeds needs a range, like 1..100\n{}", USAGE)),
+            },
+            _ => rest.push(arg.clone()),
+        }
+    }
+    let options = options_or_usage(&rest);
+    let (first, last) = match options.seeds {
+        Some(seeds) => seeds,
+        None => fail(&format!("audition needs --seeds\n{}", USAGE)),
+    };
+    if options.output.is_some() || options.pc_speaker || options.click || options.click_out.is_some()
+        || options.record_session.is_some() || options.dry_run || options.control.is_some()
+        || options.control_port.is_some() || options.explain.is_some() || options.emit.is_some()
+        || options.checkpoint.is_some() || options.resume.is_some() || !options.route.is_empty()
+        || options.midi_clock.is_some() || options.stretch != 1.0 || options.pitch != 0.0 || options.chain {
+        fail(&format!("audition plays excerpts to --device or stdout, as they're rendered\n{}", USAGE));
+    }
+    let text = read_program(&options);
+    match compile(&text) {
+        Ok(program) => report_warnings(&program.warnings, source_name(&options)),
+        Err(errors) => {
+            print_errors(&errors, source_name(&options));
+            std::process::exit(1);
+        },
+    }
+    // every excerpt's rendered before any plays, as many at once as there
+    // are cores, so moving between them doesn't wait
+    let length = (excerpt*SAMPLE_RATE) as usize;
+    let next = AtomicUsize::new(first);
+    let takes = std::sync::Mutex::new(vec![]);
+    let workers = std::thread::available_parallelism().map_or(1, |n| n.get()).min(last - first + 1);
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| while let Some(seed) = take_seed(&next, last) {
+                let program = match compile(&text) {
+                    Ok(program) => program,
+                    // already seen to compile
+                    Err(_) => break,
+                };
+                let mut renderer = Renderer::new(program, seed);
+                renderer.set_dedup(options.dedup);
+                limit_threads(&options, &mut renderer);
+                use_voice_model(&options.voice_model, &mut renderer);
+                // one that stops on an error is heard up to where it did
+                let samples = renderer.render_block(length);
+                takes.lock().unwrap().push(Take::new(seed, samples));
+            });
+        }
+    });
+    let mut takes = takes.into_inner().unwrap();
+    takes.sort_by_key(|take| take.seed);
+    let stdout = std::io::stdout();
+    let mut sink = open_sink(&options, &stdout, &render_info(&options, Some(&text), first))
+        .unwrap_or_else(|msg| fail(&msg));
+    println_stderr!("{}: k keeps a seed, n and p go to the next and the one before, r plays it again, q stops",
+                    PROGRAM_NAME);
+    let kept = match Tty::open() {
+        // the terminal goes back to normal before anything's reported
+        Ok(tty) => tty.keys().and_then(|keys| {
+            audition(&takes, &mut *sink, BLOCK_SIZE, &keys, &mut |line| println_stderr!("{}: {}", PROGRAM_NAME, line))
+        }),
+        Err(e) => fail(&format!("can't use the terminal: {}", e)),
+    };
+    let kept = kept.and_then(|kept| sink.finish().map(|_| kept)).unwrap_or_else(|e| fail(&format!("{}", e)));
+    let seeds: Vec<String> = kept.iter().map(|seed| seed.to_string()).collect();
+    match seeds.len() {
+        0 => println_stderr!("{}: no seeds kept", PROGRAM_NAME),
+        _ => println_stderr!("{}: kept seeds {}", PROGRAM_NAME, seeds.join(" ")),
     }
-    result
 }
 
-/// play the sound for this time step
-/// pre: all threads are at a sin instruction
-fn interpret_sin(threads: &Vec<ThreadState>, instructions: &[Instruction]) -> Vec<ThreadState> {
-    let mut new_threads = vec![];
-    let mut current_samples = vec![];
+/// the longest `render` draws of a program that doesn't finish by itself
+const MAX_DRAWN_SECS: f64 = 600.0;
 
-    for thread in threads {
-        if let Sin(freq, duration) = instructions[thread.pc] {
-            if (thread.sin_progress as f64) < duration*SAMPLE_RATE {
-                current_samples.push(sine_wave(freq, thread.sin_progress));
-                new_threads.push(ThreadState { sin_progress: thread.sin_progress + 1, pc: thread.pc } );
-            } else {
-                new_threads.push(ThreadState { sin_progress: 0, pc: thread.pc + 1 }); 
-            }
+/// Draw the notes of a seeded run of a program, or of each of a range of
+/// seeds with `{seed}` in the file name.
+fn run_render(args: &[String]) {
+    let options = options_or_usage(args);
+    let pattern = match options.piano_roll {
+        Some(ref path) => path.clone(),
+        None => fail(&format!("render needs --piano-roll\n{}", USAGE)),
+    };
+    let (first, last) = options.seeds.unwrap_or((0, 0));
+    if options.seeds.is_some() && !pattern.contains("{seed}") {
+        fail(&format!("render needs --piano-roll with {{seed}} in the file name for --seeds\n{}", USAGE));
+    }
+    let text = read_program(&options);
+    // compiled here once so that warnings and errors are only reported once
+    match compile(&text) {
+        Ok(program) => report_warnings(&program.warnings, source_name(&options)),
+        Err(errors) => {
+            print_errors(&errors, source_name(&options));
+            return;
+        },
+    }
+    for seed in first..=last {
+        let program = match compile(&text) {
+            Ok(program) => program,
+            Err(_) => return,
+        };
+        let log = simulate(program, seed, (MAX_DRAWN_SECS*SAMPLE_RATE) as u64);
+        if let Some(error) = log.error {
+            fail(&runtime_error_message(&error, &text, source_name(&options)));
+        }
+        let path = pattern.replace("{seed}", &seed.to_string());
+        if !log.finished {
+            println_stderr!("{}: {}: warning: only the first {} seconds are drawn", PROGRAM_NAME, path, MAX_DRAWN_SECS);
         }
-        else {
-            panic!("interpret_sin precondition not met");
+        if let Err(e) = std::fs::write(&path, piano_roll::svg(&log.events, log.duration)) {
+            fail(&format!("can't write {}: {}", path, e));
         }
     }
+}
+
+/// Write a seeded run of a program out as code for another music system.
+fn run_export(args: &[String]) {
+    let options = options_or_usage(args);
+    if options.target.is_none() && !options.module {
+        fail(&format!("export needs a --target\n{}", USAGE));
+    }
+    let text = read_program(&options);
+    let program = match compile(&text) {
+        Ok(program) => program,
+        Err(errors) => {
+            print_errors(&errors, source_name(&options));
+            return;
+        },
+    };
+    report_warnings(&program.warnings, source_name(&options));
+    let instructions = program.instructions.clone();
+    let bpm = program.bpm;
+    let mut renderer = Renderer::new(program, 0);
+    renderer.set_dedup(options.dedup);
+    renderer.set_dry_run(true);
+    let mut events = vec![];
+    while !renderer.is_finished() {
+        renderer.render_block(BLOCK_SIZE);
+        events.extend(renderer.take_events());
+    }
+    check_runtime(&mut renderer, &text, source_name(&options));
+    match options.target {
+        Some(target) => write_output(&options, export(&instructions, &events, SAMPLE_RATE, target).as_bytes()),
+        None => {
+            let name = source_name(&options).rsplit('/').next().unwrap_or("");
+            let module = match write_mod(&events, bpm, name) {
+                Ok(module) => module,
+                Err(TooLong) => fail("the tune is too long for a module, which holds 64 patterns of 64 rows"),
+            };
+            if module.dropped > 0 {
+                println_stderr!("{}: warning: left out {} notes that didn't fit on four channels or were out of \
+                                 the module's range", PROGRAM_NAME, module.dropped);
+            }
+            write_output(&options, &module.bytes);
+        },
+    }
+}
 
-    let avg = current_samples.iter().sum::<f64>() / (current_samples.len() as f64);
-    let sample: [u8; 1] = [(127.5_f64*(1_f64 + avg)) as u8];
-    std::io::stdout().write_all(&sample).unwrap();    
+/// Write `bytes` to the `-o` file, or stdout.
+fn write_output(options: &Options, bytes: &[u8]) {
+    let written = match options.output {
+        Some(ref path) => std::fs::write(path, bytes),
+        None => std::io::stdout().write_all(bytes),
+    };
+    if let Err(e) = written {
+        fail(&format!("can't write the output: {}", e));
+    }
+}
 
-    new_threads
+/// a level from 0 to 1 in dB
+fn db(level: f32) -> f64 {
+    20.0*(level as f64).log10()
 }
 
-fn build_rand() -> StdRng {
-    let seed: &[usize] = &[0];
-    let rng: StdRng = SeedableRng::from_seed(seed);
-    rng
+/// Compare two WAV files and say where they differ, optionally writing the
+/// difference between them to a third. Exits with an error if they differ.
+fn run_diff(args: &[String]) {
+    let mut files = vec![];
+    let mut output = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => match args.next() {
+                Some(path) => output = Some(path.clone()),
+                None => fail(&format!("-o needs a file name\n{}", USAGE)),
+            },
+            _ if !arg.starts_with('-') => files.push(arg.clone()),
+            _ => fail(&format!("unexpected argument '{}'\n{}", arg, USAGE)),
+        }
+    }
+    if files.len() != 2 {
+        fail(&format!("diff needs two WAV files\n{}", USAGE));
+    }
+    let load = |path: &String| match load_wav(path) {
+        Ok(samples) => samples,
+        Err(e) => fail(&format!("can't load {}: {}", path, e)),
+    };
+    let (a, b) = (load(&files[0]), load(&files[1]));
+    let result = diff(&a, &b);
+    if let Some(ref path) = output {
+        let written = WavSink::<_, f32>::create(path).and_then(|mut sink| {
+            sink.write_block(&difference(&a, &b))?;
+            sink.finish()
+        });
+        if let Err(e) = written {
+            fail(&format!("can't write {}: {}", path, e));
+        }
+    }
+    let secs = |samples: usize| samples as f64/SAMPLE_RATE;
+    if result.len_a != result.len_b {
+        println!("{} is {:.3}s long and {} is {:.3}s", files[0], secs(result.len_a), files[1], secs(result.len_b));
+    }
+    for divergence in &result.divergences {
+        println!("{:10.3}s to {:10.3}s  differ, peaking at {:.1} dBFS", secs(divergence.from), secs(divergence.to),
+                 db(divergence.peak));
+    }
+    if result.same() {
+        println!("the same, to 16 bits");
+        return;
+    }
+    println!("peak difference {:.1} dBFS at {:.4}s, RMS {:.1} dBFS", db(result.peak), secs(result.peak_at),
+             db(result.rms));
+    std::process::exit(1);
 }
 
-/// Interprets the list of instructions and produces sound. This "sound" is really
-/// an 8-bit 8000Hz PCM stream sent through stdout. It can be piped into something
-/// like aplay.
-fn interpret(instructions: &[Instruction]) {
-    let mut threads = vec![ThreadState{ sin_progress:0, pc: 0 }];
-    let mut rand = build_rand();
-    while threads.len() != 0 {
-        threads = interpret_to_sin(&threads, &instructions, &mut rand);
-        threads = interpret_sin(&threads, &instructions);
+/// Turn a tune in ABC notation into a blip program.
+fn run_import_abc(args: &[String]) {
+    let options = options_or_usage(args);
+    let text = read_program(&options);
+    match abc::import(&text) {
+        Ok(program) => write_output(&options, program.as_bytes()),
+        Err(e) => fail(&format!("{}:{}: error: {}", source_name(&options), e.line, e.message)),
     }
 }
 
-fn main() {
-    let mut inp = std::io::stdin();
-    let mut text = String::new();
-    inp.read_to_string(&mut text).unwrap();
-    match compile(&text) {
-        Ok(instructions) => {
-            interpret(&instructions);
-        },
+/// Turn an RTTTL ringtone into a blip program.
+fn run_import_rtttl(args: &[String]) {
+    let options = options_or_usage(args);
+    let text = read_program(&options);
+    match rtttl::import(&text) {
+        Ok(program) => write_output(&options, program.as_bytes()),
+        Err(ref e) if e.note == 0 => fail(&format!("{}: error: {}", source_name(&options), e.message)),
+        Err(e) => fail(&format!("{}: error: note {}: {}", source_name(&options), e.note, e.message)),
+    }
+}
+
+/// Say what looks wrong with a program, by the rules of `--table` if it's
+/// given, failing if any of it is denied.
+fn run_lint(args: &[String]) {
+    let mut table = Table::default();
+    let mut rest = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--table" => match args.next() {
+                Some(path) => {
+                    let text = std::fs::read_to_string(path).unwrap_or_else(|e| fail(&format!("can't read {}: {}",
+                                                                                              path, e)));
+                    table = Table::parse(&text).unwrap_or_else(|e| fail(&format!("{}: {}", path, e)));
+                },
+                None => fail(&format!("--table needs a file name\n{}", USAGE)),
+            },
+            _ => rest.push(arg.clone()),
+        }
+    }
+    let options = options_or_usage(&rest);
+    let text = read_program(&options);
+    let program = match compile(&text) {
+        Ok(program) => program,
         Err(errors) => {
-            print_errors(&errors);
+            print_errors(&errors, source_name(&options));
+            std::process::exit(1);
+        },
+    };
+    report_warnings(&program.warnings, source_name(&options));
+    let lints = lint(&text, &program, &table);
+    for found in &lints {
+        let level = if found.level == Level::Deny { "error" } else { "warning" };
+        println_stderr!("{}: {}:{}: {}: {} [{}]", PROGRAM_NAME, source_name(&options), found.at, level, found.message,
+                        found.rule);
+    }
+    if lints.iter().any(|found| found.level == Level::Deny) {
+        std::process::exit(1);
+    }
+}
+
+/// Play a file and keep playing through every saved edit.
+fn run_watch(args: &[String]) {
+    let options = options_or_usage(args);
+    let path = match options.input {
+        Some(ref path) => std::path::PathBuf::from(path),
+        None => fail(&format!("watch needs a program file\n{}", USAGE)),
+    };
+    if options.mml {
+        fail("watch only reads blip programs, not MML");
+    }
+    if options.strict_repro {
+        fail("watch plays a program that changes as it's edited, so it can't be --strict-repro");
+    }
+    if options.checkpoint.is_some() || options.resume.is_some() {
+        fail("watch plays a program that changes as it's edited, so it can't be checkpointed");
+    }
+    if !options.route.is_empty() {
+        fail("watch plays everything on one device, not --route");
+    }
+    if options.chain {
+        fail("watch plays the one program it's editing, not a --chain");
+    }
+    if options.voice_model.is_some() {
+        fail("watch only plays sine waves, not through a voice model or the PC speaker");
+    }
+    let stdout = std::io::stdout();
+    let mut sink = match open_sink(&options, &stdout, &render_info(&options, None, 0)) {
+        Ok(sink) => sink,
+        Err(msg) => fail(&msg),
+    };
+    let config = WatchConfig {
+        seed: 0,
+        block_size: BLOCK_SIZE,
+        crossfade: (options.crossfade*SAMPLE_RATE) as usize,
+        tempo: midi_tempo(&options, blip_machine::compiler::DEFAULT_BPM),
+        dedup: options.dedup,
+        max_threads: options.max_threads.map(|max| (max, options.steal)),
+    };
+    interrupt::catch();
+    let result = watch(&path, &config, &mut *sink, &mut |event| match event {
+        WatchEvent::Errors(errors) => {
+            report_errors(errors, source_name(&options));
+            println_stderr!("{}: keeping the last version that compiled", PROGRAM_NAME);
+        },
+        WatchEvent::Warnings(warnings) => report_warnings(warnings, source_name(&options)),
+        WatchEvent::Swapped { kept, of } => {
+            println_stderr!("{}: reloaded, {} of {} threads carried over", PROGRAM_NAME, kept, of);
+        },
+        WatchEvent::Patched { instructions } => {
+            println_stderr!("{}: reloaded, {} changed instructions swapped in", PROGRAM_NAME, instructions);
+        },
+        WatchEvent::Crossfading => {
+            println_stderr!("{}: reloaded, crossfading into the new version", PROGRAM_NAME);
+        },
+        WatchEvent::Restarted => {
+            println_stderr!("{}: reloaded from the top", PROGRAM_NAME);
+        },
+        WatchEvent::Stopped { error, text } => {
+            println_stderr!("{}: {}", PROGRAM_NAME, runtime_error_message(error, text, source_name(&options)));
+        },
+    });
+    match result {
+        Ok(()) => exit_interrupted(),
+        Err(e) => fail(&format!("{}", e)),
+    }
+}
+
+/// Load the plugin libraries of the `--plugin` arguments at the start of
+/// `args`, and return the rest.
+fn load_plugins(mut args: &[String]) -> &[String] {
+    while args.first().is_some_and(|arg| arg == "--plugin") {
+        match args.get(1) {
+            Some(path) => if let Err(msg) = plugin::load(path) {
+                fail(&format!("can't load the plugin {}: {}", path, msg));
+            },
+            None => fail(&format!("--plugin needs a library\n{}", USAGE)),
         }
+        args = &args[2..];
+    }
+    args
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args = load_plugins(&args);
+    match args.first().map(|arg| arg.as_str()) {
+        Some("devices") => print_devices(),
+        Some("watch") => run_watch(&args[1..]),
+        Some("bench") => run_bench(&args[1..]),
+        Some("diff") => run_diff(&args[1..]),
+        Some("replay") => run_replay(&args[1..]),
+        Some("batch") => run_batch(&args[1..]),
+        Some("search") => run_search(&args[1..]),
+        Some("audition") => run_audition(&args[1..]),
+        Some("render") => run_render(&args[1..]),
+        Some("export") => run_export(&args[1..]),
+        Some("import-abc") => run_import_abc(&args[1..]),
+        Some("import-rtttl") => run_import_rtttl(&args[1..]),
+        Some("lint") => run_lint(&args[1..]),
+        Some("debug") => run_screen(&args[1..], debug, true),
+        Some("mix") => run_screen(&args[1..], mix, false),
+        Some("live") => run_live(&args[1..]),
+        Some("session") => run_session(&args[1..]),
+        _ => run(args),
     }
 }
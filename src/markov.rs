@@ -0,0 +1,33 @@
+//! Markov chains over pitches, for `markov` blocks.
+
+use rand::{Rng, RngExt};
+use compiler::Dur;
+
+/// how far from 1 a row's probabilities can add up to before it's an error
+pub const TOLERANCE: f64 = 1e-6;
+
+#[derive(Clone)]
+pub struct Chain {
+    /// the pitch of each state; the first one is where the chain starts
+    pub pitches: Vec<f64>,
+    /// for each state, the states it can go to next and how likely each is
+    pub transitions: Vec<Vec<(usize, f64)>>,
+    /// how long each note lasts
+    pub dur: Dur,
+}
+
+impl Chain {
+    /// the state to go to after `state`
+    pub fn next<R: Rng>(&self, state: usize, rng: &mut R) -> usize {
+        let mut sample = rng.random::<f64>();
+        let row = &self.transitions[state];
+        for &(to, p) in row {
+            if sample < p {
+                return to;
+            }
+            sample -= p;
+        }
+        // rounding can leave a sliver past the last probability
+        row[row.len() - 1].0
+    }
+}
@@ -0,0 +1,82 @@
+//! Following an external MIDI clock.
+//!
+//! A drum machine or sequencer sends 24 timing clock messages per beat. The
+//! tempo is worked out from the average spacing of the last beat's worth of
+//! them, which smooths over the jitter of the MIDI link.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+use std::thread;
+use std::time::Instant;
+use tempo::{SharedTempo, TempoSource};
+
+const CLOCKS_PER_BEAT: usize = 24;
+
+const TIMING_CLOCK: u8 = 0xf8;
+const START: u8 = 0xfa;
+const STOP: u8 = 0xfc;
+
+/// The tempo of a raw MIDI device such as `/dev/snd/midiC1D0`, read on a
+/// background thread. Until a full beat of clocks has arrived it reports
+/// the tempo it was opened with.
+pub struct MidiClock {
+    tempo: SharedTempo,
+}
+
+impl MidiClock {
+    pub fn open<P: AsRef<Path>>(path: P, initial_bpm: f64) -> io::Result<MidiClock> {
+        let device = File::open(path)?;
+        let tempo = SharedTempo::new(initial_bpm);
+        let shared = tempo.clone();
+        thread::spawn(move || follow(device, shared));
+        Ok(MidiClock { tempo })
+    }
+
+    /// a handle on the tempo as the clock updates it
+    pub fn tempo(&self) -> SharedTempo {
+        self.tempo.clone()
+    }
+}
+
+impl TempoSource for MidiClock {
+    fn bpm(&self) -> f64 {
+        self.tempo.bpm()
+    }
+}
+
+fn follow(mut device: File, tempo: SharedTempo) {
+    let mut ticks: VecDeque<Instant> = VecDeque::with_capacity(CLOCKS_PER_BEAT + 1);
+    let mut buf = [0; 64];
+    loop {
+        let len = match device.read(&mut buf) {
+            Ok(0) | Err(_) => return,
+            Ok(len) => len,
+        };
+        let now = Instant::now();
+        // real-time messages are single bytes and can turn up anywhere,
+        // even in the middle of other messages, so everything else is skipped
+        for &byte in &buf[..len] {
+            match byte {
+                TIMING_CLOCK => {
+                    ticks.push_back(now);
+                    if ticks.len() > CLOCKS_PER_BEAT {
+                        ticks.pop_front();
+                    }
+                    if ticks.len() == CLOCKS_PER_BEAT {
+                        let span = ticks[CLOCKS_PER_BEAT - 1].duration_since(ticks[0]);
+                        let per_tick = span.as_secs_f64() / (CLOCKS_PER_BEAT - 1) as f64;
+                        if per_tick > 0.0 {
+                            tempo.set(60.0 / (per_tick*CLOCKS_PER_BEAT as f64));
+                        }
+                    }
+                },
+                // the gap across a stop says nothing about the tempo
+                START | STOP => ticks.clear(),
+                _ => {},
+            }
+        }
+    }
+}
@@ -0,0 +1,92 @@
+//! A live mixer: plays a program with a fader and a level meter for each of
+//! its buses, turned up and down from the keyboard.
+
+use std::io;
+use renderer::{Renderer, SAMPLE_RATE};
+use sink::Sink;
+use term::{Key, Pacer, Tty};
+
+/// how far one press moves a fader, in dB
+const FADER_STEP: f64 = 1.0;
+/// the fader range, in dB
+const FADER_MIN: f64 = -60.0;
+const FADER_MAX: f64 = 12.0;
+/// the quietest level the meters show, in dB
+const METER_FLOOR: f64 = -48.0;
+const METER_WIDTH: usize = 32;
+
+struct Fader {
+    db: f64,
+    muted: bool,
+}
+
+impl Fader {
+    fn gain(&self) -> f64 {
+        if self.muted { 0.0 } else { 10_f64.powf(self.db/20.0) }
+    }
+}
+
+/// a bar as long as `level` is loud, on a dB scale
+fn meter(level: f64) -> String {
+    let db = if level > 0.0 { 20.0*level.log10() } else { METER_FLOOR };
+    let filled = ((db - METER_FLOOR)/-METER_FLOOR*METER_WIDTH as f64).round().clamp(0.0, METER_WIDTH as f64) as usize;
+    format!("{}{}", "#".repeat(filled), ".".repeat(METER_WIDTH - filled))
+}
+
+fn screen(renderer: &Renderer, faders: &[Fader], levels: &[f64], selected: usize) -> Vec<String> {
+    let status = if renderer.is_finished() { "finished" } else { "playing" };
+    let mut lines = vec![
+        format!("blip-machine mixer   {:.2}s   {}", renderer.elapsed(), status),
+        String::new(),
+    ];
+    for (bus, name) in renderer.buses().iter().enumerate() {
+        let marker = if bus == selected { '>' } else { ' ' };
+        let fader = if faders[bus].muted { "  muted".to_string() } else { format!("{:+5.1} dB", faders[bus].db) };
+        lines.push(format!("{} {:<12} {:>8}  {}", marker, name, fader, meter(levels[bus])));
+    }
+    lines.push(String::new());
+    lines.push("up/down select   left/right fader   0 reset   m mute   q quit".to_string());
+    lines
+}
+
+/// Play `renderer` into `sink` with a mixer until `q` is pressed. Once it's
+/// finished the sink gets silence.
+pub fn mix(renderer: &mut Renderer, sink: &mut dyn Sink, block_size: usize, tty: &mut Tty) -> io::Result<()> {
+    let keys = tty.keys()?;
+    let silence = vec![0.0; block_size];
+    let mut faders: Vec<Fader> = (0..renderer.buses().len()).map(|bus| {
+        Fader { db: 20.0*renderer.bus_gain(bus).log10(), muted: false }
+    }).collect();
+    let mut selected = 0;
+    let mut pacer = Pacer::new(block_size, SAMPLE_RATE);
+    loop {
+        pacer.wait();
+        for key in keys.try_iter() {
+            let fader = &mut faders[selected];
+            match key {
+                Key::Up | Key::Char('k') => selected = selected.saturating_sub(1),
+                Key::Down | Key::Char('j') => selected = (selected + 1).min(renderer.buses().len() - 1),
+                Key::Left | Key::Char('h') => fader.db = (fader.db - FADER_STEP).max(FADER_MIN),
+                Key::Right | Key::Char('l') => fader.db = (fader.db + FADER_STEP).min(FADER_MAX),
+                Key::Char('0') => fader.db = 0.0,
+                Key::Char('m') => fader.muted = !fader.muted,
+                Key::Char('q') => return sink.finish(),
+                _ => {},
+            }
+        }
+        for (bus, fader) in faders.iter().enumerate() {
+            renderer.set_bus_gain(bus, fader.gain());
+        }
+        if renderer.is_finished() {
+            sink.write_block(&silence)?;
+        } else {
+            let block = renderer.render_block(block_size);
+            sink.write_block(&block)?;
+            if block.len() < block_size {
+                sink.write_block(&silence[block.len()..])?;
+            }
+        }
+        let levels = renderer.take_meters();
+        tty.draw(&screen(renderer, &faders, &levels, selected))?;
+    }
+}
@@ -0,0 +1,231 @@
+//! A front end for MML, the Music Macro Language chiptune composers write in,
+//! as in `t120 o4 l8 cdefgab>c`. It translates MML into a blip program, so
+//! everything after that is the usual compiler and renderer.
+//!
+//! The commands are the common ones: `t` tempo, `o` octave, `>` and `<` an
+//! octave up and down, `l` the default length, the notes `a` to `g` with `+`,
+//! `#` or `-` after them for sharp or flat, a length and dots, `r` for a
+//! rest, `&` to tie notes together and `[...]n` to repeat something n times.
+//! `v` for volume is accepted but has no effect. Channels are separated by
+//! `;` and play at the same time, each as a thread of its own.
+
+use compiler;
+use tuning::note_name;
+
+/// the most notes a piece can expand to once its repeats are played out,
+/// and the most commands read to get there, counting each time a repeat's
+/// body is read again, so a few nested repeats can't run away with memory
+/// or time
+pub const MAX_NOTES: usize = 100_000;
+pub const MAX_COMMANDS: usize = 1_000_000;
+/// the most repeats inside repeats
+pub const MAX_DEPTH: usize = 16;
+
+pub struct MmlError {
+    /// where it went wrong, both 1-based
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+struct Reader {
+    chars: Vec<char>,
+    pos: usize,
+    /// the commands read so far, see `MAX_COMMANDS`
+    read: usize,
+}
+
+impl Reader {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn error(&self, message: &str) -> MmlError {
+        let before = &self.chars[..self.pos.min(self.chars.len())];
+        let line = before.iter().filter(|&&c| c == '\n').count() + 1;
+        let column = before.iter().rev().take_while(|&&c| c != '\n').count() + 1;
+        MmlError { line, column, message: message.to_string() }
+    }
+
+    fn skip_space(&mut self) {
+        while self.peek().is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn number(&mut self) -> Option<u32> {
+        self.skip_space();
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect::<String>().parse().ok()
+    }
+
+    /// a length such as `4` or `8.`, in beats, or `default` if there's no
+    /// number
+    fn length(&mut self, default: f64) -> Result<f64, MmlError> {
+        let mut beats = match self.number() {
+            Some(0) => return Err(self.error("a length can't be 0")),
+            Some(n) => 4.0/n as f64,
+            None => default,
+        };
+        let mut dot = beats/2.0;
+        while self.peek() == Some('.') {
+            beats += dot;
+            dot /= 2.0;
+            self.pos += 1;
+        }
+        Ok(beats)
+    }
+}
+
+/// The state carried along a channel.
+struct Channel {
+    octave: i32,
+    /// the default length, in beats
+    length: f64,
+    /// the notes so far, as a key number (None for a rest) and a length in
+    /// beats
+    notes: Vec<(Option<i32>, f64)>,
+    /// the next note is tied onto the last
+    tied: bool,
+}
+
+/// Read commands up to the end of the channel, or the `]` that closes a
+/// repeat.
+fn channel(reader: &mut Reader, state: &mut Channel, tempo: &mut Option<u32>, depth: usize)
+           -> Result<(), MmlError> {
+    loop {
+        reader.skip_space();
+        let c = match reader.peek() {
+            Some(c) => c.to_ascii_lowercase(),
+            None => return if depth == 0 { Ok(()) } else { Err(reader.error("a [ is never closed")) },
+        };
+        reader.pos += 1;
+        reader.read += 1;
+        if reader.read > MAX_COMMANDS {
+            return Err(reader.error(&format!("the repeats go round more than {} commands", MAX_COMMANDS)));
+        }
+        match c {
+            ';' if depth == 0 => {
+                reader.pos -= 1;
+                return Ok(());
+            },
+            ']' if depth > 0 => return Ok(()),
+            't' => match reader.number() {
+                Some(bpm) if bpm > 0 => {
+                    if tempo.is_some_and(|old| old != bpm) {
+                        return Err(reader.error("the tempo can only be set once"));
+                    }
+                    *tempo = Some(bpm);
+                },
+                _ => return Err(reader.error("t needs a tempo")),
+            },
+            'o' => match reader.number() {
+                Some(octave) => state.octave = octave as i32,
+                None => return Err(reader.error("o needs an octave")),
+            },
+            'l' => state.length = reader.length(state.length)?,
+            'v' => {
+                reader.number();
+            },
+            '>' => state.octave += 1,
+            '<' => state.octave -= 1,
+            '&' => state.tied = true,
+            // the body is read again each time round, so octave changes
+            // inside it add up the way they do in other players
+            '[' if depth >= MAX_DEPTH => {
+                return Err(reader.error(&format!("repeats can only go {} deep", MAX_DEPTH)));
+            },
+            '[' => {
+                let (from, notes) = (reader.pos, state.notes.len());
+                channel(reader, state, tempo, depth + 1)?;
+                let times = reader.number().unwrap_or(2);
+                let after = reader.pos;
+                for _ in 1..times {
+                    reader.pos = from;
+                    channel(reader, state, tempo, depth + 1)?;
+                }
+                reader.pos = after;
+                if times == 0 {
+                    state.notes.truncate(notes);
+                }
+            },
+            'a'..='g' | 'r' | 'p' => {
+                let key = match c {
+                    'r' | 'p' => None,
+                    _ => {
+                        let semitone = match c {
+                            'c' => 0, 'd' => 2, 'e' => 4, 'f' => 5, 'g' => 7, 'a' => 9, _ => 11,
+                        };
+                        let mut shift = 0;
+                        while let Some(accidental) = reader.peek() {
+                            match accidental {
+                                '+' | '#' => shift += 1,
+                                '-' => shift -= 1,
+                                _ => break,
+                            }
+                            reader.pos += 1;
+                        }
+                        Some((state.octave + 1)*12 + semitone + shift)
+                    },
+                };
+                let beats = reader.length(state.length)?;
+                let tie = ::std::mem::replace(&mut state.tied, false);
+                match state.notes.last_mut() {
+                    Some(last) if tie && last.0 == key => last.1 += beats,
+                    _ => state.notes.push((key, beats)),
+                }
+                if state.notes.len() > MAX_NOTES {
+                    return Err(reader.error(&format!("the repeats come to more than {} notes", MAX_NOTES)));
+                }
+            },
+            _ => return Err(reader.error(&format!("unknown command '{}'", c))),
+        }
+    }
+}
+
+/// Translate MML into a blip program.
+pub fn translate(text: &str) -> Result<String, MmlError> {
+    let mut reader = Reader { chars: text.chars().collect(), pos: 0, read: 0 };
+    let mut tempo = None;
+    let mut channels = vec![];
+    loop {
+        let mut state = Channel { octave: 4, length: 1.0, notes: vec![], tied: false };
+        channel(&mut reader, &mut state, &mut tempo, 0)?;
+        channels.push(state.notes);
+        if reader.peek() == Some(';') {
+            reader.pos += 1;
+        } else {
+            break;
+        }
+    }
+    channels.retain(|notes| !notes.is_empty());
+
+    let mut out = String::new();
+    if let Some(bpm) = tempo {
+        out.push_str(&format!("@bpm {}\n", bpm));
+    }
+    // the first thread plays the first channel, after starting one for
+    // each of the others
+    for n in 1..channels.len() {
+        out.push_str(&format!("pfork channel{} 1\n", n + 1));
+    }
+    for (n, notes) in channels.iter().enumerate() {
+        if channels.len() > 1 {
+            out.push_str(&format!("lbl channel{}\n", n + 1));
+        }
+        for &(key, beats) in notes {
+            let pitch = key.map_or("0".to_string(), note_name);
+            out.push_str(&format!("sin {} {}\n", pitch, compiler::beats(beats)));
+        }
+        if channels.len() > 1 {
+            out.push_str("pjump end 1\n");
+        }
+    }
+    if channels.len() > 1 {
+        out.push_str("lbl end\n");
+    }
+    Ok(out)
+}
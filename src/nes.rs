@@ -0,0 +1,127 @@
+//! The NES's sound chip, the 2A03's APU: two pulse channels, a triangle and a
+//! noise channel.
+//!
+//! Pitches are rounded to what the channels' 11-bit timers can play, volumes to
+//! 4 bits, and the channels are mixed with the APU's non-linear DACs and the
+//! high-pass filter that follows them. Pulses play the duty an `@duty` line
+//! asks for. Drums go to the noise channel and everything else to the pulses,
+//! then the triangle, which has no volume control and is either on or off.
+
+use std::f64::consts::PI;
+use renderer::SAMPLE_RATE;
+use voices::{Allocator, Voice, VoiceModel};
+
+/// the NTSC CPU clock, which every channel's timer counts
+const CPU_CLOCK: f64 = 1_789_773.0;
+
+/// the noise channel's sixteen periods, in CPU cycles
+const NOISE_PERIODS: [f64; 16] = [4.0, 8.0, 16.0, 32.0, 64.0, 96.0, 128.0, 160.0, 202.0, 254.0, 380.0, 508.0,
+                                  762.0, 1016.0, 2034.0, 4068.0];
+
+/// the lowest sound the NES's output filter lets through
+const HIGH_PASS: f64 = 90.0;
+
+const PULSE1: usize = 0;
+const PULSE2: usize = 1;
+const TRIANGLE: usize = 2;
+const NOISE: usize = 3;
+
+/// The pitch a timer counting in steps of `divider` CPU cycles gets closest
+/// to `freq` with, or None if it's out of the timer's range.
+fn timer_freq(freq: f64, divider: f64, shortest: f64) -> Option<f64> {
+    let timer = (CPU_CLOCK/(divider*freq) - 1.0).round();
+    if timer < shortest || timer > 0x7ff as f64 {
+        None
+    } else {
+        Some(CPU_CLOCK/(divider*(timer + 1.0)))
+    }
+}
+
+/// a gain from 0 to 1 as one of the 4-bit volumes
+fn volume(gain: f64) -> f64 {
+    (gain*15.0).round().clamp(0.0, 15.0)
+}
+
+pub struct Nes {
+    allocator: Allocator,
+    /// how far through its waveform each pulse channel and the triangle are
+    phases: [f64; 3],
+    /// the noise channel's shift register, and the CPU cycles since it last
+    /// shifted
+    lfsr: u16,
+    noise_cycles: f64,
+    /// the filter's last input and output
+    last_in: f64,
+    last_out: f64,
+}
+
+impl Nes {
+    pub fn new() -> Nes {
+        Nes { allocator: Allocator::new(4), phases: [0.0; 3], lfsr: 1, noise_cycles: 0.0, last_in: 0.0, last_out: 0.0 }
+    }
+
+    fn pulse(&mut self, channel: usize, voice: Option<Voice>) -> f64 {
+        match voice.and_then(|voice| timer_freq(voice.freq, 16.0, 8.0).map(|freq| (voice, freq))) {
+            Some((voice, freq)) => {
+                self.phases[channel] = (self.phases[channel] + freq/SAMPLE_RATE).fract();
+                if self.phases[channel] < voice.patch.duty { volume(voice.gain) } else { 0.0 }
+            },
+            None => 0.0,
+        }
+    }
+
+    fn triangle(&mut self, voice: Option<Voice>) -> f64 {
+        // a silenced triangle stops where it is rather than dropping to 0
+        let freq = voice.filter(|voice| voice.gain > 0.0).and_then(|voice| timer_freq(voice.freq, 32.0, 2.0));
+        if let Some(freq) = freq {
+            self.phases[TRIANGLE] = (self.phases[TRIANGLE] + freq/SAMPLE_RATE).fract();
+        }
+        let step = (self.phases[TRIANGLE]*32.0) as u8 % 32;
+        (if step < 16 { 15 - step } else { step - 16 }) as f64
+    }
+
+    fn noise(&mut self, voice: Option<Voice>) -> f64 {
+        let voice = match voice {
+            Some(voice) => voice,
+            None => return 0.0,
+        };
+        // higher drums get a faster noise, by a ratio that puts a kick
+        // around the middle of the periods
+        let rate = voice.freq*100.0;
+        let period = NOISE_PERIODS.iter().cloned()
+            .min_by(|a, b| (CPU_CLOCK/a/rate).ln().abs().partial_cmp(&(CPU_CLOCK/b/rate).ln().abs()).unwrap())
+            .unwrap();
+        self.noise_cycles += CPU_CLOCK/SAMPLE_RATE;
+        while self.noise_cycles >= period {
+            self.noise_cycles -= period;
+            let feedback = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+            self.lfsr = (self.lfsr >> 1) | (feedback << 14);
+        }
+        if self.lfsr & 1 == 1 { 0.0 } else { volume(voice.gain) }
+    }
+}
+
+impl Default for Nes {
+    fn default() -> Nes {
+        Nes::new()
+    }
+}
+
+impl VoiceModel for Nes {
+    fn next_sample(&mut self, voices: &[Voice]) -> f64 {
+        let channels = self.allocator.assign(voices, |channel, voice| (channel == NOISE) == voice.drum);
+        let pulses = self.pulse(PULSE1, channels[PULSE1]) + self.pulse(PULSE2, channels[PULSE2]);
+        let triangle = self.triangle(channels[TRIANGLE]);
+        let noise = self.noise(channels[NOISE]);
+        // the APU's mixer, as measured on real hardware
+        let pulse_out = if pulses == 0.0 { 0.0 } else { 95.88/(8128.0/pulses + 100.0) };
+        let tnd = triangle/8227.0 + noise/12241.0;
+        let tnd_out = if tnd == 0.0 { 0.0 } else { 159.79/(1.0/tnd + 100.0) };
+        let rc = 1.0/(2.0*PI*HIGH_PASS);
+        let alpha = rc/(rc + 1.0/SAMPLE_RATE);
+        let input = pulse_out + tnd_out;
+        self.last_out = alpha*(self.last_out + input - self.last_in);
+        self.last_in = input;
+        self.last_out
+    }
+}
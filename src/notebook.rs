@@ -0,0 +1,70 @@
+//! Hearing renders inline in Jupyter, through the evcxr kernel, while
+//! exploring a program's seeds from Rust:
+//!
+//! ```text
+//! for seed in 0..4 {
+//!     notebook::render(&text, seed, 30.0).unwrap().evcxr_display();
+//! }
+//! ```
+//!
+//! evcxr also calls `evcxr_display` by itself on an `Audio` left as the
+//! value of a cell.
+
+use std::io::Cursor;
+use audio_io::WavSink;
+use compiler::{compile, CompileError};
+use renderer::{Renderer, SAMPLE_RATE};
+use sink::Sink;
+
+/// A render as the bytes of a 16-bit WAV file.
+pub struct Audio {
+    pub wav: Vec<u8>,
+}
+
+/// Render `text` with `seed` to a WAV file in memory, stopping after
+/// `max_secs` if it hasn't finished by then.
+pub fn render(text: &str, seed: usize, max_secs: f64) -> Result<Audio, Vec<CompileError>> {
+    let program = compile(text)?;
+    let mut renderer = Renderer::new(program, seed);
+    let info = [(*b"ICMT", format!("seed {}", seed))];
+    // writing to memory can't fail
+    let mut sink = WavSink::<_, i16>::new(Cursor::new(vec![])).unwrap().with_info(&info);
+    let mut left = (max_secs*SAMPLE_RATE) as usize;
+    let mut block = [0.0; 1024];
+    while left > 0 && !renderer.is_finished() {
+        let len = left.min(block.len());
+        let rendered = renderer.render_into(&mut block[..len]);
+        sink.write_block(&block[..rendered]).unwrap();
+        left -= len;
+    }
+    sink.finish().unwrap();
+    Ok(Audio { wav: sink.into_inner().into_inner() })
+}
+
+impl Audio {
+    /// an HTML audio player with the WAV file in it
+    pub fn to_html(&self) -> String {
+        format!("<audio controls src=\"data:audio/wav;base64,{}\"></audio>", base64(&self.wav))
+    }
+
+    /// Show the player in evcxr's output for the cell.
+    pub fn evcxr_display(&self) {
+        println!("EVCXR_BEGIN_CONTENT text/html\n{}\nEVCXR_END_CONTENT", self.to_html());
+    }
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const DIGITS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::with_capacity(bytes.len().div_ceil(3)*4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0_u32, |n, (i, &byte)| n | (byte as u32) << (16 - 8*i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(DIGITS[(n >> (18 - 6*i) & 63) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
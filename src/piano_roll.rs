@@ -0,0 +1,71 @@
+//! A render's notes drawn as a piano roll, for `render --piano-roll`: time
+//! runs left to right and pitch bottom to top, a semitone to a row, with
+//! each note a bar in its thread's colour. Bends and sweeps slope from the
+//! pitch they start on to the one they end on, and softer notes are fainter.
+//! Looking over a few seeds this way shows the shape of a generative piece
+//! much faster than listening to each one.
+
+use std::fmt::Write;
+use renderer::Event;
+
+/// how wide a second is, and how tall a semitone
+const SECOND: f64 = 80.0;
+const ROW: f64 = 6.0;
+/// room on the left for the octave names, below for the seconds, and on
+/// the right for the last of them
+const LEFT: f64 = 36.0;
+const BOTTOM: f64 = 18.0;
+const RIGHT: f64 = 20.0;
+
+/// the pitch as a MIDI note number, which goes up one a semitone
+fn key(freq: f64) -> f64 {
+    69.0 + 12.0*(freq/440.0).log2()
+}
+
+/// a colour of its own for each thread, going round the colour wheel by the
+/// golden angle so threads started one after the other stand apart
+fn colour(thread: usize) -> String {
+    format!("hsl({:.0}, 70%, 45%)", (thread as f64*137.508) % 360.0)
+}
+
+/// An SVG of `events`, from a render `duration` seconds long.
+pub fn svg(events: &[Event], duration: f64) -> String {
+    let notes: Vec<&Event> = events.iter().filter(|e| e.freq > 0.0 && e.to > 0.0 && e.dur > 0.0).collect();
+    let keys = notes.iter().flat_map(|e| vec![key(e.freq), key(e.to)]);
+    let (low, high) = keys.fold((f64::INFINITY, f64::NEG_INFINITY), |(low, high), k| (low.min(k), high.max(k)));
+    // a couple of semitones either side, and an octave to show if there
+    // are no notes at all
+    let (low, high) = if low <= high { (low.floor() - 2.0, high.ceil() + 2.0) } else { (58.0, 72.0) };
+    let seconds = duration.ceil().max(1.0) as usize;
+    let width = LEFT + seconds as f64*SECOND + RIGHT;
+    let height = (high - low)*ROW + BOTTOM;
+    let x = |secs: f64| LEFT + secs*SECOND;
+    let y = |k: f64| (high - k)*ROW;
+
+    let mut out = String::new();
+    // writing to a String can't fail
+    let _ = writeln!(out, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{:.0}\" height=\"{:.0}\" \
+                           font-family=\"sans-serif\" font-size=\"10\">", width, height);
+    let _ = writeln!(out, "<rect width=\"100%\" height=\"100%\" fill=\"white\"/>");
+    // a line and a name for every C
+    let mut c = (low/12.0).ceil()*12.0;
+    while c <= high {
+        let _ = writeln!(out, "<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" stroke=\"#ddd\"/>", LEFT, y(c),
+                         width - RIGHT, y(c));
+        let _ = writeln!(out, "<text x=\"2\" y=\"{:.1}\">C{}</text>", y(c) + 3.0, c as i64/12 - 1);
+        c += 12.0;
+    }
+    // and for every second
+    for secs in 0..=seconds {
+        let _ = writeln!(out, "<line x1=\"{:.1}\" y1=\"0\" x2=\"{:.1}\" y2=\"{:.1}\" stroke=\"#eee\"/>", x(secs as f64),
+                         x(secs as f64), height - BOTTOM);
+        let _ = writeln!(out, "<text x=\"{:.1}\" y=\"{:.1}\">{}s</text>", x(secs as f64) + 2.0, height - 5.0, secs);
+    }
+    for e in notes {
+        let _ = writeln!(out, "<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" stroke=\"{}\" \
+                               stroke-width=\"{}\" stroke-opacity=\"{:.2}\"/>", x(e.time), y(key(e.freq)),
+                         x(e.time + e.dur), y(key(e.to)), colour(e.thread), ROW - 1.0, 0.3 + 0.7*e.velocity);
+    }
+    out.push_str("</svg>\n");
+    out
+}
@@ -0,0 +1,103 @@
+//! Playing through a sound card.
+//!
+//! Playback goes through ALSA's `aplay`, exactly like piping stdout into it by
+//! hand, except that the machine starts it and picks the device.
+
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use renderer::SAMPLE_RATE;
+use sink::{PcmSink, Sink};
+
+pub struct Device {
+    /// what to pass to `--device`, e.g. `hw:1,0`
+    pub name: String,
+    pub description: String,
+}
+
+/// List the playback devices ALSA knows about, starting with `default`.
+pub fn list_devices() -> io::Result<Vec<Device>> {
+    let mut text = String::new();
+    File::open("/proc/asound/pcm")?.read_to_string(&mut text)?;
+
+    let mut devices = vec![Device { name: "default".to_string(), description: "system default".to_string() }];
+    for line in text.lines() {
+        // 00-03: HDMI 0 : HDMI 0 : playback 1
+        let fields: Vec<&str> = line.split(" : ").map(|f| f.trim()).collect();
+        if !fields.iter().any(|f| f.starts_with("playback")) {
+            continue;
+        }
+        let (id, description) = match fields[0].find(':') {
+            Some(at) => (&fields[0][..at], fields[0][at + 1..].trim()),
+            None => continue,
+        };
+        let mut numbers = id.split('-').map(|n| n.parse::<u32>());
+        if let (Some(Ok(card)), Some(Ok(device))) = (numbers.next(), numbers.next()) {
+            devices.push(Device {
+                name: format!("hw:{},{}", card, device),
+                description: description.to_string(),
+            });
+        }
+    }
+    Ok(devices)
+}
+
+/// A sink that plays on a sound card through a child `aplay` process.
+pub struct DeviceSink {
+    child: Child,
+    /// None once finished, which closes aplay's input
+    pcm: Option<PcmSink<ChildStdin, u8>>,
+}
+
+impl DeviceSink {
+    pub fn open(device: &str) -> io::Result<DeviceSink> {
+        DeviceSink::open_channels(device, 1)
+    }
+
+    /// Play blocks of left and right samples, one after the other.
+    pub fn open_stereo(device: &str) -> io::Result<DeviceSink> {
+        DeviceSink::open_channels(device, 2)
+    }
+
+    fn open_channels(device: &str, channels: u16) -> io::Result<DeviceSink> {
+        let mut command = Command::new("aplay");
+        command.args(["-q", "-t", "raw", "-f", "U8", "-c"])
+            .arg(format!("{}", channels))
+            .arg("-r").arg(format!("{}", SAMPLE_RATE as u32))
+            .arg("-D").arg(device)
+            .stdin(Stdio::piped());
+        // a process group of its own, so that Ctrl-C in the terminal doesn't
+        // stop it before it's played the fade-out, see `interrupt`
+        #[cfg(unix)]
+        ::std::os::unix::process::CommandExt::process_group(&mut command, 0);
+        let mut child = command.spawn()?;
+        let stdin = match child.stdin.take() {
+            Some(stdin) => stdin,
+            None => return Err(io::Error::other("aplay has no stdin")),
+        };
+        Ok(DeviceSink { child, pcm: Some(PcmSink::new(stdin)) })
+    }
+}
+
+impl Sink for DeviceSink {
+    fn write_block(&mut self, samples: &[f32]) -> io::Result<()> {
+        match self.pcm {
+            Some(ref mut pcm) => pcm.write_block(samples),
+            None => Err(io::Error::other("playback already finished")),
+        }
+    }
+
+    /// Waits for aplay to drain what it has buffered.
+    fn finish(&mut self) -> io::Result<()> {
+        if let Some(mut pcm) = self.pcm.take() {
+            pcm.finish()?;
+        }
+        let status = self.child.wait()?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(io::Error::other(format!("aplay exited with {}", status)))
+        }
+    }
+}
@@ -0,0 +1,91 @@
+//! The engine side of a web playground. The page compiles what's in its
+//! editor with `load`, shows whatever `diagnostics` has to say about it, and
+//! pulls blocks with `render` for an AudioWorklet running at `SAMPLE_RATE`.
+//! With the `wasm` feature the same calls are exported for JavaScript, see
+//! `wasm`.
+
+use compiler::{compile, CompileError, CompileWarning, Span};
+use control::json_string;
+use renderer::Renderer;
+
+/// A program being played in a playground, and what the compiler said about
+/// the last one it was given.
+pub struct Playground {
+    renderer: Option<Renderer>,
+    diagnostics: String,
+    /// the block `render` last filled
+    block: Vec<f32>,
+}
+
+impl Default for Playground {
+    fn default() -> Playground {
+        Playground::new()
+    }
+}
+
+impl Playground {
+    pub fn new() -> Playground {
+        Playground { renderer: None, diagnostics: "[]".to_string(), block: vec![] }
+    }
+
+    /// Compile `text` and, if it compiles, play it from the top with
+    /// `seed`. If it doesn't, whatever was playing carries on and false
+    /// comes back.
+    pub fn load(&mut self, text: &str, seed: usize) -> bool {
+        match compile(text) {
+            Ok(program) => {
+                self.diagnostics = diagnostics_json(&[], &program.warnings);
+                self.renderer = Some(Renderer::new(program, seed));
+                true
+            },
+            Err(errors) => {
+                self.diagnostics = diagnostics_json(&errors, &[]);
+                false
+            },
+        }
+    }
+
+    /// what the compiler said about the last program `load` was given, see
+    /// `diagnostics_json`
+    pub fn diagnostics(&self) -> &str {
+        &self.diagnostics
+    }
+
+    /// Render the next `len` samples. Fewer come back once the program
+    /// finishes, and none before one has been loaded.
+    pub fn render(&mut self, len: usize) -> &[f32] {
+        self.block.resize(len, 0.0);
+        let rendered = match self.renderer {
+            Some(ref mut renderer) => renderer.render_into(&mut self.block),
+            None => 0,
+        };
+        self.block.truncate(rendered);
+        &self.block
+    }
+
+    /// the samples `render` last came back with
+    pub fn block(&self) -> &[f32] {
+        &self.block
+    }
+
+    /// true once the program has finished, or if there's none
+    pub fn is_finished(&self) -> bool {
+        self.renderer.as_ref().is_none_or(|renderer| renderer.is_finished())
+    }
+}
+
+fn diagnostic(severity: &str, at: Span, message: &str) -> String {
+    format!("{{\"severity\":\"{}\",\"line\":{},\"start\":{},\"end\":{},\"message\":{}}}", severity, at.line,
+            at.start, at.end, json_string(message))
+}
+
+/// Errors and warnings as a JSON list, in that order, of objects like
+/// `{"severity": "error", "line": 3, "start": 1, "end": 9, "message": "bad
+/// syntax"}`. Lines and columns count from 1, and `end` is the column just
+/// past the last character.
+pub fn diagnostics_json(errors: &[CompileError], warnings: &[CompileWarning]) -> String {
+    let errors = errors.iter().map(|error| diagnostic("error", error.span(), &error.message()));
+    let warnings = warnings.iter().map(|warning| diagnostic("warning", warning.span(), &warning.message()));
+    let all: Vec<String> = errors.chain(warnings).collect();
+    format!("[{}]", all.join(","))
+}
@@ -0,0 +1,193 @@
+//! Instructions from outside blip-machine: a program embedding it can
+//! register a `Plugin` for a mnemonic of its own, and from then on the
+//! compiler reads `<mnemonic> <args...> <dur>` lines as that plugin's
+//! instruction and the renderer asks the plugin for each of its samples.
+//!
+//! A plugin is a Rust value registered in-process, or a dynamic library
+//! `load` opens, as `--plugin` does, with these C functions:
+//!
+//! ```text
+//! const char *blip_mnemonic(void);
+//! int blip_compile(const char *const *args, size_t len, double *params, size_t cap);
+//! const char *blip_error(void);
+//! double blip_freq(const double *params, size_t len, double secs);
+//! double blip_sample(const double *params, size_t len, double secs, double phase);
+//! ```
+//!
+//! `blip_compile` writes up to `cap` numbers and returns how many, or -1 if
+//! the arguments are wrong, with `blip_error` saying how; `blip_error` can
+//! be left out. There's no WASM loader: it would need a WASM runtime, which
+//! this crate doesn't have.
+
+use std::sync::{Arc, Mutex};
+
+/// A custom instruction.
+pub trait Plugin: Send + Sync {
+    /// the word that starts the instruction's lines
+    fn mnemonic(&self) -> &'static str;
+    /// Check the words between the mnemonic and the duration, and turn
+    /// them into the numbers the render callbacks are given, or say what's
+    /// wrong with them.
+    fn compile(&self, args: &[&str]) -> Result<Vec<f64>, String>;
+    /// the pitch `secs` seconds into a note, which the renderer moves the
+    /// wave's phase along by and voice models and exports play
+    fn freq(&self, params: &[f64], secs: f64) -> f64;
+    /// the sample `secs` seconds into a note and at `phase` of its wave,
+    /// from -1 to 1
+    fn sample(&self, params: &[f64], secs: f64, phase: f64) -> f64;
+}
+
+static REGISTRY: Mutex<Vec<Arc<dyn Plugin>>> = Mutex::new(Vec::new());
+
+/// Make `plugin`'s mnemonic an instruction in every program compiled after
+/// this, in place of any plugin registered for it before. Lines that a
+/// built-in instruction reads stay the built-in's.
+pub fn register(plugin: Arc<dyn Plugin>) {
+    let mut registry = REGISTRY.lock().unwrap();
+    registry.retain(|old| old.mnemonic() != plugin.mnemonic());
+    registry.push(plugin);
+}
+
+/// the plugin registered for `mnemonic`, if any
+pub fn find(mnemonic: &str) -> Option<Arc<dyn Plugin>> {
+    REGISTRY.lock().unwrap().iter().find(|plugin| plugin.mnemonic() == mnemonic).cloned()
+}
+
+/// Open the plugin library at `path`, see the module docs, and register it.
+/// It stays loaded until the process exits.
+pub fn load(path: &str) -> Result<(), String> {
+    let plugin = os::open(path)?;
+    register(Arc::new(plugin));
+    Ok(())
+}
+
+/// the most numbers a library plugin's `blip_compile` can write
+const MAX_PARAMS: usize = 64;
+
+#[cfg(unix)]
+mod os {
+    use std::ffi::{CStr, CString};
+    use std::os::raw::{c_char, c_int, c_void};
+    use super::{Plugin, MAX_PARAMS};
+
+    /// `RTLD_NOW`, resolving every symbol on opening, so a missing one is
+    /// found then rather than mid-render
+    const NOW: c_int = 2;
+
+    extern "C" {
+        fn dlopen(filename: *const c_char, flags: c_int) -> *mut c_void;
+        fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+        fn dlerror() -> *const c_char;
+    }
+
+    type Compile = unsafe extern "C" fn(*const *const c_char, usize, *mut f64, usize) -> c_int;
+    type Text = unsafe extern "C" fn() -> *const c_char;
+    type Freq = unsafe extern "C" fn(*const f64, usize, f64) -> f64;
+    type Sample = unsafe extern "C" fn(*const f64, usize, f64, f64) -> f64;
+
+    /// A plugin library's functions. The library's never closed, so they
+    /// stay good.
+    pub struct Library {
+        mnemonic: &'static str,
+        compile: Compile,
+        error: Option<Text>,
+        freq: Freq,
+        sample: Sample,
+    }
+
+    fn last_error() -> String {
+        let error = unsafe { dlerror() };
+        if error.is_null() {
+            "unknown error".to_string()
+        } else {
+            unsafe { CStr::from_ptr(error) }.to_string_lossy().into_owned()
+        }
+    }
+
+    fn symbol(handle: *mut c_void, name: &str) -> Option<*mut c_void> {
+        let name = CString::new(name).unwrap();
+        Some(unsafe { dlsym(handle, name.as_ptr()) }).filter(|symbol| !symbol.is_null())
+    }
+
+    pub fn open(path: &str) -> Result<Library, String> {
+        let filename = CString::new(path).map_err(|_| "a path with a nul in it".to_string())?;
+        let handle = unsafe { dlopen(filename.as_ptr(), NOW) };
+        if handle.is_null() {
+            return Err(last_error());
+        }
+        let needed = |name: &str| symbol(handle, name).ok_or_else(|| format!("it has no {}", name));
+        let mnemonic = unsafe { ::std::mem::transmute::<*mut c_void, Text>(needed("blip_mnemonic")?)() };
+        if mnemonic.is_null() {
+            return Err("blip_mnemonic gave no mnemonic".to_string());
+        }
+        let mnemonic = unsafe { CStr::from_ptr(mnemonic) }.to_str().map_err(|_| "a mnemonic that isn't UTF-8")?;
+        Ok(Library {
+            mnemonic: Box::leak(mnemonic.to_string().into_boxed_str()),
+            compile: unsafe { ::std::mem::transmute::<*mut c_void, Compile>(needed("blip_compile")?) },
+            error: symbol(handle, "blip_error")
+                .map(|error| unsafe { ::std::mem::transmute::<*mut c_void, Text>(error) }),
+            freq: unsafe { ::std::mem::transmute::<*mut c_void, Freq>(needed("blip_freq")?) },
+            sample: unsafe { ::std::mem::transmute::<*mut c_void, Sample>(needed("blip_sample")?) },
+        })
+    }
+
+    impl Plugin for Library {
+        fn mnemonic(&self) -> &'static str {
+            self.mnemonic
+        }
+
+        fn compile(&self, args: &[&str]) -> Result<Vec<f64>, String> {
+            let args: Vec<CString> = args.iter().map(|arg| CString::new(*arg).unwrap_or_default()).collect();
+            let pointers: Vec<*const c_char> = args.iter().map(|arg| arg.as_ptr()).collect();
+            let mut params = vec![0.0; MAX_PARAMS];
+            let written = unsafe { (self.compile)(pointers.as_ptr(), pointers.len(), params.as_mut_ptr(), MAX_PARAMS) };
+            if written < 0 {
+                let error = self.error.map_or(::std::ptr::null(), |error| unsafe { error() });
+                return Err(if error.is_null() {
+                    format!("bad arguments to {}", self.mnemonic)
+                } else {
+                    unsafe { CStr::from_ptr(error) }.to_string_lossy().into_owned()
+                });
+            }
+            params.truncate((written as usize).min(MAX_PARAMS));
+            Ok(params)
+        }
+
+        fn freq(&self, params: &[f64], secs: f64) -> f64 {
+            unsafe { (self.freq)(params.as_ptr(), params.len(), secs) }
+        }
+
+        fn sample(&self, params: &[f64], secs: f64, phase: f64) -> f64 {
+            unsafe { (self.sample)(params.as_ptr(), params.len(), secs, phase) }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod os {
+    use super::Plugin;
+
+    pub enum Library {}
+
+    pub fn open(_: &str) -> Result<Library, String> {
+        Err("plugin libraries can only be loaded on Unix".to_string())
+    }
+
+    impl Plugin for Library {
+        fn mnemonic(&self) -> &'static str {
+            match *self {}
+        }
+
+        fn compile(&self, _: &[&str]) -> Result<Vec<f64>, String> {
+            match *self {}
+        }
+
+        fn freq(&self, _: &[f64], _: f64) -> f64 {
+            match *self {}
+        }
+
+        fn sample(&self, _: &[f64], _: f64, _: f64) -> f64 {
+            match *self {}
+        }
+    }
+}
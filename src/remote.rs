@@ -0,0 +1,181 @@
+//! A small HTTP API for controlling a program as it plays, from a tablet or
+//! anything else on the network that can make requests:
+//!
+//! ```text
+//! GET  /status     the state of play as JSON: buses, and what every thread is doing
+//! POST /start      carry on playing
+//! POST /stop       play silence until a start
+//! POST /score      swap in the program in the request body
+//! POST /command    carry out the control command in the body, like `set drums 0.2`
+//! ```
+//!
+//! Requests are served one at a time on a background thread, and what they
+//! ask for is carried out between blocks like any other control command.
+//! Every request but a CORS preflight has to carry the token, as
+//! `Authorization: Bearer <token>`. Responses only allow another origin, so a
+//! web page served from elsewhere can use the API, if one's given.
+
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, Instant};
+use compiler::compile;
+use control::{json_string, Command};
+
+/// the biggest request body taken, a generous size for a program's text
+const MAX_BODY: usize = 1 << 20;
+
+/// the biggest request line and headers taken, between them
+const MAX_HEAD: u64 = 8 << 10;
+
+/// how long a client gets to send its whole request, or take the response,
+/// before it's dropped, so one that connects and says nothing, or sends a
+/// byte at a time, doesn't hold up the rest
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A connection that stops reading once the time for the request is up,
+/// however it's spread over reads.
+struct Deadline {
+    stream: TcpStream,
+    by: Instant,
+}
+
+impl Read for Deadline {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.by.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "the request took too long"));
+        }
+        self.stream.set_read_timeout(Some(left))?;
+        self.stream.read(buf)
+    }
+}
+
+/// Who's let in.
+pub struct Access {
+    /// what every request has to carry
+    pub token: String,
+    /// the origin, like `https://panel.example`, whose web pages may use the
+    /// API, or none
+    pub origin: Option<String>,
+}
+
+/// A token hard enough to guess, for when none is given.
+pub fn new_token() -> String {
+    format!("{:016x}{:016x}", ::rand::random::<u64>(), ::rand::random::<u64>())
+}
+
+/// Listen on `addr`, sending what requests ask for to `commands` and
+/// answering `/status` with whatever's latest in `status`.
+pub fn serve(addr: SocketAddr, access: Access, commands: Sender<Result<Command, String>>,
+             status: Arc<Mutex<String>>) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            // a client that goes away mid-request doesn't stop the server
+            let _ = answer(stream, &access, &commands, &status);
+        }
+    });
+    Ok(())
+}
+
+/// A response: its status line and its body, which is JSON.
+struct Response(&'static str, String);
+
+fn ok() -> Response {
+    Response("200 OK", "{\"ok\":true}".to_string())
+}
+
+fn error(status: &'static str, why: &str) -> Response {
+    Response(status, format!("{{\"ok\":false,\"error\":{}}}", json_string(why)))
+}
+
+fn answer(stream: TcpStream, access: &Access, commands: &Sender<Result<Command, String>>, status: &Mutex<String>)
+          -> io::Result<()> {
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut reader = BufReader::new(Deadline { stream: stream.try_clone()?, by: Instant::now() + TIMEOUT });
+    // the head is read no further than MAX_HEAD, so a line that never ends
+    // can't fill memory
+    let mut head = reader.by_ref().take(MAX_HEAD);
+    let mut request = String::new();
+    head.read_line(&mut request)?;
+    let mut too_big = head.limit() == 0 && !request.ends_with('\n');
+    let mut length = 0;
+    let mut authorized = false;
+    while !too_big {
+        let mut header = String::new();
+        if head.read_line(&mut header)? == 0 {
+            too_big = head.limit() == 0;
+            break;
+        }
+        too_big = head.limit() == 0 && !header.ends_with('\n');
+        if header.trim().is_empty() {
+            break;
+        }
+        let mut parts = header.splitn(2, ':');
+        if let (Some(name), Some(value)) = (parts.next(), parts.next()) {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().unwrap_or(0);
+            }
+            if name.trim().eq_ignore_ascii_case("authorization") {
+                authorized = value.trim().strip_prefix("Bearer ").is_some_and(|token| token.trim() == access.token);
+            }
+        }
+    }
+    let words: Vec<&str> = request.split_whitespace().collect();
+    let response = if too_big {
+        error("431 Request Header Fields Too Large", "the request line and headers are too big")
+    } else if length > MAX_BODY {
+        error("413 Payload Too Large", "the body is too big")
+    } else if !authorized && words.first() != Some(&"OPTIONS") {
+        error("401 Unauthorized", "no token, or the wrong one")
+    } else {
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body)?;
+        let body = String::from_utf8_lossy(&body).into_owned();
+        match &words[..] {
+            ["OPTIONS", ..] => ok(),
+            ["GET", "/status", ..] => Response("200 OK", status.lock().unwrap().clone()),
+            ["POST", "/start", ..] => send(commands, Ok(Command::Start)),
+            ["POST", "/stop", ..] => send(commands, Ok(Command::Stop)),
+            // checked here so a score that doesn't compile gets a useful
+            // answer, rather than only a complaint on the player's stderr
+            ["POST", "/score", ..] => match compile(&body) {
+                Ok(_) => send(commands, Ok(Command::Swap(body))),
+                Err(errors) => {
+                    error("400 Bad Request", &format!("the score doesn't compile ({} errors)", errors.len()))
+                },
+            },
+            ["POST", "/command", ..] => match Command::parse(&body) {
+                Ok(command) => send(commands, Ok(command)),
+                Err(why) => error("400 Bad Request", &why),
+            },
+            [_, "/status", ..] | [_, "/start", ..] | [_, "/stop", ..] | [_, "/score", ..] | [_, "/command", ..] => {
+                error("405 Method Not Allowed", "wrong method")
+            },
+            _ => error("404 Not Found", "no such thing"),
+        }
+    };
+    let Response(status_line, body) = response;
+    let cors = match access.origin {
+        Some(ref origin) => format!("Access-Control-Allow-Origin: {}\r\nAccess-Control-Allow-Methods: GET, POST, \
+                                     OPTIONS\r\nAccess-Control-Allow-Headers: Authorization, Content-Type\r\n\
+                                     Vary: Origin\r\n", origin),
+        None => String::new(),
+    };
+    let mut stream = stream;
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n{}\
+                    Connection: close\r\n\r\n{}",
+           status_line, body.len(), cors, body)?;
+    stream.flush()
+}
+
+fn send(commands: &Sender<Result<Command, String>>, command: Result<Command, String>) -> Response {
+    match commands.send(command) {
+        Ok(()) => ok(),
+        Err(_) => error("503 Service Unavailable", "the program has finished"),
+    }
+}
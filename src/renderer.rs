@@ -0,0 +1,2101 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::io;
+use audit::audited;
+use rand::{Rng, RngExt, SeedableRng, TryRng};
+use rand::rngs::StdRng;
+use ca::generation;
+use checkpoint::{Decoder, Encoder};
+use compiler::*;
+use dist::Dist;
+use duck::{Duck, Follower};
+#[cfg(feature = "fixed")]
+use fixed;
+use session::{Decisions, Generator};
+use smooth::{Smoothed, DEFAULT_RAMP};
+use voices::{Voice, VoiceModel};
+use tempo::{swung_length, TempoSource};
+use tuning::nearest;
+use ugen::Graph;
+
+/// the most forks back a thread remembers of how it came to be
+const MAX_LINEAGE: usize = 8;
+
+/// How a thread came to be: the thread that forked it and at which
+/// instruction, then how that one came to be, and so on. It's kept in the
+/// thread itself rather than shared, so starting a thread doesn't allocate.
+#[derive(Clone, Copy, Default, PartialEq)]
+struct Origin {
+    /// by and pc, the latest fork first
+    forks: [(usize, usize); MAX_LINEAGE],
+    len: usize,
+}
+
+impl Origin {
+    /// how a thread `by` forked at `pc` came to be, forgetting the oldest
+    /// fork if it's already as far back as it goes
+    fn then(&self, by: usize, pc: usize) -> Origin {
+        let mut forks = [(0, 0); MAX_LINEAGE];
+        forks[0] = (by, pc);
+        let len = (self.len + 1).min(MAX_LINEAGE);
+        forks[1..len].copy_from_slice(&self.forks[..len - 1]);
+        Origin { forks, len }
+    }
+
+    fn forks(&self) -> &[(usize, usize)] {
+        &self.forks[..self.len]
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct ThreadState {
+    sin_progress: i64,
+    pc: usize,
+    /// with swing on, the length of the current note in (swung) beats, worked
+    /// out when it starts
+    swung_beats: Option<f64>,
+    /// where the oscillator is in its cycle, from 0 to 1
+    phase: f64,
+    /// the pitch the thread last played, if it has played anything
+    freq: Option<f64>,
+    /// the pitch the current bend started from
+    bend_from: Option<f64>,
+    /// the volume of the current note, from 0 to 1
+    velocity: f64,
+    /// samples the current note stays silent for before it sounds
+    rest: i64,
+    /// the length drawn for the current note, if it has a random one
+    drawn: Option<Dur>,
+    /// which note of a walk or chain the thread is on, and its pitch
+    note: usize,
+    note_freq: f64,
+    /// the state of the markov chain being played
+    chain_state: usize,
+    /// how many identical threads this one stands for
+    weight: usize,
+    /// a number for telling threads apart in error messages, and how the
+    /// thread came to be (no forks for a thread the program started with)
+    id: usize,
+    origin: Origin,
+    /// how many forks it's been since a thread the program started with,
+    /// however far back `origin` goes
+    generation: usize,
+    /// the thread's own random numbers since a `reseed`, if it's had one
+    stream: Option<u64>,
+    /// the bus a `modulate` aimed it at and by how many Hz, if it's a
+    /// modulator rather than heard
+    modulates: Option<(usize, f64)>,
+}
+
+impl ThreadState {
+    fn start(id: usize) -> ThreadState {
+        ThreadState { sin_progress: 0, pc: 0, swung_beats: None, phase: 0.0, freq: None, bend_from: None, velocity: 1.0, rest: 0, drawn: None, note: 0, note_freq: 0.0, chain_state: 0, weight: 1, id, origin: Origin::default(), generation: 0, stream: None, modulates: None }
+    }
+
+    /// the same thread at another instruction, not yet playing anything
+    fn goto(&self, pc: usize) -> ThreadState {
+        ThreadState { sin_progress: 0, pc, swung_beats: None, rest: 0, drawn: None, note: 0, chain_state: 0, ..*self }
+    }
+
+    /// the same thread on the next note of the walk or chain it's on
+    fn next_note(&self) -> ThreadState {
+        ThreadState { note: self.note + 1, ..self.goto(self.pc) }
+    }
+
+    /// a new thread `id` forked off this one, starting at `pc`
+    fn spawn(&self, pc: usize, id: usize) -> ThreadState {
+        // a stream of its own too, so a fork doesn't draw what the thread
+        // that started it will
+        let stream = self.stream.map(|state| splitmix(&mut (state ^ (pc as u64) ^ (self.note as u64) << 32)));
+        ThreadState { id, origin: self.origin.then(self.id, self.pc), generation: self.generation + 1, stream, ..self.goto(pc) }
+    }
+
+    /// Write down everything about the thread but how it came to be, which
+    /// only error messages use, for a checkpoint.
+    fn save(&self, out: &mut Encoder) {
+        out.i64(self.sin_progress);
+        out.u64(self.pc as u64);
+        out.option_f64(self.swung_beats);
+        out.f64(self.phase);
+        out.option_f64(self.freq);
+        out.option_f64(self.bend_from);
+        out.f64(self.velocity);
+        out.i64(self.rest);
+        // a drawn length is always a plain one
+        out.option_f64(match self.drawn {
+            Some(Dur::Secs(secs)) => Some(secs),
+            _ => None,
+        });
+        out.option_f64(match self.drawn {
+            Some(Dur::Beats(beats)) => Some(beats),
+            _ => None,
+        });
+        out.u64(self.note as u64);
+        out.f64(self.note_freq);
+        out.u64(self.chain_state as u64);
+        out.u64(self.weight as u64);
+        out.u64(self.id as u64);
+        out.u64(self.generation as u64);
+        out.option_u64(self.stream);
+        out.option_u64(self.modulates.map(|(bus, _)| bus as u64));
+        out.f64(self.modulates.map_or(0.0, |(_, depth)| depth));
+    }
+
+    /// a thread `save` wrote down
+    fn load(from: &mut Decoder) -> io::Result<ThreadState> {
+        let sin_progress = from.i64()?;
+        let pc = from.usize()?;
+        let swung_beats = from.option_f64()?;
+        let phase = from.f64()?;
+        let freq = from.option_f64()?;
+        let bend_from = from.option_f64()?;
+        let velocity = from.f64()?;
+        let rest = from.i64()?;
+        let drawn = match (from.option_f64()?, from.option_f64()?) {
+            (Some(secs), _) => Some(Dur::Secs(secs)),
+            (None, Some(beats)) => Some(Dur::Beats(beats)),
+            (None, None) => None,
+        };
+        Ok(ThreadState { sin_progress, pc, swung_beats, phase, freq, bend_from, velocity, rest, drawn,
+                         note: from.usize()?, note_freq: from.f64()?, chain_state: from.usize()?,
+                         weight: from.usize()?, id: from.usize()?, origin: Origin::default(), generation: from.usize()?,
+                         stream: from.option_u64()?, modulates: match (from.option_u64()?, from.f64()?) {
+                             (Some(bus), depth) => Some((bus as usize, depth)),
+                             (None, _) => None,
+                         } })
+    }
+
+    /// a new thread `id` for one note of a rhythm this one is playing, no
+    /// more forks from the start than this one is
+    fn hit(&self, pc: usize, id: usize) -> ThreadState {
+        ThreadState { generation: self.generation, ..self.spawn(pc, id) }
+    }
+
+    /// where this thread is, and the forks that led to it
+    fn context(&self, spans: &[Option<Span>]) -> ThreadContext {
+        let span = |pc: usize| spans.get(pc).cloned().unwrap_or(None);
+        let forks = self.origin.forks().iter().map(|&(by, pc)| Fork { by, pc, span: span(pc) }).collect();
+        ThreadContext { id: self.id, pc: self.pc, span: span(self.pc), forks }
+    }
+
+    /// the length in samples of the note it's on, if that lasts `duration`
+    fn length(&self, duration: Dur, bpm: f64) -> f64 {
+        match self.swung_beats {
+            Some(beats) => dur_samples(Dur::Beats(beats), bpm),
+            None => dur_samples(self.drawn.unwrap_or(duration), bpm),
+        }
+    }
+
+    /// move the wave along one time step at `freq`
+    fn advance(&mut self, freq: f64) {
+        self.phase = (self.phase + freq/SAMPLE_RATE).fract();
+        self.freq = Some(freq);
+    }
+
+    /// this thread standing for `weight` threads
+    fn weighted(self, weight: usize) -> ThreadState {
+        ThreadState { weight, ..self }
+    }
+
+    /// true if the two would play the same thing, whatever their weights and
+    /// wherever they came from
+    fn same_voice(&self, other: &ThreadState) -> bool {
+        ThreadState { weight: other.weight, id: other.id, origin: other.origin, generation: other.generation,
+                      ..*self } == *other
+    }
+}
+
+pub static SAMPLE_RATE: f64 = 8000.0;
+
+/// Which thread to stop to make room for a new one once there are as many
+/// as `Renderer::set_max_threads` allows.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Steal {
+    /// the one that started first
+    Oldest,
+    /// the one that's softest right now, see `loudness`
+    Quietest,
+    /// the one the most forks away from the threads the program started with
+    Deepest,
+}
+
+impl Steal {
+    pub fn parse(name: &str) -> Option<Steal> {
+        match name {
+            "oldest" => Some(Steal::Oldest),
+            "quietest" => Some(Steal::Quietest),
+            "deepest" => Some(Steal::Deepest),
+            _ => None,
+        }
+    }
+
+    /// what `parse` reads it from
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Steal::Oldest => "oldest",
+            Steal::Quietest => "quietest",
+            Steal::Deepest => "deepest",
+        }
+    }
+}
+
+/// How loud `thread` is playing now, its envelope and bus gain and all,
+/// for `Steal::Quietest`. A thread resting, on an instruction that doesn't
+/// play a pitch, or modulating is silent. A note that's only starting
+/// hasn't had its velocity set yet, so it's taken from its fork decay.
+fn loudness(thread: &ThreadState, instructions: &[Instruction], bus_of: &[usize], gains: &[Smoothed],
+            fork_decay: f64) -> f64 {
+    let instruction = &instructions[thread.pc];
+    let progress = thread.sin_progress - thread.rest;
+    if thread.modulates.is_some() || progress < 0 || freq_at(instruction, thread, 0.0, 0.0).is_none() {
+        return 0.0;
+    }
+    let velocity = if thread.sin_progress == 0 {
+        fork_decay.powi(thread.generation.min(i32::MAX as usize) as i32)
+    } else {
+        thread.velocity
+    };
+    let gain = bus_of.get(thread.pc).and_then(|&bus| gains.get(bus)).map_or(1.0, |gain| gain.value());
+    Graph::of(instruction, velocity).level(progress as f64/SAMPLE_RATE)*gain
+}
+
+/// Stop threads, by `steal`, until there are at most `max`; of threads
+/// standing for several, only as many as need to go. Returns how many went.
+/// Ties go to the oldest. `loud` is how loud each thread is playing, for
+/// `Steal::Quietest`.
+fn steal_threads(threads: &mut Vec<ThreadState>, max: usize, steal: Steal, loud: &dyn Fn(&ThreadState) -> f64)
+                 -> usize {
+    let mut count = threads.iter().fold(0, |count: usize, thread| count.saturating_add(thread.weight));
+    let mut stolen = 0;
+    let mut levels: Vec<f64> = match steal {
+        Steal::Quietest => threads.iter().map(loud).collect(),
+        _ => vec![],
+    };
+    while count > max {
+        let victim = (0..threads.len()).min_by(|&a, &b| {
+            let by = match steal {
+                Steal::Oldest => ::std::cmp::Ordering::Equal,
+                Steal::Quietest => levels[a].total_cmp(&levels[b]),
+                Steal::Deepest => threads[b].generation.cmp(&threads[a].generation),
+            };
+            by.then(threads[a].id.cmp(&threads[b].id))
+        }).unwrap();
+        let cut = (count - max).min(threads[victim].weight);
+        threads[victim].weight -= cut;
+        if threads[victim].weight == 0 {
+            threads.remove(victim);
+            if !levels.is_empty() {
+                levels.remove(victim);
+            }
+        }
+        count -= cut;
+        stolen += cut;
+    }
+    stolen
+}
+
+/// `freq` bent by `cents`
+fn bent(freq: f64, cents: f64) -> f64 {
+    freq*2_f64.powf(cents/1200.0)
+}
+
+/// how much faster a zap's pitch drops than its volume
+pub const ZAP_PITCH_SPEED: f64 = 4.0;
+
+/// The pitch a sounding instruction plays `t` of the way through, from 0 to
+/// 1, and `secs` seconds in, or None if it has nothing to play.
+fn freq_at(instruction: &Instruction, thread: &ThreadState, t: f64, secs: f64) -> Option<f64> {
+    match *instruction {
+        Sin(freq, _) => Some(freq),
+        // a thread that hasn't played anything yet has nothing to bend, so it
+        // keeps quiet
+        Bend(cents, _) => thread.bend_from.map(|from| bent(from, cents*t)),
+        Sweep(from, to, _, Curve::Lin) => Some(from + (to - from)*t),
+        Sweep(from, to, _, Curve::Exp) => Some(from*(to/from).powf(t)),
+        Zap(from, to, _, decay) => Some(to + (from - to)*(-secs*ZAP_PITCH_SPEED/decay).exp()),
+        Walk(..) | Play(..) => Some(thread.note_freq),
+        Custom(ref plugin, ref params, _) => Some(plugin.freq(params, secs)),
+        _ => None,
+    }
+}
+
+/// Where a thread is in the part of its note that sounds, `sounding` samples
+/// long: its pitch and how many seconds in, or None while it rests or if it
+/// has nothing to play.
+fn sounding_at(instruction: &Instruction, thread: &ThreadState, sounding: f64) -> Option<(f64, f64)> {
+    let progress = (thread.sin_progress - thread.rest) as f64;
+    if progress < 0.0 {
+        return None;
+    }
+    let (t, secs) = (progress/sounding, progress/SAMPLE_RATE);
+    freq_at(instruction, thread, t, secs).map(|freq| (freq, secs))
+}
+
+/// Take every jump and fork at `pcs` with probability `p` from now on.
+fn set_prob(instructions: &mut [Instruction], pcs: &[usize], p: f64) {
+    for &pc in pcs {
+        if let PJump(ref mut prob, _) | PFork(ref mut prob, _) | QJump(ref mut prob, _, _) | QFork(ref mut prob, _, _)
+               = instructions[pc] {
+            *prob = p;
+        }
+    }
+}
+
+/// true if a thread playing `old` can carry on into `new` at the same place
+fn same_note(old: &Instruction, new: &Instruction) -> bool {
+    matches!((old, new), (&Sin(_, _), &Sin(_, _)) | (&Bend(_, _), &Bend(_, _))
+             | (&Sweep(..), &Sweep(..))
+             | (&Zap(..), &Zap(..))
+             | (&Walk(..), &Walk(..))
+             | (&Play(..), &Play(..))
+             | (&Euclid(..), &Euclid(..))
+             | (&Ca(..), &Ca(..))
+             | (&Custom(..), &Custom(..)))
+}
+
+/// a bus's share of the mix, from the samples played into it
+#[cfg(not(feature = "fixed"))]
+fn bus_level<I: Iterator<Item = f32>>(samples: I, gain: f64, voices: f64) -> f32 {
+    gain as f32*samples.sum::<f32>() / voices as f32
+}
+
+#[cfg(feature = "fixed")]
+fn bus_level<I: Iterator<Item = f32>>(samples: I, gain: f64, voices: f64) -> f32 {
+    fixed::mix_bus(samples, gain, voices)
+}
+
+/// which boundaries the current time step falls on, and when it is in
+/// seconds
+struct Boundaries {
+    beat: bool,
+    bar: bool,
+    secs: f64,
+}
+
+impl Boundaries {
+    fn on(&self, quantum: Quantum) -> bool {
+        match quantum {
+            Quantum::Beat => self.beat,
+            Quantum::Bar => self.bar,
+        }
+    }
+}
+
+/// a random number from 0 to 1
+fn unit_random<R: Rng>(rng: &mut R) -> f64 {
+    rng.random()
+}
+
+/// the next number from a splitmix64 generator at `state`
+fn splitmix(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Where a thread's random numbers come from: the renderer's generator, or
+/// the stream a `reseed` gave it, which comes out the same whatever the seed.
+enum Draws<'a, R: 'a> {
+    Shared(&'a mut R),
+    Own(&'a mut u64),
+}
+
+fn draws_for<'a, R: Rng>(stream: &'a mut Option<u64>, rng: &'a mut R) -> Draws<'a, R> {
+    match *stream {
+        Some(ref mut state) => Draws::Own(state),
+        None => Draws::Shared(rng),
+    }
+}
+
+impl<'a, R: Rng> TryRng for Draws<'a, R> {
+    type Error = Infallible;
+
+    fn try_next_u32(&mut self) -> Result<u32, Infallible> {
+        Ok(match *self {
+            Draws::Shared(ref mut rng) => rng.next_u32(),
+            Draws::Own(ref mut state) => (splitmix(state) >> 32) as u32,
+        })
+    }
+
+    fn try_next_u64(&mut self) -> Result<u64, Infallible> {
+        Ok(match *self {
+            Draws::Shared(ref mut rng) => rng.next_u64(),
+            Draws::Own(ref mut state) => splitmix(state),
+        })
+    }
+
+    fn try_fill_bytes(&mut self, dst: &mut [u8]) -> Result<(), Infallible> {
+        match *self {
+            Draws::Shared(ref mut rng) => rng.fill_bytes(dst),
+            Draws::Own(ref mut state) => for chunk in dst.chunks_mut(8) {
+                chunk.copy_from_slice(&splitmix(state).to_le_bytes()[..chunk.len()]);
+            },
+        }
+        Ok(())
+    }
+}
+
+/// The renderer's random numbers, counting how many 32-bit words they take
+/// from the generator. A seeded generator is back where it was once that
+/// many have been drawn from it again, which is how a checkpoint saves it.
+struct Counted<'a> {
+    rng: &'a mut Decisions,
+    words: &'a mut u64,
+}
+
+impl<'a> TryRng for Counted<'a> {
+    type Error = Infallible;
+
+    fn try_next_u32(&mut self) -> Result<u32, Infallible> {
+        *self.words += 1;
+        Ok(self.rng.next_u32())
+    }
+
+    fn try_next_u64(&mut self) -> Result<u64, Infallible> {
+        *self.words += 2;
+        Ok(self.rng.next_u64())
+    }
+
+    /// which `Decisions` draws a u64 at a time
+    fn try_fill_bytes(&mut self, dst: &mut [u8]) -> Result<(), Infallible> {
+        *self.words += 2*dst.len().div_ceil(8) as u64;
+        self.rng.fill_bytes(dst);
+        Ok(())
+    }
+}
+
+/// Mean and variance past which `successes` goes by the normal
+/// approximation rather than adding up exact probabilities.
+const BINOMIAL_EXACT_UP_TO: f64 = 30.0;
+
+/// How many of `n` identical threads take a branch with probability `p`, all
+/// drawn at once. A single thread takes one random number, the same one it
+/// always has.
+fn successes<R: Rng>(p: f64, n: usize, rng: &mut R) -> usize {
+    if n == 1 {
+        return if p > unit_random(rng) { 1 } else { 0 };
+    }
+    if p <= 0.0 || p >= 1.0 {
+        return if p >= 1.0 { n } else { 0 };
+    }
+    // count whichever outcome is rarer, so that the exact sum stays short
+    if p > 0.5 {
+        return n - successes(1.0 - p, n, rng);
+    }
+    let (nf, q) = (n as f64, 1.0 - p);
+    if nf*p*q > BINOMIAL_EXACT_UP_TO {
+        let approx = Dist::Normal(nf*p, (nf*p*q).sqrt()).sample(rng).round();
+        return approx.max(0.0).min(nf) as usize;
+    }
+    // walk up the distribution until it passes a uniform draw
+    let mut sample = unit_random(rng);
+    let mut prob = q.powf(nf);
+    for k in 0..n {
+        if sample < prob {
+            return k;
+        }
+        sample -= prob;
+        prob *= (nf - k as f64)/(k as f64 + 1.0)*p/q;
+    }
+    n
+}
+
+/// the most jumps and forks a thread can go through in one time step before
+/// it's taken to be stuck in a loop that never plays anything
+pub const MAX_TRANSITIONS: usize = 10_000;
+
+/// how many of a stuck thread's last jumps to look at for the loop it's in
+const CYCLE_TRAIL: usize = 1_000;
+
+/// how many samples a `@listen` hears its level over, and so how often the
+/// branches it sets change
+pub const LISTEN_BLOCK: u64 = 256;
+
+/// One of the forks that led to a thread: the thread that forked and where.
+pub struct Fork {
+    pub by: usize,
+    pub pc: usize,
+    pub span: Option<Span>,
+}
+
+/// Which thread something went wrong in, where it was, and how it came to
+/// be there. Threads merged by `set_dedup` go by the number of the first.
+pub struct ThreadContext {
+    pub id: usize,
+    pub pc: usize,
+    pub span: Option<Span>,
+    /// the fork that started this thread, then the one that started that
+    /// thread, and so on, going back at most 8 forks
+    pub forks: Vec<Fork>,
+}
+
+/// What a live thread is up to, for showing in a debugger.
+pub struct ThreadInfo {
+    pub id: usize,
+    pub pc: usize,
+    pub span: Option<Span>,
+    /// the keyword of the instruction it's at
+    pub op: &'static str,
+    /// how many threads it stands for, see `Renderer::set_dedup`
+    pub weight: usize,
+    /// seconds left of the note or step it's on, if it's on one
+    pub remaining: Option<f64>,
+}
+
+/// Something that goes wrong while a program plays, which stops it.
+pub enum RuntimeError {
+    /// a thread went past `MAX_TRANSITIONS` in one time step
+    Cycle {
+        /// the instructions it kept jumping to
+        targets: Vec<usize>,
+        /// where the jumps and forks that kept sending it round are
+        jumps: Vec<Span>,
+        thread: ThreadContext,
+    },
+}
+
+/// A jump or fork that could have gone either way, or a fork that started a
+/// thread, as `Renderer::set_explain` records them.
+pub struct Branch {
+    /// seconds from the start
+    pub time: f64,
+    /// the thread that got to it, and where it is
+    pub thread: usize,
+    pub pc: usize,
+    pub span: Option<Span>,
+    /// where it goes if it's taken, and how likely that was
+    pub to: usize,
+    pub p: f64,
+    pub fork: bool,
+    /// how many of the threads it stood for took it, see `set_dedup`
+    pub taken: usize,
+    pub weight: usize,
+    /// the number of the thread a fork started
+    pub started: Option<usize>,
+}
+
+/// Something for the debugger to pause on, checked as the threads are
+/// dispatched every time step.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Breakpoint {
+    /// the number of threads, going by weight, coming out more than, less
+    /// than or equal to some number, once it does
+    Threads(Ordering, usize),
+    /// any thread reaching a label, with the pc it's at
+    Label(String, usize),
+}
+
+/// A breakpoint that's been hit.
+#[derive(Clone, Debug)]
+pub struct Break {
+    pub breakpoint: Breakpoint,
+    /// the thread that reached the label, for a `Label`
+    pub thread: Option<usize>,
+}
+
+/// what `interpret_to_sin` notes down as it dispatches the threads
+struct Hook<'a> {
+    /// every branch, when they're being explained
+    branches: Option<&'a mut Vec<Branch>>,
+    /// the pcs breakpoints are waiting for a thread to reach, and the first
+    /// thread to reach one, with where
+    watched: &'a [usize],
+    reached: Option<(usize, usize)>,
+}
+
+impl<'a> Hook<'a> {
+    fn arrive(&mut self, thread: &ThreadState) {
+        if self.reached.is_none() && self.watched.contains(&thread.pc) {
+            self.reached = Some((thread.id, thread.pc));
+        }
+    }
+}
+
+/// A thread `interpret_to_sin` has yet to dispatch, with how many transitions
+/// led to it and the last few jumps taken, from where to where.
+type Pending = (ThreadState, usize, Vec<(usize, usize)>);
+
+/// Buffers the render reuses from one time step to the next, so once they've
+/// grown to fit the program it renders without allocating, see `audit`.
+#[derive(Default)]
+struct Pools {
+    /// `interpret_to_sin`'s threads still to dispatch, and the ones it's
+    /// lined up at a note
+    pending: Vec<Pending>,
+    dispatched: Vec<ThreadState>,
+    /// `split_drawing`'s threads
+    split: Vec<ThreadState>,
+    /// `merge_identical`'s threads, the first of them at each place in each
+    /// note, and for each the one before it at the same place
+    merged: Vec<ThreadState>,
+    at: HashMap<(usize, i64, u64), usize>,
+    same_place: Vec<Option<usize>>,
+    /// `interpret_sin`'s threads after the time step, and each sounding
+    /// one's sample
+    after: Vec<ThreadState>,
+    samples: Vec<(usize, f32)>,
+    /// a `ca`'s generation, and the one after it as it's worked out
+    cells: Vec<bool>,
+    next_cells: Vec<bool>,
+    /// the modulation bus on the next time step, see `Fm`
+    modulation: Vec<f64>,
+}
+
+impl Pools {
+    /// how much they hold between them, which only goes up if one grew
+    fn capacity(&self) -> usize {
+        self.pending.capacity() + self.dispatched.capacity() + self.split.capacity() + self.merged.capacity()
+            + self.at.capacity() + self.same_place.capacity() + self.after.capacity() + self.samples.capacity()
+            + self.cells.capacity() + self.next_cells.capacity() + self.modulation.capacity()
+    }
+}
+
+/// have all threads interpret until they're lined up at a sin instruction, or
+/// waiting at a quantized jump or fork for its boundary, into
+/// `pools.dispatched`, noting down what `hook` asks for on the way
+#[allow(clippy::too_many_arguments)]
+fn interpret_to_sin<R: Rng>(threads: &[ThreadState], instructions: &[Instruction], spans: &[Option<Span>],
+                            now: &Boundaries, rng: &mut R, next_id: &mut usize,
+                            hook: &mut Hook, pools: &mut Pools) -> Result<(), RuntimeError> {
+    let (result, pending) = (&mut pools.dispatched, &mut pools.pending);
+    result.clear();
+    // left over from a step that stopped on an error
+    pending.clear();
+    for thread in threads {
+        // Interpret, branching out like a tree, spawning nodes at forks, and
+        // killing nodes when the program counter reaches the terminate
+        // instruction. This goes depth first, the branch taken before the
+        // one that isn't.
+        pending.push((*thread, 0, vec![]));
+        // the thread being dispatched was already where it is, and every
+        // one after it has just got there
+        let mut arrived = false;
+        while let Some((mut thread, transitions, mut trail)) = pending.pop() {
+            if arrived {
+                hook.arrive(&thread);
+            }
+            arrived = true;
+            let (p, line, fork) = match instructions[thread.pc] {
+                Sin(_, _) | Bend(_, _) | Sweep(..) | Zap(..) | Walk(..) | Play(..) | Euclid(..) | Ca(..)
+                | Custom(..) => {
+                    result.push(thread);
+                    continue;
+                },
+                QJump(_, _, quantum) | QFork(_, _, quantum) if !now.on(quantum) => {
+                    result.push(thread);
+                    continue;
+                },
+                PJump(p, line) | QJump(p, line, _) => (p, line, false),
+                PFork(p, line) | QFork(p, line, _) => (p, line, true),
+                Reseed(seed) => {
+                    // not a branch, so not a step towards a hang either
+                    pending.push((ThreadState { stream: seed, ..thread.goto(thread.pc + 1) }, transitions, trail));
+                    continue;
+                },
+                Modulate(modulates) => {
+                    pending.push((ThreadState { modulates, ..thread.goto(thread.pc + 1) }, transitions, trail));
+                    continue;
+                },
+                Terminate => continue,
+            };
+            if transitions == MAX_TRANSITIONS {
+                let mut targets: Vec<usize> = trail.iter().map(|&(_, to)| to).collect();
+                targets.sort();
+                targets.dedup();
+                let mut froms: Vec<usize> = trail.iter().map(|&(from, _)| from).collect();
+                froms.sort();
+                froms.dedup();
+                let jumps = froms.into_iter().filter_map(|pc| spans.get(pc).cloned().unwrap_or(None)).collect();
+                return Err(RuntimeError::Cycle { targets, jumps, thread: thread.context(spans) });
+            }
+            let taken = successes(p, thread.weight, &mut draws_for(&mut thread.stream, rng));
+            if taken > 0 && transitions + CYCLE_TRAIL >= MAX_TRANSITIONS {
+                trail.push((thread.pc, line));
+            }
+            let stay = if fork { thread.weight } else { thread.weight - taken };
+            if stay > 0 {
+                pending.push((thread.goto(thread.pc + 1).weighted(stay), transitions + 1, trail.clone()));
+            }
+            let mut started = None;
+            if taken > 0 {
+                let to = if fork {
+                    *next_id += 1;
+                    started = Some(*next_id);
+                    thread.spawn(line, *next_id)
+                } else {
+                    thread.goto(line)
+                };
+                pending.push((to.weighted(taken), transitions + 1, trail));
+            }
+            if let Some(ref mut explain) = hook.branches {
+                if (p > 0.0 && p < 1.0) || started.is_some() {
+                    let span = spans.get(thread.pc).cloned().unwrap_or(None);
+                    explain.push(Branch { time: now.secs, thread: thread.id, pc: thread.pc, span, to: line, p, fork,
+                                          taken, weight: thread.weight, started });
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// the length of a duration in samples at the given tempo
+fn dur_samples(dur: Dur, bpm: f64) -> f64 {
+    match dur {
+        Dur::Secs(secs) => secs*SAMPLE_RATE,
+        Dur::Beats(beats) => beats*60.0/bpm*SAMPLE_RATE,
+        // only reached for a note that hasn't drawn its length yet
+        Dur::RandomSecs(dist) => dur_samples(Dur::Secs(dist.mean()), bpm),
+        Dur::RandomBeats(dist) => dur_samples(Dur::Beats(dist.mean()), bpm),
+    }
+}
+
+/// Pick a length for a note that's starting. Random lengths come out
+/// negative now and then, and those notes are skipped.
+fn draw_dur<R: Rng>(dur: Dur, rng: &mut R) -> Dur {
+    match dur {
+        Dur::RandomSecs(dist) => Dur::Secs(dist.sample(rng).max(0.0)),
+        Dur::RandomBeats(dist) => Dur::Beats(dist.sample(rng).max(0.0)),
+        _ => dur,
+    }
+}
+
+/// how long each note or step of an instruction lasts, if it plays any
+fn duration_of(instruction: &Instruction) -> Option<Dur> {
+    match *instruction {
+        Sin(_, duration) | Bend(_, duration) | Sweep(_, _, duration, _) | Zap(_, _, duration, _)
+        | Walk(_, _, duration, _, _) | Euclid(_, duration, _) | Ca(_, _, duration, _, _)
+        | Custom(_, _, duration) => Some(duration),
+        Play(ref chain, _) => Some(chain.dur),
+        _ => None,
+    }
+}
+
+/// true if `instruction` draws random numbers as the thread starts it
+fn draws(instruction: &Instruction, thread: &ThreadState, humanize: &Humanize) -> bool {
+    let duration = duration_of(instruction);
+    let random_dur = matches!(duration, Some(Dur::RandomSecs(_)) | Some(Dur::RandomBeats(_)));
+    let random_pitch = matches!(*instruction, Walk(..) | Play(..)) && thread.note > 0;
+    let humanized = matches!(*instruction, Sin(..) | Sweep(..) | Zap(..) | Walk(..) | Play(..) | Custom(..))
+        && (humanize.vel > 0.0 || humanize.time > 0.0);
+    random_dur || random_pitch || humanized
+}
+
+/// A weighted thread starting a note that draws random numbers would give
+/// every thread it stands for the same draw, so it splits back up first.
+fn splits(thread: &ThreadState, instructions: &[Instruction], humanize: &Humanize) -> bool {
+    thread.weight > 1 && thread.sin_progress == 0 && draws(&instructions[thread.pc], thread, humanize)
+}
+
+/// `threads` with each that `splits` split up, into `result`
+fn split_drawing(threads: &[ThreadState], instructions: &[Instruction], humanize: &Humanize,
+                 result: &mut Vec<ThreadState>) {
+    result.clear();
+    for thread in threads {
+        if splits(thread, instructions, humanize) {
+            result.extend((0..thread.weight).map(|_| thread.weighted(1)));
+        } else {
+            result.push(*thread);
+        }
+    }
+}
+
+/// Merge threads in exactly the same state into one weighted thread, into
+/// `pools.merged`. They play the same samples until something random sends
+/// them different ways, and that splits them up again.
+fn merge_identical(threads: &[ThreadState], pools: &mut Pools) {
+    let (merged, at, same_place) = (&mut pools.merged, &mut pools.at, &mut pools.same_place);
+    merged.clear();
+    at.clear();
+    same_place.clear();
+    for thread in threads {
+        // only threads at the same place in the same note, at the same point
+        // in the wave, can be the same
+        let place = (thread.pc, thread.sin_progress, thread.phase.to_bits());
+        let mut candidate = at.get(&place).cloned();
+        while let Some(i) = candidate {
+            if merged[i].same_voice(thread) {
+                break;
+            }
+            candidate = same_place[i];
+        }
+        match candidate {
+            // a program that forks forever can outgrow any count
+            Some(i) => merged[i].weight = merged[i].weight.saturating_add(thread.weight),
+            None => {
+                same_place.push(at.insert(place, merged.len()));
+                merged.push(*thread);
+            },
+        }
+    }
+}
+
+/// A note starting, as recorded by a dry run.
+pub struct Event {
+    /// seconds from the start
+    pub time: f64,
+    /// the thread that played it
+    pub thread: usize,
+    pub pc: usize,
+    /// the instruction's keyword
+    pub op: &'static str,
+    /// the pitch it starts on
+    pub freq: f64,
+    /// and the pitch it ends up on
+    pub to: f64,
+    /// how long it lasts in seconds, at the tempo it started at
+    pub dur: f64,
+    /// the threads playing it, and how hard
+    pub weight: usize,
+    pub velocity: f64,
+    /// the sample it starts on
+    pub start: u64,
+    /// the line that played it
+    pub span: Option<Span>,
+}
+
+/// where the current time step is in musical time
+struct Clock {
+    bpm: f64,
+    /// beats since the start
+    beat_pos: f64,
+    swing: Option<f64>,
+    /// samples since the start
+    now: u64,
+    /// how loud a thread a fork starts plays next to its parent
+    fork_decay: f64,
+}
+
+/// the modulation bus, which carries the waves of the threads a `modulate`
+/// made modulators over to the pitches of the buses they're aimed at
+struct Fm<'a> {
+    /// the bus each instruction plays into
+    bus_of: &'a [usize],
+    /// how many Hz each bus's pitches are pushed by on this time step, from
+    /// its modulators on the last one
+    now: &'a [f64],
+    /// and on the next, from its modulators on this one
+    next: &'a mut [f64],
+}
+
+/// what `interpret_sin` makes of the threads that sound
+enum Collect<'a> {
+    /// a sample each, to mix, and the notes that start if they're wanted,
+    /// with the modulators' samples going to the modulation bus instead
+    Samples(Option<&'a mut Vec<Event>>, Fm<'a>),
+    /// the notes that start, for a dry run, without computing any samples
+    Events(&'a mut Vec<Event>),
+    /// the threads themselves, for a voice model to play, with each
+    /// instruction's patch
+    Voices(&'a mut Vec<Voice>, &'a [Patch], Option<&'a mut Vec<Event>>),
+}
+
+impl<'a> Collect<'a> {
+    /// where to note down the notes that start, if anywhere
+    fn events(&mut self) -> Option<&mut Vec<Event>> {
+        match *self {
+            Collect::Samples(ref mut events, _) | Collect::Voices(_, _, ref mut events) => events.as_deref_mut(),
+            Collect::Events(ref mut events) => Some(&mut **events),
+        }
+    }
+}
+
+/// compute each sounding thread's sample for this time step, with the
+/// instruction it's from, into `pools.samples`, and the threads after it
+/// into `pools.after`, or whatever else `collect` asks for, and return how
+/// many threads the samples stand for
+/// pre: all threads are at a sin instruction or waiting for a boundary
+#[allow(clippy::too_many_arguments)]
+fn interpret_sin<R: Rng>(threads: &[ThreadState], instructions: &[Instruction], clock: &Clock, humanize: &Humanize,
+                         rng: &mut R, mut collect: Collect, next_id: &mut usize, pools: &mut Pools) -> f64 {
+    let (new_threads, current_samples) = (&mut pools.after, &mut pools.samples);
+    new_threads.clear();
+    current_samples.clear();
+    let mut voices = 0.0;
+
+    // most steps nothing splits, and the threads are only read as they are
+    let threads = if threads.iter().any(|thread| splits(thread, instructions, humanize)) {
+        split_drawing(threads, instructions, humanize, &mut pools.split);
+        &pools.split[..]
+    } else {
+        threads
+    };
+    for thread in threads {
+        let duration = match instructions[thread.pc] {
+            Sin(_, duration) | Bend(_, duration) | Sweep(_, _, duration, _) | Zap(_, _, duration, _)
+            | Walk(_, _, duration, _, _) | Custom(_, _, duration) => duration,
+            Play(ref chain, _) => chain.dur,
+            Euclid(_, step, _) | Ca(_, _, step, _, _) => step,
+            QJump(..) | QFork(..) => {
+                new_threads.push(*thread);
+                continue;
+            },
+            _ => panic!("interpret_sin precondition not met"),
+        };
+        let mut thread = *thread;
+        if thread.sin_progress == 0 {
+            if let Dur::RandomSecs(_) | Dur::RandomBeats(_) = duration {
+                thread.drawn = Some(draw_dur(duration, &mut draws_for(&mut thread.stream, rng)));
+            }
+        }
+        let duration = thread.drawn.unwrap_or(duration);
+        if thread.sin_progress == 0 {
+            if let (Some(swing), Dur::Beats(beats)) = (clock.swing, duration) {
+                thread.swung_beats = Some(swung_length(clock.beat_pos, beats, swing));
+            }
+            if let Play(ref chain, _) = instructions[thread.pc] {
+                if thread.note > 0 {
+                    thread.chain_state = chain.next(thread.chain_state, &mut draws_for(&mut thread.stream, rng));
+                }
+                thread.note_freq = chain.pitches[thread.chain_state];
+            }
+            if let Walk(start, step, _, _, ref scale) = instructions[thread.pc] {
+                thread.note_freq = if thread.note == 0 {
+                    match scale.as_ref() {
+                        Some(pitches) => pitches[nearest(pitches, start)],
+                        None => start,
+                    }
+                } else {
+                    let up = unit_random(&mut draws_for(&mut thread.stream, rng)) < 0.5;
+                    let freq = bent(thread.note_freq, if up { step } else { -step });
+                    match scale.as_ref() {
+                        // a step too small to reach the next pitch of the
+                        // scale still moves to it
+                        Some(pitches) => {
+                            let (from, to) = (nearest(pitches, thread.note_freq), nearest(pitches, freq));
+                            pitches[if to != from {
+                                to
+                            } else if up {
+                                (from + 1).min(pitches.len() - 1)
+                            } else {
+                                from.saturating_sub(1)
+                            }]
+                        },
+                        None => freq,
+                    }
+                };
+            }
+            match instructions[thread.pc] {
+                // a bend carries on from wherever the last note got to, the
+                // rest start at the top of their wave
+                Bend(_, _) => thread.bend_from = thread.freq,
+                // each step of a rhythm is silent itself, and starts a thread
+                // to play its note if it's an onset
+                Euclid(ref pattern, _, note) => if pattern[thread.note] {
+                    *next_id += 1;
+                    new_threads.push(thread.hit(note, *next_id));
+                },
+                // likewise each generation of an automaton, for each cell
+                // that's alive
+                Ca(rule, width, _, _, first) => {
+                    generation(rule, width, thread.note, &mut pools.cells, &mut pools.next_cells);
+                    for (cell, &alive) in pools.cells.iter().enumerate() {
+                        if alive {
+                            *next_id += 1;
+                            new_threads.push(thread.hit(first + 2*cell, *next_id));
+                        }
+                    }
+                },
+                _ => {
+                    thread.phase = 0.0;
+                    // each generation of forks a step quieter than the one
+                    // before, so the threads the program started with
+                    // stay in front
+                    thread.velocity = clock.fork_decay.powi(thread.generation.min(i32::MAX as usize) as i32);
+                    // only draw when asked to, so programs without it keep
+                    // the random choices they've always made
+                    if humanize.vel > 0.0 {
+                        thread.velocity *= 1.0 - humanize.vel*unit_random(&mut draws_for(&mut thread.stream, rng));
+                    }
+                    // a late start comes out of the note itself, so the next
+                    // one is still on time
+                    if humanize.time > 0.0 {
+                        let late = unit_random(&mut draws_for(&mut thread.stream, rng));
+                        thread.rest = (humanize.time*SAMPLE_RATE*late) as i64;
+                    }
+                },
+            }
+        }
+        let length = thread.length(duration, clock.bpm);
+        let sounding = (length - thread.rest as f64).max(0.0);
+        if let Some(events) = collect.events() {
+            // a modulator's notes aren't heard
+            if thread.sin_progress == 0 && sounding > 0.0 && thread.modulates.is_none() {
+                let instruction = &instructions[thread.pc];
+                let dur = sounding/SAMPLE_RATE;
+                if let (Some(freq), Some(to)) = (freq_at(instruction, &thread, 0.0, 0.0),
+                                                 freq_at(instruction, &thread, 1.0, dur)) {
+                    let start = clock.now + thread.rest as u64;
+                    events.push(Event { time: start as f64/SAMPLE_RATE, thread: thread.id, pc: thread.pc,
+                                        op: instruction.op(), freq, to, dur, weight: thread.weight,
+                                        velocity: thread.velocity, start, span: None });
+                }
+            }
+        }
+        if (thread.sin_progress as f64) < length {
+            let instruction = &instructions[thread.pc];
+            if let Some((freq, secs)) = sounding_at(instruction, &thread, sounding) {
+                let mut pushed = 0.0;
+                match collect {
+                    Collect::Samples(_, ref mut fm) => {
+                        let sample = Graph::of(instruction, thread.velocity).sample(secs, thread.phase);
+                        match thread.modulates {
+                            Some((bus, depth)) => fm.next[bus] += thread.weight as f64*depth*sample as f64,
+                            None => {
+                                current_samples.push((thread.pc, thread.weight as f32*sample));
+                                voices += thread.weight as f64;
+                            },
+                        }
+                        pushed = fm.now[fm.bus_of[thread.pc]];
+                    },
+                    // a voice model's chips can't modulate each other, so
+                    // modulators are only left out
+                    Collect::Voices(ref mut sounding, patches, _) if thread.modulates.is_none() => {
+                        let gain = Graph::of(instruction, thread.velocity).level(secs);
+                        let drum = matches!(*instruction, Zap(..));
+                        sounding.push(Voice { id: thread.id, freq, gain, drum, patch: patches[thread.pc] });
+                    },
+                    Collect::Voices(..) | Collect::Events(_) => {},
+                }
+                thread.advance(freq);
+                // only the wave's pushed, so a bend still goes from the
+                // pitch as written
+                if pushed != 0.0 {
+                    thread.phase = (thread.phase + pushed/SAMPLE_RATE).rem_euclid(1.0);
+                }
+            }
+            thread.sin_progress += 1;
+            new_threads.push(thread);
+        } else {
+            // land exactly where a bend or sweep was headed
+            if let Some(freq) = freq_at(&instructions[thread.pc], &thread, 1.0, sounding/SAMPLE_RATE) {
+                thread.freq = Some(freq);
+            }
+            match instructions[thread.pc] {
+                Walk(_, _, _, n, _) | Play(_, n) if thread.note + 1 < n => new_threads.push(thread.next_note()),
+                Euclid(ref pattern, _, _) if thread.note + 1 < pattern.len() => new_threads.push(thread.next_note()),
+                Ca(_, _, _, n, _) if thread.note + 1 < n => new_threads.push(thread.next_note()),
+                _ => new_threads.push(thread.goto(thread.pc + 1)),
+            }
+        }
+    }
+
+    voices
+}
+
+fn build_rand(seed: usize) -> Generator {
+    Box::new(StdRng::seed_from_u64(seed as u64))
+}
+
+/// Interprets a list of instructions one time step at a time. All of the
+/// interpreter state lives here, so rendering can stop after any block and
+/// pick up again later exactly where it left off.
+pub struct Renderer {
+    instructions: Vec<Instruction>,
+    /// where each instruction came from
+    spans: Vec<Option<Span>>,
+    /// the mixer's buses, the bus each instruction plays into, how loud each
+    /// bus is turned up, and the loudest each has been since the meters were
+    /// last read
+    buses: Vec<String>,
+    bus_of: Vec<usize>,
+    gains: Vec<Smoothed>,
+    /// how many samples a change to a gain or the tempo glides in over
+    ramp: f64,
+    /// the `@duty` and `@wave` of each instruction, for a voice model
+    patches: Vec<Patch>,
+    meters: Vec<f64>,
+    /// the `@duck`s, each following its source bus
+    followers: Vec<Follower>,
+    /// how many Hz the modulators are pushing each bus's pitches by, see
+    /// `Fm`
+    modulation: Vec<f64>,
+    /// each bus's samples since `take_bus_blocks`, if they're being kept
+    split: Option<Vec<Vec<f32>>>,
+    pools: Pools,
+    /// what the `@duck`s turn each bus down to on this time step
+    ducked: Vec<f64>,
+    /// the `@anneal`s, with the seconds and beats in they started at
+    anneals: Vec<(Anneal, f64, f64)>,
+    /// the `@listen`s, each with the level it heard over the last block,
+    /// and the sum of the squares of the mix's samples and of each bus's so
+    /// far in this one
+    listens: Vec<(Listen, f64)>,
+    heard: f64,
+    heard_buses: Vec<f64>,
+    /// the program's own tempo, or the one it's been set to since
+    bpm: Smoothed,
+    /// beats per bar
+    meter: f64,
+    swing: Option<f64>,
+    humanize: Humanize,
+    fork_decay: f64,
+    /// beats since the start, at this time step and the one before
+    beat_pos: f64,
+    last_beat_pos: f64,
+    /// overrides the program's tempo, e.g. to follow an external clock
+    tempo: Option<Box<dyn TempoSource + Send>>,
+    threads: Vec<ThreadState>,
+    rng: Decisions,
+    /// the words drawn from `rng`'s generator so far, see `Counted`
+    words: u64,
+    /// whether `set_rng` swapped the seeded generator for another
+    own_rng: bool,
+    /// samples rendered so far
+    now: u64,
+    /// a dry run records the notes played instead of computing any samples
+    dry_run: bool,
+    /// true to record them while rendering as well
+    emit: bool,
+    events: Vec<Event>,
+    /// the branches taken and not taken, when they're being explained
+    branches: Option<Vec<Branch>>,
+    /// the debugger's breakpoints, each with whether it held on the last
+    /// time step, the pcs of the labels among them, and the one that's
+    /// been hit if one has
+    breakpoints: Vec<(Breakpoint, bool)>,
+    watched: Vec<usize>,
+    broke: Option<Break>,
+    /// the most threads there can be at once, which to stop to stay under
+    /// it, and how many have been stopped so far
+    max_threads: Option<(usize, Steal)>,
+    stolen: usize,
+    /// merge threads in identical states after every time step
+    dedup: bool,
+    /// what stopped the program, if something did
+    error: Option<RuntimeError>,
+    /// the number of the latest thread started
+    next_id: usize,
+    /// plays the threads through an old sound chip's channels instead of
+    /// the mixer
+    voice_model: Option<Box<dyn VoiceModel + Send>>,
+}
+
+impl Renderer {
+    pub fn new(program: Program, seed: usize) -> Renderer {
+        Renderer {
+            instructions: program.instructions,
+            spans: program.spans,
+            gains: vec![Smoothed::new(1.0); program.buses.len()],
+            ramp: DEFAULT_RAMP*SAMPLE_RATE,
+            meters: vec![0.0; program.buses.len()],
+            followers: program.ducks.into_iter().map(Follower::new).collect(),
+            modulation: vec![0.0; program.buses.len()],
+            split: None,
+            pools: Pools::default(),
+            ducked: vec![],
+            anneals: program.anneals.into_iter().map(|anneal| (anneal, 0.0, 0.0)).collect(),
+            listens: program.listens.into_iter().map(|listen| (listen, 0.0)).collect(),
+            heard: 0.0,
+            heard_buses: vec![],
+            buses: program.buses,
+            bus_of: program.bus_of,
+            patches: program.patches,
+            bpm: Smoothed::new(program.bpm),
+            meter: program.meter,
+            swing: program.swing,
+            fork_decay: program.fork_decay,
+            humanize: program.humanize,
+            beat_pos: 0.0,
+            // so that the very first time step is on a beat and a bar
+            last_beat_pos: -1.0,
+            tempo: None,
+            threads: vec![ThreadState::start(0)],
+            rng: Decisions::Live(build_rand(seed)),
+            words: 0,
+            own_rng: false,
+            now: 0,
+            dry_run: false,
+            emit: false,
+            events: vec![],
+            branches: None,
+            breakpoints: vec![],
+            watched: vec![],
+            broke: None,
+            max_threads: None,
+            stolen: 0,
+            dedup: false,
+            error: None,
+            next_id: 0,
+            voice_model: None,
+        }
+    }
+
+    /// Follow `tempo` instead of the program's `@bpm`. It's asked again on
+    /// every sample, so tempo changes take effect right away.
+    pub fn set_tempo_source(&mut self, tempo: Box<dyn TempoSource + Send>) {
+        self.tempo = Some(tempo);
+    }
+
+    /// the tempo right now
+    pub fn bpm(&self) -> f64 {
+        match self.tempo {
+            Some(ref tempo) => tempo.bpm(),
+            None => self.bpm.value(),
+        }
+    }
+
+    /// Glide to `bpm` over `secs`, or say why it can't: it's following a
+    /// `TempoSource`.
+    pub fn ramp_bpm(&mut self, bpm: f64, secs: f64) -> Result<(), String> {
+        if self.tempo.is_some() {
+            return Err("the tempo's coming from an external clock".to_string());
+        }
+        self.bpm.set(bpm, secs*SAMPLE_RATE);
+        Ok(())
+    }
+
+    /// How long from now on a change to a bus's gain or the tempo glides in
+    /// over, in seconds, by default `smooth::DEFAULT_RAMP`.
+    pub fn set_ramp(&mut self, secs: f64) {
+        self.ramp = secs*SAMPLE_RATE;
+    }
+
+    /// see `set_ramp`
+    pub fn ramp(&self) -> f64 {
+        self.ramp/SAMPLE_RATE
+    }
+
+    /// Go through the program's control flow without computing any audio:
+    /// every sample comes out as silence, and each note that starts is
+    /// recorded for `take_events`. Random choices come out just as they
+    /// would in a real render with the same seed.
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+
+    /// Record each note that starts for `take_events` while rendering as
+    /// usual, the way a dry run would.
+    pub fn set_emit(&mut self, emit: bool) {
+        self.emit = emit;
+    }
+
+    /// Merge threads that end up in exactly the same state, say after a fork
+    /// sends two down the same path, into one that's mixed in as many times.
+    /// A merged thread decides a branch for all the threads it stands for in
+    /// one go, so the work stays the same however many clones there are. The
+    /// random numbers it takes to do that are different, though, so the
+    /// output isn't the same as without it for the same seed.
+    pub fn set_dedup(&mut self, dedup: bool) {
+        self.dedup = dedup;
+    }
+
+    /// Never have more than `max` threads at once, stopping threads as
+    /// `steal` says when new ones would go over, rather than letting a
+    /// program that forks too much slow everything to a crawl.
+    pub fn set_max_threads(&mut self, max: usize, steal: Steal) {
+        self.max_threads = Some((max, steal));
+    }
+
+    /// Size the buffers the render reuses for `threads` threads at once up
+    /// front, so a realtime host's audio thread doesn't have to grow them
+    /// the first time there are that many. Going over only grows them again.
+    pub fn reserve_threads(&mut self, threads: usize) {
+        let pools = &mut self.pools;
+        for buffer in [&mut pools.dispatched, &mut pools.split, &mut pools.after, &mut self.threads] {
+            buffer.reserve(threads.saturating_sub(buffer.len()));
+        }
+        pools.pending.reserve(threads);
+        pools.samples.reserve(threads);
+    }
+
+    /// how many threads have been stopped to stay under `set_max_threads`
+    pub fn stolen(&self) -> usize {
+        self.stolen
+    }
+
+    /// the notes a dry run has started since this was last called
+    pub fn take_events(&mut self) -> Vec<Event> {
+        let mut events = ::std::mem::take(&mut self.events);
+        for event in &mut events {
+            event.span = self.spans.get(event.pc).cloned().unwrap_or(None);
+        }
+        events
+    }
+
+    /// Note down every jump and fork that could have gone either way, and
+    /// every fork that starts a thread, for `take_branches`.
+    pub fn set_explain(&mut self, explain: bool) {
+        self.branches = if explain { Some(vec![]) } else { None };
+    }
+
+    /// the branches noted down since this was last called
+    pub fn take_branches(&mut self) -> Vec<Branch> {
+        self.branches.as_mut().map_or(vec![], ::std::mem::take)
+    }
+
+    /// Stop rendering a block as soon as one of `breakpoints` is hit, for
+    /// `take_break` to say which.
+    pub fn set_breakpoints(&mut self, breakpoints: Vec<Breakpoint>) {
+        self.watched = breakpoints.iter().filter_map(|breakpoint| match *breakpoint {
+            Breakpoint::Label(_, pc) => Some(pc),
+            Breakpoint::Threads(..) => None,
+        }).collect();
+        self.breakpoints = breakpoints.into_iter().map(|breakpoint| (breakpoint, false)).collect();
+    }
+
+    /// the breakpoint hit since this was last called, if one was
+    pub fn take_break(&mut self) -> Option<Break> {
+        self.broke.take()
+    }
+
+    /// samples rendered so far
+    pub fn samples(&self) -> u64 {
+        self.now
+    }
+
+    /// seconds rendered so far
+    pub fn elapsed(&self) -> f64 {
+        self.now as f64/SAMPLE_RATE
+    }
+
+    /// What went wrong, if the program was stopped by a runtime error rather
+    /// than finishing. Once it's taken the renderer is simply finished.
+    pub fn take_error(&mut self) -> Option<RuntimeError> {
+        self.error.take()
+    }
+
+    /// Draw random numbers from `rng` instead of the seeded generator, such
+    /// as a hardware generator on a board with no other source of entropy.
+    /// Recording and replaying go on as before, only with `rng` underneath.
+    /// A render drawing from it can't be saved, see `save_state`.
+    pub fn set_rng(&mut self, rng: Generator) {
+        *self.rng.generator() = rng;
+        self.own_rng = true;
+    }
+
+    /// Everything about how far the render has got that the program, the
+    /// seed and the settings don't already say, for `checkpoint`: the
+    /// threads, the clock, the `@duck` followers, what the `@listen`s have
+    /// heard and how far into its generator it is. Only a render mixing sine
+    /// waves to its own tempo, from the generator it was seeded with, can be
+    /// saved.
+    pub fn save_state(&self) -> Result<Vec<u8>, String> {
+        if self.voice_model.is_some() {
+            return Err("a voice model's chips can't be saved".to_string());
+        }
+        if self.tempo.is_some() {
+            return Err("an external tempo can't be saved".to_string());
+        }
+        if !matches!(self.rng, Decisions::Live(_)) {
+            return Err("a session being recorded or replayed can't be saved".to_string());
+        }
+        // resuming takes the words drawn from the seeded generator again,
+        // which aren't the ones this one drew
+        if self.own_rng {
+            return Err("random numbers from a generator of its own can't be saved".to_string());
+        }
+        let mut out = Encoder::default();
+        out.u64(self.now);
+        out.f64(self.beat_pos);
+        out.f64(self.last_beat_pos);
+        out.u64(self.words);
+        out.u64(self.next_id as u64);
+        out.u64(self.stolen as u64);
+        out.u64(self.followers.len() as u64);
+        for follower in &self.followers {
+            out.f64(follower.envelope());
+        }
+        out.u64(self.modulation.len() as u64);
+        for &hz in &self.modulation {
+            out.f64(hz);
+        }
+        out.u64(self.listens.len() as u64);
+        for &(_, heard) in &self.listens {
+            out.f64(heard);
+        }
+        out.f64(self.heard);
+        out.u64(self.heard_buses.len() as u64);
+        for &heard in &self.heard_buses {
+            out.f64(heard);
+        }
+        out.u64(self.threads.len() as u64);
+        for thread in &self.threads {
+            thread.save(&mut out);
+        }
+        Ok(out.into_bytes())
+    }
+
+    /// Carry on from `state`, from `save_state` on a renderer of the same
+    /// program, seed and settings, as though it had rendered up to there
+    /// itself. Only the threads' origins are lost, so a runtime error can't
+    /// say how a thread came to be.
+    ///
+    /// The generator can't be wound forward, so it's taken back to where it
+    /// was by drawing every word it had drawn again, one at a time. Loading
+    /// a checkpoint takes time in proportion to how many random numbers the
+    /// render had used, then, rather than to the size of the checkpoint.
+    pub fn load_state(&mut self, state: &[u8]) -> io::Result<()> {
+        let bad = |what: &str| io::Error::new(io::ErrorKind::InvalidData, format!("bad checkpoint: {}", what));
+        let mut from = Decoder::new(state);
+        let now = from.u64()?;
+        let (beat_pos, last_beat_pos) = (from.f64()?, from.f64()?);
+        let words = from.u64()?;
+        let (next_id, stolen) = (from.usize()?, from.usize()?);
+        if from.usize()? != self.followers.len() {
+            return Err(bad("it's of a program with other @ducks"));
+        }
+        let envelopes = (0..self.followers.len()).map(|_| from.f64()).collect::<io::Result<Vec<f64>>>()?;
+        if from.usize()? != self.buses.len() {
+            return Err(bad("it's of a program with other buses"));
+        }
+        let modulation = (0..self.buses.len()).map(|_| from.f64()).collect::<io::Result<Vec<f64>>>()?;
+        if from.usize()? != self.listens.len() {
+            return Err(bad("it's of a program with other @listens"));
+        }
+        let levels = (0..self.listens.len()).map(|_| from.f64()).collect::<io::Result<Vec<f64>>>()?;
+        let heard = from.f64()?;
+        let heard_buses = from.usize()?;
+        if heard_buses > self.buses.len() {
+            return Err(bad("it's of a program with other buses"));
+        }
+        let heard_buses = (0..heard_buses).map(|_| from.f64()).collect::<io::Result<Vec<f64>>>()?;
+        let count = from.usize()?;
+        let mut threads = vec![];
+        for _ in 0..count {
+            let thread = ThreadState::load(&mut from)?;
+            if thread.pc >= self.instructions.len() {
+                return Err(bad("a thread is past the end of the program"));
+            }
+            if thread.modulates.is_some_and(|(bus, _)| bus >= self.buses.len()) {
+                return Err(bad("a thread modulates a bus the program doesn't have"));
+            }
+            threads.push(thread);
+        }
+        from.finish()?;
+        for (follower, envelope) in self.followers.iter_mut().zip(envelopes) {
+            follower.set_envelope(envelope);
+        }
+        for (&mut (ref listen, ref mut heard), level) in self.listens.iter_mut().zip(levels) {
+            *heard = level;
+            set_prob(&mut self.instructions, &listen.pcs, listen.prob(level));
+        }
+        self.heard = heard;
+        self.heard_buses = heard_buses;
+        // the generator was seeded when the renderer was made, and drawing
+        // the same number of words again takes it to the same place, one by
+        // one since a boxed generator has no way to skip ahead
+        for _ in self.words..words {
+            self.rng.next_u32();
+        }
+        self.words = self.words.max(words);
+        self.now = now;
+        self.beat_pos = beat_pos;
+        self.last_beat_pos = last_beat_pos;
+        self.next_id = next_id;
+        self.stolen = stolen;
+        self.threads = threads;
+        self.modulation = modulation;
+        Ok(())
+    }
+
+    /// Write down every random number drawn from here on, for a session.
+    pub fn record_decisions(&mut self) {
+        self.rng = match ::std::mem::replace(&mut self.rng, Decisions::Live(build_rand(0))) {
+            Decisions::Live(rng) => Decisions::Recording(rng, vec![]),
+            other => other,
+        };
+    }
+
+    /// the random numbers written down since `record_decisions`
+    pub fn take_decisions(&mut self) -> Vec<u64> {
+        match self.rng {
+            Decisions::Recording(_, ref mut log) => ::std::mem::take(log),
+            _ => vec![],
+        }
+    }
+
+    /// Draw random numbers from `log`, as a recorded session did, instead of
+    /// the generator.
+    pub fn replay_decisions(&mut self, log: Vec<u64>) {
+        let fallback = match ::std::mem::replace(&mut self.rng, Decisions::Live(build_rand(0))) {
+            Decisions::Live(rng) | Decisions::Recording(rng, _) | Decisions::Replaying { fallback: rng, .. } => rng,
+        };
+        self.rng = Decisions::Replaying { log, next: 0, fallback };
+    }
+
+    /// true if a replay has drawn more random numbers than were recorded, so
+    /// it's no longer following the session
+    pub fn replay_diverged(&self) -> bool {
+        self.rng.ran_out()
+    }
+
+    /// true once every thread has reached the terminate instruction
+    pub fn is_finished(&self) -> bool {
+        self.threads.is_empty()
+    }
+
+    /// how many threads are alive
+    pub fn thread_count(&self) -> usize {
+        self.threads.iter().fold(0, |count: usize, thread| count.saturating_add(thread.weight))
+    }
+
+    /// the instruction each live thread is at
+    pub fn thread_pcs(&self) -> Vec<usize> {
+        self.threads.iter().map(|thread| thread.pc).collect()
+    }
+
+    /// what each live thread is doing, in the order they're mixed
+    pub fn threads(&self) -> Vec<ThreadInfo> {
+        let bpm = self.bpm();
+        self.threads.iter().map(|thread| {
+            let instruction = &self.instructions[thread.pc];
+            let remaining = duration_of(instruction).map(|duration| {
+                (thread.length(duration, bpm) - thread.sin_progress as f64).max(0.0)/SAMPLE_RATE
+            });
+            ThreadInfo {
+                id: thread.id,
+                pc: thread.pc,
+                span: self.spans.get(thread.pc).cloned().unwrap_or(None),
+                op: instruction.op(),
+                weight: thread.weight,
+                remaining,
+            }
+        }).collect()
+    }
+
+    /// Stop thread `id` dead, wherever it is. Returns false if there's no
+    /// such thread.
+    pub fn kill(&mut self, id: usize) -> bool {
+        let before = self.threads.len();
+        self.threads.retain(|thread| thread.id != id);
+        self.threads.len() != before
+    }
+
+    /// Stop every thread playing into `bus`, wherever it is. Returns how
+    /// many there were.
+    pub fn kill_bus(&mut self, bus: usize) -> usize {
+        let before = self.thread_count();
+        let bus_of = &self.bus_of;
+        self.threads.retain(|thread| bus_of[thread.pc] != bus);
+        before - self.thread_count()
+    }
+
+    /// Start a new thread at instruction `pc`, alongside the ones already
+    /// playing, and return its number.
+    pub fn spawn(&mut self, pc: usize) -> usize {
+        self.next_id += 1;
+        self.threads.push(ThreadState { pc, ..ThreadState::start(self.next_id) });
+        self.next_id
+    }
+
+    /// Add `program` after the end of the one playing and start a thread at
+    /// its top, alongside the ones already playing, returning the thread's
+    /// number. Its buses join the mixer's. Its tempo and the rest of its
+    /// directives are left out, so the program that's playing keeps its own.
+    pub fn inject(&mut self, program: Program) -> usize {
+        let start = self.instructions.len();
+        for mut instruction in program.instructions {
+            instruction.relocate(start);
+            self.instructions.push(instruction);
+        }
+        self.spans.extend(program.spans);
+        // where each of its buses is among the mixer's
+        let buses: Vec<usize> = program.buses.iter().map(|name| {
+            match self.buses.iter().position(|playing| playing == name) {
+                Some(bus) => bus,
+                None => {
+                    self.buses.push(name.clone());
+                    self.gains.push(Smoothed::new(1.0));
+                    self.meters.push(0.0);
+                    self.buses.len() - 1
+                },
+            }
+        }).collect();
+        self.bus_of.extend(program.bus_of.iter().map(|&bus| buses[bus]));
+        for instruction in &mut self.instructions[start..] {
+            if let Modulate(Some((ref mut bus, _))) = *instruction {
+                *bus = buses[*bus];
+            }
+        }
+        for duck in program.ducks {
+            let (target, source) = (buses[duck.target], buses[duck.source]);
+            self.followers.push(Follower::new(Duck { target, source, ..duck }));
+        }
+        self.patches.extend(program.patches);
+        // an injected program's anneals start when it does
+        for mut anneal in program.anneals {
+            for pc in &mut anneal.pcs {
+                *pc += start;
+            }
+            self.anneals.push((anneal, self.elapsed(), self.beat_pos));
+        }
+        for mut listen in program.listens {
+            for pc in &mut listen.pcs {
+                *pc += start;
+            }
+            listen.bus = listen.bus.map(|bus| buses[bus]);
+            self.listens.push((listen, 0.0));
+        }
+        self.spawn(start)
+    }
+
+    /// Start the program over from the top with a single thread. The random
+    /// number generator carries on where it was.
+    pub fn restart(&mut self) {
+        self.next_id += 1;
+        self.threads = vec![ThreadState::start(self.next_id)];
+    }
+
+    /// Advance one time step and return its sample in range -1 to 1, or None
+    /// when the program is finished.
+    pub fn next_sample(&mut self) -> Option<f64> {
+        if self.is_finished() {
+            return None;
+        }
+        let (secs, beats) = (self.elapsed(), self.beat_pos);
+        for &(ref anneal, from_secs, from_beats) in &self.anneals {
+            set_prob(&mut self.instructions, &anneal.pcs, anneal.prob(secs - from_secs, beats - from_beats));
+        }
+        let crossed = |unit: f64| (self.last_beat_pos / unit).floor() != (self.beat_pos / unit).floor();
+        let now = Boundaries { beat: crossed(1.0), bar: crossed(self.meter), secs: self.elapsed() };
+        let mut rng = Counted { rng: &mut self.rng, words: &mut self.words };
+        let mut hook = Hook { branches: self.branches.as_mut(), watched: &self.watched, reached: None };
+        let mut pools = ::std::mem::take(&mut self.pools);
+        if let Err(e) = interpret_to_sin(&self.threads, &self.instructions, &self.spans, &now, &mut rng,
+                                         &mut self.next_id, &mut hook, &mut pools) {
+            self.error = Some(e);
+            self.threads.clear();
+            self.pools = pools;
+            return None;
+        }
+        let reached = hook.reached;
+        let mut threads = ::std::mem::take(&mut pools.dispatched);
+        if self.dedup {
+            merge_identical(&threads, &mut pools);
+            ::std::mem::swap(&mut threads, &mut pools.merged);
+        }
+        if let Some((max, steal)) = self.max_threads {
+            let (instructions, bus_of, gains, fork_decay) = (&self.instructions, &self.bus_of, &self.gains,
+                                                             self.fork_decay);
+            let loud = |thread: &ThreadState| loudness(thread, instructions, bus_of, gains, fork_decay);
+            self.stolen += steal_threads(&mut threads, max, steal, &loud);
+        }
+        let bpm = self.bpm();
+        let clock = Clock { bpm, beat_pos: self.beat_pos, swing: self.swing, now: self.now,
+                            fork_decay: self.fork_decay };
+        let mut sounding = vec![];
+        // buses can come and go with the program
+        self.modulation.resize(self.buses.len(), 0.0);
+        let mut modulation = ::std::mem::take(&mut pools.modulation);
+        modulation.clear();
+        modulation.resize(self.buses.len(), 0.0);
+        // a dry run that listens has to hear itself, as sine waves, to branch
+        // the way a render would
+        let collect = if self.dry_run && self.listens.is_empty() {
+            Collect::Events(&mut self.events)
+        } else {
+            let events = if self.emit || self.dry_run { Some(&mut self.events) } else { None };
+            match self.voice_model {
+                Some(_) if !self.dry_run => Collect::Voices(&mut sounding, &self.patches, events),
+                _ => Collect::Samples(events, Fm { bus_of: &self.bus_of, now: &self.modulation,
+                                                      next: &mut modulation }),
+            }
+        };
+        let mut rng = Counted { rng: &mut self.rng, words: &mut self.words };
+        let voices = interpret_sin(&threads, &self.instructions, &clock, &self.humanize, &mut rng, collect,
+                                   &mut self.next_id, &mut pools);
+        let sample = match self.voice_model {
+            Some(ref mut model) if !self.dry_run => model.next_sample(&sounding),
+            _ => self.mix(&pools.samples, voices),
+        };
+        if !self.breakpoints.is_empty() {
+            self.check_breakpoints(&threads, &pools.after, reached);
+        }
+        // the old threads and modulation make room for the next step's
+        ::std::mem::swap(&mut self.threads, &mut pools.after);
+        ::std::mem::swap(&mut self.modulation, &mut modulation);
+        pools.dispatched = threads;
+        pools.modulation = modulation;
+        self.pools = pools;
+        self.now += 1;
+        self.last_beat_pos = self.beat_pos;
+        self.beat_pos += bpm / 60.0 / SAMPLE_RATE;
+        self.bpm.tick();
+        self.listen(sample);
+        Some(if self.dry_run { 0.0 } else { sample })
+    }
+
+    /// Hit the first breakpoint that's come true over a time step that took
+    /// `before` to `after`, whose dispatch saw `reached` get to a watched pc.
+    fn check_breakpoints(&mut self, before: &[ThreadState], after: &[ThreadState],
+                         mut reached: Option<(usize, usize)>) {
+        // a thread whose note ended has got to its next instruction too,
+        // though it won't be dispatched from there until the next step
+        if reached.is_none() && !self.watched.is_empty() {
+            let was: HashMap<usize, usize> = before.iter().map(|thread| (thread.id, thread.pc)).collect();
+            reached = after.iter().find(|thread| {
+                thread.sin_progress == 0 && was.get(&thread.id) != Some(&thread.pc)
+                    && self.watched.contains(&thread.pc)
+            }).map(|thread| (thread.id, thread.pc));
+        }
+        let count = after.iter().fold(0, |count: usize, thread| count.saturating_add(thread.weight));
+        for &mut (ref breakpoint, ref mut held) in &mut self.breakpoints {
+            let hit = match *breakpoint {
+                // only as it comes true, not on every step it stays true
+                Breakpoint::Threads(ordering, than) => {
+                    let holds = count.cmp(&than) == ordering;
+                    let hit = holds && !*held;
+                    *held = holds;
+                    hit
+                },
+                Breakpoint::Label(_, pc) => reached.is_some_and(|(_, at)| at == pc),
+            };
+            if hit && self.broke.is_none() {
+                let thread = match *breakpoint {
+                    Breakpoint::Label(..) => reached.map(|(thread, _)| thread),
+                    Breakpoint::Threads(..) => None,
+                };
+                self.broke = Some(Break { breakpoint: breakpoint.clone(), thread });
+            }
+        }
+    }
+
+    /// Hear a time step's `sample`, and at the end of each block set the
+    /// branches of every `@listen` by how loud it was.
+    fn listen(&mut self, sample: f64) {
+        if self.listens.is_empty() {
+            return;
+        }
+        self.heard += sample*sample;
+        if self.now % LISTEN_BLOCK != 0 {
+            return;
+        }
+        // a sine wave at full scale is heard as 1
+        let level = |heard: f64| (2.0*heard/LISTEN_BLOCK as f64).sqrt();
+        for &mut (ref listen, ref mut heard) in &mut self.listens {
+            *heard = level(match listen.bus {
+                Some(bus) => self.heard_buses.get(bus).cloned().unwrap_or(0.0),
+                None => self.heard,
+            });
+            set_prob(&mut self.instructions, &listen.pcs, listen.prob(*heard));
+        }
+        self.heard = 0.0;
+        for heard in &mut self.heard_buses {
+            *heard = 0.0;
+        }
+    }
+
+    /// Mix a time step's samples down through the buses, metering each one.
+    fn mix(&mut self, samples: &[(usize, f32)], voices: f64) -> f64 {
+        // what the `@duck`s turn each bus down to, following each source's
+        // level before its fader, as loud as its threads sounding together
+        // could be
+        let ducked = &mut self.ducked;
+        ducked.clear();
+        if !self.followers.is_empty() {
+            ducked.resize(self.buses.len(), 1.0);
+        }
+        for gain in &mut self.gains {
+            gain.tick();
+        }
+        if !self.listens.is_empty() {
+            self.heard_buses.resize(self.buses.len(), 0.0);
+        }
+        let bus_of = &self.bus_of;
+        for follower in &mut self.followers {
+            let source = follower.duck().source;
+            let (sum, count) = samples.iter().filter(|&&(pc, _)| bus_of[pc] == source)
+                .fold((0.0, 0), |(sum, count), &(_, s)| (sum + s as f64, count + 1));
+            let level = if count == 0 { 0.0 } else { sum.abs()/count as f64 };
+            ducked[follower.duck().target] *= follower.follow(level);
+        }
+        if let Some(ref mut split) = self.split {
+            // a bus that's joined since comes in with the silence it's had
+            let len = split.first().map_or(0, Vec::len);
+            split.resize(self.buses.len(), vec![0.0; len]);
+        }
+        // a step where no thread sounds is silence rather than 0/0
+        if samples.is_empty() {
+            for bus in self.split.iter_mut().flatten() {
+                bus.push(0.0);
+            }
+            return 0.0;
+        }
+        let mut out = 0.0;
+        for bus in 0..self.buses.len() {
+            let played = samples.iter().filter(|&&(pc, _)| bus_of[pc] == bus).map(|&(_, s)| s);
+            let level = bus_level(played, self.gains[bus].value()*ducked.get(bus).unwrap_or(&1.0), voices) as f64;
+            self.meters[bus] = self.meters[bus].max(level.abs());
+            if let Some(heard) = self.heard_buses.get_mut(bus) {
+                *heard += level*level;
+            }
+            if let Some(ref mut split) = self.split {
+                split[bus].push(level as f32);
+            }
+            out += level;
+        }
+        out
+    }
+
+    /// Play through `model`'s channels from now on instead of mixing every
+    /// thread as a sine wave. The mixer's buses and faders don't apply.
+    pub fn set_voice_model(&mut self, model: Box<dyn VoiceModel + Send>) {
+        self.voice_model = Some(model);
+    }
+
+    /// the names of the program's buses
+    pub fn buses(&self) -> &[String] {
+        &self.buses
+    }
+
+    /// Keep each bus's share of the mix apart as well, from the next sample
+    /// on, for `take_bus_blocks`. Not for a voice model, which has no buses.
+    pub fn split_buses(&mut self) {
+        self.split = Some(vec![vec![]; self.buses.len()]);
+    }
+
+    /// each bus's samples since this was last called, which add up to the
+    /// samples rendered over that time
+    pub fn take_bus_blocks(&mut self) -> Vec<Vec<f32>> {
+        match self.split {
+            Some(ref mut split) => split.iter_mut().map(::std::mem::take).collect(),
+            None => vec![],
+        }
+    }
+
+    /// how loud `bus` is turned up, 1 being as written, once any change has
+    /// glided in
+    pub fn bus_gain(&self, bus: usize) -> f64 {
+        self.gains[bus].target()
+    }
+
+    /// Turn `bus` up or down, gliding there over the ramp, see `set_ramp`.
+    pub fn set_bus_gain(&mut self, bus: usize, gain: f64) {
+        let ramp = self.ramp;
+        self.gains[bus].set(gain, ramp);
+    }
+
+    /// Turn `bus` up or down, gliding there over `secs`.
+    pub fn ramp_bus_gain(&mut self, bus: usize, gain: f64, secs: f64) {
+        self.gains[bus].set(gain, secs*SAMPLE_RATE);
+    }
+
+    /// the peak level of each bus since this was last called
+    pub fn take_meters(&mut self) -> Vec<f64> {
+        let meters = self.meters.clone();
+        for meter in &mut self.meters {
+            *meter = 0.0;
+        }
+        meters
+    }
+
+    /// Render up to `len` samples. The block only comes back shorter than
+    /// requested when the program finishes partway through it, or a
+    /// breakpoint's hit.
+    pub fn render_block(&mut self, len: usize) -> Vec<f32> {
+        let mut block = vec![0.0; len];
+        let rendered = self.render_into(&mut block);
+        block.truncate(rendered);
+        block
+    }
+
+    /// Fill `out` with samples, without allocating a block for them, and
+    /// return how many were written, which is only fewer than `out` holds
+    /// when the program finishes partway through, or a breakpoint's hit.
+    /// For a caller with a buffer of its own to fill, like a DAC's DMA
+    /// buffer.
+    ///
+    /// With the `audit-alloc` feature it asserts that it didn't allocate, see
+    /// `audit`, unless one of the buffers it reuses had to grow, the program
+    /// stopped on an error, or something that allocates anyway is on: a dry
+    /// run, `--emit`, `--explain`, breakpoints, a voice model, split buses or
+    /// recording a session.
+    pub fn render_into(&mut self, out: &mut [f32]) -> usize {
+        if !cfg!(feature = "audit-alloc") {
+            return self.render_samples(out);
+        }
+        let pooled = self.pooled();
+        let (rendered, allocations) = audited(|| self.render_samples(out));
+        assert!(allocations == 0 || self.pooled() > pooled || self.error.is_some() || self.allocates(),
+                "rendering allocated {} times, up to sample {}", allocations, self.now);
+        rendered
+    }
+
+    /// what the buffers the render reuses hold between them, which only goes
+    /// up if one grew
+    fn pooled(&self) -> usize {
+        self.pools.capacity() + self.threads.capacity() + self.modulation.capacity() + self.ducked.capacity()
+    }
+
+    /// whether something's on that allocates as it renders whatever happens
+    fn allocates(&self) -> bool {
+        self.dry_run || self.emit || self.branches.is_some() || !self.breakpoints.is_empty()
+            || self.voice_model.is_some() || self.split.is_some() || matches!(self.rng, Decisions::Recording(..))
+    }
+
+    fn render_samples(&mut self, out: &mut [f32]) -> usize {
+        let mut i = 0;
+        while i < out.len() {
+            let run = self.run_length().min(out.len() - i);
+            if run > 0 {
+                self.render_run(&mut out[i..i + run]);
+                i += run;
+                continue;
+            }
+            match self.next_sample() {
+                Some(sample) => out[i] = sample as f32,
+                None => return i,
+            }
+            i += 1;
+            if self.broke.is_some() {
+                return i;
+            }
+        }
+        out.len()
+    }
+
+    /// How many time steps every thread can carry on with the note it's in
+    /// the middle of, with nothing for the interpreter to decide: no note
+    /// starts or ends, no thread is waiting for a boundary, the tempo can't
+    /// change under them, and none are modulating others, which ties them
+    /// together step by step. 0 if something needs deciding now.
+    fn run_length(&self) -> usize {
+        // a tempo on its way somewhere changes the length of notes as they play
+        if self.dry_run || self.voice_model.is_some() || self.tempo.is_some() || !self.bpm.settled()
+           || self.threads.is_empty() {
+            return 0;
+        }
+        if self.threads.iter().any(|thread| thread.modulates.is_some()) || self.modulation.iter().any(|&hz| hz != 0.0) {
+            return 0;
+        }
+        let bpm = self.bpm();
+        self.threads.iter().map(|thread| match duration_of(&self.instructions[thread.pc]) {
+            Some(duration) if thread.sin_progress > 0 => {
+                (thread.length(duration, bpm).ceil() as i64 - thread.sin_progress).max(0) as usize
+            },
+            _ => 0,
+        }).min().unwrap_or(0)
+    }
+
+    /// Render time steps that `run_length` says need no decisions, each
+    /// thread carrying on with its note rather than being dispatched again on
+    /// every step. The mix is the same as `next_sample`'s, step by step.
+    fn render_run(&mut self, out: &mut [f32]) {
+        let bpm = self.bpm();
+        let mut samples = ::std::mem::take(&mut self.pools.samples);
+        for slot in out {
+            samples.clear();
+            let mut voices = 0.0;
+            for thread in &mut self.threads {
+                let instruction = &self.instructions[thread.pc];
+                let length = thread.length(duration_of(instruction).unwrap(), bpm);
+                let sounding = (length - thread.rest as f64).max(0.0);
+                if let Some((freq, secs)) = sounding_at(instruction, thread, sounding) {
+                    let sample = Graph::of(instruction, thread.velocity).sample(secs, thread.phase);
+                    samples.push((thread.pc, thread.weight as f32*sample));
+                    voices += thread.weight as f64;
+                    thread.advance(freq);
+                }
+                thread.sin_progress += 1;
+            }
+            *slot = self.mix(&samples, voices) as f32;
+            self.now += 1;
+            self.last_beat_pos = self.beat_pos;
+            self.beat_pos += bpm / 60.0 / SAMPLE_RATE;
+            self.listen(*slot as f64);
+        }
+        self.pools.samples = samples;
+    }
+
+    /// Swap in new instructions at some pcs, with where each came from,
+    /// leaving the rest of the program as it is. A thread on one of them
+    /// carries on where it was if it's still the same kind of note.
+    pub fn patch_instructions(&mut self, changes: Vec<(usize, Instruction, Span)>) {
+        for (pc, instruction, at) in changes {
+            if !same_note(&self.instructions[pc], &instruction) {
+                for thread in self.threads.iter_mut().filter(|thread| thread.pc == pc) {
+                    thread.sin_progress = 0;
+                }
+            }
+            self.instructions[pc] = instruction;
+            self.spans[pc] = Some(at);
+        }
+    }
+
+    /// Swap in a new program without stopping. `map` gives the pc in the new
+    /// program where a thread at an old pc should carry on, or None to drop
+    /// that thread. A note that's playing keeps its place if it lands on
+    /// another note. Returns how many threads survived.
+    pub fn swap_program<F: FnMut(usize) -> Option<usize>>(&mut self, program: Program, mut map: F) -> usize {
+        let instructions = program.instructions;
+        let mut threads = vec![];
+        let (buses, now) = (&self.buses, &program.buses);
+        for thread in self.threads.drain(..) {
+            let pc = match map(thread.pc) {
+                Some(pc) if pc < instructions.len() => pc,
+                _ => continue,
+            };
+            let sin_progress = if same_note(&self.instructions[thread.pc], &instructions[pc]) {
+                thread.sin_progress
+            } else {
+                0
+            };
+            // a modulator follows its bus, and is heard again if that's gone
+            let modulates = thread.modulates.and_then(|(bus, depth)| {
+                now.iter().position(|name| *name == buses[bus]).map(|bus| (bus, depth))
+            });
+            threads.push(ThreadState { sin_progress, pc, modulates, ..thread });
+        }
+        self.instructions = instructions;
+        self.spans = program.spans;
+        // buses that are still there keep their gain
+        self.gains = program.buses.iter().map(|name| match self.buses.iter().position(|b| b == name) {
+            Some(bus) => self.gains[bus],
+            None => Smoothed::new(1.0),
+        }).collect();
+        self.meters = vec![0.0; program.buses.len()];
+        self.modulation = vec![0.0; program.buses.len()];
+        self.followers = program.ducks.into_iter().map(Follower::new).collect();
+        self.anneals = program.anneals.into_iter().map(|anneal| (anneal, 0.0, 0.0)).collect();
+        self.listens = program.listens.into_iter().map(|listen| (listen, 0.0)).collect();
+        self.buses = program.buses;
+        self.bus_of = program.bus_of;
+        self.patches = program.patches;
+        self.bpm = Smoothed::new(program.bpm);
+        self.meter = program.meter;
+        self.swing = program.swing;
+        self.humanize = program.humanize;
+        self.fork_decay = program.fork_decay;
+        self.threads = threads;
+        self.thread_count()
+    }
+}
+
+/// What a program did in a simulated run, with no audio made.
+pub struct EventLog {
+    /// each note as it started
+    pub events: Vec<Event>,
+    /// how long the run went on for, in seconds
+    pub duration: f64,
+    /// true if every thread terminated within the steps allowed
+    pub finished: bool,
+    /// the most threads that were alive at once
+    pub peak_threads: usize,
+    /// what stopped the program, if a runtime error did
+    pub error: Option<RuntimeError>,
+}
+
+/// Run `program` as a dry run for at most `max_steps` time steps (samples)
+/// and log what it played. Nothing but the program and `seed` goes into
+/// it, so the same ones always give the same log, and a test can check a
+/// property of a program across many seeds, like that it always ends
+/// within some time or never has more than so many threads going.
+pub fn simulate(program: Program, seed: usize, max_steps: u64) -> EventLog {
+    let mut renderer = Renderer::new(program, seed);
+    renderer.set_dry_run(true);
+    let mut peak_threads = renderer.thread_count();
+    while renderer.now < max_steps && renderer.next_sample().is_some() {
+        peak_threads = peak_threads.max(renderer.thread_count());
+    }
+    EventLog {
+        events: renderer.take_events(),
+        duration: renderer.elapsed(),
+        finished: renderer.is_finished() && renderer.error.is_none(),
+        peak_threads,
+        error: renderer.take_error(),
+    }
+}
@@ -0,0 +1,174 @@
+//! Buses played on sound cards of their own, for `--route drums=hw:1
+//! pads=hw:2`, so an installation can give each part of a piece a room of
+//! its own.
+//!
+//! Every card runs off its own crystal, so over a long run their clocks
+//! drift apart, from each other and from the render. Each output keeps a
+//! queue of samples between the render and its card, and plays through it a
+//! touch faster or slower, by resampling, to keep it the same length. That
+//! holds every card in time with the render, and so with each other.
+
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use interrupt::FadeOut;
+use playback::DeviceSink;
+use sink::Sink;
+
+/// how many samples each output aims to keep queued, and so how far ahead of
+/// the cards the render has to start
+pub const LEAD: usize = 2048;
+const TARGET: f64 = LEAD as f64;
+/// how many it plays at a time
+const CHUNK: usize = 256;
+/// the furthest an output's speed is nudged from the render's, as a
+/// fraction: far more than any two crystals disagree by, too little to hear
+const MAX_DRIFT: f64 = 0.002;
+/// how much of the queue's length each chunk counts towards its average,
+/// which smooths over the blocks coming and going
+const SMOOTHING: f64 = 0.02;
+
+/// `bus=device`, split at the `=`
+pub fn parse_route(text: &str) -> Option<(&str, &str)> {
+    match text.split_once('=') {
+        Some((bus, device)) if !bus.is_empty() && !device.is_empty() => Some((bus, device)),
+        _ => None,
+    }
+}
+
+/// The samples waiting for a card, and whether there'll be any more.
+struct Queue {
+    samples: VecDeque<f32>,
+    finished: bool,
+}
+
+type Shared = Arc<(Mutex<Queue>, Condvar)>;
+
+/// One card, and the buses mixed together onto it.
+struct Output {
+    buses: Vec<usize>,
+    device: String,
+    queue: Shared,
+    fade: FadeOut,
+    player: JoinHandle<io::Result<()>>,
+}
+
+/// Plays through a queue at whatever speed keeps it at `TARGET`.
+struct Resampler {
+    /// how far between the first two samples queued it's got
+    pos: f64,
+    /// the queue's length, averaged
+    queued: f64,
+}
+
+impl Resampler {
+    /// how many samples of the queue a chunk plays through, at most
+    fn wanted() -> usize {
+        (CHUNK as f64*(1.0 + MAX_DRIFT)).ceil() as usize + 2
+    }
+
+    /// The next chunk to play from `queue`, which has at least `wanted`.
+    fn next_chunk(&mut self, queue: &mut VecDeque<f32>) -> Vec<f32> {
+        self.queued += SMOOTHING*(queue.len() as f64 - self.queued);
+        // a queue growing means the card's slow, so it plays through faster
+        let off = ((self.queued - TARGET)/TARGET).clamp(-1.0, 1.0);
+        let speed = 1.0 + MAX_DRIFT*off;
+        let mut chunk = Vec::with_capacity(CHUNK);
+        for _ in 0..CHUNK {
+            let at = self.pos as usize;
+            let frac = (self.pos - at as f64) as f32;
+            chunk.push(queue[at] + (queue[at + 1] - queue[at])*frac);
+            self.pos += speed;
+        }
+        let played = self.pos as usize;
+        queue.drain(..played);
+        self.pos -= played as f64;
+        chunk
+    }
+}
+
+/// Feed `sink` from `queue` until the render's finished and it's all played.
+fn play(mut sink: DeviceSink, queue: Shared) -> io::Result<()> {
+    let mut resampler = Resampler { pos: 0.0, queued: TARGET };
+    let (ref lock, ref ready) = *queue;
+    loop {
+        let mut waiting = lock.lock().unwrap();
+        while waiting.samples.len() < Resampler::wanted() && !waiting.finished {
+            waiting = ready.wait(waiting).unwrap();
+        }
+        if waiting.samples.len() < Resampler::wanted() {
+            // once it's over the rest goes as it is
+            let rest: Vec<f32> = waiting.samples.drain(..).collect();
+            drop(waiting);
+            sink.write_block(&rest)?;
+            return sink.finish();
+        }
+        let chunk = resampler.next_chunk(&mut waiting.samples);
+        drop(waiting);
+        sink.write_block(&chunk)?;
+    }
+}
+
+/// Plays buses on separate cards, each kept in time with the render.
+pub struct Router {
+    outputs: Vec<Output>,
+}
+
+impl Router {
+    /// Start playing each set of buses on its device.
+    pub fn open(routes: Vec<(Vec<usize>, String)>) -> io::Result<Router> {
+        let mut outputs = vec![];
+        for (buses, device) in routes {
+            let sink = DeviceSink::open(&device)?;
+            let queue: Shared = Arc::new((Mutex::new(Queue { samples: VecDeque::new(), finished: false }),
+                                          Condvar::new()));
+            let playing = queue.clone();
+            let player = thread::spawn(move || play(sink, playing));
+            outputs.push(Output { buses, device, queue, fade: FadeOut::new(1), player });
+        }
+        Ok(Router { outputs })
+    }
+
+    /// Queue a block of each bus's samples, from `Renderer::take_bus_blocks`,
+    /// for its card. Returns whether Ctrl-C has faded them all out, see
+    /// `interrupt`.
+    pub fn write(&mut self, blocks: &[Vec<f32>]) -> io::Result<bool> {
+        let len = blocks.first().map_or(0, Vec::len);
+        let mut faded = true;
+        for output in &mut self.outputs {
+            // only an error stops a card before the end
+            if output.player.is_finished() {
+                return Err(io::Error::other(format!("playback on {} stopped", output.device)));
+            }
+            let mut block = vec![0.0; len];
+            for &bus in &output.buses {
+                for (mixed, &sample) in block.iter_mut().zip(&blocks[bus]) {
+                    *mixed += sample;
+                }
+            }
+            faded &= output.fade.apply(&mut block);
+            let (ref lock, ref ready) = *output.queue;
+            lock.lock().unwrap().samples.extend(block);
+            ready.notify_one();
+        }
+        Ok(faded)
+    }
+
+    /// Play out whatever's still queued and wait for every card to finish.
+    pub fn finish(self) -> io::Result<()> {
+        for output in &self.outputs {
+            let (ref lock, ref ready) = *output.queue;
+            lock.lock().unwrap().finished = true;
+            ready.notify_one();
+        }
+        let mut result = Ok(());
+        for output in self.outputs {
+            let device = output.device;
+            let played = output.player.join().unwrap_or_else(|_| Err(io::Error::other("playback panicked")));
+            let played = played.map_err(|e| io::Error::other(format!("{}: {}", device, e)));
+            result = result.and(played);
+        }
+        result
+    }
+}
@@ -0,0 +1,106 @@
+//! Importing ringtones in RTTTL, the text format old Nokia phones used, as
+//! in `Axel:d=8,o=5,b=125:4f,g#.,f,16f,a#,f,d#`.
+//!
+//! A ringtone has a name, its defaults for note length, octave and tempo, then
+//! the notes, each an optional length, a letter (or `p` for a pause), an
+//! optional sharp, dot and octave. Octaves are numbered the usual way, so
+//! `a4` is 440 Hz.
+
+use compiler;
+use tuning::note_name;
+
+pub struct RtttlError {
+    /// which note was wrong, counting from 1, or 0 for the defaults
+    pub note: usize,
+    pub message: String,
+}
+
+fn error(note: usize, message: &str) -> RtttlError {
+    RtttlError { note, message: message.to_string() }
+}
+
+/// the lengths notes can be, as fractions of a whole note
+fn valid_length(length: u32) -> bool {
+    [1, 2, 4, 8, 16, 32].contains(&length)
+}
+
+/// A blip program that plays the first ringtone in `text`. Later ones, a line
+/// each, are ignored.
+pub fn import(text: &str) -> Result<String, RtttlError> {
+    let tune = text.lines().map(|line| line.trim()).find(|line| !line.is_empty()).unwrap_or("");
+    let sections: Vec<&str> = tune.splitn(3, ':').collect();
+    if sections.len() != 3 {
+        return Err(error(0, "expected name:defaults:notes"));
+    }
+    let (mut length, mut octave, mut bpm) = (4, 6, 63.0);
+    for default in sections[1].split(',').map(|part| part.trim()).filter(|part| !part.is_empty()) {
+        let mut parts = default.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some("d"), Some(value)) => match value.trim().parse() {
+                Ok(value) if valid_length(value) => length = value,
+                _ => return Err(error(0, "bad default length")),
+            },
+            (Some("o"), Some(value)) => match value.trim().parse() {
+                Ok(value) => octave = value,
+                _ => return Err(error(0, "bad default octave")),
+            },
+            (Some("b"), Some(value)) => match value.trim().parse() {
+                Ok(value) if value > 0.0 => bpm = value,
+                _ => return Err(error(0, "bad tempo")),
+            },
+            _ => return Err(error(0, "unknown default")),
+        }
+    }
+
+    let mut out = String::new();
+    if bpm != 120.0 {
+        out.push_str(&format!("@bpm {}\n", bpm));
+    }
+    for (n, note) in sections[2].split(',').map(|note| note.trim().to_ascii_lowercase()).enumerate() {
+        let n = n + 1;
+        if note.is_empty() {
+            continue;
+        }
+        let digits = note.len() - note.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let note_length = match &note[..digits] {
+            "" => length,
+            value => match value.parse() {
+                Ok(value) if valid_length(value) => value,
+                _ => return Err(error(n, "bad length")),
+            },
+        };
+        let mut rest = note[digits..].chars().peekable();
+        // `h` is what some phones called b
+        let semitone = match rest.next() {
+            Some('c') => Some(0), Some('d') => Some(2), Some('e') => Some(4), Some('f') => Some(5),
+            Some('g') => Some(7), Some('a') => Some(9), Some('b') | Some('h') => Some(11),
+            Some('p') => None,
+            _ => return Err(error(n, "expected a note")),
+        };
+        let sharp = rest.peek() == Some(&'#');
+        if sharp {
+            rest.next();
+        }
+        // the dot can come before or after the octave
+        let mut dotted = false;
+        let mut note_octave = String::new();
+        for c in rest {
+            match c {
+                '.' => dotted = true,
+                '0'..='9' => note_octave.push(c),
+                _ => return Err(error(n, "unexpected character")),
+            }
+        }
+        let note_octave: i32 = match note_octave.as_str() {
+            "" => octave,
+            value => value.parse().map_err(|_| error(n, "bad octave"))?,
+        };
+        let beats = 4.0/note_length as f64*if dotted { 1.5 } else { 1.0 };
+        let pitch = match semitone {
+            Some(semitone) => note_name((note_octave + 1)*12 + semitone + if sharp { 1 } else { 0 }),
+            None => "0".to_string(),
+        };
+        out.push_str(&format!("sin {} {}\n", pitch, compiler::beats(beats)));
+    }
+    Ok(out)
+}
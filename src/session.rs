@@ -0,0 +1,165 @@
+//! Recording a render so that it can be played back exactly, later or
+//! somewhere else.
+//!
+//! A session is a directory with the program's source in `program.blip`, the
+//! settings it was rendered with in `session.txt`, and every random number
+//! the render drew in `decisions.bin`. Replaying takes its random numbers from
+//! the log rather than the generator, so it comes out the same even if the
+//! generator changes.
+
+use std::fs;
+use std::io;
+use std::convert::Infallible;
+use std::path::Path;
+use rand::{Rng, TryRng};
+
+/// a random number generator: the seeded one, or whatever the caller gave
+/// `Renderer::set_rng`
+pub type Generator = Box<dyn Rng + Send>;
+
+/// Where a renderer's random numbers come from.
+pub enum Decisions {
+    Live(Generator),
+    /// drawn from the generator and written down
+    Recording(Generator, Vec<u64>),
+    /// read back from a log, falling back on the generator if it runs out
+    Replaying { log: Vec<u64>, next: usize, fallback: Generator },
+}
+
+impl Decisions {
+    /// true if a replay has needed more numbers than were recorded
+    pub fn ran_out(&self) -> bool {
+        match *self {
+            Decisions::Replaying { ref log, next, .. } => next > log.len(),
+            _ => false,
+        }
+    }
+
+    /// the generator numbers come from when there's no log to read
+    pub fn generator(&mut self) -> &mut Generator {
+        match *self {
+            Decisions::Live(ref mut rng) | Decisions::Recording(ref mut rng, _)
+            | Decisions::Replaying { fallback: ref mut rng, .. } => rng,
+        }
+    }
+
+    fn replayed(log: &[u64], next: &mut usize) -> Option<u64> {
+        let value = log.get(*next).cloned();
+        *next += 1;
+        value
+    }
+}
+
+impl TryRng for Decisions {
+    type Error = Infallible;
+
+    fn try_next_u32(&mut self) -> Result<u32, Infallible> {
+        Ok(match *self {
+            Decisions::Live(ref mut rng) => rng.next_u32(),
+            Decisions::Recording(ref mut rng, ref mut log) => {
+                let value = rng.next_u32();
+                log.push(value as u64);
+                value
+            },
+            Decisions::Replaying { ref log, ref mut next, ref mut fallback } => {
+                Decisions::replayed(log, next).map_or_else(|| fallback.next_u32(), |value| value as u32)
+            },
+        })
+    }
+
+    fn try_next_u64(&mut self) -> Result<u64, Infallible> {
+        Ok(match *self {
+            Decisions::Live(ref mut rng) => rng.next_u64(),
+            Decisions::Recording(ref mut rng, ref mut log) => {
+                let value = rng.next_u64();
+                log.push(value);
+                value
+            },
+            Decisions::Replaying { ref log, ref mut next, ref mut fallback } => {
+                Decisions::replayed(log, next).unwrap_or_else(|| fallback.next_u64())
+            },
+        })
+    }
+
+    /// a u64 at a time, so every byte is a decision that gets written down
+    fn try_fill_bytes(&mut self, dst: &mut [u8]) -> Result<(), Infallible> {
+        for chunk in dst.chunks_mut(8) {
+            let bytes = self.try_next_u64()?.to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+        Ok(())
+    }
+}
+
+/// Everything it takes to render a program again exactly as it was.
+pub struct Session {
+    pub source: String,
+    pub seed: usize,
+    pub dedup: bool,
+    /// a chip to play through, see `voices`
+    pub voice_model: Option<String>,
+    pub decisions: Vec<u64>,
+    /// what recorded it, from `engine`, if the session says
+    pub engine: Option<String>,
+}
+
+/// the first line of `session.txt`, in case the format ever changes
+static HEADER: &str = "blip-machine session 1";
+
+/// The version of blip-machine rendering, and the features built in that
+/// change how it sounds. Renders by the same engine from the same program,
+/// seed and settings come out bit for bit the same.
+pub fn engine() -> String {
+    let fixed = if cfg!(feature = "fixed") { "+fixed" } else { "" };
+    format!("{}{}", env!("CARGO_PKG_VERSION"), fixed)
+}
+
+impl Session {
+    pub fn save<P: AsRef<Path>>(&self, dir: P) -> io::Result<()> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        fs::write(dir.join("program.blip"), &self.source)?;
+        let mut settings = format!("{}\nseed {}\ndedup {}\n", HEADER, self.seed, self.dedup);
+        if let Some(ref model) = self.voice_model {
+            settings.push_str(&format!("voice-model {}\n", model));
+        }
+        if let Some(ref engine) = self.engine {
+            settings.push_str(&format!("engine {}\n", engine));
+        }
+        fs::write(dir.join("session.txt"), settings)?;
+        let bytes: Vec<u8> = self.decisions.iter().flat_map(|value| value.to_le_bytes().to_vec()).collect();
+        fs::write(dir.join("decisions.bin"), bytes)
+    }
+
+    pub fn load<P: AsRef<Path>>(dir: P) -> io::Result<Session> {
+        let dir = dir.as_ref();
+        let bad = |what: &str| io::Error::new(io::ErrorKind::InvalidData, format!("bad session.txt: {}", what));
+        let source = fs::read_to_string(dir.join("program.blip"))?;
+        let settings = fs::read_to_string(dir.join("session.txt"))?;
+        let mut lines = settings.lines();
+        if lines.next() != Some(HEADER) {
+            return Err(bad("not a session, or from a newer version"));
+        }
+        let (mut seed, mut dedup, mut voice_model, mut engine) = (0, false, None, None);
+        for line in lines {
+            match line.split_whitespace().collect::<Vec<&str>>()[..] {
+                ["seed", value] => seed = value.parse().map_err(|_| bad(line))?,
+                ["dedup", value] => dedup = value.parse().map_err(|_| bad(line))?,
+                ["voice-model", name] => voice_model = Some(name.to_string()),
+                ["engine", version] => engine = Some(version.to_string()),
+                [] => {},
+                _ => return Err(bad(line)),
+            }
+        }
+        let bytes = fs::read(dir.join("decisions.bin"))?;
+        if bytes.len() % 8 != 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "decisions.bin is cut short"));
+        }
+        let decisions = bytes.chunks(8).map(|chunk| {
+            let mut value = [0; 8];
+            value.copy_from_slice(chunk);
+            u64::from_le_bytes(value)
+        }).collect();
+        Ok(Session { source, seed, dedup, voice_model, decisions, engine })
+    }
+}
@@ -0,0 +1,173 @@
+//! The Commodore 64's SID: three voices that can each play a triangle, a saw,
+//! a pulse or noise, and one multimode filter that any of them can go through.
+//!
+//! `@waveform` picks a voice's wave and `@duty` its pulse width, which `@pwm`
+//! sweeps back and forth. With `@ring on` a triangle is ring-modulated by the
+//! voice before it, and with `@sync on` its wave restarts every time that
+//! voice's does, as on the chip. `@filter` sends a voice through the filter:
+//! low, band or high pass, with a cutoff, a resonance, and how hard it's
+//! driven into the filter, which is where the 6581's grit comes from. There's
+//! only one filter, set by the lowest voice that uses it. Pitches are rounded
+//! to the 16-bit frequency registers, waves to 12 bits and volumes to 8.
+//! Drums are noise, on whichever voice is free.
+
+use std::f64::consts::PI;
+use compiler::{FilterMode, Patch, Waveform};
+use renderer::SAMPLE_RATE;
+use voices::{Allocator, Voice, VoiceModel};
+
+/// the PAL C64's clock, which the frequency registers count in
+const CLOCK: f64 = 985_248.0;
+
+/// how widely `@pwm` sweeps a pulse, as the narrowest share of the cycle
+const PWM_NARROWEST: f64 = 0.1;
+
+const VOICES: usize = 3;
+
+/// the pitch the 16-bit frequency register gets closest to `freq` with, or
+/// None if it's too high
+fn register_freq(freq: f64) -> Option<f64> {
+    let register = (freq*16_777_216.0/CLOCK).round();
+    if register > 0xffff as f64 {
+        None
+    } else {
+        Some(register*CLOCK/16_777_216.0)
+    }
+}
+
+/// a wave from -1 to 1 as one of the 12-bit levels
+fn quantize(level: f64) -> f64 {
+    ((level + 1.0)*2047.5).round()/2047.5 - 1.0
+}
+
+#[derive(Clone, Copy, Default)]
+struct Oscillator {
+    phase: f64,
+    /// whether the phase wrapped round on this time step, for syncing the
+    /// voice after
+    wrapped: bool,
+    /// the noise's shift register, and the phase it last shifted at
+    lfsr: u32,
+    noise_step: u32,
+    /// how far through its sweep `@pwm` is
+    pwm_phase: f64,
+}
+
+pub struct Sid {
+    allocator: Allocator,
+    oscillators: [Oscillator; VOICES],
+    /// the filter's band and low pass outputs from the last time step
+    band: f64,
+    low: f64,
+}
+
+impl Sid {
+    pub fn new() -> Sid {
+        let oscillator = Oscillator { lfsr: 0x7ffff8, ..Oscillator::default() };
+        Sid { allocator: Allocator::new(VOICES), oscillators: [oscillator; VOICES], band: 0.0, low: 0.0 }
+    }
+
+    /// Move voice `n`'s oscillator on a step and give its wave, from -1 to 1.
+    fn oscillator(&mut self, n: usize, voice: Voice) -> f64 {
+        // the voice before this one, the way round the chip wires them
+        let before = self.oscillators[(n + VOICES - 1) % VOICES];
+        let patch = voice.patch;
+        let waveform = if voice.drum { Waveform::Noise } else { patch.waveform };
+        // noise shifts 16 times a cycle, and drums are pitched up to where
+        // their noise sounds like the hit, as on the other chips
+        let freq = if voice.drum { voice.freq*100.0/16.0 } else { voice.freq };
+        let freq = match register_freq(freq) {
+            Some(freq) => freq,
+            None => return 0.0,
+        };
+        let osc = &mut self.oscillators[n];
+        let next = osc.phase + freq/SAMPLE_RATE;
+        osc.wrapped = next >= 1.0;
+        osc.phase = if patch.sync && before.wrapped { 0.0 } else { next.fract() };
+        let wave = match waveform {
+            Waveform::Triangle => {
+                let tri = 1.0 - 4.0*(osc.phase - 0.5).abs();
+                // ring modulation flips the triangle's top bit with the
+                // other voice's
+                if patch.ring && before.phase >= 0.5 { -tri } else { tri }
+            },
+            Waveform::Saw => 2.0*osc.phase - 1.0,
+            Waveform::Pulse => {
+                let width = match patch.pwm {
+                    Some(rate) => {
+                        osc.pwm_phase = (osc.pwm_phase + rate/SAMPLE_RATE).fract();
+                        let sweep = 1.0 - 2.0*(osc.pwm_phase - 0.5).abs();
+                        PWM_NARROWEST + (1.0 - 2.0*PWM_NARROWEST)*sweep
+                    },
+                    None => patch.duty,
+                };
+                if osc.phase < width { 1.0 } else { -1.0 }
+            },
+            Waveform::Noise => {
+                let step = (osc.phase*16.0) as u32;
+                if step != osc.noise_step || osc.wrapped {
+                    osc.noise_step = step;
+                    let feedback = ((osc.lfsr >> 22) ^ (osc.lfsr >> 17)) & 1;
+                    osc.lfsr = ((osc.lfsr << 1) | feedback) & 0x7fffff;
+                }
+                // the top byte of the register
+                ((osc.lfsr >> 15) & 0xff) as f64/127.5 - 1.0
+            },
+        };
+        quantize(wave)*(voice.gain*255.0).round().clamp(0.0, 255.0)/255.0
+    }
+
+    /// Run `input` through the filter as `patch` sets it.
+    fn filter(&mut self, input: f64, patch: &Patch) -> f64 {
+        let filter = match patch.filter {
+            Some(filter) => filter,
+            None => return input,
+        };
+        // a state variable filter, which stays stable up to about a sixth of
+        // the sample rate
+        let cutoff = filter.cutoff.min(SAMPLE_RATE/6.0);
+        let f = 2.0*(PI*cutoff/SAMPLE_RATE).sin();
+        let damping = 2.0*(1.0 - 0.95*filter.resonance);
+        let input = (input*filter.drive).tanh();
+        self.low += f*self.band;
+        let high = input - self.low - damping*self.band;
+        self.band += f*high;
+        match filter.mode {
+            FilterMode::LowPass => self.low,
+            FilterMode::BandPass => self.band,
+            FilterMode::HighPass => high,
+        }
+    }
+}
+
+impl Default for Sid {
+    fn default() -> Sid {
+        Sid::new()
+    }
+}
+
+impl VoiceModel for Sid {
+    fn next_sample(&mut self, voices: &[Voice]) -> f64 {
+        let channels = self.allocator.assign(voices, |_, _| true);
+        let (mut direct, mut filtered, mut settings) = (0.0, 0.0, None);
+        for (n, voice) in channels.into_iter().enumerate() {
+            match voice {
+                Some(voice) => {
+                    let wave = self.oscillator(n, voice);
+                    if voice.patch.filter.is_some() {
+                        filtered += wave;
+                        settings = settings.or(Some(voice.patch));
+                    } else {
+                        direct += wave;
+                    }
+                },
+                None => self.oscillators[n].wrapped = false,
+            }
+        }
+        let filtered = match settings {
+            Some(patch) => self.filter(filtered, &patch),
+            None => 0.0,
+        };
+        (direct + filtered)/VOICES as f64
+    }
+}
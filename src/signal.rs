@@ -0,0 +1,26 @@
+//! The renderer as an endless signal of mono frames, shaped like
+//! `dasp::Signal`: `next` always returns a frame, and once the program has
+//! finished it returns silence and `is_exhausted` reports true.
+
+use renderer::Renderer;
+
+pub struct Signal {
+    renderer: Renderer,
+}
+
+impl Signal {
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> [f32; 1] {
+        [self.renderer.next_sample().unwrap_or(0.0) as f32]
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.renderer.is_finished()
+    }
+}
+
+impl Renderer {
+    pub fn into_signal(self) -> Signal {
+        Signal { renderer: self }
+    }
+}
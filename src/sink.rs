@@ -0,0 +1,109 @@
+//! Where rendered samples end up.
+//!
+//! The engine works in f32 from -1 to 1; `Sample` converts to and from the
+//! usual PCM formats the way `dasp::Sample` does, so samples coming out of (or
+//! going into) a dasp pipeline need no glue.
+
+use std::io;
+use std::io::Write;
+use std::marker::PhantomData;
+
+pub trait Sample: Copy {
+    /// the value of silence
+    const EQUILIBRIUM: Self;
+
+    fn from_f32(sample: f32) -> Self;
+    fn to_f32(self) -> f32;
+
+    /// append the little endian encoding of this sample
+    fn write_le(self, out: &mut Vec<u8>);
+}
+
+impl Sample for f32 {
+    const EQUILIBRIUM: f32 = 0.0;
+    fn from_f32(sample: f32) -> f32 { sample }
+    fn to_f32(self) -> f32 { self }
+    fn write_le(self, out: &mut Vec<u8>) { out.extend_from_slice(&self.to_le_bytes()) }
+}
+
+impl Sample for f64 {
+    const EQUILIBRIUM: f64 = 0.0;
+    fn from_f32(sample: f32) -> f64 { sample as f64 }
+    fn to_f32(self) -> f32 { self as f32 }
+    fn write_le(self, out: &mut Vec<u8>) { out.extend_from_slice(&self.to_le_bytes()) }
+}
+
+impl Sample for i16 {
+    const EQUILIBRIUM: i16 = 0;
+    fn from_f32(sample: f32) -> i16 { (sample*32767.0) as i16 }
+    fn to_f32(self) -> f32 { self as f32 / 32768.0 }
+    fn write_le(self, out: &mut Vec<u8>) { out.extend_from_slice(&self.to_le_bytes()) }
+}
+
+impl Sample for i32 {
+    const EQUILIBRIUM: i32 = 0;
+    fn from_f32(sample: f32) -> i32 { (sample as f64*2147483647.0) as i32 }
+    fn to_f32(self) -> f32 { (self as f64 / 2147483648.0) as f32 }
+    fn write_le(self, out: &mut Vec<u8>) { out.extend_from_slice(&self.to_le_bytes()) }
+}
+
+/// unsigned 8-bit, centered on 128 like aplay's default U8 format
+impl Sample for u8 {
+    const EQUILIBRIUM: u8 = 128;
+    fn from_f32(sample: f32) -> u8 { (127.5_f32*(1_f32 + sample)) as u8 }
+    fn to_f32(self) -> f32 { self as f32 / 127.5 - 1.0 }
+    fn write_le(self, out: &mut Vec<u8>) { out.push(self) }
+}
+
+/// Something that consumes rendered audio one block at a time.
+pub trait Sink {
+    fn write_block(&mut self, samples: &[f32]) -> io::Result<()>;
+
+    /// Called once after the last block, e.g. to flush or write headers.
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Get everything written so far out of any buffers, e.g. before a
+    /// checkpoint is saved.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Write samples in any supported format.
+    fn write_samples<S: Sample>(&mut self, samples: &[S]) -> io::Result<()> where Self: Sized {
+        let block: Vec<f32> = samples.iter().map(|s| s.to_f32()).collect();
+        self.write_block(&block)
+    }
+}
+
+/// Headerless PCM in the sample format `S`, the kind of stream aplay reads.
+pub struct PcmSink<W: Write, S: Sample> {
+    out: W,
+    buf: Vec<u8>,
+    format: PhantomData<S>,
+}
+
+impl<W: Write, S: Sample> PcmSink<W, S> {
+    pub fn new(out: W) -> PcmSink<W, S> {
+        PcmSink { out, buf: vec![], format: PhantomData }
+    }
+}
+
+impl<W: Write, S: Sample> Sink for PcmSink<W, S> {
+    fn write_block(&mut self, samples: &[f32]) -> io::Result<()> {
+        self.buf.clear();
+        for &sample in samples {
+            S::from_f32(sample).write_le(&mut self.buf);
+        }
+        self.out.write_all(&self.buf)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
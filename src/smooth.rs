@@ -0,0 +1,71 @@
+//! Parameters changed while a program plays, a bus's gain from `--control`
+//! or the mixer's faders and the tempo, glide to where they're set over a
+//! short ramp instead of jumping there. A gain that jumps clicks, a fader
+//! nudged step by step zippers, and a tempo that jumps lurches.
+
+/// how long a change takes to glide in unless `--ramp` says otherwise, in
+/// seconds
+pub const DEFAULT_RAMP: f64 = 0.05;
+
+/// A value heading for where it was last set, a sample at a time.
+#[derive(Clone, Copy)]
+pub struct Smoothed {
+    value: f64,
+    target: f64,
+    /// how far it moves each sample
+    step: f64,
+}
+
+impl Smoothed {
+    /// `value`, staying there until it's set.
+    pub fn new(value: f64) -> Smoothed {
+        Smoothed { value, target: value, step: 0.0 }
+    }
+
+    /// where it is now
+    pub fn value(&self) -> f64 {
+        self.value
+    }
+
+    /// where it's heading
+    pub fn target(&self) -> f64 {
+        self.target
+    }
+
+    /// true once it's got where it's heading
+    pub fn settled(&self) -> bool {
+        self.value == self.target
+    }
+
+    /// Head for `target`, getting there in `samples`, or straight away if
+    /// that's less than one. Setting it to where it's already heading, like
+    /// a fader set on every block, leaves it going at the pace it was.
+    pub fn set(&mut self, target: f64, samples: f64) {
+        if target == self.target {
+            return;
+        }
+        self.target = target;
+        if samples < 1.0 {
+            self.value = target;
+        }
+        self.step = (target - self.value).abs()/samples.max(1.0);
+    }
+
+    /// Move a sample's worth towards the target.
+    pub fn tick(&mut self) {
+        self.value = if self.value < self.target {
+            (self.value + self.step).min(self.target)
+        } else {
+            (self.value - self.step).max(self.target)
+        };
+    }
+}
+
+/// `2s`, `50ms` or `0.5` as seconds, `0` meaning no ramp at all
+pub fn parse_ramp(text: &str) -> Option<f64> {
+    let secs = match text.strip_suffix("ms") {
+        Some(ms) => ms.parse::<f64>().ok()?/1000.0,
+        None => text.strip_suffix('s').unwrap_or(text).parse().ok()?,
+    };
+    Some(secs).filter(|secs| secs.is_finite() && *secs >= 0.0)
+}
@@ -0,0 +1,99 @@
+//! A pull-based source of samples for audio playback libraries.
+//!
+//! `BlipSource` is an `Iterator<Item = f32>` carrying the same stream
+//! metadata as `rodio::Source` (`current_frame_len`, `channels`,
+//! `sample_rate`, `total_duration`), so implementing that trait is a matter
+//! of forwarding each method. `PcmReader` is the same stream as bytes, for
+//! anything that takes an `io::Read`.
+
+use std::io;
+use std::io::Read;
+use std::marker::PhantomData;
+use std::time::Duration;
+use compiler::Program;
+use renderer::{Renderer, SAMPLE_RATE};
+use sink::Sample;
+
+pub struct BlipSource {
+    renderer: Renderer,
+}
+
+impl BlipSource {
+    pub fn new(program: Program, seed: usize) -> BlipSource {
+        BlipSource { renderer: Renderer::new(program, seed) }
+    }
+
+    /// The sample format never changes mid-stream.
+    pub fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    pub fn channels(&self) -> u16 {
+        1
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE as u32
+    }
+
+    /// Unknown: how long a program plays depends on the branches it takes.
+    pub fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl Iterator for BlipSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        self.renderer.next_sample().map(|sample| sample as f32)
+    }
+}
+
+/// how many samples `PcmReader` renders at a time
+const READ_BLOCK: usize = 1024;
+
+/// A renderer read as headerless PCM in the sample format `S`, the bytes a
+/// `PcmSink` of the same format would be written, rendered as they're read.
+/// It's at its end once the program finishes; a runtime error that stopped
+/// it is left for `into_renderer` to take.
+pub struct PcmReader<S: Sample> {
+    renderer: Renderer,
+    block: Vec<f32>,
+    /// the encoded block, and how much of it has been read
+    bytes: Vec<u8>,
+    read: usize,
+    format: PhantomData<S>,
+}
+
+impl<S: Sample> PcmReader<S> {
+    pub fn new(renderer: Renderer) -> PcmReader<S> {
+        PcmReader { renderer, block: vec![0.0; READ_BLOCK], bytes: vec![], read: 0, format: PhantomData }
+    }
+
+    /// Hands back the renderer, e.g. to take its error. Bytes rendered but
+    /// not yet read are dropped.
+    pub fn into_renderer(self) -> Renderer {
+        self.renderer
+    }
+}
+
+impl<S: Sample> Read for PcmReader<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.read == self.bytes.len() {
+            if self.renderer.is_finished() {
+                return Ok(0);
+            }
+            let rendered = self.renderer.render_into(&mut self.block);
+            self.bytes.clear();
+            self.read = 0;
+            for &sample in &self.block[..rendered] {
+                S::from_f32(sample).write_le(&mut self.bytes);
+            }
+        }
+        let len = buf.len().min(self.bytes.len() - self.read);
+        buf[..len].copy_from_slice(&self.bytes[self.read..self.read + len]);
+        self.read += len;
+        Ok(len)
+    }
+}
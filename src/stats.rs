@@ -0,0 +1,43 @@
+//! Keeping an eye on rendered audio, to catch scores that clip or make no
+//! sound at all.
+
+/// below this a sample rounds to nothing even at 16 bits
+pub static SILENCE: f32 = 1.0/32768.0;
+
+pub struct AudioStats {
+    pub samples: usize,
+    /// samples outside -1 to 1, which the output format has to clip
+    pub clipped: usize,
+    /// the loudest sample so far
+    pub peak: f32,
+}
+
+impl AudioStats {
+    pub fn new() -> AudioStats {
+        AudioStats { samples: 0, clipped: 0, peak: 0.0 }
+    }
+
+    pub fn observe(&mut self, block: &[f32]) {
+        for &sample in block {
+            let level = sample.abs();
+            if level > 1.0 {
+                self.clipped += 1;
+            }
+            if level > self.peak {
+                self.peak = level;
+            }
+        }
+        self.samples += block.len();
+    }
+
+    /// true if nothing so far was loud enough to hear
+    pub fn is_silent(&self) -> bool {
+        self.peak < SILENCE
+    }
+}
+
+impl Default for AudioStats {
+    fn default() -> AudioStats {
+        AudioStats::new()
+    }
+}
@@ -0,0 +1,73 @@
+//! Async access to the renderer, enabled with the `async` feature.
+//!
+//! Blocks are only rendered when the stream is polled, so a consumer that
+//! stops reading (a slow client, a full socket buffer) stops the rendering
+//! too. `poll_next` has the same shape as `futures::Stream::poll_next`, which
+//! keeps any executor-specific adapter down to a one-liner; the crate doesn't
+//! depend on `futures` to implement the trait itself.
+//!
+//! Rendering is plain CPU work, done inside the poll. Between blocks the
+//! stream yields, returning `Pending` once so other tasks on the worker get
+//! a turn, but each block still holds the worker for as long as it takes to
+//! render. To serve many streams at once without starving an executor's
+//! workers, keep blocks small or render on a blocking thread, like inside
+//! `tokio::task::spawn_blocking`, sending the blocks over a channel.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use renderer::Renderer;
+
+pub struct BlockStream {
+    renderer: Renderer,
+    block_size: usize,
+    /// whether it's yielded since the last block
+    yielded: bool,
+}
+
+impl BlockStream {
+    pub fn new(renderer: Renderer, block_size: usize) -> BlockStream {
+        assert!(block_size > 0, "block size must be positive");
+        BlockStream { renderer, block_size, yielded: false }
+    }
+
+    /// Yields the next rendered block, or None once the program has finished.
+    /// Every other poll is a `Pending` that wakes itself straight away, see
+    /// the module docs.
+    pub fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Vec<f32>>> {
+        let this = self.get_mut();
+        if this.renderer.is_finished() {
+            return Poll::Ready(None);
+        }
+        if !this.yielded {
+            this.yielded = true;
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        this.yielded = false;
+        Poll::Ready(Some(this.renderer.render_block(this.block_size)))
+    }
+
+    /// `while let Some(block) = stream.next().await { ... }`
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Next<'_> {
+        Next { stream: self }
+    }
+
+    /// Hands back the paused renderer, e.g. to continue it synchronously.
+    pub fn into_renderer(self) -> Renderer {
+        self.renderer
+    }
+}
+
+pub struct Next<'a> {
+    stream: &'a mut BlockStream,
+}
+
+impl<'a> Future for Next<'a> {
+    type Output = Option<Vec<f32>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Vec<f32>>> {
+        Pin::new(&mut *self.stream).poll_next(cx)
+    }
+}
@@ -0,0 +1,55 @@
+//! Where the beat comes from.
+//!
+//! Durations written in beats are turned into samples using the tempo at the
+//! moment they're played. Normally that's the program's `@bpm`, but a
+//! renderer can be handed a `TempoSource` to follow instead.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub trait TempoSource {
+    /// beats per minute, always positive
+    fn bpm(&self) -> f64;
+}
+
+/// A tempo that can be changed from another thread while a renderer is
+/// following it. Clones share the same tempo.
+#[derive(Clone)]
+pub struct SharedTempo {
+    bits: Arc<AtomicU64>,
+}
+
+impl SharedTempo {
+    pub fn new(bpm: f64) -> SharedTempo {
+        SharedTempo { bits: Arc::new(AtomicU64::new(bpm.to_bits())) }
+    }
+
+    pub fn set(&self, bpm: f64) {
+        self.bits.store(bpm.to_bits(), Ordering::Relaxed);
+    }
+}
+
+impl TempoSource for SharedTempo {
+    fn bpm(&self) -> f64 {
+        f64::from_bits(self.bits.load(Ordering::Relaxed))
+    }
+}
+
+/// Swing stretches the first half of every beat to `swing` of the beat and
+/// squeezes the second half into the rest. Maps straight time to swung time.
+fn swing_warp(beats: f64, swing: f64) -> f64 {
+    let f = beats.fract();
+    beats.floor() + if f < 0.5 { f*2.0*swing } else { swing + (f - 0.5)*2.0*(1.0 - swing) }
+}
+
+/// The inverse of `swing_warp`.
+fn swing_unwarp(beats: f64, swing: f64) -> f64 {
+    let f = beats.fract();
+    beats.floor() + if f < swing { f / (2.0*swing) } else { 0.5 + (f - swing) / (2.0*(1.0 - swing)) }
+}
+
+/// How many real beats a note of `beats` straight beats lasts when it starts
+/// `start` beats in and swing is on.
+pub fn swung_length(start: f64, beats: f64, swing: f64) -> f64 {
+    swing_warp(swing_unwarp(start, swing) + beats, swing) - start
+}
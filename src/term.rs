@@ -0,0 +1,132 @@
+//! Just enough terminal handling for the full-screen modes.
+//!
+//! Everything goes through `/dev/tty`, so stdin stays free for the program
+//! and stdout for the audio. Keys are read raw by switching the terminal over
+//! with `stty`, which is put back the way it was when the `Tty` is dropped.
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Key {
+    Char(char),
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+/// the keys in what one read of the terminal came back with
+fn parse_keys(bytes: &[u8]) -> Vec<Key> {
+    let mut keys = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        // arrow keys are ESC [ A to D
+        if bytes[i] == 0x1b && i + 2 < bytes.len() && bytes[i + 1] == b'[' {
+            match bytes[i + 2] {
+                b'A' => keys.push(Key::Up),
+                b'B' => keys.push(Key::Down),
+                b'C' => keys.push(Key::Right),
+                b'D' => keys.push(Key::Left),
+                _ => {},
+            }
+            i += 3;
+        } else {
+            keys.push(Key::Char(bytes[i] as char));
+            i += 1;
+        }
+    }
+    keys
+}
+
+/// Keeps the full-screen modes going at the pace of the audio, even when the
+/// sink would take blocks faster, like a file does.
+pub struct Pacer {
+    block_time: Duration,
+    due: Instant,
+}
+
+impl Pacer {
+    pub fn new(block_size: usize, sample_rate: f64) -> Pacer {
+        Pacer { block_time: Duration::from_secs_f64(block_size as f64/sample_rate), due: Instant::now() }
+    }
+
+    /// wait until it's time for the next block
+    pub fn wait(&mut self) {
+        let now = Instant::now();
+        if self.due > now {
+            thread::sleep(self.due - now);
+        }
+        self.due += self.block_time;
+    }
+}
+
+/// The controlling terminal in raw mode.
+pub struct Tty {
+    out: File,
+    /// the settings to put back, as `stty -g` printed them
+    saved: String,
+}
+
+/// run `stty` on the terminal and return what it printed
+fn stty(args: &[&str]) -> io::Result<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::from(File::open("/dev/tty")?))
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!("stty {} failed", args.join(" "))));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+impl Tty {
+    pub fn open() -> io::Result<Tty> {
+        let out = OpenOptions::new().write(true).open("/dev/tty")?;
+        let saved = stty(&["-g"])?;
+        stty(&["-icanon", "-echo", "min", "1"])?;
+        let mut tty = Tty { out, saved };
+        // hide the cursor while drawing
+        tty.out.write_all(b"\x1b[?25l")?;
+        Ok(tty)
+    }
+
+    /// Keys as they're pressed, read on a background thread.
+    pub fn keys(&self) -> io::Result<Receiver<Key>> {
+        let mut input = File::open("/dev/tty")?;
+        let (send, keys) = channel();
+        thread::spawn(move || {
+            // a key that sends several bytes sends them all at once
+            let mut buf = [0; 16];
+            while let Ok(n) = input.read(&mut buf) {
+                if n == 0 || parse_keys(&buf[..n]).into_iter().any(|key| send.send(key).is_err()) {
+                    break;
+                }
+            }
+        });
+        Ok(keys)
+    }
+
+    /// Redraw the whole screen with these lines.
+    pub fn draw(&mut self, lines: &[String]) -> io::Result<()> {
+        let mut screen = String::from("\x1b[H\x1b[2J");
+        for line in lines {
+            screen.push_str(line);
+            screen.push_str("\r\n");
+        }
+        self.out.write_all(screen.as_bytes())?;
+        self.out.flush()
+    }
+}
+
+impl Drop for Tty {
+    fn drop(&mut self) {
+        let _ = self.out.write_all(b"\x1b[?25h\r\n");
+        let _ = stty(&[&self.saved]);
+    }
+}
@@ -0,0 +1,162 @@
+//! Writing a realized run out as a ProTracker module, so it can be opened and
+//! reworked in a tracker like OpenMPT or MilkyTracker.
+//!
+//! The module has four channels and three sine samples, each a single looped
+//! cycle of a different length so that between them they cover the pitches
+//! blip-machine plays. Rows are an eighth of a beat at the program's tempo,
+//! and notes are put on the nearest row. Pitches that move, like sweeps and
+//! bends, are held at the pitch they start on.
+
+use std::f64::consts::PI;
+use renderer::Event;
+
+/// the Amiga's clock for PAL machines, which the periods count in
+const PAL_CLOCK: f64 = 3546894.6;
+
+/// the periods ProTracker can play, from B-3 down to C-1
+const MIN_PERIOD: f64 = 113.0;
+const MAX_PERIOD: f64 = 856.0;
+
+/// the bytes in one cycle of each sample, longest (lowest) first
+const CYCLES: [usize; 3] = [128, 32, 8];
+
+const CHANNELS: usize = 4;
+const ROWS: usize = 64;
+/// the most patterns a `M.K.` module can hold
+const MAX_PATTERNS: usize = 64;
+/// ticks per row, which makes the module's tempo the same as the program's
+const SPEED: u8 = 3;
+
+const SET_VOLUME: u8 = 0xc;
+const SET_SPEED: u8 = 0xf;
+
+pub struct Module {
+    pub bytes: Vec<u8>,
+    /// notes left out because all four channels were already busy, or
+    /// because they're too high or low for any sample
+    pub dropped: usize,
+}
+
+/// The tune is too long to fit in a module.
+pub struct TooLong;
+
+#[derive(Clone, Copy, Default)]
+struct Cell {
+    sample: u8,
+    period: u16,
+    effect: u8,
+    param: u8,
+}
+
+/// the sample and period that play `freq`, if any of them can
+fn sample_for(freq: f64) -> Option<(u8, u16)> {
+    CYCLES.iter().enumerate().map(|(i, &cycle)| (i, PAL_CLOCK/(freq*cycle as f64)))
+        .find(|&(_, period)| (MIN_PERIOD..=MAX_PERIOD).contains(&period))
+        .map(|(i, period)| (i as u8 + 1, period.round() as u16))
+}
+
+fn put_name(bytes: &mut Vec<u8>, name: &str, len: usize) {
+    let mut field: Vec<u8> = name.bytes().take(len).collect();
+    field.resize(len, 0);
+    bytes.extend(field);
+}
+
+fn put_word(bytes: &mut Vec<u8>, word: u16) {
+    bytes.extend_from_slice(&word.to_be_bytes());
+}
+
+/// Lay `events` out on the rows of a module at `bpm`.
+pub fn write_mod(events: &[Event], bpm: f64, title: &str) -> Result<Module, TooLong> {
+    let row_of = |secs: f64| (secs*bpm/60.0*8.0).round() as usize;
+    let tempo = bpm.round().clamp(32.0, 255.0) as u8;
+    let mut rows: Vec<[Cell; CHANNELS]> = vec![];
+    // the row each channel is busy until
+    let mut busy = [0; CHANNELS];
+    let mut dropped = 0;
+    for event in events {
+        // rests don't need a channel
+        if event.freq <= 0.0 {
+            continue;
+        }
+        let start = row_of(event.time);
+        let end = row_of(event.time + event.dur).max(start + 1);
+        let free = busy.iter().position(|&until| until <= start);
+        let (channel, (sample, period)) = match (free, sample_for(event.freq)) {
+            (Some(channel), Some(sample)) => (channel, sample),
+            _ => {
+                dropped += 1;
+                continue;
+            },
+        };
+        if rows.len() < end + 1 {
+            rows.resize(end + 1, [Cell::default(); CHANNELS]);
+        }
+        let volume = (event.velocity*64.0).round().clamp(0.0, 64.0) as u8;
+        rows[start][channel] = Cell { sample, period, effect: SET_VOLUME, param: volume };
+        // stop the note unless another starts straight after it
+        if rows[end][channel].period == 0 {
+            rows[end][channel] = Cell { sample: 0, period: 0, effect: SET_VOLUME, param: 0 };
+        }
+        busy[channel] = end;
+    }
+    let patterns = rows.len().div_ceil(ROWS).max(1);
+    if patterns > MAX_PATTERNS {
+        return Err(TooLong);
+    }
+    rows.resize(patterns*ROWS, [Cell::default(); CHANNELS]);
+    // the speed and tempo go in the first row, on whichever channels are free
+    let mut settings = vec![(SET_SPEED, SPEED), (SET_SPEED, tempo)].into_iter();
+    for cell in rows[0].iter_mut() {
+        if cell.effect == 0 {
+            match settings.next() {
+                Some((effect, param)) => {
+                    cell.effect = effect;
+                    cell.param = param;
+                },
+                None => break,
+            }
+        }
+    }
+
+    let mut bytes = vec![];
+    put_name(&mut bytes, title, 20);
+    for i in 0..31 {
+        match CYCLES.get(i) {
+            Some(&cycle) => {
+                put_name(&mut bytes, &format!("sine {}", cycle), 22);
+                put_word(&mut bytes, (cycle/2) as u16);
+                bytes.push(0);
+                bytes.push(64);
+                put_word(&mut bytes, 0);
+                put_word(&mut bytes, (cycle/2) as u16);
+            },
+            None => {
+                put_name(&mut bytes, "", 22);
+                put_word(&mut bytes, 0);
+                bytes.extend_from_slice(&[0, 0]);
+                put_word(&mut bytes, 0);
+                put_word(&mut bytes, 1);
+            },
+        }
+    }
+    bytes.push(patterns as u8);
+    bytes.push(127);
+    for position in 0..128 {
+        bytes.push(if position < patterns { position as u8 } else { 0 });
+    }
+    bytes.extend_from_slice(b"M.K.");
+    for row in &rows {
+        for cell in row {
+            bytes.push((cell.sample & 0xf0) | (cell.period >> 8) as u8);
+            bytes.push(cell.period as u8);
+            bytes.push((cell.sample << 4) | cell.effect);
+            bytes.push(cell.param);
+        }
+    }
+    for &cycle in CYCLES.iter() {
+        for i in 0..cycle {
+            bytes.push(((2.0*PI*i as f64/cycle as f64).sin()*127.0).round() as i8 as u8);
+        }
+    }
+    Ok(Module { bytes, dropped })
+}
@@ -0,0 +1,137 @@
+//! Turning note names into frequencies.
+//!
+//! A note name like `C4` or `F#3` picks out a key, numbered the MIDI way (middle
+//! C is 60), and the tuning in effect decides what frequency that key plays.
+//! Every tuning puts middle C at the same frequency it has in 12-TET, which is
+//! also where Scala puts it when there's no keyboard mapping.
+
+use std::fs;
+use std::path::Path;
+
+pub static MIDDLE_C: f64 = 261.625_565_300_598_6;
+const MIDDLE_C_KEY: i32 = 60;
+
+pub trait Tuning {
+    fn freq(&self, key: i32) -> f64;
+}
+
+/// An equal division of the octave. Keys snap to the nearest step, so note
+/// names keep roughly their usual pitches; with 12 steps this is ordinary
+/// 12-TET.
+pub struct Edo {
+    pub steps: u32,
+}
+
+impl Tuning for Edo {
+    fn freq(&self, key: i32) -> f64 {
+        let steps = self.steps as f64;
+        let degree = ((key - MIDDLE_C_KEY) as f64*steps/12.0).round();
+        MIDDLE_C*2_f64.powf(degree/steps)
+    }
+}
+
+/// A scale from a Scala `.scl` file. Consecutive keys play consecutive
+/// degrees of the scale, starting with middle C on the first degree, and the
+/// last degree gives the interval that repeats (usually the octave).
+pub struct Scale {
+    /// the ratio of each degree above the first, ending with the period
+    ratios: Vec<f64>,
+}
+
+impl Scale {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Scale, String> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) => return Err(format!("{}", e)),
+        };
+        Scale::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Scale, String> {
+        let mut lines = text.lines().filter(|line| !line.starts_with('!'));
+        // the first line is a description, which can be anything (even blank)
+        if lines.next().is_none() {
+            return Err("empty scale file".to_string());
+        }
+        let count: usize = match lines.next().map(|line| line.trim().parse()) {
+            Some(Ok(count)) => count,
+            _ => return Err("expected the number of notes".to_string()),
+        };
+        let mut ratios = vec![];
+        for line in lines.take(count) {
+            let value = line.split_whitespace().next().unwrap_or("");
+            match parse_interval(value) {
+                Some(ratio) if ratio > 0.0 => ratios.push(ratio),
+                _ => return Err(format!("bad pitch '{}'", value)),
+            }
+        }
+        if ratios.len() != count || count == 0 {
+            return Err(format!("expected {} pitches, found {}", count, ratios.len()));
+        }
+        Ok(Scale { ratios })
+    }
+}
+
+/// `701.955` is in cents, `3/2` and `2` are ratios
+fn parse_interval(text: &str) -> Option<f64> {
+    if text.contains('.') {
+        return text.parse::<f64>().ok().map(|cents| 2_f64.powf(cents/1200.0));
+    }
+    let mut parts = text.splitn(2, '/');
+    let num: f64 = parts.next()?.parse().ok()?;
+    let den: f64 = match parts.next() {
+        Some(den) => den.parse().ok()?,
+        None => 1.0,
+    };
+    if den == 0.0 { None } else { Some(num/den) }
+}
+
+impl Tuning for Scale {
+    fn freq(&self, key: i32) -> f64 {
+        let size = self.ratios.len() as i32;
+        let period = self.ratios[self.ratios.len() - 1];
+        let offset = key - MIDDLE_C_KEY;
+        let (repeats, degree) = (offset.div_euclid(size), offset.rem_euclid(size));
+        let ratio = if degree == 0 { 1.0 } else { self.ratios[degree as usize - 1] };
+        MIDDLE_C*period.powi(repeats)*ratio
+    }
+}
+
+/// The key number of a note name such as `C4`, `F#3`, `Bb-1`. Letters are
+/// upper case so that `b` can only mean flat.
+pub fn parse_note(text: &str) -> Option<i32> {
+    let mut chars = text.chars();
+    let semitone = match chars.next()? {
+        'C' => 0, 'D' => 2, 'E' => 4, 'F' => 5, 'G' => 7, 'A' => 9, 'B' => 11,
+        _ => return None,
+    };
+    let rest = chars.as_str();
+    let accidentals = rest.len() - rest.trim_start_matches(['#', 'b']).len();
+    let shift: i32 = rest[..accidentals].chars().map(|c| if c == '#' { 1 } else { -1 }).sum();
+    let octave: i32 = rest[accidentals..].parse().ok()?;
+    Some((octave + 1)*12 + semitone + shift)
+}
+
+/// The note name of a key number, the other way round from `parse_note`.
+/// Black keys are written as sharps.
+pub fn note_name(key: i32) -> String {
+    let names = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+    format!("{}{}", names[key.rem_euclid(12) as usize], key.div_euclid(12) - 1)
+}
+
+/// Every pitch a tuning can play on the 128 keys of MIDI, low to high.
+pub fn pitches(tuning: &dyn Tuning) -> Vec<f64> {
+    let mut pitches: Vec<f64> = (0..128).map(|key| tuning.freq(key)).collect();
+    pitches.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    pitches.dedup();
+    pitches
+}
+
+/// The index of the pitch in `pitches` closest to `freq` by ear (in cents,
+/// not hertz).
+pub fn nearest(pitches: &[f64], freq: f64) -> usize {
+    let distance = |pitch: f64| (pitch/freq).ln().abs();
+    (0..pitches.len())
+        .min_by(|&a, &b| distance(pitches[a]).partial_cmp(&distance(pitches[b])).unwrap())
+        .unwrap_or(0)
+}
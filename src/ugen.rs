@@ -0,0 +1,119 @@
+//! The unit generators a thread's sample is made with: an oscillator, then
+//! an envelope, then a gain. `Graph::of` wires up the ones an instruction
+//! plays with, so what a note sounds like is a matter of which nodes it
+//! gets rather than a branch of its own at every step of the renderer.
+//!
+//! The nodes hold no state between samples. Where a thread is in its wave
+//! and its note is the renderer's to keep, which keeps threads easy to
+//! compare and merge.
+
+use compiler::Instruction::{self, *};
+#[cfg(feature = "fixed")]
+use fixed;
+use plugin::Plugin;
+
+/// What makes a thread's wave.
+pub enum Osc<'a> {
+    Sine,
+    /// a plugin's sample callback and the numbers it compiled its line to
+    Plugin(&'a dyn Plugin, &'a [f64]),
+}
+
+impl<'a> Osc<'a> {
+    /// the wave at `phase`, `secs` into the note, from -1 to 1
+    #[cfg(not(feature = "fixed"))]
+    fn sample(&self, secs: f64, phase: f64) -> f32 {
+        match *self {
+            // a phase from 0 to 1 needs no more than f32 to get it right to
+            // well past what 16 bits can hear
+            Osc::Sine => (2.0*::std::f32::consts::PI*phase as f32).sin(),
+            Osc::Plugin(plugin, params) => plugin.sample(params, secs, phase) as f32,
+        }
+    }
+
+    #[cfg(feature = "fixed")]
+    fn sample(&self, secs: f64, phase: f64) -> i32 {
+        match *self {
+            Osc::Sine => fixed::sine(phase),
+            // plugins work in floats, which only need rounding to Q15
+            Osc::Plugin(plugin, params) => fixed::q15(plugin.sample(params, secs, phase)),
+        }
+    }
+}
+
+/// How a thread's volume moves through a note.
+#[derive(Clone, Copy)]
+pub enum Envelope {
+    Hold,
+    /// dying away, by a factor of e every so many seconds
+    Decay(f64),
+}
+
+impl Envelope {
+    /// the level `secs` into the note, from 0 to 1
+    pub fn level(&self, secs: f64) -> f64 {
+        match *self {
+            Envelope::Hold => 1.0,
+            Envelope::Decay(decay) => (-secs/decay).exp(),
+        }
+    }
+
+    #[cfg(not(feature = "fixed"))]
+    fn sample(&self, secs: f64) -> f32 {
+        match *self {
+            Envelope::Hold => 1.0,
+            Envelope::Decay(decay) => (-(secs/decay) as f32).exp(),
+        }
+    }
+
+    #[cfg(feature = "fixed")]
+    fn sample(&self, secs: f64) -> i32 {
+        match *self {
+            Envelope::Hold => fixed::ONE,
+            Envelope::Decay(decay) => fixed::decay(secs/decay),
+        }
+    }
+}
+
+/// The nodes one thread's note goes through.
+pub struct Graph<'a> {
+    pub osc: Osc<'a>,
+    pub envelope: Envelope,
+    /// the note's velocity
+    pub gain: f64,
+}
+
+impl<'a> Graph<'a> {
+    /// the graph that plays a sounding instruction at `velocity`
+    pub fn of(instruction: &'a Instruction, velocity: f64) -> Graph<'a> {
+        let osc = match *instruction {
+            Custom(ref plugin, ref params, _) => Osc::Plugin(&**plugin, params),
+            _ => Osc::Sine,
+        };
+        let envelope = match *instruction {
+            Zap(_, _, _, decay) => Envelope::Decay(decay),
+            _ => Envelope::Hold,
+        };
+        Graph { osc, envelope, gain: velocity }
+    }
+
+    /// How loud the note is `secs` in, for a voice model, which makes the
+    /// wave itself.
+    pub fn level(&self, secs: f64) -> f64 {
+        self.gain*self.envelope.level(secs)
+    }
+
+    /// The sample `secs` into the note and at `phase` of its wave. Samples
+    /// are f32 from here to the output; only the clock, pitches and phases,
+    /// which add up over a whole render, need f64.
+    #[cfg(not(feature = "fixed"))]
+    pub fn sample(&self, secs: f64, phase: f64) -> f32 {
+        self.gain as f32*self.envelope.sample(secs)*self.osc.sample(secs, phase)
+    }
+
+    #[cfg(feature = "fixed")]
+    pub fn sample(&self, secs: f64, phase: f64) -> f32 {
+        let voiced = fixed::mul(self.osc.sample(secs, phase), fixed::q15(self.gain));
+        fixed::to_f32(fixed::mul(voiced, self.envelope.sample(secs)) as i64)
+    }
+}
@@ -0,0 +1,121 @@
+//! Stretching a finished render in time and shifting it in pitch, with a
+//! phase vocoder, so a realization that came out well can be made to fit
+//! a scene without rolling new seeds.
+//!
+//! The render is cut into overlapping windows, and each window's spectrum is
+//! laid down again further apart (or closer together), with every bin's
+//! phase moved on by however far its frequency would have carried it in the
+//! new gap. A pitch shift is the same stretch by the shift's ratio, then
+//! read back at that ratio faster so the length comes back to where it was.
+
+use std::f64::consts::PI;
+use std::io;
+use fft::{fft, ifft};
+use sink::Sink;
+
+/// the samples in each window, a power of two for the FFT
+const WINDOW: usize = 512;
+/// how far apart windows are read, a quarter of a window
+const HOP: usize = WINDOW/4;
+
+/// `samples` made `factor` times as long, without changing their pitch
+pub fn stretch(samples: &[f32], factor: f64) -> Vec<f32> {
+    if samples.is_empty() {
+        return vec![];
+    }
+    let hann: Vec<f64> = (0..WINDOW).map(|i| 0.5 - 0.5*(2.0*PI*i as f64/WINDOW as f64).cos()).collect();
+    let frames = samples.len().div_ceil(HOP) + 1;
+    // where each frame is written out; rounding each from the start keeps
+    // the length right however many frames there are
+    let place = |frame: usize| (frame as f64*HOP as f64*factor).round() as usize;
+    let len = (samples.len() as f64*factor).round() as usize;
+    let mut out = vec![0.0; place(frames) + WINDOW];
+    let mut norm = vec![0.0; out.len()];
+    let mut last_phase = vec![0.0; WINDOW];
+    let mut phase = vec![0.0; WINDOW];
+    for frame in 0..frames {
+        // frames start half a window early so the first samples get a
+        // whole window's worth of overlap
+        let start = (frame*HOP) as isize - (WINDOW/2) as isize;
+        let mut re: Vec<f64> = (0..WINDOW).map(|i| {
+            let at = start + i as isize;
+            let sample = if at < 0 { 0.0 } else { samples.get(at as usize).map_or(0.0, |&s| s as f64) };
+            sample*hann[i]
+        }).collect();
+        let mut im = vec![0.0; WINDOW];
+        fft(&mut re, &mut im);
+        let gap = if frame == 0 { 0.0 } else { (place(frame) - place(frame - 1)) as f64 };
+        for bin in 0..WINDOW {
+            let (mag, now) = ((re[bin]*re[bin] + im[bin]*im[bin]).sqrt(), im[bin].atan2(re[bin]));
+            let expected = 2.0*PI*bin as f64*HOP as f64/WINDOW as f64;
+            let mut drift = now - last_phase[bin] - expected;
+            drift -= 2.0*PI*(drift/(2.0*PI)).round();
+            last_phase[bin] = now;
+            // the bin's true frequency, in radians per sample
+            let freq = (expected + drift)/HOP as f64;
+            phase[bin] = if frame == 0 { now } else { phase[bin] + freq*gap };
+            re[bin] = mag*phase[bin].cos();
+            im[bin] = mag*phase[bin].sin();
+        }
+        ifft(&mut re, &mut im);
+        for i in 0..WINDOW {
+            out[place(frame) + i] += re[i]*hann[i];
+            norm[place(frame) + i] += hann[i]*hann[i];
+        }
+    }
+    // undo the half window frames started early by, and the windows'
+    // overlap
+    out.iter().zip(norm.iter()).skip(WINDOW/2).take(len)
+        .map(|(&sample, &norm)| if norm > 1e-3 { (sample/norm) as f32 } else { 0.0 })
+        .collect()
+}
+
+/// `samples` read at `ratio` times the speed, with straight lines between
+/// them
+fn resample(samples: &[f32], ratio: f64) -> Vec<f32> {
+    let len = (samples.len() as f64/ratio).round() as usize;
+    (0..len).map(|i| {
+        let at = i as f64*ratio;
+        let (whole, part) = (at as usize, (at.fract()) as f32);
+        let a = samples.get(whole).cloned().unwrap_or(0.0);
+        let b = samples.get(whole + 1).cloned().unwrap_or(0.0);
+        a + (b - a)*part
+    }).collect()
+}
+
+/// `samples` made `factor` times as long and shifted by `semitones`
+pub fn process(samples: &[f32], factor: f64, semitones: f64) -> Vec<f32> {
+    let ratio = 2_f64.powf(semitones/12.0);
+    if ratio == 1.0 {
+        return stretch(samples, factor);
+    }
+    resample(&stretch(samples, factor*ratio), ratio)
+}
+
+/// A sink that holds the whole render, then stretches and shifts it and
+/// writes it on to `out` when it's finished.
+pub struct Vocoded<'a> {
+    out: Box<dyn Sink + 'a>,
+    samples: Vec<f32>,
+    factor: f64,
+    semitones: f64,
+}
+
+impl<'a> Vocoded<'a> {
+    pub fn new(out: Box<dyn Sink + 'a>, factor: f64, semitones: f64) -> Vocoded<'a> {
+        Vocoded { out, samples: vec![], factor, semitones }
+    }
+}
+
+impl<'a> Sink for Vocoded<'a> {
+    fn write_block(&mut self, samples: &[f32]) -> io::Result<()> {
+        self.samples.extend_from_slice(samples);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let processed = process(&self.samples, self.factor, self.semitones);
+        self.out.write_block(&processed)?;
+        self.out.finish()
+    }
+}
@@ -0,0 +1,77 @@
+//! Voice models: playing a program through the fixed channels of an old sound
+//! chip instead of mixing every thread as its own sine wave.
+//!
+//! Each time step the renderer hands the model the threads that are sounding,
+//! as `Voice`s. The model decides which channel each one gets with an
+//! `Allocator`, then makes the sample the way the chip would. A thread that
+//! doesn't get a channel isn't heard, just as on the real thing.
+
+use beep::Beep;
+use compiler::Patch;
+use gameboy::GameBoy;
+use nes::Nes;
+use sid::Sid;
+
+/// A thread that's sounding on this time step.
+#[derive(Clone, Copy)]
+pub struct Voice {
+    /// the thread's number, which keeps it on the same channel from one
+    /// step to the next
+    pub id: usize,
+    pub freq: f64,
+    /// how loud it is right now, from 0 to 1, counting its envelope
+    pub gain: f64,
+    /// a drum hit, which chips play on their noise channel
+    pub drum: bool,
+    /// the duty and wave its line asked for
+    pub patch: Patch,
+}
+
+pub trait VoiceModel {
+    /// the sample for this time step, from the voices sounding on it
+    fn next_sample(&mut self, voices: &[Voice]) -> f64;
+}
+
+/// The voice model called `name`.
+pub fn voice_model(name: &str) -> Option<Box<dyn VoiceModel + Send>> {
+    match name {
+        "nes" => Some(Box::new(Nes::new())),
+        "gameboy" => Some(Box::new(GameBoy::new())),
+        "sid" => Some(Box::new(Sid::new())),
+        "beep" => Some(Box::new(Beep::new())),
+        _ => None,
+    }
+}
+
+/// Shares out a chip's channels among the threads that want them. A thread
+/// keeps its channel for as long as it sounds, and a thread that starts while
+/// every channel it could use is taken waits until one comes free.
+pub struct Allocator {
+    held: Vec<Option<usize>>,
+}
+
+impl Allocator {
+    pub fn new(channels: usize) -> Allocator {
+        Allocator { held: vec![None; channels] }
+    }
+
+    /// The voice each channel plays on this time step, if any. `fits` says
+    /// whether a channel can play a voice at all. Channels are tried in
+    /// order, so the ones a model would rather use go first.
+    pub fn assign<F: Fn(usize, &Voice) -> bool>(&mut self, voices: &[Voice], fits: F) -> Vec<Option<Voice>> {
+        let mut assigned: Vec<Option<Voice>> = self.held.iter().enumerate().map(|(channel, held)| {
+            held.and_then(|id| voices.iter().find(|voice| voice.id == id && fits(channel, voice)).cloned())
+        }).collect();
+        for voice in voices {
+            if assigned.iter().flatten().any(|playing| playing.id == voice.id) {
+                continue;
+            }
+            let free = (0..assigned.len()).find(|&channel| assigned[channel].is_none() && fits(channel, voice));
+            if let Some(channel) = free {
+                assigned[channel] = Some(*voice);
+            }
+        }
+        self.held = assigned.iter().map(|voice| voice.map(|voice| voice.id)).collect();
+        assigned
+    }
+}
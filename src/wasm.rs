@@ -0,0 +1,112 @@
+//! A playground's calls exported for JavaScript, from a build like
+//! `cargo rustc --release --lib --features wasm --target wasm32-unknown-unknown -- --crate-type cdylib`.
+//!
+//! Source goes in as UTF-8 written into memory from `blip_alloc`. What comes
+//! back out, the diagnostics and the samples, is a pointer and a length
+//! into the module's memory, good until the next call on the same
+//! playground. An AudioWorklet's `process` calls `blip_render` for each
+//! quantum and copies that many samples out from `blip_block`.
+
+use std::mem;
+use std::slice;
+use playground::Playground;
+use renderer::SAMPLE_RATE;
+
+/// the rate to run the AudioContext at
+#[no_mangle]
+pub extern "C" fn blip_sample_rate() -> f64 {
+    SAMPLE_RATE
+}
+
+/// `len` bytes to write into, to give back with `blip_free`
+#[no_mangle]
+pub extern "C" fn blip_alloc(len: usize) -> *mut u8 {
+    let mut bytes = Vec::<u8>::with_capacity(len);
+    let ptr = bytes.as_mut_ptr();
+    mem::forget(bytes);
+    ptr
+}
+
+/// # Safety
+///
+/// `ptr` and `len` must be from a call to `blip_alloc`, and given back once.
+#[no_mangle]
+pub unsafe extern "C" fn blip_free(ptr: *mut u8, len: usize) {
+    drop(Vec::from_raw_parts(ptr, 0, len));
+}
+
+#[no_mangle]
+pub extern "C" fn blip_new() -> *mut Playground {
+    Box::into_raw(Box::new(Playground::new()))
+}
+
+/// # Safety
+///
+/// `playground` must be from `blip_new`, and not used again.
+#[no_mangle]
+pub unsafe extern "C" fn blip_drop(playground: *mut Playground) {
+    drop(Box::from_raw(playground));
+}
+
+/// Load the `len` bytes of source at `text`, see `Playground::load`. 1 if
+/// it compiled, 0 if not.
+///
+/// # Safety
+///
+/// `playground` must be from `blip_new`, and `text` must point at `len`
+/// bytes.
+#[no_mangle]
+pub unsafe extern "C" fn blip_load(playground: *mut Playground, text: *const u8, len: usize, seed: u32) -> u32 {
+    let text = String::from_utf8_lossy(slice::from_raw_parts(text, len));
+    (*playground).load(&text, seed as usize) as u32
+}
+
+/// where the JSON of `Playground::diagnostics` starts
+///
+/// # Safety
+///
+/// `playground` must be from `blip_new`.
+#[no_mangle]
+pub unsafe extern "C" fn blip_diagnostics(playground: *const Playground) -> *const u8 {
+    (*playground).diagnostics().as_ptr()
+}
+
+/// how many bytes of JSON `blip_diagnostics` points at
+///
+/// # Safety
+///
+/// `playground` must be from `blip_new`.
+#[no_mangle]
+pub unsafe extern "C" fn blip_diagnostics_len(playground: *const Playground) -> usize {
+    (*playground).diagnostics().len()
+}
+
+/// Render up to `len` samples into the block, returning how many.
+///
+/// # Safety
+///
+/// `playground` must be from `blip_new`.
+#[no_mangle]
+pub unsafe extern "C" fn blip_render(playground: *mut Playground, len: usize) -> usize {
+    (*playground).render(len).len()
+}
+
+/// where the samples `blip_render` made start
+///
+/// # Safety
+///
+/// `playground` must be from `blip_new`.
+#[no_mangle]
+pub unsafe extern "C" fn blip_block(playground: *const Playground) -> *const f32 {
+    (*playground).block().as_ptr()
+}
+
+/// 1 once the program has finished, or if none has been loaded
+///
+/// # Safety
+///
+/// `playground` must be from `blip_new`.
+#[no_mangle]
+pub unsafe extern "C" fn blip_finished(playground: *const Playground) -> u32 {
+    (*playground).is_finished() as u32
+}
@@ -0,0 +1,216 @@
+//! Live coding: play a program from a file and pick up every saved edit
+//! without stopping the sound.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+use compiler::{compile, label_table, CompileError, CompileWarning, Incremental, Instruction, Program, Recompiled, Span};
+use hotswap::migrate_pc;
+use interrupt::FadeOut;
+use crossfade::Crossfade;
+use renderer::{Renderer, RuntimeError, Steal};
+use sink::Sink;
+use tempo::SharedTempo;
+
+pub enum WatchEvent<'a> {
+    /// the file changed but doesn't compile; the old version keeps playing
+    Errors(&'a [CompileError]),
+    /// the file compiled, but with warnings
+    Warnings(&'a [CompileWarning]),
+    /// the new version took over, carrying `kept` of `of` threads across
+    Swapped { kept: usize, of: usize },
+    /// only some instructions changed, and just those were swapped in
+    Patched { instructions: usize },
+    /// nothing could be carried across, so the new version starts from the
+    /// top while the old one fades out
+    Crossfading,
+    /// nothing could be carried across, so the new version starts over
+    Restarted,
+    /// the program in `text` stopped with a runtime error, and stays silent
+    /// until the next edit
+    Stopped { error: &'a RuntimeError, text: &'a str },
+}
+
+pub struct WatchConfig {
+    pub seed: usize,
+    pub block_size: usize,
+    /// samples to crossfade over when no thread survives a reload, 0 for a
+    /// hard cut
+    pub crossfade: usize,
+    /// follow this tempo instead of each version's `@bpm`
+    pub tempo: Option<SharedTempo>,
+    /// merge identical threads, see `Renderer::set_dedup`
+    pub dedup: bool,
+    /// the most threads at once, see `Renderer::set_max_threads`
+    pub max_threads: Option<(usize, Steal)>,
+}
+
+fn start(program: Program, config: &WatchConfig) -> Renderer {
+    let mut renderer = Renderer::new(program, config.seed);
+    renderer.set_dedup(config.dedup);
+    if let Some((max, steal)) = config.max_threads {
+        renderer.set_max_threads(max, steal);
+    }
+    if let Some(ref tempo) = config.tempo {
+        renderer.set_tempo_source(Box::new(tempo.clone()));
+    }
+    renderer
+}
+
+enum Playing {
+    Nothing,
+    One(Box<Renderer>),
+    Fading(Box<Crossfade>),
+}
+
+/// Swap `changes` into what's playing, if it's still going and so has
+/// something to patch; false if it needs the whole new program instead.
+fn patch(playing: &mut Playing, changes: Vec<(usize, Instruction, Span)>) -> bool {
+    let renderer = match *playing {
+        Playing::One(ref mut renderer) => &mut **renderer,
+        Playing::Fading(ref mut fade) => fade.incoming(),
+        Playing::Nothing => return false,
+    };
+    if renderer.is_finished() {
+        return false;
+    }
+    renderer.patch_instructions(changes);
+    true
+}
+
+fn modified(path: &Path) -> io::Result<SystemTime> {
+    fs::metadata(path)?.modified()
+}
+
+/// Play the program in `path` forever, swapping in each new version as it's
+/// saved. Once every thread has finished the output stays silent (rather
+/// than stopping) until the next edit. It only stops once Ctrl-C has been
+/// pressed, if `interrupt::catch` is catching it, finishing `sink` after
+/// the fade-out.
+pub fn watch(path: &Path, config: &WatchConfig, sink: &mut dyn Sink, report: &mut dyn FnMut(WatchEvent)) -> io::Result<()> {
+    let mut fade = FadeOut::new(1);
+    let mut last_modified = modified(path)?;
+    let mut text = fs::read_to_string(path)?;
+    // None until a version compiles
+    let mut compiler = None;
+    let mut playing = match Incremental::new(&text) {
+        Ok((incremental, program)) => {
+            compiler = Some(incremental);
+            if !program.warnings.is_empty() {
+                report(WatchEvent::Warnings(&program.warnings));
+            }
+            Playing::One(Box::new(start(program, config)))
+        },
+        Err(errors) => {
+            report(WatchEvent::Errors(&errors));
+            Playing::Nothing
+        }
+    };
+
+    loop {
+        // editors often replace the file, so it can briefly be missing
+        if let Ok(time) = modified(path) {
+            if time != last_modified {
+                last_modified = time;
+                let new_text = fs::read_to_string(path)?;
+                let recompiled = match compiler {
+                    Some(ref mut incremental) => incremental.recompile(&new_text),
+                    None => Incremental::new(&new_text).map(|(incremental, program)| {
+                        compiler = Some(incremental);
+                        Recompiled::Full(Box::new(program))
+                    }),
+                };
+                let recompiled = match recompiled {
+                    Ok(Recompiled::Patched { changes, warnings }) => {
+                        if !warnings.is_empty() {
+                            report(WatchEvent::Warnings(&warnings));
+                        }
+                        let instructions = changes.len();
+                        if patch(&mut playing, changes) {
+                            report(WatchEvent::Patched { instructions });
+                            text = new_text.clone();
+                            None
+                        } else {
+                            // a finished program starts again from the top
+                            // instead, which takes all of it
+                            Some(compile(&new_text))
+                        }
+                    },
+                    Ok(Recompiled::Full(program)) => Some(Ok(*program)),
+                    Err(errors) => Some(Err(errors)),
+                };
+                match recompiled {
+                    None => {},
+                    Some(Ok(program)) => {
+                        if !program.warnings.is_empty() {
+                            report(WatchEvent::Warnings(&program.warnings));
+                        }
+                        let old_labels = label_table(&text);
+                        let new_labels = label_table(&new_text);
+                        let len = program.instructions.len();
+                        let migrate = |pc| migrate_pc(pc, &old_labels, &new_labels, len);
+
+                        // a reload during a fade cuts the fade short
+                        let current = match ::std::mem::replace(&mut playing, Playing::Nothing) {
+                            Playing::Nothing => None,
+                            Playing::One(renderer) => Some(*renderer),
+                            Playing::Fading(fade) => Some(fade.into_new()),
+                        };
+                        playing = match current {
+                            Some(mut renderer) => {
+                                let of = renderer.thread_count();
+                                if renderer.thread_pcs().into_iter().any(|pc| migrate(pc).is_some()) {
+                                    let kept = renderer.swap_program(program, migrate);
+                                    report(WatchEvent::Swapped { kept, of });
+                                    Playing::One(Box::new(renderer))
+                                } else if config.crossfade > 0 && !renderer.is_finished() {
+                                    report(WatchEvent::Crossfading);
+                                    let incoming = start(program, config);
+                                    Playing::Fading(Box::new(Crossfade::new(renderer, incoming, config.crossfade)))
+                                } else {
+                                    report(WatchEvent::Restarted);
+                                    Playing::One(Box::new(start(program, config)))
+                                }
+                            },
+                            None => {
+                                report(WatchEvent::Restarted);
+                                Playing::One(Box::new(start(program, config)))
+                            },
+                        };
+                        text = new_text;
+                    },
+                    Some(Err(errors)) => report(WatchEvent::Errors(&errors)),
+                }
+            }
+        }
+
+        let mut block = match playing {
+            Playing::One(ref mut renderer) if !renderer.is_finished() => {
+                let block = renderer.render_block(config.block_size);
+                if let Some(error) = renderer.take_error() {
+                    report(WatchEvent::Stopped { error: &error, text: &text });
+                }
+                block
+            },
+            Playing::Fading(ref mut fade) => fade.render_block(config.block_size),
+            _ => vec![],
+        };
+        let faded = match playing {
+            Playing::Fading(ref fade) => fade.is_done(),
+            _ => false,
+        };
+        if faded {
+            if let Playing::Fading(fade) = ::std::mem::replace(&mut playing, Playing::Nothing) {
+                playing = Playing::One(Box::new(fade.into_new()));
+            }
+        }
+        // silence where nothing's playing
+        block.resize(config.block_size, 0.0);
+        let faded_out = fade.apply(&mut block);
+        sink.write_block(&block)?;
+        if faded_out {
+            return sink.finish();
+        }
+    }
+}
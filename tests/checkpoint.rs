@@ -0,0 +1,59 @@
+//! A render saved partway and carried on by another renderer, as `--resume`
+//! does, against the same render straight through.
+
+extern crate blip_machine;
+
+use blip_machine::{compile, Program, Renderer};
+use blip_machine::audio_diff::diff;
+
+/// a loop that keeps drawing on the generator, forking and choosing as it
+/// goes, so a resumed render only matches if the generator is back where it
+/// was
+const PROGRAM: &str = "pfork kick 1
+pjump tune 1
+
+lbl kick
+zap 180 50 0.4 0.15
+pjump kick 0.8
+pjump End 1
+
+lbl tune
+walk A4 200 0.25 16
+pfork tune 0.3
+lbl End
+";
+
+const SEED: usize = 7;
+
+fn program() -> Program {
+    compile(PROGRAM).ok().expect("the program compiles")
+}
+
+fn render_rest(renderer: &mut Renderer, samples: &mut Vec<f32>) {
+    while !renderer.is_finished() {
+        samples.extend(renderer.render_block(1024));
+    }
+}
+
+#[test]
+fn a_resumed_render_sounds_the_same_as_one_straight_through() {
+    let mut straight = vec![];
+    render_rest(&mut Renderer::new(program(), SEED), &mut straight);
+
+    let mut first = Renderer::new(program(), SEED);
+    let mut resumed = vec![];
+    for _ in 0..40 {
+        resumed.extend(first.render_block(1024));
+    }
+    assert!(!first.is_finished(), "the render ended before it was saved");
+    let state = first.save_state().expect("the render can be saved");
+    drop(first);
+
+    let mut second = Renderer::new(program(), SEED);
+    second.load_state(&state).expect("the checkpoint loads");
+    render_rest(&mut second, &mut resumed);
+
+    let difference = diff(&resumed, &straight);
+    assert!(difference.same(), "{} samples against {}, {} off at sample {}",
+            difference.len_a, difference.len_b, difference.peak, difference.peak_at);
+}
@@ -0,0 +1,88 @@
+//! Edits patched in by `Incremental`, against the edited program compiled
+//! from scratch.
+
+extern crate blip_machine;
+
+use blip_machine::{compile, Dur, Instruction, Program};
+use blip_machine::compiler::{Incremental, Recompiled};
+use blip_machine::compiler::Instruction::*;
+
+/// labels, a voice and an lsystem in front of the plain lines, so the
+/// instructions the edits land on aren't on the lines they were written on
+const PROGRAM: &str = "voice arp low high
+lbl again
+sin $low 1/8
+sin $high 1/8
+pjump again 0.75
+end
+
+spawn arp C4 G4
+lsystem 3
+axiom A
+rule A A B
+rule B A
+sym A sin C5 0.125
+sym B sin G4 0.125
+end
+lbl verse
+sin 330 0.5
+zap 180 50 0.4 0.15
+pjump verse 0.5
+pfork chorus 0.25
+walk A4 200 0.25 4
+lbl chorus
+sin 440 1b
+";
+
+fn dur(dur: Dur) -> String {
+    match dur {
+        Dur::Secs(secs) => format!("{}s", secs),
+        Dur::Beats(beats) => format!("{}b", beats),
+        Dur::RandomSecs(_) | Dur::RandomBeats(_) => "random".to_string(),
+    }
+}
+
+/// enough of an instruction to tell it from any other the program could
+/// have compiled to
+fn describe(instruction: &Instruction) -> String {
+    match *instruction {
+        Sin(freq, d) => format!("sin {} {}", freq, dur(d)),
+        Zap(from, to, d, decay) => format!("zap {} {} {} {}", from, to, dur(d), decay),
+        Walk(freq, step, d, n, _) => format!("walk {} {} {} {}", freq, step, dur(d), n),
+        PJump(prob, to) => format!("pjump {} {}", prob, to),
+        PFork(prob, to) => format!("pfork {} {}", prob, to),
+        Terminate => "terminate".to_string(),
+        ref other => panic!("the test doesn't describe {}", other.op()),
+    }
+}
+
+fn describe_all(program: &Program) -> Vec<String> {
+    program.instructions.iter().map(describe).collect()
+}
+
+/// `PROGRAM` with line `line` (counting from 1) replaced by `with`
+fn edited(line: usize, with: &str) -> String {
+    PROGRAM.lines().enumerate().map(|(i, text)| if i + 1 == line { with } else { text })
+        .map(|text| format!("{}\n", text)).collect()
+}
+
+#[test]
+fn a_patched_line_compiles_the_same_as_the_whole_program() {
+    let edits = [(17, "sin 349.2 0.25b"), (18, "zap 200 40 0.3 0.1"), (19, "pjump chorus 0.9"),
+                 (20, "pfork verse 0.5"), (21, "walk C5 100 1/8 6")];
+    for &(line, with) in &edits {
+        let text = edited(line, with);
+        let (mut incremental, mut program) = Incremental::new(PROGRAM).ok().expect("the program compiles");
+        let changes = match incremental.recompile(&text).ok().expect("the edit compiles") {
+            Recompiled::Patched { changes, .. } => changes,
+            Recompiled::Full(_) => panic!("editing line {} compiled the whole program", line),
+        };
+        assert_eq!(changes.len(), 1, "editing line {}", line);
+        let edited_program = compile(&text).ok().expect("the edited program compiles");
+        for (pc, instruction, at) in changes {
+            program.instructions[pc] = instruction;
+            assert_eq!(Some(at), edited_program.spans[pc], "editing line {}", line);
+        }
+        assert_eq!(describe_all(&program), describe_all(&edited_program), "editing line {}", line);
+    }
+}
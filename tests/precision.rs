@@ -0,0 +1,47 @@
+//! The renderer works in f32 from each thread's sine to the output. These
+//! check that against the same notes worked out in f64 all the way. The
+//! `fixed` feature works in Q15 instead, and `fixed` has tests of its own.
+
+#![cfg(not(feature = "fixed"))]
+
+extern crate blip_machine;
+
+use std::f64::consts::PI;
+use blip_machine::{compile, Renderer, SAMPLE_RATE};
+use blip_machine::audio_diff::diff;
+
+fn render(text: &str) -> Vec<f32> {
+    let program = compile(text).ok().expect("the program compiles");
+    let mut renderer = Renderer::new(program, 0);
+    let mut samples = vec![];
+    while !renderer.is_finished() {
+        samples.extend(renderer.render_block(1024));
+    }
+    samples
+}
+
+/// `freqs` played together from the start for `secs`, each a thread of its
+/// own sharing the mix, in f64 until the end
+fn reference(freqs: &[f64], secs: f64) -> Vec<f32> {
+    let mut phases = vec![0.0; freqs.len()];
+    (0..(secs*SAMPLE_RATE) as usize).map(|_| {
+        let mut sum = 0.0;
+        for (phase, &freq) in phases.iter_mut().zip(freqs) {
+            sum += (2.0*PI**phase).sin();
+            *phase = (*phase + freq/SAMPLE_RATE).fract();
+        }
+        (sum/freqs.len() as f64) as f32
+    }).collect()
+}
+
+#[test]
+fn a_chord_in_f32_sounds_the_same_as_in_f64() {
+    let program = "pfork E4 1\npfork G4 1\nsin 261.6 2\npjump End 1\nlbl E4\nsin 329.6 2\npjump End 1\n\
+                   lbl G4\nsin 392.0 2\nlbl End\n";
+    let rendered = render(program);
+    let expected = reference(&[261.6, 329.6, 392.0], 2.0);
+    assert!(rendered.len() >= expected.len(), "only {} samples", rendered.len());
+    // a render runs a sample on past its last note
+    let difference = diff(&rendered[..expected.len()], &expected);
+    assert!(difference.same(), "{} off at sample {}", difference.peak, difference.peak_at);
+}
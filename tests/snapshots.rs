@@ -0,0 +1,64 @@
+//! Renders checked against fingerprints stored in `tests/snapshots`, see
+//! `fingerprint`. To take a new snapshot on purpose, delete the old one and
+//! run the tests again.
+
+extern crate blip_machine;
+
+use std::path::Path;
+use blip_machine::{compile, Renderer};
+use blip_machine::fingerprint::{check_snapshot, Mismatch};
+
+/// the README's chord, a kick under it, and a line wandering over the top,
+/// so every kind of note and some branching is in it
+const PROGRAM: &str = "pfork C4 1
+pfork E4 1
+pfork G4 1
+pfork kick 1
+pjump tune 1
+
+lbl C4
+sin 261.6 2
+pjump End 1
+
+lbl E4
+sin 329.6 2
+pjump End 1
+
+lbl G4
+sin 392.0 2
+pjump End 1
+
+lbl kick
+zap 180 50 0.4 0.15
+sweep 200 2000 0.1
+pjump kick 0.8
+pjump End 1
+
+lbl tune
+walk A4 200 0.25 8
+pjump End 1
+
+lbl End
+";
+
+fn render(text: &str, seed: usize) -> Vec<f32> {
+    let program = compile(text).ok().expect("the program compiles");
+    let mut renderer = Renderer::new(program, seed);
+    let mut samples = vec![];
+    while !renderer.is_finished() {
+        samples.extend(renderer.render_block(1024));
+    }
+    samples
+}
+
+#[test]
+fn chord_matches_its_snapshot() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots/chord.txt");
+    match check_snapshot(&path, &render(PROGRAM, 7), 3.0).unwrap() {
+        Ok(()) => {},
+        Err(Mismatch::Length { expected, got }) => panic!("{} seconds long, not {}", got, expected),
+        Err(Mismatch::Level { second, band, expected, got }) => {
+            panic!("second {}, from {} Hz: {:.1} dB, not {:.1} dB", second, band, got, expected)
+        },
+    }
+}
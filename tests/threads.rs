@@ -0,0 +1,77 @@
+//! How many threads a render keeps: merged under `set_dedup`, and held to
+//! `set_max_threads` whichever way it steals.
+
+extern crate blip_machine;
+
+use blip_machine::{compile, Renderer, Steal};
+
+fn renderer(text: &str, seed: usize) -> Renderer {
+    Renderer::new(compile(text).ok().expect("the program compiles"), seed)
+}
+
+/// `forks` forks in a row, each taken with `prob` and going on to the next
+/// line, where the thread that forked goes too, so every one of them forks
+/// at the next; they all end on the same two notes
+fn fork_heavy(forks: usize, prob: f64) -> String {
+    let mut text: String = (0..forks).map(|i| format!("pfork F{} {}\nlbl F{}\n", i, prob, i)).collect();
+    text.push_str("sin 440 0.5\nsin 660 0.5\n");
+    text
+}
+
+#[test]
+fn identical_threads_merge_into_one_that_stands_for_them_all() {
+    let mut renderer = renderer(&fork_heavy(12, 1.0), 0);
+    renderer.set_dedup(true);
+    renderer.render_block(64);
+    let threads = renderer.threads();
+    assert_eq!(threads.len(), 1);
+    assert_eq!(threads[0].weight, 1 << 12);
+    assert_eq!(renderer.thread_count(), 1 << 12);
+}
+
+#[test]
+fn weighted_threads_fork_some_of_what_they_stand_for() {
+    for seed in 0..8 {
+        let mut renderer = renderer(&fork_heavy(12, 0.5), seed);
+        renderer.set_dedup(true);
+        renderer.render_block(64);
+        let threads = renderer.threads();
+        // each fork only sends threads to where the others already are
+        assert_eq!(threads.len(), 1, "seed {}", seed);
+        let count = renderer.thread_count();
+        assert_eq!(threads[0].weight, count, "seed {}", seed);
+        // each fork takes some of the threads that reach it, but not all
+        assert!(count > 1 && count < 1 << 12, "seed {}: {} threads", seed, count);
+    }
+}
+
+/// a fork every twentieth of a second, each one a two second note that
+/// sometimes forks again
+const SWARM: &str = "lbl loop
+pfork voice 1
+sin 440 0.05
+pjump loop 1
+lbl voice
+pfork echo 0.5
+sin 220 2
+pjump End 1
+lbl echo
+sin 330 1
+lbl End
+";
+
+#[test]
+fn max_threads_holds_the_count_at_the_cap_for_every_steal() {
+    for &steal in &[Steal::Oldest, Steal::Quietest, Steal::Deepest] {
+        let mut renderer = renderer(SWARM, 0);
+        renderer.set_max_threads(8, steal);
+        let mut most = 0;
+        for _ in 0..100 {
+            renderer.render_block(1024);
+            most = most.max(renderer.thread_count());
+        }
+        assert_eq!(most, 8, "{:?}", steal);
+        assert!(renderer.stolen() > 0, "{:?}", steal);
+    }
+}
+
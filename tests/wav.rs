@@ -0,0 +1,28 @@
+//! WAV files as big as their 32-bit sizes can say.
+
+extern crate blip_machine;
+
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::process;
+use blip_machine::{Sink, WavSink};
+
+#[test]
+fn a_wav_file_stops_before_its_sizes_wrap_around() {
+    let path = env::temp_dir().join(format!("blip-machine-full-{}.wav", process::id()));
+    drop(WavSink::<_, i16>::create(&path).unwrap());
+    // all but the last sample the data chunk's size can count, left as a
+    // hole in the file rather than written
+    let samples = u32::MAX as u64/2;
+    OpenOptions::new().write(true).open(&path).unwrap().set_len(44 + 2*samples).unwrap();
+    let mut sink = WavSink::<_, i16>::resume(&path, samples).unwrap();
+
+    let written = sink.write_block(&[0.0]).expect_err("one more sample is refused");
+    assert_eq!(written.to_string(), "WAV data over 4 GiB");
+    // the data chunk's size still fits, but the RIFF chunk's is 36 more
+    let finished = sink.finish().expect_err("the header is refused");
+    assert_eq!(finished.to_string(), "WAV data over 4 GiB");
+
+    drop(sink);
+    fs::remove_file(&path).unwrap();
+}